@@ -11,7 +11,7 @@ from collections.abc import Callable
 from datetime import datetime
 
 from PyQt6.QtCore import Qt, QTimer, pyqtSignal
-from PyQt6.QtGui import QAction, QClipboard
+from PyQt6.QtGui import QAction, QClipboard, QIcon
 from PyQt6.QtWidgets import (
     QApplication,
     QButtonGroup,
@@ -1069,7 +1069,9 @@ class MiddlePanel(QWidget):
         """Stop the device monitor."""
         if self.device_monitor:
             try:
-                self.device_monitor.stop()
+                # Don't let a wedged monitor thread hang shutdown
+                if not self.device_monitor.stop(timeout=2.0):
+                    logger.warning("Device monitor thread did not stop in time")
             except Exception as e:
                 logger.warning("Error stopping device monitor: %s", e)
         # Cancel any pending deferred operations so they don't fire after teardown
@@ -1083,11 +1085,19 @@ class MiddlePanel(QWidget):
             self.devices = quinoa_audio.list_devices()
             default_index = 0
 
-            for device in self.devices:
-                if device.device_type == quinoa_audio.DeviceType.Microphone:
-                    self.mic_combo.addItem(f"{device.name}", device.id)
-                    if device.is_default:
-                        default_index = self.mic_combo.count() - 1
+            mics = quinoa_audio.rank_devices(
+                [d for d in self.devices if d.device_type == quinoa_audio.DeviceType.Microphone]
+            )
+            names = [d.name for d in mics]
+            for device in mics:
+                label = device.name
+                # Tell identical devices apart by the port they are plugged into
+                if names.count(device.name) > 1 and device.bus_path:
+                    label = f"{device.name} ({device.bus_path})"
+                icon = QIcon.fromTheme(device.icon_name) if device.icon_name else QIcon()
+                self.mic_combo.addItem(icon, label, device.id)
+                if device.is_default:
+                    default_index = self.mic_combo.count() - 1
 
             if self.mic_combo.count() > 0:
                 self.mic_combo.setCurrentIndex(default_index)
@@ -1926,6 +1936,56 @@ class MiddlePanel(QWidget):
                 elif event.type_ == "pipewire_disconnected":
                     self.status_label.setText("Reconnecting...")
                     self.status_label.setStyleSheet(STATUS_LABEL_PAUSED)
+                elif event.type_ == "reconnecting":
+                    self.status_label.setText(
+                        f"Reconnecting to audio service (attempt {event.attempt})..."
+                    )
+                    self.status_label.setStyleSheet(STATUS_LABEL_PAUSED)
+                    logger.info(
+                        "Reconnecting to PipeWire (attempt %s, retry in %.1fs)",
+                        event.attempt,
+                        event.retry_delay or 0.0,
+                    )
+                elif event.type_ == "dropout":
+                    logger.warning("Audio %s", event.message)
+                elif event.type_ == "queue_overflow":
+                    logger.warning("Audio %s: %s", event.stream, event.message)
+                elif event.type_ == "memory_limit_reached":
+                    logger.warning("Audio %s", event.message)
+                elif event.type_ == "config_mismatch":
+                    logger.warning("Audio %s", event.message)
+                elif event.type_ == "speaking":
+                    logger.debug(
+                        "Speaking: mic=%s system=%s", event.mic_speaking, event.system_speaking
+                    )
+                elif event.type_ == "idle_started":
+                    logger.info("Audio idle: %s", event.message)
+                    self.mic_level_bar.setValue(0)
+                    self.sys_level_bar.setValue(0)
+                elif event.type_ == "idle_ended":
+                    logger.info("Audio idle ended")
+                elif event.type_ == "mic_muted":
+                    logger.info("Audio: %s", event.message)
+                    self.status_label.setText("Recording... (mic muted)")
+                elif event.type_ == "mic_unmuted":
+                    logger.info("Audio: microphone unmuted")
+                    self.status_label.setText("Recording...")
+                elif event.type_ == "stream_inactive":
+                    logger.warning("Audio %s", event.message)
+                    if event.stream == "mic":
+                        self.status_label.setText("Recording... (no mic audio, is it muted?)")
+                elif event.type_ == "format_negotiated":
+                    logger.info("Audio format: %s", event.message)
+                elif event.type_ == "format_changed":
+                    logger.warning("Audio format changed: %s", event.message)
+                elif event.type_ == "narrowband_input":
+                    logger.warning("Audio quality: %s", event.message)
+                elif event.type_ == "bt_profile_switched":
+                    logger.warning("Bluetooth: %s", event.message)
+                elif event.type_ == "default_status_changed":
+                    logger.info("Audio: %s", event.message)
+                elif event.type_ == "segment_started":
+                    logger.info("Audio %s continues in %s", event.stream, event.path)
                 elif event.type_ == "started":
                     self.status_label.setText("Recording...")
                     self.status_label.setStyleSheet("")  # Reset to default
@@ -1952,8 +2012,22 @@ class MiddlePanel(QWidget):
             try:
                 device_events = self.device_monitor.poll()
                 for event in device_events:
-                    # Only react to real device changes (ignore spurious events with no name)
-                    if event.type_ in ["added", "removed"] and event.device_name:
+                    if event.type_ in ["disconnected", "reconnecting"]:
+                        logger.warning("Device monitor %s (PipeWire unavailable)", event.type_)
+                        continue
+                    # The devices may have changed while PipeWire was down
+                    if event.type_ == "resynced":
+                        logger.info("Device monitor resynced after reconnecting")
+                        self.refresh_devices()
+                        break
+                    # Only react to real device changes (ignore spurious events with no
+                    # name, and the devices reported as already present at startup);
+                    # "changed" covers renames and other property updates
+                    if (
+                        event.type_ in ["added", "removed", "changed"]
+                        and event.device_name
+                        and not event.initial
+                    ):
                         logger.info("Device %s: %s", event.type_, event.device_name)
                         self.refresh_devices()
                         break
@@ -0,0 +1,3 @@
+fn main() {
+    std::process::exit(quinoa_audio::cli::main());
+}
@@ -0,0 +1,81 @@
+//! Which stream and device an event came from, for telling apart the events
+//! of several sessions and microphones
+
+use crate::capture::session::AudioEvent;
+
+/// The device each stream records, kept current through mic switches
+#[derive(Debug, Default)]
+pub struct StreamDevices {
+    pub mic: Option<String>,
+    /// None while recording the default output's monitor
+    pub system: Option<String>,
+}
+
+impl StreamDevices {
+    /// Fill in the device of an event that names only its stream, and the
+    /// stream of one that names only a recorded device
+    pub fn attribute(&mut self, event: &mut AudioEvent) {
+        if event.type_ == "mic_switched" && event.device_id.is_some() {
+            self.mic = event.device_id.clone();
+        }
+        match (event.stream.as_deref(), &event.device_id) {
+            (Some("mic"), None) => event.device_id = self.mic.clone(),
+            (Some("system"), None) => event.device_id = self.system.clone(),
+            (None, Some(id)) if self.mic.as_ref() == Some(id) => {
+                event.stream = Some("mic".to_string());
+            }
+            (None, Some(id)) if self.system.as_ref() == Some(id) => {
+                event.stream = Some("system".to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(type_: &str, stream: Option<&str>, device_id: Option<&str>) -> AudioEvent {
+        AudioEvent {
+            stream: stream.map(String::from),
+            device_id: device_id.map(String::from),
+            ..AudioEvent::of_type(type_)
+        }
+    }
+
+    #[test]
+    fn test_attribute_events() {
+        let mut devices = StreamDevices {
+            mic: Some("alsa_input.usb".to_string()),
+            system: None,
+        };
+        let mut attributed = |mut e: AudioEvent| {
+            devices.attribute(&mut e);
+            (e.stream, e.device_id)
+        };
+        let pair =
+            |stream: &str, id: Option<&str>| (Some(stream.to_string()), id.map(String::from));
+
+        assert_eq!(
+            attributed(event("dropout", Some("mic"), None)),
+            pair("mic", Some("alsa_input.usb"))
+        );
+        assert_eq!(
+            attributed(event("device_lost", None, Some("alsa_input.usb"))),
+            pair("mic", Some("alsa_input.usb"))
+        );
+        assert_eq!(
+            attributed(event("dropout", Some("system"), None)),
+            pair("system", None)
+        );
+        // Session-wide events stay unattributed
+        assert_eq!(attributed(event("started", None, None)), (None, None));
+
+        attributed(event("mic_switched", Some("mic"), Some("bluez_input.AA")));
+        assert_eq!(
+            attributed(event("stream_error", Some("mic"), None)),
+            pair("mic", Some("bluez_input.AA"))
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+use crate::capture::session::RecordingConfig;
+use crate::capture::validate::check_settings;
+use crate::errors::{ConfigError, UnsupportedFormatError};
+
+/// Output formats `RecordingConfigBuilder.format()` accepts. WAV files are
+/// always written; the others are made alongside them.
+pub const OUTPUT_FORMATS: &[&str] = &["wav", "mka", "opus"];
+
+/// Fluent construction of a `RecordingConfig`, from
+/// `RecordingConfig.builder()`. Each method returns the builder; `build()`
+/// checks the settings together and raises ConfigError,
+/// UnsupportedFormatError or OutputDirError for the first problem found.
+#[pyclass]
+#[derive(Clone, Debug, Default)]
+pub struct RecordingConfigBuilder {
+    config: RecordingConfig,
+    format: Option<String>,
+    bitrate_kbps: Option<u32>,
+}
+
+impl RecordingConfigBuilder {
+    /// Checks of settings that only conflict when asked for explicitly
+    fn check_combinations(&self) -> Result<(), String> {
+        let config = &self.config;
+        if config.split_mic_channels && config.mic_device_id.is_none() {
+            return Err("split_mic_channels needs a mic to split".to_string());
+        }
+        if let Some(kbps) = self.bitrate_kbps {
+            let compressed = config.opus_output
+                || config.icecast_url.is_some()
+                || config.hls_stream.is_some()
+                || config.opus_packet_stream.is_some();
+            if !compressed {
+                return Err(format!(
+                    "bitrate {} kbps has nothing to apply to: {} output is uncompressed",
+                    kbps,
+                    self.format.as_deref().unwrap_or("wav")
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl RecordingConfigBuilder {
+    fn output_dir(mut slf: PyRefMut<'_, Self>, path: String) -> PyRefMut<'_, Self> {
+        slf.config.output_dir = Some(path);
+        slf
+    }
+
+    /// Record this microphone (node name, global id, persistent key or
+    /// description)
+    fn mic(mut slf: PyRefMut<'_, Self>, device_id: String) -> PyRefMut<'_, Self> {
+        slf.config.mic_device_id = Some(device_id);
+        slf
+    }
+
+    #[pyo3(signature = (enabled=true))]
+    fn system_audio(mut slf: PyRefMut<'_, Self>, enabled: bool) -> PyRefMut<'_, Self> {
+        slf.config.system_audio = enabled;
+        slf
+    }
+
+    /// Record system audio from this output rather than the default one
+    fn system_device(mut slf: PyRefMut<'_, Self>, device_id: String) -> PyRefMut<'_, Self> {
+        slf.config.system_audio = true;
+        slf.config.system_device_id = Some(device_id);
+        slf
+    }
+
+    fn sample_rate(mut slf: PyRefMut<'_, Self>, hz: u32) -> PyRefMut<'_, Self> {
+        slf.config.sample_rate = hz;
+        slf
+    }
+
+    /// "wav", "mka" (also write `session.mka`) or "opus" (also transcode
+    /// each WAV file to Opus)
+    fn format(mut slf: PyRefMut<'_, Self>, format: String) -> PyResult<PyRefMut<'_, Self>> {
+        let format = format.to_ascii_lowercase();
+        if !OUTPUT_FORMATS.contains(&format.as_str()) {
+            return Err(UnsupportedFormatError::new_err(format!(
+                "Unsupported output format {:?} (expected one of {:?})",
+                format, OUTPUT_FORMATS
+            )));
+        }
+        slf.config.mka_output = format == "mka";
+        slf.config.opus_output = format == "opus";
+        slf.format = Some(format);
+        Ok(slf)
+    }
+
+    /// Target bitrate of every compressed output (Opus files, live streams)
+    fn bitrate(mut slf: PyRefMut<'_, Self>, kbps: u32) -> PyRefMut<'_, Self> {
+        slf.bitrate_kbps = Some(kbps);
+        slf.config.encoder_options.bitrate_kbps = Some(kbps);
+        slf
+    }
+
+    #[pyo3(signature = (enabled=true))]
+    fn split_mic_channels(mut slf: PyRefMut<'_, Self>, enabled: bool) -> PyRefMut<'_, Self> {
+        slf.config.split_mic_channels = enabled;
+        slf
+    }
+
+    #[pyo3(signature = (max_attempts=None, initial_delay=1.0, max_delay=30.0))]
+    fn reconnect(
+        mut slf: PyRefMut<'_, Self>,
+        max_attempts: Option<u32>,
+        initial_delay: f64,
+        max_delay: f64,
+    ) -> PyRefMut<'_, Self> {
+        slf.config.max_reconnect_attempts = max_attempts;
+        slf.config.reconnect_initial_delay = initial_delay;
+        slf.config.reconnect_max_delay = max_delay;
+        slf
+    }
+
+    #[pyo3(signature = (url, stream="system".to_string(), format="mp3".to_string()))]
+    fn icecast(
+        mut slf: PyRefMut<'_, Self>,
+        url: String,
+        stream: String,
+        format: String,
+    ) -> PyRefMut<'_, Self> {
+        slf.config.icecast_url = Some(url);
+        slf.config.icecast_stream = stream;
+        slf.config.icecast_format = format;
+        slf
+    }
+
+    #[pyo3(signature = (stream="system".to_string(), format="fmp4".to_string(), codec="aac".to_string()))]
+    fn hls(
+        mut slf: PyRefMut<'_, Self>,
+        stream: String,
+        format: String,
+        codec: String,
+    ) -> PyRefMut<'_, Self> {
+        slf.config.hls_stream = Some(stream);
+        slf.config.hls_format = format;
+        slf.config.hls_codec = codec;
+        slf
+    }
+
+    /// See `RecordingConfig.filename_template`
+    fn filename_template(mut slf: PyRefMut<'_, Self>, template: String) -> PyRefMut<'_, Self> {
+        slf.config.filename_template = Some(template);
+        slf
+    }
+
+    /// See `RecordingConfig.on_existing`
+    fn on_existing(mut slf: PyRefMut<'_, Self>, policy: String) -> PyRefMut<'_, Self> {
+        slf.config.on_existing = policy;
+        slf
+    }
+
+    /// See `RecordingConfig.stream_inactive_seconds`
+    fn stream_inactive_after(mut slf: PyRefMut<'_, Self>, seconds: u32) -> PyRefMut<'_, Self> {
+        slf.config.stream_inactive_seconds = seconds;
+        slf
+    }
+
+    /// See `RecordingConfig.resample_narrowband_mic`
+    #[pyo3(signature = (enabled=true))]
+    fn resample_narrowband_mic(mut slf: PyRefMut<'_, Self>, enabled: bool) -> PyRefMut<'_, Self> {
+        slf.config.resample_narrowband_mic = enabled;
+        slf
+    }
+
+    /// See `RecordingConfig.refuse_bt_profile_switch`
+    #[pyo3(signature = (enabled=true))]
+    fn refuse_bt_profile_switch(mut slf: PyRefMut<'_, Self>, enabled: bool) -> PyRefMut<'_, Self> {
+        slf.config.refuse_bt_profile_switch = enabled;
+        slf
+    }
+
+    /// Cut segments at wall-clock boundaries every `seconds`; see
+    /// `RecordingConfig.wall_clock_segment_seconds`
+    fn wall_clock_segments(mut slf: PyRefMut<'_, Self>, seconds: u32) -> PyRefMut<'_, Self> {
+        slf.config.wall_clock_segment_seconds = Some(seconds);
+        slf
+    }
+
+    /// "block" or "drop"; see `RecordingConfig.encoder_overflow`
+    fn encoder_overflow(mut slf: PyRefMut<'_, Self>, policy: String) -> PyRefMut<'_, Self> {
+        slf.config.encoder_overflow = policy;
+        slf
+    }
+
+    /// Cap the session's buffers at `mb` together; see
+    /// `RecordingConfig.memory_limit_mb`
+    fn memory_limit(mut slf: PyRefMut<'_, Self>, mb: u32) -> PyRefMut<'_, Self> {
+        slf.config.memory_limit_mb = Some(mb);
+        slf
+    }
+
+    /// Channel count each stream should be written with; see
+    /// `RecordingConfig.channels`
+    fn channels(mut slf: PyRefMut<'_, Self>, count: u32) -> PyRefMut<'_, Self> {
+        slf.config.channels = Some(count);
+        slf
+    }
+
+    /// Report speaking events for streams above `threshold_db`, ending once
+    /// one stays below it for `hold_ms`; see `RecordingConfig.speaking_threshold_db`
+    #[pyo3(signature = (threshold_db, hold_ms=500))]
+    fn speaking(
+        mut slf: PyRefMut<'_, Self>,
+        threshold_db: f64,
+        hold_ms: u32,
+    ) -> PyRefMut<'_, Self> {
+        slf.config.speaking_threshold_db = Some(threshold_db);
+        slf.config.speaking_hold_ms = hold_ms;
+        slf
+    }
+
+    /// Go idle after `seconds` without sound; see
+    /// `RecordingConfig.idle_after_seconds`
+    fn idle_after(mut slf: PyRefMut<'_, Self>, seconds: u32) -> PyRefMut<'_, Self> {
+        slf.config.idle_after_seconds = Some(seconds);
+        slf
+    }
+
+    /// See `RecordingConfig.pause_when_muted`
+    #[pyo3(signature = (enabled=true))]
+    fn pause_when_muted(mut slf: PyRefMut<'_, Self>, enabled: bool) -> PyRefMut<'_, Self> {
+        slf.config.pause_when_muted = enabled;
+        slf
+    }
+
+    /// Encrypt the recordings to this age recipient; see
+    /// `RecordingConfig.encryption_recipient`
+    fn encrypt(mut slf: PyRefMut<'_, Self>, recipient: String) -> PyRefMut<'_, Self> {
+        slf.config.encryption_recipient = Some(recipient);
+        slf
+    }
+
+    /// Upload finished files to `url` with these extra headers; see
+    /// `RecordingConfig.upload_url` and `RecordingConfig.upload_resume`
+    #[pyo3(signature = (url, headers=None, max_attempts=5, resume=false))]
+    fn upload(
+        mut slf: PyRefMut<'_, Self>,
+        url: String,
+        headers: Option<HashMap<String, String>>,
+        max_attempts: u32,
+        resume: bool,
+    ) -> PyRefMut<'_, Self> {
+        slf.config.upload_url = Some(url);
+        slf.config.upload_headers = headers.unwrap_or_default();
+        slf.config.upload_max_attempts = max_attempts;
+        slf.config.upload_resume = resume;
+        slf
+    }
+
+    /// POST session notifications to `url` with these extra headers; see
+    /// `RecordingConfig.webhook_url`
+    #[pyo3(signature = (url, headers=None))]
+    fn webhook(
+        mut slf: PyRefMut<'_, Self>,
+        url: String,
+        headers: Option<HashMap<String, String>>,
+    ) -> PyRefMut<'_, Self> {
+        slf.config.webhook_url = Some(url);
+        slf.config.webhook_headers = headers.unwrap_or_default();
+        slf
+    }
+
+    /// Make the session's trace part of the caller's; see
+    /// `RecordingConfig.traceparent`
+    fn traceparent(mut slf: PyRefMut<'_, Self>, traceparent: String) -> PyRefMut<'_, Self> {
+        slf.config.traceparent = Some(traceparent);
+        slf
+    }
+
+    /// See `RecordingConfig.event_log`
+    #[pyo3(signature = (enabled=true))]
+    fn event_log(mut slf: PyRefMut<'_, Self>, enabled: bool) -> PyRefMut<'_, Self> {
+        slf.config.event_log = enabled;
+        slf
+    }
+
+    /// Cut audio quieter than `threshold_db` from the ends of each file; see
+    /// `RecordingConfig.trim_silence_db`
+    fn trim_silence(mut slf: PyRefMut<'_, Self>, threshold_db: f32) -> PyRefMut<'_, Self> {
+        slf.config.trim_silence_db = Some(threshold_db);
+        slf
+    }
+
+    /// Set any other `RecordingConfig` attribute by name
+    fn set<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        name: &str,
+        value: &Bound<'py, PyAny>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let py = slf.py();
+        let config = Bound::new(py, slf.config.clone())?;
+        if !config.hasattr(name)? || name.starts_with('_') {
+            return Err(ConfigError::new_err(format!(
+                "RecordingConfig has no setting {:?}",
+                name
+            )));
+        }
+        config.setattr(name, value)?;
+        slf.config = config.borrow().clone();
+        Ok(slf)
+    }
+
+    /// The config, once its settings are known to work together. Devices
+    /// and the output dir are checked when recording starts.
+    fn build(&self) -> PyResult<RecordingConfig> {
+        let mut config = self.config.clone();
+        check_settings(&mut config)?;
+        self.check_combinations().map_err(ConfigError::new_err)?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitrate_needs_compressed_output() {
+        let mut builder = RecordingConfigBuilder::default();
+        builder.config.system_audio = true;
+        builder.bitrate_kbps = Some(64);
+        assert!(builder
+            .check_combinations()
+            .unwrap_err()
+            .contains("uncompressed"));
+
+        builder.config.opus_output = true;
+        assert!(builder.check_combinations().is_ok());
+
+        builder.config.split_mic_channels = true;
+        assert!(builder.check_combinations().is_err());
+        builder.config.mic_device_id = Some("mock_mic_1".to_string());
+        assert!(builder.check_combinations().is_ok());
+    }
+}
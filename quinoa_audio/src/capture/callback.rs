@@ -0,0 +1,71 @@
+//! Marks code running inside a realtime audio callback, where taking the GIL
+//! could stall the PipeWire graph for as long as Python holds it (or forever,
+//! if the thread holding it is waiting on the session).
+//!
+//! Every `process`, `param_changed` and `state_changed` callback (and each
+//! tick of the mock audio loop) enters a `CallbackScope`; all Python calls
+//! made by the capture code go through `with_gil`, which refuses to run in
+//! one.
+
+use std::cell::Cell;
+
+use pyo3::prelude::*;
+
+thread_local! {
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Held for the duration of an audio callback
+pub struct CallbackScope {
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+/// Enter an audio callback until the returned scope is dropped
+pub fn enter() -> CallbackScope {
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    CallbackScope {
+        _not_send: std::marker::PhantomData,
+    }
+}
+
+impl Drop for CallbackScope {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+    }
+}
+
+/// Whether this thread is inside an audio callback
+pub fn active() -> bool {
+    DEPTH.with(|depth| depth.get() > 0)
+}
+
+/// Run `f` with the GIL held, unless this thread is inside an audio callback.
+/// `what` names the call in errors, e.g. "Encoder plugin write()".
+pub fn with_gil<R>(what: &str, f: impl FnOnce(Python<'_>) -> PyResult<R>) -> Result<R, String> {
+    if active() {
+        return Err(format!(
+            "{} called from the audio callback, which must not take the GIL",
+            what
+        ));
+    }
+    Python::with_gil(f).map_err(|e| format!("{} failed: {}", what, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_nest_per_thread() {
+        assert!(!active());
+        let outer = enter();
+        {
+            let _inner = enter();
+            assert!(active());
+            assert!(!std::thread::spawn(active).join().unwrap());
+        }
+        assert!(active());
+        drop(outer);
+        assert!(!active());
+    }
+}
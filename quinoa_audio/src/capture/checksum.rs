@@ -0,0 +1,212 @@
+//! SHA-256 checksums of a session's output files, kept in its manifest so
+//! copies of the files can be checked against it
+
+use pyo3::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::capture::manifest::SessionManifest;
+use crate::errors::ConfigError;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 (FIPS 180-4) over data given a piece at a time
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Bytes not yet making up a whole block
+    block: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: INITIAL_STATE,
+            block: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 64 {
+                let block: [u8; 64] = self.block[..].try_into().expect("block is 64 bytes");
+                self.compress(&block);
+                self.block.clear();
+            }
+        }
+    }
+
+    /// The digest as lowercase hex
+    pub fn hex(mut self) -> String {
+        let bits = self.length * 8;
+        // A 1 bit, zeros up to 8 bytes short of a block, then the length
+        let mut padding = vec![0x80];
+        padding.resize(1 + (119 - self.block.len()) % 64, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        self.update(&padding);
+        self.state
+            .iter()
+            .map(|word| format!("{:08x}", word))
+            .collect()
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// SHA-256 of a file's contents, as lowercase hex
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let err = |e: std::io::Error| format!("Failed to read {:?}: {}", path, e);
+    let mut file = File::open(path).map_err(err)?;
+    let mut hasher = Sha256::default();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).map_err(err)?;
+        if n == 0 {
+            return Ok(hasher.hex());
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+/// How a session's files compare with the checksums in its manifest
+#[derive(Clone, Debug, Default, Serialize)]
+#[pyclass]
+pub struct SessionVerification {
+    /// Every file listed is present and matches
+    #[pyo3(get)]
+    pub ok: bool,
+    /// Files that match, relative to the manifest's directory
+    #[pyo3(get)]
+    pub verified: Vec<String>,
+    #[pyo3(get)]
+    pub missing: Vec<String>,
+    /// Files whose contents differ, e.g. truncated or corrupted copies
+    #[pyo3(get)]
+    pub mismatched: Vec<String>,
+}
+
+#[pymethods]
+impl SessionVerification {
+    fn __repr__(&self) -> String {
+        format!(
+            "SessionVerification(ok={}, verified={}, missing={:?}, mismatched={:?})",
+            if self.ok { "True" } else { "False" },
+            self.verified.len(),
+            self.missing,
+            self.mismatched
+        )
+    }
+}
+
+/// Check the files next to a `session.json` against its checksums
+pub fn verify_session(manifest_path: &Path) -> PyResult<SessionVerification> {
+    let manifest = SessionManifest::load(manifest_path).map_err(ConfigError::new_err)?;
+    let dir = manifest_path.parent().unwrap_or(Path::new("."));
+    let mut result = SessionVerification::default();
+    for (name, expected) in &manifest.checksums {
+        let path = dir.join(name);
+        if !path.exists() {
+            result.missing.push(name.clone());
+        } else if sha256_file(&path).map_err(pyo3::exceptions::PyOSError::new_err)? == *expected {
+            result.verified.push(name.clone());
+        } else {
+            result.mismatched.push(name.clone());
+        }
+    }
+    result.ok = result.missing.is_empty() && result.mismatched.is_empty();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_matches_known_digests() {
+        let digest = |data: &[u8]| {
+            let mut hasher = Sha256::default();
+            hasher.update(data);
+            hasher.hex()
+        };
+        assert_eq!(
+            digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks once padded
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+
+        // Fed in pieces that don't line up with blocks
+        let data = vec![b'a'; 1000];
+        let mut hasher = Sha256::default();
+        for piece in data.chunks(37) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.hex(), digest(&data));
+    }
+}
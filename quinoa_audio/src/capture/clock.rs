@@ -0,0 +1,135 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Snapshot of the PipeWire graph clock paired with the output file position.
+///
+/// `position` and `nsec` are read from the driver's clock at the start of a
+/// process cycle, and `sample_offset` is the number of frames that had been
+/// written to the output file at that moment. External pipelines (e.g. a
+/// video capture) can use this pairing to place our audio on the graph clock.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[pyclass]
+pub struct ClockInfo {
+    /// Global id of the node driving the graph
+    #[pyo3(get)]
+    pub driver_id: u32,
+    /// Clock ticks per second (usually the graph sample rate)
+    #[pyo3(get)]
+    pub rate: u32,
+    /// Clock position in ticks
+    #[pyo3(get)]
+    pub position: u64,
+    /// CLOCK_MONOTONIC time of `position` in nanoseconds
+    #[pyo3(get)]
+    pub nsec: u64,
+    /// Output file frame offset corresponding to `position`
+    #[pyo3(get)]
+    pub sample_offset: u64,
+    /// Sample rate of the output file
+    #[pyo3(get)]
+    pub sample_rate: u32,
+}
+
+impl ClockInfo {
+    /// Map a frame offset in the output file to (clock position, nsec).
+    pub fn map(&self, sample_offset: u64) -> (u64, u64) {
+        if self.sample_rate == 0 {
+            return (self.position, self.nsec);
+        }
+        let delta = sample_offset as i128 - self.sample_offset as i128;
+        let sample_rate = self.sample_rate as i128;
+        let position = self.position as i128 + delta * self.rate as i128 / sample_rate;
+        let nsec = self.nsec as i128 + delta * 1_000_000_000 / sample_rate;
+        (position.max(0) as u64, nsec.max(0) as u64)
+    }
+}
+
+#[pymethods]
+impl ClockInfo {
+    /// Map a frame offset in the output file to a `(position, nsec)` tuple on the graph clock.
+    fn map_sample(&self, sample_offset: u64) -> (u64, u64) {
+        self.map(sample_offset)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ClockInfo(driver_id={}, rate={}, position={}, nsec={}, sample_offset={})",
+            self.driver_id, self.rate, self.position, self.nsec, self.sample_offset
+        )
+    }
+}
+
+/// Latest clock snapshot per stream, shared between the audio thread and the session handle
+#[derive(Default)]
+pub struct SessionClock {
+    pub mic: Mutex<Option<ClockInfo>>,
+    pub system: Mutex<Option<ClockInfo>>,
+}
+
+impl SessionClock {
+    pub fn update(&self, is_mic: bool, info: ClockInfo) {
+        let slot = if is_mic { &self.mic } else { &self.system };
+        if let Ok(mut guard) = slot.lock() {
+            *guard = Some(info);
+        }
+    }
+
+    pub fn get(&self, is_mic: bool) -> Option<ClockInfo> {
+        let slot = if is_mic { &self.mic } else { &self.system };
+        slot.lock().ok().and_then(|guard| guard.clone())
+    }
+}
+
+/// Graph rate and quantum as last seen by a stream. Another application can
+/// force a different rate or quantum mid-session, changing our latency.
+#[derive(Debug, Default)]
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub struct GraphClockWatch {
+    last: Option<(u32, u32)>,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl GraphClockWatch {
+    /// Record the graph `rate` and `quantum` (frames per cycle) of this
+    /// cycle, returning the previous ones if they changed
+    pub fn observe(&mut self, rate: u32, quantum: u32) -> Option<(u32, u32)> {
+        if rate == 0 || quantum == 0 {
+            return None;
+        }
+        let previous = self.last.replace((rate, quantum));
+        previous.filter(|&last| last != (rate, quantum))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_sample_scales_to_clock_rate() {
+        let info = ClockInfo {
+            driver_id: 42,
+            rate: 48000,
+            position: 96000,
+            nsec: 2_000_000_000,
+            sample_offset: 1000,
+            sample_rate: 16000,
+        };
+
+        // One second of file audio after the snapshot
+        assert_eq!(info.map(17000), (144000, 3_000_000_000));
+        // Offsets before the snapshot map backwards
+        assert_eq!(info.map(0), (93000, 1_937_500_000));
+    }
+
+    #[test]
+    fn test_graph_watch_reports_changes_only() {
+        let mut watch = GraphClockWatch::default();
+        assert_eq!(watch.observe(48000, 1024), None);
+        assert_eq!(watch.observe(48000, 1024), None);
+        assert_eq!(watch.observe(0, 0), None);
+        assert_eq!(watch.observe(44100, 1024), Some((48000, 1024)));
+        assert_eq!(watch.observe(44100, 256), Some((44100, 1024)));
+    }
+}
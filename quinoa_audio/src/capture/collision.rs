@@ -0,0 +1,32 @@
+//! What `RecordingConfig.on_existing` does with files an earlier recording
+//! left in the output directory
+
+/// Accepted `on_existing` values
+pub const COLLISION_POLICIES: &[&str] = &["error", "overwrite", "suffix", "append"];
+
+/// `name` with `-<n>` before its extension: `microphone.wav` ->
+/// `microphone-2.wav`
+pub fn suffixed(name: &str, n: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}-{}.{}", stem, n, ext),
+        _ => format!("{}-{}", name, n),
+    }
+}
+
+/// The lowest suffix from 2 up for which `taken` is false
+pub fn free_suffix(mut taken: impl FnMut(u32) -> bool) -> u32 {
+    (2..).find(|&n| !taken(n)).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suffixes() {
+        assert_eq!(suffixed("microphone.wav", 2), "microphone-2.wav");
+        assert_eq!(suffixed("session.json", 3), "session-3.json");
+        assert_eq!(suffixed("notes", 2), "notes-2");
+        assert_eq!(free_suffix(|n| n < 4), 4);
+    }
+}
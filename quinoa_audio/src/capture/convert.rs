@@ -0,0 +1,145 @@
+/// Byte order of a sample format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Endian = Endian::Little;
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Endian = Endian::Big;
+}
+
+/// Sample encodings we can convert to f32
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    U8,
+    S16(Endian),
+    /// Packed 24-bit
+    S24(Endian),
+    /// 24-bit in the low bits of a 32-bit container
+    S24_32(Endian),
+    S32(Endian),
+    F32(Endian),
+    F64(Endian),
+}
+
+impl SampleFormat {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16(_) => 2,
+            SampleFormat::S24(_) => 3,
+            SampleFormat::S24_32(_) | SampleFormat::S32(_) | SampleFormat::F32(_) => 4,
+            SampleFormat::F64(_) => 8,
+        }
+    }
+}
+
+/// Decode raw samples to f32 in [-1.0, 1.0], ignoring a trailing partial sample
+pub fn decode_samples(bytes: &[u8], format: SampleFormat) -> Vec<f32> {
+    bytes
+        .chunks_exact(format.bytes_per_sample())
+        .map(|b| decode_sample(b, format))
+        .collect()
+}
+
+fn decode_sample(b: &[u8], format: SampleFormat) -> f32 {
+    // Read up to 8 bytes as an unsigned integer in the given byte order
+    let uint = |endian: Endian| -> u64 {
+        let fold = |acc: u64, &byte: &u8| (acc << 8) | u64::from(byte);
+        match endian {
+            Endian::Big => b.iter().fold(0, fold),
+            Endian::Little => b.iter().rev().fold(0, fold),
+        }
+    };
+    // Sign-extend the low `bits` bits and scale to [-1.0, 1.0)
+    let signed = |raw: u64, bits: u32| -> f32 {
+        let shift = 64 - bits;
+        let value = ((raw << shift) as i64) >> shift;
+        (value as f64 / (1u64 << (bits - 1)) as f64) as f32
+    };
+
+    match format {
+        SampleFormat::U8 => (f32::from(b[0]) - 128.0) / 128.0,
+        SampleFormat::S16(e) => signed(uint(e), 16),
+        SampleFormat::S24(e) => signed(uint(e), 24),
+        SampleFormat::S24_32(e) => signed(uint(e) & 0xFF_FFFF, 24),
+        SampleFormat::S32(e) => signed(uint(e), 32),
+        SampleFormat::F32(e) => f32::from_bits(uint(e) as u32),
+        SampleFormat::F64(e) => f64::from_bits(uint(e)) as f32,
+    }
+}
+
+/// Interleave per-channel planes into frames. Planes of unequal length are
+/// cut to the shortest so every frame has a sample for each channel.
+pub fn interleave(planes: &[Vec<f32>]) -> Vec<f32> {
+    let frames = planes.iter().map(Vec::len).min().unwrap_or(0);
+    let mut samples = Vec::with_capacity(frames * planes.len());
+    for n in 0..frames {
+        for plane in planes {
+            samples.push(plane[n]);
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave_planes() {
+        let planes = vec![vec![1.0, 2.0, 3.0], vec![-1.0, -2.0]];
+        assert_eq!(interleave(&planes), vec![1.0, -1.0, 2.0, -2.0]);
+    }
+
+    #[test]
+    fn test_decode_formats() {
+        use Endian::{Big, Little};
+
+        let f32_le: Vec<u8> = [0.5f32, -0.25]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .chain([0u8])
+            .collect();
+        assert_eq!(
+            decode_samples(&f32_le, SampleFormat::F32(Little)),
+            vec![0.5, -0.25]
+        );
+        let f32_be = 0.75f32.to_be_bytes();
+        assert_eq!(decode_samples(&f32_be, SampleFormat::F32(Big)), vec![0.75]);
+
+        assert_eq!(
+            decode_samples(&[0x00, 0x40, 0x00, 0xC0], SampleFormat::S16(Little)),
+            vec![0.5, -0.5]
+        );
+        assert_eq!(
+            decode_samples(&[0x40, 0x00], SampleFormat::S16(Big)),
+            vec![0.5]
+        );
+        assert_eq!(
+            decode_samples(&[0x00, 0x00, 0xC0], SampleFormat::S24(Little)),
+            vec![-0.5]
+        );
+        // The container's top byte is ignored
+        assert_eq!(
+            decode_samples(&[0x00, 0x00, 0x40, 0xFF], SampleFormat::S24_32(Little)),
+            vec![0.5]
+        );
+        assert_eq!(
+            decode_samples(&[0x80, 0x00, 0x00, 0x00], SampleFormat::S32(Big)),
+            vec![-1.0]
+        );
+        assert_eq!(
+            decode_samples(&[0, 128, 192], SampleFormat::U8),
+            vec![-1.0, 0.0, 0.5]
+        );
+        assert_eq!(
+            decode_samples(&(-0.5f64).to_le_bytes(), SampleFormat::F64(Little)),
+            vec![-0.5]
+        );
+    }
+}
@@ -0,0 +1,760 @@
+//! Background recording: the audio worker runs in a helper process of its
+//! own, so the session survives the interpreter that started it exiting or
+//! crashing.
+//!
+//! The helper is a second interpreter running `_serve_detached()`. It takes
+//! the helper's pid as the session id and leaves `<id>.sock` and `<id>.json`
+//! in `runtime_dir()` so the session can be found and re-attached, with its
+//! log lines in `<id>.log` until it exits. Each request is one connection to
+//! the socket carrying one JSON line each way.
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::capture::manifest::unix_now;
+use crate::capture::plugin::EncoderPlugins;
+use crate::capture::session::{
+    relay_session, start_recording_impl, AudioCommand, AudioEvent, InternalAudioEvent,
+    RecordingConfig, RecordingSession,
+};
+use crate::capture::validate::validate_config;
+use crate::errors::{
+    ConfigError, DeviceNotFoundError, InsufficientDiskSpaceError, OutputDirError,
+    OutputExistsError, UnsupportedFormatError,
+};
+
+/// Run by the helper interpreter, with the config as JSON on stdin rather
+/// than in argv, where any user could read its secrets
+const HELPER_SCRIPT: &str =
+    "import sys, quinoa_audio; quinoa_audio._serve_detached(sys.stdin.read())";
+/// How long start_detached() waits for the helper to start recording
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a request other than stop waits for the helper's reply
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the helper checks for connections and collects events
+const SERVE_INTERVAL: Duration = Duration::from_millis(20);
+/// How often a `RecordingSession` attached to a helper polls it for events
+const RELAY_INTERVAL: Duration = Duration::from_millis(50);
+/// Events kept for the next poll; the oldest go while no one is polling
+const MAX_BACKLOG: usize = 10_000;
+/// How long a helper whose session ended on its own (e.g. on an error)
+/// waits for its last events to be collected
+const FINISHED_LINGER: Duration = Duration::from_secs(60);
+
+/// Set by SIGTERM in the helper; the session is stopped and finalized
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_terminate(_: libc::c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    PollEvents,
+    Pause,
+    Resume,
+    SwitchMic {
+        device_id: String,
+    },
+    /// Stop and finalize; the helper exits after replying
+    Stop,
+}
+
+/// The helper's answer to a request, and its first line on stdout
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Exception type `error` was raised as, e.g. "DeviceNotFoundError"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Sent once recording has started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<DetachedInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<AudioEvent>,
+    #[serde(default)]
+    pub running: bool,
+}
+
+impl Reply {
+    fn failed(py: Python<'_>, error: PyErr) -> Self {
+        Reply {
+            error: Some(error.value(py).to_string()),
+            kind: error.get_type(py).name().ok().map(|name| name.to_string()),
+            ..Reply::default()
+        }
+    }
+
+    /// Raise the error the helper reported, as the same exception type
+    fn into_result(self) -> PyResult<Self> {
+        let Some(message) = self.error.clone() else {
+            return Ok(self);
+        };
+        Err(match self.kind.as_deref() {
+            Some("ConfigError") => ConfigError::new_err(message),
+            Some("OutputDirError") => OutputDirError::new_err(message),
+            Some("OutputExistsError") => OutputExistsError::new_err(message),
+            Some("DeviceNotFoundError") => DeviceNotFoundError::new_err(message),
+            Some("UnsupportedFormatError") => UnsupportedFormatError::new_err(message),
+            Some("InsufficientDiskSpaceError") => InsufficientDiskSpaceError::new_err(message),
+            Some("ValueError") => PyValueError::new_err(message),
+            _ => PyRuntimeError::new_err(message),
+        })
+    }
+}
+
+/// What the helper records about its session in `<id>.json`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DetachedInfo {
+    /// The helper's pid
+    pub session_id: u64,
+    pub output_dir: Option<String>,
+    /// Unix time recording started
+    pub started_at: f64,
+    /// Redacted, like the copy in session.json
+    pub config: RecordingConfig,
+}
+
+/// Where helpers of this user leave their sockets, info and log files
+pub fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("quinoa_audio"),
+        // SAFETY: getuid() has no preconditions and can't fail
+        None => std::env::temp_dir().join(format!("quinoa_audio-{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// Fail unless `dir` is a directory (not a link to one) that only this user
+/// can use. Without XDG_RUNTIME_DIR it is in the shared temp dir, where
+/// another user could have made it first to plant or read our files.
+fn check_private(dir: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(dir)?;
+    // SAFETY: getuid() has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o777 != 0o700 {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{:?} isn't a directory only this user can use; remove it to record in the background",
+                dir
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn session_file(session_id: u64, extension: &str) -> PathBuf {
+    runtime_dir().join(format!("{}.{}", session_id, extension))
+}
+
+/// Send one request to a helper and wait up to `timeout` for the reply
+fn request(session_id: u64, request: &Request, timeout: Option<Duration>) -> io::Result<Reply> {
+    let mut stream = UnixStream::connect(session_file(session_id, "sock"))?;
+    stream.set_read_timeout(timeout)?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "the helper closed the connection",
+        ));
+    }
+    Ok(serde_json::from_str(&reply)?)
+}
+
+/// The helper has exited (or was never there)
+fn is_gone(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::NotFound
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+fn request_failed(session_id: u64, error: io::Error) -> PyErr {
+    PyRuntimeError::new_err(format!(
+        "Request to detached session {} failed: {}",
+        session_id, error
+    ))
+}
+
+/// A recording running in a helper process, from
+/// `start_detached_recording()` or `attach_detached()`. Dropping it leaves
+/// the recording running.
+#[pyclass]
+pub struct DetachedSession {
+    info: DetachedInfo,
+    /// Events stop() received, for the next poll_events()
+    pending_events: Mutex<Vec<AudioEvent>>,
+}
+
+impl DetachedSession {
+    fn new(info: DetachedInfo) -> Self {
+        Self {
+            info,
+            pending_events: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn command(&self, py: Python<'_>, command: Request) -> PyResult<()> {
+        let id = self.info.session_id;
+        py.allow_threads(|| request(id, &command, Some(REQUEST_TIMEOUT)))
+            .map_err(|e| request_failed(id, e))?
+            .into_result()
+            .map(drop)
+    }
+}
+
+#[pymethods]
+impl DetachedSession {
+    /// The helper's pid; pass it to `attach_detached()` to control the
+    /// session from another interpreter
+    #[getter]
+    fn session_id(&self) -> u64 {
+        self.info.session_id
+    }
+
+    #[getter]
+    fn output_dir(&self) -> Option<&str> {
+        self.info.output_dir.as_deref()
+    }
+
+    /// Unix time recording started
+    #[getter]
+    fn started_at(&self) -> f64 {
+        self.info.started_at
+    }
+
+    /// True while the helper is recording
+    fn is_running(&self, py: Python<'_>) -> bool {
+        let id = self.info.session_id;
+        py.allow_threads(|| request(id, &Request::Status, Some(REQUEST_TIMEOUT)))
+            .is_ok_and(|reply| reply.running)
+    }
+
+    /// Events since the last call, including any from before this handle
+    /// was attached (the helper keeps the latest 10000). Empty once the
+    /// helper has exited.
+    fn poll_events(&self, py: Python<'_>) -> PyResult<Vec<AudioEvent>> {
+        let mut events = self
+            .pending_events
+            .lock()
+            .map(|mut pending| std::mem::take(&mut *pending))
+            .unwrap_or_default();
+        let id = self.info.session_id;
+        match py.allow_threads(|| request(id, &Request::PollEvents, Some(REQUEST_TIMEOUT))) {
+            Ok(reply) => events.extend(reply.events),
+            Err(e) if is_gone(&e) => {}
+            Err(e) => return Err(request_failed(id, e)),
+        }
+        Ok(events)
+    }
+
+    fn pause(&self, py: Python<'_>) -> PyResult<()> {
+        self.command(py, Request::Pause)
+    }
+
+    fn resume(&self, py: Python<'_>) -> PyResult<()> {
+        self.command(py, Request::Resume)
+    }
+
+    fn switch_mic(&self, py: Python<'_>, new_device_id: String) -> PyResult<()> {
+        self.command(
+            py,
+            Request::SwitchMic {
+                device_id: new_device_id,
+            },
+        )
+    }
+
+    /// Stop recording and wait for the helper to finalize the files.
+    ///
+    /// With a `timeout` (seconds), returns False if the helper hasn't
+    /// finished when it expires. The session's last events are returned by
+    /// the next `poll_events()`.
+    #[pyo3(signature = (timeout=None))]
+    fn stop(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<bool> {
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| PyValueError::new_err(format!("Invalid timeout: {}", e)))?;
+        let id = self.info.session_id;
+        match py.allow_threads(|| request(id, &Request::Stop, timeout)) {
+            Ok(reply) => {
+                if let Ok(mut pending) = self.pending_events.lock() {
+                    pending.extend(reply.events);
+                }
+                Ok(true)
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+            Err(e) if is_gone(&e) => Ok(true),
+            Err(e) => Err(request_failed(id, e)),
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "DetachedSession(session_id={}, output_dir={})",
+            self.info.session_id,
+            self.info
+                .output_dir
+                .as_ref()
+                .map_or_else(|| "None".to_string(), |dir| format!("'{}'", dir))
+        )
+    }
+}
+
+/// Start a helper recording `config` and wait until it is recording
+pub fn start_detached(py: Python<'_>, mut config: RecordingConfig) -> PyResult<DetachedSession> {
+    // Raise configuration errors here rather than from the helper
+    validate_config(&mut config)?;
+    let sys = py.import("sys")?;
+    let executable: PathBuf = sys.getattr("executable")?.extract()?;
+    if executable.as_os_str().is_empty() {
+        return Err(PyRuntimeError::new_err(
+            "Can't find the Python interpreter to run the recording helper",
+        ));
+    }
+    // The helper imports this module the way this interpreter did
+    let python_path = sys.getattr("path")?.extract::<Vec<String>>()?.join(":");
+    let config =
+        serde_json::to_string(&config).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    let started = py.allow_threads(|| spawn_helper(&executable, &python_path, &config))?;
+    let info = started
+        .into_result()?
+        .info
+        .ok_or_else(|| PyRuntimeError::new_err("The recording helper didn't report its session"))?;
+    Ok(DetachedSession::new(info))
+}
+
+/// Start the helper and read its first line
+fn spawn_helper(executable: &Path, python_path: &str, config: &str) -> PyResult<Reply> {
+    let mut command = Command::new(executable);
+    command
+        .args(["-c", HELPER_SCRIPT])
+        .env("PYTHONPATH", python_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    // A session of its own, so Ctrl+C in the terminal and the terminal
+    // closing don't reach it
+    // SAFETY: setsid() is async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().map_err(|e| {
+        PyRuntimeError::new_err(format!("Failed to start the recording helper: {}", e))
+    })?;
+    let pid = child.id();
+    if let Some(mut stdin) = child.stdin.take() {
+        // A helper that exits without reading it is reported below
+        let _ = stdin.write_all(config.as_bytes());
+    }
+    let stdout = child.stdout.take();
+    let (line_tx, line_rx) = mpsc::channel();
+    thread::Builder::new()
+        .name(format!("detached-{}", pid))
+        .spawn(move || {
+            let mut line = String::new();
+            if let Some(stdout) = stdout {
+                let _ = BufReader::new(stdout).read_line(&mut line);
+            }
+            let _ = line_tx.send(line);
+            // Reap the helper whenever it exits
+            let _ = child.wait();
+        })
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+
+    let Ok(line) = line_rx.recv_timeout(STARTUP_TIMEOUT) else {
+        // SAFETY: signals the child spawned above, which hasn't been reaped
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        return Err(PyRuntimeError::new_err(
+            "The recording helper didn't start recording in time",
+        ));
+    };
+    if line.is_empty() {
+        return Err(PyRuntimeError::new_err(
+            "The recording helper exited before it started recording",
+        ));
+    }
+    serde_json::from_str(&line).map_err(|e| {
+        PyRuntimeError::new_err(format!("Unreadable reply from the recording helper: {}", e))
+    })
+}
+
+/// Handle to a running background session. Removes the files of a helper
+/// that exited without cleaning up (e.g. it was killed).
+pub fn attach(py: Python<'_>, session_id: u64) -> PyResult<DetachedSession> {
+    let not_running = || PyValueError::new_err(format!("No session {} is running", session_id));
+    check_private(&runtime_dir()).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => not_running(),
+        _ => PyRuntimeError::new_err(e.to_string()),
+    })?;
+    let info: DetachedInfo = fs::read_to_string(session_file(session_id, "json"))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .ok_or_else(not_running)?;
+    match py.allow_threads(|| request(session_id, &Request::Status, Some(REQUEST_TIMEOUT))) {
+        Ok(_) => Ok(DetachedSession::new(info)),
+        Err(e) if is_gone(&e) => {
+            let _ = fs::remove_file(session_file(session_id, "sock"));
+            let _ = fs::remove_file(session_file(session_id, "json"));
+            Err(not_running())
+        }
+        Err(e) => Err(request_failed(session_id, e)),
+    }
+}
+
+/// A `RecordingSession` handle on a background session
+pub fn attach_session(py: Python<'_>, session_id: u64) -> PyResult<RecordingSession> {
+    let config = attach(py, session_id)?.info.config;
+    Ok(relay_session(
+        session_id,
+        config,
+        move |commands, events| relay(session_id, commands, events),
+    ))
+}
+
+/// Pass a handle's commands on to the helper and the helper's events back,
+/// until the session has stopped
+fn relay(session_id: u64, commands: Receiver<AudioCommand>, events: Sender<InternalAudioEvent>) {
+    loop {
+        let outgoing = match commands.recv_timeout(RELAY_INTERVAL) {
+            Ok(AudioCommand::Stop) => Request::Stop,
+            Ok(AudioCommand::Pause) => Request::Pause,
+            Ok(AudioCommand::Resume) => Request::Resume,
+            Ok(AudioCommand::SwitchMic(device_id)) => Request::SwitchMic { device_id },
+            Err(RecvTimeoutError::Timeout) => Request::PollEvents,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let stopping = outgoing == Request::Stop;
+        // A stop waits for the helper to finalize the files
+        let timeout = (!stopping).then_some(REQUEST_TIMEOUT);
+        match request(session_id, &outgoing, timeout) {
+            Ok(reply) => {
+                if let Some(message) = reply.error {
+                    let _ = events.send(InternalAudioEvent::Error(message));
+                }
+                for event in reply.events {
+                    let _ = events.send(InternalAudioEvent::Relayed(Box::new(event)));
+                }
+                if stopping {
+                    return;
+                }
+            }
+            Err(e) if is_gone(&e) => return,
+            Err(e) => log!("Request to detached session {} failed: {}", session_id, e),
+        }
+    }
+}
+
+/// Running background sessions of this user, oldest first
+pub fn list(py: Python<'_>) -> Vec<DetachedSession> {
+    let dir = runtime_dir();
+    if let Err(e) = check_private(&dir) {
+        if e.kind() != ErrorKind::NotFound {
+            log!("Not listing background sessions: {}", e);
+        }
+        return Vec::new();
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<DetachedSession> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let session_id = path.file_stem()?.to_str()?.parse().ok()?;
+            attach(py, session_id).ok()
+        })
+        .collect();
+    sessions.sort_by(|a, b| a.info.started_at.total_cmp(&b.info.started_at));
+    sessions
+}
+
+/// The helper process: record `config`, report the outcome as the first
+/// line on stdout, then serve requests until the session is stopped
+pub fn serve(py: Python<'_>, config: &str) -> PyResult<()> {
+    let session_id = u64::from(std::process::id());
+    // mode() only applies to a directory created here
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(runtime_dir())?;
+    check_private(&runtime_dir())?;
+    // Log lines go to <id>.log; the starting interpreter isn't listening
+    let log_file = session_file(session_id, "log");
+    if let Ok(log) = OpenOptions::new().create(true).append(true).open(&log_file) {
+        redirect(&log, libc::STDERR_FILENO);
+    }
+
+    let socket = session_file(session_id, "sock");
+    // A helper with the same pid that was killed may have left one behind
+    let _ = fs::remove_file(&socket);
+    let started = UnixListener::bind(&socket)
+        .map_err(PyErr::from)
+        .and_then(|listener| {
+            let config = RecordingConfig::from_json(config)?;
+            let session = start_recording_impl(config.clone(), EncoderPlugins::default())?;
+            Ok((listener, session, config))
+        });
+    let (listener, session, config) = match started {
+        Ok(started) => started,
+        Err(e) => {
+            let _ = fs::remove_file(&socket);
+            let _ = fs::remove_file(&log_file);
+            report(&Reply::failed(py, e));
+            return Ok(());
+        }
+    };
+
+    let info = DetachedInfo {
+        session_id,
+        output_dir: config.output_dir.clone(),
+        started_at: unix_now(),
+        config: config.redacted(),
+    };
+    let info_file = session_file(session_id, "json");
+    if let Ok(json) = serde_json::to_string(&info) {
+        let _ = fs::write(&info_file, json);
+    }
+    let handler = on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+    }
+    report(&Reply {
+        info: Some(info),
+        running: true,
+        ..Reply::default()
+    });
+
+    py.allow_threads(|| Server::new(session, session_id).run(listener));
+    let _ = fs::remove_file(&socket);
+    let _ = fs::remove_file(&info_file);
+    // Kept only by a helper that didn't get to exit cleanly
+    let _ = fs::remove_file(&log_file);
+    Ok(())
+}
+
+/// Write the helper's first line, then let go of stdout so the starting
+/// process can stop reading it
+fn report(reply: &Reply) {
+    if let Ok(line) = serde_json::to_string(reply) {
+        let mut stdout = io::stdout();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+    if let Ok(null) = File::options().write(true).open("/dev/null") {
+        redirect(&null, libc::STDOUT_FILENO);
+    }
+}
+
+fn redirect(file: &File, fd: libc::c_int) {
+    // SAFETY: both descriptors are open; dup2 replaces `fd` atomically
+    unsafe {
+        libc::dup2(file.as_raw_fd(), fd);
+    }
+}
+
+struct Server {
+    session: RecordingSession,
+    session_id: u64,
+    /// Events not yet polled, oldest first
+    backlog: VecDeque<AudioEvent>,
+    /// When the session ended on its own
+    finished_at: Option<Instant>,
+}
+
+impl Server {
+    fn new(session: RecordingSession, session_id: u64) -> Self {
+        Self {
+            session,
+            session_id,
+            backlog: VecDeque::new(),
+            finished_at: None,
+        }
+    }
+
+    fn run(&mut self, listener: UnixListener) {
+        if let Err(e) = listener.set_nonblocking(true) {
+            log!("Detached session can't serve requests: {}", e);
+        }
+        loop {
+            if TERMINATED.load(Ordering::SeqCst) {
+                log!("Terminated, stopping the recording");
+                self.stop();
+                return;
+            }
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if self.handle(stream) {
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => log!("Failed to accept a connection: {}", e),
+            }
+            self.collect_events();
+            if self.finished_at.is_none() && self.session.join(Some(Duration::ZERO)) {
+                self.finished_at = Some(Instant::now());
+                self.collect_events();
+            }
+            if let Some(finished_at) = self.finished_at {
+                if self.backlog.is_empty() || finished_at.elapsed() > FINISHED_LINGER {
+                    return;
+                }
+            }
+            thread::sleep(SERVE_INTERVAL);
+        }
+    }
+
+    fn collect_events(&mut self) {
+        for mut event in self.session.poll_events().unwrap_or_default() {
+            event.session_id = Some(self.session_id);
+            if self.backlog.len() == MAX_BACKLOG {
+                self.backlog.pop_front();
+            }
+            self.backlog.push_back(event);
+        }
+    }
+
+    fn stop(&mut self) {
+        self.session.send_stop();
+        self.session.join(None);
+        self.collect_events();
+    }
+
+    /// Answer one request; true once the session has been stopped
+    fn handle(&mut self, stream: UnixStream) -> bool {
+        let _ = stream.set_nonblocking(false);
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+        let mut line = String::new();
+        if BufReader::new(&stream).read_line(&mut line).is_err() {
+            return false;
+        }
+        let request = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let reply = Reply {
+                    error: Some(format!("Invalid request: {}", e)),
+                    ..Reply::default()
+                };
+                respond(stream, &reply);
+                return false;
+            }
+        };
+        let stopping = request == Request::Stop;
+        let drains = stopping || request == Request::PollEvents;
+        let outcome = match request {
+            Request::Status | Request::PollEvents => Ok(()),
+            Request::Pause => self.session.pause(),
+            Request::Resume => self.session.resume(),
+            Request::SwitchMic { device_id } => self.session.switch_mic(device_id),
+            Request::Stop => {
+                self.stop();
+                Ok(())
+            }
+        };
+        let mut reply = match outcome {
+            Ok(()) => Reply::default(),
+            Err(e) => Python::with_gil(|py| Reply::failed(py, e)),
+        };
+        self.collect_events();
+        if drains {
+            reply.events = self.backlog.drain(..).collect();
+        }
+        reply.running = !stopping && self.finished_at.is_none();
+        respond(stream, &reply);
+        stopping
+    }
+}
+
+fn respond(mut stream: UnixStream, reply: &Reply) {
+    if let Ok(mut line) = serde_json::to_string(reply) {
+        line.push('\n');
+        let _ = stream.write_all(line.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_lines() {
+        let switch = Request::SwitchMic {
+            device_id: "alsa_input.usb-mic".to_string(),
+        };
+        let line = serde_json::to_string(&switch).unwrap();
+        assert_eq!(
+            line,
+            r#"{"command":"switch_mic","device_id":"alsa_input.usb-mic"}"#
+        );
+        assert_eq!(serde_json::from_str::<Request>(&line).unwrap(), switch);
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command":"stop"}"#).unwrap(),
+            Request::Stop
+        );
+
+        let reply: Reply = serde_json::from_str(r#"{"running":true}"#).unwrap();
+        assert!(reply.running && reply.error.is_none() && reply.events.is_empty());
+        assert_eq!(
+            serde_json::to_string(&Reply::default()).unwrap(),
+            r#"{"running":false}"#
+        );
+    }
+
+    #[test]
+    fn test_runtime_dir_must_be_ours_alone() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = crate::testing::TempDir::new("detached-runtime");
+        let runtime = dir.join("quinoa_audio");
+        fs::DirBuilder::new().mode(0o700).create(&runtime).unwrap();
+        assert!(check_private(&runtime).is_ok());
+
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&runtime, &link).unwrap();
+        assert!(check_private(&link).is_err());
+
+        fs::set_permissions(&runtime, fs::Permissions::from_mode(0o755)).unwrap();
+        let err = check_private(&runtime).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(
+            check_private(&dir.join("missing")).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::capture::clock::ClockInfo;
+use crate::capture::health::HealthReport;
+use crate::capture::session::{AudioEvent, RecordingConfig};
+use crate::capture::stats::SessionStats;
+use crate::Device;
+
+/// Events kept for diagnostics; levels events are left out so they don't
+/// push everything else out
+const RECENT_EVENTS: usize = 200;
+
+/// The last `RECENT_EVENTS` events of a session, whether or not Python has
+/// polled them yet
+#[derive(Debug, Default)]
+pub struct EventHistory {
+    events: Mutex<VecDeque<AudioEvent>>,
+}
+
+impl EventHistory {
+    pub fn record(&self, event: &AudioEvent) {
+        if event.type_ == "levels" {
+            return;
+        }
+        if let Ok(mut events) = self.events.lock() {
+            if events.len() == RECENT_EVENTS {
+                events.pop_front();
+            }
+            events.push_back(event.clone());
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<AudioEvent> {
+        self.events
+            .lock()
+            .map(|events| events.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Output file and format of one stream as it is being recorded
+#[derive(Debug, Serialize)]
+pub struct StreamDiagnostics {
+    pub stream: &'static str,
+    pub path: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub segment: u32,
+    pub frames_written: u64,
+    pub clock: Option<ClockInfo>,
+}
+
+/// Everything `RecordingSession.dump_diagnostics()` writes, for attaching to
+/// bug reports such as "the recording was silent"
+#[derive(Debug, Serialize)]
+pub struct Diagnostics {
+    /// Seconds since the Unix epoch
+    pub generated_at: u64,
+    pub library_version: &'static str,
+    /// "pipewire" or "mock"
+    pub backend: &'static str,
+    pub process_id: u32,
+    /// PipeWire daemon name, version and properties, or why they couldn't be read
+    pub server: Result<BTreeMap<String, String>, String>,
+    pub devices: Result<Vec<Device>, String>,
+    pub session_id: u64,
+    pub config: RecordingConfig,
+    pub streams: Vec<StreamDiagnostics>,
+    pub health: HealthReport,
+    pub stats: SessionStats,
+    pub recent_events: Vec<AudioEvent>,
+}
+
+impl Diagnostics {
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize diagnostics: {}", e))?;
+        std::fs::write(path, json).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn backend() -> &'static str {
+    if cfg!(feature = "real-audio") {
+        "pipewire"
+    } else {
+        "mock"
+    }
+}
+
+pub fn server_info() -> Result<BTreeMap<String, String>, String> {
+    #[cfg(feature = "real-audio")]
+    {
+        crate::device::enumerate::server_info_pw()
+    }
+    #[cfg(not(feature = "real-audio"))]
+    {
+        Ok(BTreeMap::from([
+            ("name".to_string(), "mock".to_string()),
+            ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        ]))
+    }
+}
@@ -0,0 +1,112 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often the session checks free space in the output directory
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Free bytes available to unprivileged users on the filesystem containing `path`
+pub fn free_space(path: &Path) -> Result<u64, String> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| format!("Invalid path {:?}: {}", path, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat is a valid out pointer
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if ret != 0 {
+        return Err(format!(
+            "Failed to stat filesystem for {:?}: {}",
+            path,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DiskStatus {
+    Ok,
+    /// Free space dropped below the warning threshold (reported once per crossing)
+    Low(u64),
+    /// Free space dropped below the reserve; the session should stop
+    Full(u64),
+}
+
+/// Rate-limited free space checks for a session's output directory
+pub struct DiskMonitor {
+    /// None for a session that writes nothing, which is never low on space
+    path: Option<PathBuf>,
+    low_bytes: u64,
+    full_bytes: u64,
+    last_check: Option<Instant>,
+    warned: bool,
+}
+
+impl DiskMonitor {
+    pub fn new(path: Option<PathBuf>, low_mb: u64, full_mb: u64) -> Self {
+        Self {
+            path,
+            low_bytes: low_mb * 1024 * 1024,
+            full_bytes: full_mb * 1024 * 1024,
+            last_check: None,
+            warned: false,
+        }
+    }
+
+    /// Check free space if the interval has elapsed
+    pub fn poll(&mut self) -> DiskStatus {
+        if self
+            .last_check
+            .is_some_and(|last| last.elapsed() < DISK_CHECK_INTERVAL)
+        {
+            return DiskStatus::Ok;
+        }
+        let Some(path) = &self.path else {
+            return DiskStatus::Ok;
+        };
+        self.last_check = Some(Instant::now());
+
+        match free_space(path) {
+            Ok(free) => self.classify(free),
+            Err(e) => {
+                log!("{}", e);
+                DiskStatus::Ok
+            }
+        }
+    }
+
+    fn classify(&mut self, free: u64) -> DiskStatus {
+        if free < self.full_bytes {
+            DiskStatus::Full(free)
+        } else if free < self.low_bytes {
+            if self.warned {
+                DiskStatus::Ok
+            } else {
+                self.warned = true;
+                DiskStatus::Low(free)
+            }
+        } else {
+            // Re-arm the warning once space has been freed
+            self.warned = false;
+            DiskStatus::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_warning_fires_once_per_crossing() {
+        let mut monitor = DiskMonitor::new(Some(PathBuf::from("/")), 100, 10);
+        let mb = 1024 * 1024;
+
+        assert_eq!(monitor.classify(500 * mb), DiskStatus::Ok);
+        assert_eq!(monitor.classify(50 * mb), DiskStatus::Low(50 * mb));
+        assert_eq!(monitor.classify(40 * mb), DiskStatus::Ok);
+        assert_eq!(monitor.classify(5 * mb), DiskStatus::Full(5 * mb));
+        assert_eq!(monitor.classify(500 * mb), DiskStatus::Ok);
+        assert_eq!(monitor.classify(50 * mb), DiskStatus::Low(50 * mb));
+    }
+}
@@ -0,0 +1,69 @@
+/// Longest gap that is filled with silence. Anything longer is more likely a
+/// clock jump than lost audio.
+const MAX_FILL_SECONDS: u64 = 60;
+
+/// Detects lost buffers from the graph clock so the gap can be filled with
+/// silence and the file stays aligned with wall-clock time.
+#[derive(Default)]
+pub struct DropoutTracker {
+    /// (driver id, clock position expected at the next cycle)
+    expected: Option<(u32, u64)>,
+}
+
+impl DropoutTracker {
+    /// Record a process cycle and return how many frames (at `stream_rate`)
+    /// went missing since the previous one.
+    ///
+    /// `position` and `duration` are in ticks of the driver clock running at
+    /// `clock_rate`.
+    pub fn observe(
+        &mut self,
+        driver_id: u32,
+        position: u64,
+        duration: u64,
+        clock_rate: u32,
+        stream_rate: u32,
+    ) -> u64 {
+        let missing = match self.expected {
+            // A different driver has its own, unrelated timeline
+            Some((id, expected)) if id == driver_id && position > expected && clock_rate > 0 => {
+                let ticks = position - expected;
+                ticks * u64::from(stream_rate) / u64::from(clock_rate)
+            }
+            _ => 0,
+        };
+        self.expected = Some((driver_id, position + duration));
+
+        if missing > MAX_FILL_SECONDS * u64::from(stream_rate) {
+            0
+        } else {
+            missing
+        }
+    }
+
+    /// Forget the timeline, e.g. while paused
+    pub fn reset(&mut self) {
+        self.expected = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_skipped_cycles() {
+        let mut tracker = DropoutTracker::default();
+        assert_eq!(tracker.observe(1, 0, 1024, 48000, 48000), 0);
+        assert_eq!(tracker.observe(1, 1024, 1024, 48000, 48000), 0);
+        // Two cycles lost, reported at the stream rate
+        assert_eq!(tracker.observe(1, 4096, 1024, 48000, 16000), 682);
+        // Driver change: no comparison possible
+        assert_eq!(tracker.observe(2, 100, 1024, 48000, 48000), 0);
+        // Huge jumps are not filled
+        assert_eq!(
+            tracker.observe(2, 1124 + 48000 * 3600, 1024, 48000, 48000),
+            0
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Sidechain ducking: lowering the system audio while the mic is active, so
+//! speech stays intelligible over music or video sound
+
+use std::time::Duration;
+
+/// How far and how fast the system audio is lowered under the mic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DuckingOptions {
+    /// Mic level above which the system audio is lowered, in dBFS
+    pub threshold_db: f32,
+    /// Every dB the mic is over the threshold lowers the system audio by
+    /// `1 - 1/ratio` dB
+    pub ratio: f32,
+    /// How fast the ducking follows the mic getting louder
+    pub attack: Duration,
+    /// How fast the system audio comes back once the mic is quieter
+    pub release: Duration,
+}
+
+impl Default for DuckingOptions {
+    fn default() -> Self {
+        Self {
+            threshold_db: -40.0,
+            ratio: 4.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(300),
+        }
+    }
+}
+
+/// Fraction of the way a one-pole follower with time constant `tau` moves per frame
+fn coefficient(tau: Duration, sample_rate: u32) -> f32 {
+    if tau.is_zero() {
+        return 1.0;
+    }
+    (1.0 - (-1.0 / (tau.as_secs_f64() * f64::from(sample_rate.max(1)))).exp()) as f32
+}
+
+/// Follows the mic's envelope frame by frame and gives the gain for the
+/// system audio at each frame
+pub struct Ducker {
+    options: DuckingOptions,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl Ducker {
+    pub fn new(options: DuckingOptions, sample_rate: u32) -> Self {
+        Self {
+            options,
+            attack: coefficient(options.attack, sample_rate),
+            release: coefficient(options.release, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    /// Gain for the system audio at a frame where the mic's level is `level`
+    /// (its absolute sample value)
+    pub fn gain(&mut self, level: f32) -> f32 {
+        let rate = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope += (level - self.envelope) * rate;
+        if self.envelope <= 0.0 {
+            return 1.0;
+        }
+        let over = 20.0 * self.envelope.log10() - self.options.threshold_db;
+        if over <= 0.0 {
+            return 1.0;
+        }
+        let reduction = over * (1.0 - 1.0 / self.options.ratio.max(1.0));
+        10f32.powf(-reduction / 20.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_audio_ducks_under_speech_and_recovers() {
+        let rate = 1000;
+        let mut ducker = Ducker::new(DuckingOptions::default(), rate);
+        assert_eq!(ducker.gain(0.0), 1.0);
+
+        // Speech at -20 dBFS, 20 dB over the threshold: 15 dB down at 4:1
+        let mut gain = 1.0;
+        for _ in 0..100 {
+            gain = ducker.gain(0.1);
+        }
+        assert!((20.0 * gain.log10() + 15.0).abs() < 0.5, "{}", gain);
+
+        // Back up within a few release times of silence
+        for _ in 0..3000 {
+            gain = ducker.gain(0.0);
+        }
+        assert!(gain > 0.99, "{}", gain);
+    }
+
+    #[test]
+    fn test_threshold_and_ratio_set_how_far_it_ducks() {
+        let ducked = |options: DuckingOptions| {
+            let mut ducker = Ducker::new(options, 1000);
+            let gain = (0..100).map(|_| ducker.gain(0.1)).last().unwrap();
+            20.0 * gain.log10()
+        };
+        let defaults = DuckingOptions::default();
+        // 20 dB over at 2:1 halves it, against 15 dB down at 4:1
+        let gentle = ducked(DuckingOptions {
+            ratio: 2.0,
+            ..defaults
+        });
+        assert!((gentle + 10.0).abs() < 0.5, "{}", gentle);
+        // Speech at -20 dBFS doesn't reach a -10 dBFS threshold
+        let high = DuckingOptions {
+            threshold_db: -10.0,
+            ..defaults
+        };
+        assert_eq!(ducked(high), 0.0);
+    }
+}
@@ -1,56 +1,1713 @@
 use hound::{WavSpec, WavWriter};
-use std::fs::File;
-use std::io::BufWriter;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::capture::loudness::{tag_wav, LoudnessMeter};
+use crate::capture::manifest::unix_now;
+use crate::capture::memory::MemoryBudget;
+use crate::capture::naming::FileTemplate;
+use crate::capture::peaks::PeaksBuilder;
+use crate::capture::plugin::EncoderPlugins;
+use crate::capture::pool::{EncodePool, EncodeQueue, EncodeWorkerStats, OverflowPolicy};
+use crate::capture::stats::{SessionTimings, TimingWindow};
+use crate::capture::trace;
+use crate::capture::wallclock::{frames_to_boundary, period_frames, utc_offset};
+
+type Writers = Vec<WavWriter<BufWriter<File>>>;
+
+/// Destination of a stream's samples: WAV files, or an encoder plugin
+pub trait EncoderBackend: Send {
+    /// Write interleaved 16-bit samples
+    fn write(&mut self, samples: &[i16]) -> Result<(), String>;
+    fn finalize(self: Box<Self>) -> Result<(), String>;
+}
+
+/// Opens a backend for each output of a stream (the first file, and each
+/// segment after a format change or reconnect) in place of WAV files
+pub trait EncoderFactory: Send + Sync + std::fmt::Debug {
+    /// `path` is where the WAV file would have been written
+    fn open(&self, spec: WavSpec, path: &Path) -> Result<Box<dyn EncoderBackend>, String>;
+}
+
+/// What is measured about a WAV file while it is written
+struct FileMeters {
+    loudness: LoudnessMeter,
+    /// Waveform written next to the file, if enabled
+    peaks: Option<PeaksBuilder>,
+}
+
+impl FileMeters {
+    fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            loudness: LoudnessMeter::new(sample_rate, channels),
+            peaks: None,
+        }
+    }
+
+    fn add(&mut self, samples: &[i16]) {
+        self.loudness.add(samples);
+        if let Some(peaks) = self.peaks.as_mut() {
+            peaks.add(samples);
+        }
+    }
+
+    fn finish(&self, path: &Path) -> Result<(), String> {
+        tag_wav(path, &self.loudness)?;
+        self.peaks
+            .as_ref()
+            .map_or(Ok(()), |peaks| peaks.write(path))
+    }
+}
+
+/// One set of meters per file, kept across reopens so they cover the whole file
+type Meters = Arc<Mutex<Vec<FileMeters>>>;
+
+/// One interleaved writer, or one mono writer per channel when split.
+/// Finalizing tags each file with its ReplayGain and writes its peaks.
+struct WavBackend {
+    writers: Writers,
+    paths: Vec<PathBuf>,
+    meters: Meters,
+}
+
+impl EncoderBackend for WavBackend {
+    fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        let n_writers = self.writers.len();
+        if let Ok(mut meters) = self.meters.lock() {
+            if n_writers == 1 {
+                meters[0].add(samples);
+            } else {
+                for (ch, meter) in meters.iter_mut().enumerate() {
+                    let channel: Vec<i16> = samples
+                        .iter()
+                        .skip(ch)
+                        .step_by(n_writers)
+                        .copied()
+                        .collect();
+                    meter.add(&channel);
+                }
+            }
+        }
+        for (i, &sample) in samples.iter().enumerate() {
+            // Interleaved input: sample i belongs to channel i % channels
+            self.writers[i % n_writers]
+                .write_sample(sample)
+                .map_err(|e| format!("Failed to write sample: {:?}", e))?;
+        }
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        finalize_writers(self.writers)?;
+        let meters = self
+            .meters
+            .lock()
+            .map_err(|_| "Meter mutex poisoned".to_string())?;
+        self.paths
+            .iter()
+            .zip(meters.iter())
+            .try_for_each(|(path, meter)| meter.finish(path))
+    }
+}
+
+/// Default length of the gain ramps at the start and end of a file, around
+/// pauses, and around a splice (e.g. a mic switch)
+pub const DEFAULT_FADE: Duration = Duration::from_millis(10);
+
+/// Frames of silence written at a time when filling a gap
+const SILENCE_CHUNK_FRAMES: usize = 4096;
+
+/// Longest disconnection a reopen fills with silence. Anything longer (a
+/// suspend, or hours of retrying) is left out of the file rather than filled.
+const MAX_REOPEN_FILL: Duration = Duration::from_secs(600);
+
+/// Open output plus a short tail of audio held back from it, so the end of
+/// the current source can still be faded out when it stops.
+struct Sink {
+    backend: Box<dyn EncoderBackend>,
+    /// Get a copy of everything written (e.g. live streams)
+    mirrors: Vec<Box<dyn EncoderBackend>>,
+    channels: usize,
+    /// Most recent interleaved samples, not yet written
+    held: Vec<f32>,
+    /// Frames to hold back and to ramp over
+    fade_frames: usize,
+    /// Progress of the current fade-in (None when not fading in)
+    fade_in: Option<usize>,
+    /// Zeros written repeatedly to fill gaps, so a long gap doesn't need a
+    /// buffer of its own
+    silence: Vec<i16>,
+}
+
+impl Sink {
+    fn new(backend: Box<dyn EncoderBackend>, spec: WavSpec, fade: Duration) -> Self {
+        let mut sink = Self {
+            backend,
+            mirrors: Vec::new(),
+            channels: usize::from(spec.channels.max(1)),
+            held: Vec::new(),
+            fade_frames: 0,
+            fade_in: Some(0),
+            silence: vec![0; SILENCE_CHUNK_FRAMES * usize::from(spec.channels.max(1))],
+        };
+        sink.set_fade(fade, spec.sample_rate);
+        sink
+    }
+
+    fn set_fade(&mut self, fade: Duration, sample_rate: u32) {
+        self.fade_frames = (fade.as_secs_f64() * f64::from(sample_rate)) as usize;
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let start = self.held.len();
+        self.held.extend_from_slice(samples);
+        if let Some(pos) = self.fade_in {
+            let done = ramp(
+                &mut self.held[start..],
+                self.channels,
+                pos,
+                self.fade_frames,
+                true,
+            );
+            self.fade_in = (done < self.fade_frames).then_some(done);
+        }
+
+        let keep = self.fade_frames * self.channels;
+        if self.held.len() > keep {
+            let ready: Vec<f32> = self.held.drain(..self.held.len() - keep).collect();
+            self.write_raw(&ready)?;
+        }
+        Ok(())
+    }
+
+    /// Fade out and write the held tail; whatever is written next fades in
+    fn splice(&mut self) -> Result<(), String> {
+        let mut tail = std::mem::take(&mut self.held);
+        let frames = tail.len() / self.channels;
+        ramp(&mut tail, self.channels, 0, frames, false);
+        self.write_raw(&tail)?;
+        self.fade_in = Some(0);
+        Ok(())
+    }
+
+    fn write_raw(&mut self, samples: &[f32]) -> Result<(), String> {
+        // Convert f32 (-1.0 to 1.0) to i16
+        let samples: Vec<i16> = samples
+            .iter()
+            .map(|&sample| (sample.clamp(-1.0, 1.0) * 32767.0) as i16)
+            .collect();
+        write_all(&mut self.backend, &mut self.mirrors, &samples)
+    }
+
+    /// Write `frames` of silence straight to the outputs, a chunk at a time
+    fn write_silence(&mut self, frames: u64) -> Result<(), String> {
+        let mut left = frames * self.channels as u64;
+        while left > 0 {
+            let chunk = &self.silence[..left.min(self.silence.len() as u64) as usize];
+            write_all(&mut self.backend, &mut self.mirrors, chunk)?;
+            left -= chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Write the held tail, faded out unless the next file continues the
+    /// audio
+    fn finalize(mut self, fade_out: bool) -> Result<(), String> {
+        let flushed = if fade_out {
+            self.splice()
+        } else {
+            let tail = std::mem::take(&mut self.held);
+            self.write_raw(&tail)
+        };
+        let mirrored = self
+            .mirrors
+            .into_iter()
+            .map(|mirror| mirror.finalize())
+            .fold(Ok(()), Result::and);
+        self.backend.finalize()?;
+        flushed.and(mirrored)
+    }
+}
+
+fn write_all(
+    backend: &mut Box<dyn EncoderBackend>,
+    mirrors: &mut [Box<dyn EncoderBackend>],
+    samples: &[i16],
+) -> Result<(), String> {
+    backend.write(samples)?;
+    for mirror in mirrors {
+        mirror.write(samples)?;
+    }
+    Ok(())
+}
+
+/// Apply a linear gain ramp to interleaved samples, starting `pos` frames into
+/// a ramp of `len` frames. Frames past the end of the ramp get full gain
+/// (fade in) or silence (fade out). Returns the ramp position after the buffer.
+fn ramp(samples: &mut [f32], channels: usize, pos: usize, len: usize, fade_in: bool) -> usize {
+    let mut pos = pos;
+    for frame in samples.chunks_mut(channels.max(1)) {
+        // Frames past the end of the ramp are fully faded in (or out)
+        let progress = ((pos + 1) as f32 / (len + 1) as f32).min(1.0);
+        let gain = if fade_in { progress } else { 1.0 - progress };
+        for sample in frame {
+            *sample *= gain;
+        }
+        pos += 1;
+    }
+    pos
+}
+
+/// A stretch of writes that found the encode queue full, from the first
+/// until one found room again
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Overflow {
+    /// Audio discarded under `OverflowPolicy::Drop`
+    pub dropped: Duration,
+    /// How long capture waited for the worker under `OverflowPolicy::Block`
+    pub waited: Duration,
+}
+
+#[derive(Debug, Default)]
+struct OverflowState {
+    /// Frames discarded in the current overflow, written as silence by the
+    /// write that ends it
+    dropped_frames: u64,
+    waited: Duration,
+    /// An overflow that has ended and hasn't been reported
+    ended: Option<Overflow>,
+}
 
 pub struct AudioEncoder {
-    writer: Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>,
-    #[allow(dead_code)]
+    sink: Arc<Mutex<Option<Sink>>>,
     spec: WavSpec,
+    frames_written: AtomicU64,
+    /// The files being written; a plugin's changes when it is reopened
+    paths: Mutex<Vec<PathBuf>>,
+    /// 1 for the original file, incremented each time the output is rotated
+    segment: AtomicU32,
+    /// The output the files belong to, whose next segment a plugin
+    /// continues in when it is reopened
+    output: Option<OutputTarget>,
+    fade: Duration,
+    /// When the files were last finalized, so a reopen can fill the gap
+    closed_at: Mutex<Option<Instant>>,
+    /// Receives the paths once the files are final (e.g. for post-processing)
+    on_finalized: Option<Sender<PathBuf>>,
+    released: AtomicBool,
+    /// Encoder receiving the audio instead of WAV files
+    plugin: Option<Arc<dyn EncoderFactory>>,
+    /// Encoders receiving a copy of the audio
+    mirrors: Vec<Arc<dyn EncoderFactory>>,
+    /// Worker that writes to the sink; None to write inline
+    queue: Option<EncodeQueue>,
+    /// First error from a queued write, reported by the next call
+    failed: Arc<Mutex<Option<String>>>,
+    /// What a write does when the queue is full
+    overflow_policy: OverflowPolicy,
+    overflow: Mutex<OverflowState>,
+    /// Accounts for the audio waiting in the queue
+    budget: Option<Arc<MemoryBudget>>,
+    /// Whether the sink is open, readable without waiting on the worker
+    open: AtomicBool,
+    /// Loudness of each WAV file so far (empty for plugins)
+    meters: Meters,
+    /// Records how long each write takes to encode
+    encode_timer: Option<Arc<TimingWindow>>,
+    /// Frame at which the file ends and the next segment begins, for
+    /// outputs cut at wall-clock boundaries
+    cut_at: Option<u64>,
 }
 
 impl AudioEncoder {
     pub fn new<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> Result<Self, String> {
-        let spec = WavSpec {
-            channels,
-            sample_rate,
-            bits_per_sample: 16,
-            sample_format: hound::SampleFormat::Int,
+        let spec = wav_spec(sample_rate, channels);
+        let path = path.as_ref().to_path_buf();
+        let writer = WavWriter::create(&path, spec)
+            .map_err(|e| format!("Failed to create WAV writer: {:?}", e))?;
+        let meters = Arc::new(Mutex::new(vec![FileMeters::new(sample_rate, channels)]));
+        let backend = WavBackend {
+            writers: vec![writer],
+            paths: vec![path.clone()],
+            meters: meters.clone(),
         };
+        let mut encoder = Self::from_backend(Box::new(backend), spec, vec![path]);
+        encoder.meters = meters;
+        Ok(encoder)
+    }
 
-        let writer = WavWriter::create(path, spec)
-            .map_err(|e| format!("Failed to create WAV writer: {:?}", e))?;
+    /// Send the audio to an encoder plugin; `path` is only passed on to the
+    /// plugin as the suggested output location
+    pub fn with_plugin<P: AsRef<Path>>(
+        plugin: Arc<dyn EncoderFactory>,
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, String> {
+        let spec = wav_spec(sample_rate, channels);
+        let path = path.as_ref().to_path_buf();
+        let backend = plugin.open(spec, &path)?;
+        let mut encoder = Self::from_backend(backend, spec, vec![path]);
+        encoder.plugin = Some(plugin);
+        Ok(encoder)
+    }
 
-        Ok(Self {
-            writer: Arc::new(Mutex::new(Some(writer))),
+    fn from_backend(backend: Box<dyn EncoderBackend>, spec: WavSpec, paths: Vec<PathBuf>) -> Self {
+        Self {
+            sink: Arc::new(Mutex::new(Some(Sink::new(backend, spec, DEFAULT_FADE)))),
             spec,
-        })
+            frames_written: AtomicU64::new(0),
+            paths: Mutex::new(paths),
+            segment: AtomicU32::new(1),
+            output: None,
+            fade: DEFAULT_FADE,
+            closed_at: Mutex::new(None),
+            on_finalized: None,
+            released: AtomicBool::new(false),
+            plugin: None,
+            mirrors: Vec::new(),
+            queue: None,
+            failed: Arc::new(Mutex::new(None)),
+            overflow_policy: OverflowPolicy::default(),
+            overflow: Mutex::new(OverflowState::default()),
+            budget: None,
+            open: AtomicBool::new(true),
+            meters: Meters::default(),
+            encode_timer: None,
+            cut_at: None,
+        }
+    }
+
+    /// Write each channel to its own mono file, `<stem>_ch<N>.wav` next to `path`
+    pub fn new_split<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, String> {
+        let spec = wav_spec(sample_rate, channels);
+        let mono = wav_spec(sample_rate, 1);
+        let paths: Vec<PathBuf> = (0..channels)
+            .map(|ch| channel_path(path.as_ref(), ch))
+            .collect();
+        let writers = paths
+            .iter()
+            .map(|p| {
+                WavWriter::create(p, mono)
+                    .map_err(|e| format!("Failed to create WAV writer for {:?}: {:?}", p, e))
+            })
+            .collect::<Result<Writers, String>>()?;
+        let meters = Arc::new(Mutex::new(
+            (0..channels)
+                .map(|_| FileMeters::new(sample_rate, 1))
+                .collect(),
+        ));
+        let backend = WavBackend {
+            writers,
+            paths: paths.clone(),
+            meters: meters.clone(),
+        };
+        let mut encoder = Self::from_backend(Box::new(backend), spec, paths);
+        encoder.meters = meters;
+        Ok(encoder)
+    }
+
+    /// Also send everything written to a backend opened from `mirror`
+    pub fn attach_mirror(&mut self, mirror: Arc<dyn EncoderFactory>) -> Result<(), String> {
+        let backend = mirror.open(self.spec, &self.path())?;
+        if let Ok(mut guard) = self.sink.lock() {
+            if let Some(sink) = guard.as_mut() {
+                sink.mirrors.push(backend);
+            }
+        }
+        self.mirrors.push(mirror);
+        Ok(())
+    }
+
+    /// Write a `<stem>.peaks.json` waveform next to each WAV file
+    pub fn with_peaks(self) -> Self {
+        if let Ok(mut meters) = self.meters.lock() {
+            let channels = if meters.len() == 1 {
+                self.spec.channels
+            } else {
+                1
+            };
+            for meter in meters.iter_mut() {
+                meter.peaks = Some(PeaksBuilder::new(self.spec.sample_rate, channels));
+            }
+        }
+        self
+    }
+
+    /// Use a different ramp length at the start and end of the file and around
+    /// splices (zero disables fading)
+    pub fn with_fade(mut self, fade: Duration) -> Self {
+        if let Ok(mut guard) = self.sink.lock() {
+            if let Some(sink) = guard.as_mut() {
+                sink.set_fade(fade, self.spec.sample_rate);
+            }
+        }
+        self.fade = fade;
+        self
+    }
+
+    /// Start at full gain, for a file continuing the previous one seamlessly
+    pub fn without_fade_in(self) -> Self {
+        if let Ok(mut guard) = self.sink.lock() {
+            if let Some(sink) = guard.as_mut() {
+                sink.fade_in = None;
+            }
+        }
+        self
+    }
+
+    /// Output file (the first channel's file when split)
+    pub fn path(&self) -> PathBuf {
+        self.paths().swap_remove(0)
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.paths.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.spec.sample_rate
     }
 
+    pub fn channels(&self) -> u16 {
+        self.spec.channels
+    }
+
+    pub fn segment(&self) -> u32 {
+        self.segment.load(Ordering::Relaxed)
+    }
+
+    /// Whether writes reach the files; false while they are closed for a
+    /// reconnect and once they are finalized
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Whether the files were finalized for good
+    pub fn is_finalized(&self) -> bool {
+        self.released.load(Ordering::Relaxed)
+    }
+
+    /// Number of frames (samples per channel) written so far
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written.load(Ordering::Relaxed)
+    }
+
+    /// Write interleaved samples. The last few milliseconds are held back
+    /// until more audio arrives, a splice, or finalize.
+    ///
+    /// When the worker's queue is full, the overflow policy decides between
+    /// waiting for it and dropping the audio; `take_overflow()` reports each
+    /// overflow once it has ended. Audio the memory budget has no room for is
+    /// dropped the same way.
     pub fn write(&self, samples: &[f32]) -> Result<(), String> {
-        if let Ok(mut guard) = self.writer.lock() {
-            if let Some(writer) = guard.as_mut() {
-                for &sample in samples {
-                    // Convert f32 (-1.0 to 1.0) to i16
-                    let val = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
-                    writer
-                        .write_sample(val)
-                        .map_err(|e| format!("Failed to write sample: {:?}", e))?;
+        if !self.open.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let frames = (samples.len() / usize::from(self.spec.channels.max(1))) as u64;
+        let bytes = std::mem::size_of_val(samples);
+        let gap = self.overflow.lock().map_or(0, |o| o.dropped_frames);
+        let timer = self.encode_timer.clone();
+        let write = move |sink: &mut Sink, samples: &[f32]| {
+            if gap > 0 {
+                // Keep the timeline through the audio that was dropped
+                sink.splice()?;
+                sink.write_silence(gap)?;
+            }
+            let started = Instant::now();
+            let result = sink.write(samples);
+            if let Some(timer) = timer {
+                timer.record(started.elapsed());
+            }
+            result
+        };
+        let Some(queue) = &self.queue else {
+            self.with_sink_inline(|sink| write(sink, samples))?;
+            self.frames_written.fetch_add(frames, Ordering::Relaxed);
+            return Ok(());
+        };
+        self.take_failure()?;
+        let reservation = match &self.budget {
+            Some(budget) => match budget.reserve(bytes, "dropping audio waiting to be encoded") {
+                Some(reservation) => Some(reservation),
+                None => {
+                    self.drop_frames(frames);
+                    return Ok(());
                 }
+            },
+            None => None,
+        };
+        // Copied only now, as the job outlives the caller's buffer
+        let samples = samples.to_vec();
+        let job = self.sink_job(move |sink| {
+            let _reservation = reservation;
+            write(sink, &samples)
+        });
+        let waited = match self.overflow_policy {
+            OverflowPolicy::Block => queue.submit(job),
+            OverflowPolicy::Drop if queue.try_submit(job) => Duration::ZERO,
+            OverflowPolicy::Drop => {
+                self.drop_frames(frames);
+                return Ok(());
+            }
+        };
+        if let Ok(mut overflow) = self.overflow.lock() {
+            if !waited.is_zero() {
+                overflow.waited += waited;
+            } else if gap > 0 || !overflow.waited.is_zero() {
+                let rate = u64::from(self.spec.sample_rate.max(1));
+                overflow.ended = Some(Overflow {
+                    dropped: Duration::from_nanos(gap * 1_000_000_000 / rate),
+                    waited: overflow.waited,
+                });
+                overflow.dropped_frames = 0;
+                overflow.waited = Duration::ZERO;
             }
         }
+        self.frames_written
+            .fetch_add(gap + frames, Ordering::Relaxed);
         Ok(())
     }
 
-    pub fn finalize(&self) -> Result<(), String> {
-        if let Ok(mut guard) = self.writer.lock() {
-            if let Some(writer) = guard.take() {
-                writer
-                    .finalize()
-                    .map_err(|e| format!("Failed to finalize WAV file: {:?}", e))?;
+    /// Count audio that wasn't queued, to be written as silence once it can be
+    fn drop_frames(&self, frames: u64) {
+        if let Ok(mut overflow) = self.overflow.lock() {
+            overflow.dropped_frames += frames;
+        }
+    }
+
+    /// An overflow of the encode queue that has ended since the last call
+    pub fn take_overflow(&self) -> Option<Overflow> {
+        self.overflow.lock().ok()?.ended.take()
+    }
+
+    /// Mark a discontinuity (a mic switch, or a pause): the audio written so
+    /// far fades out and the next audio fades in, so the splice doesn't click.
+    pub fn splice(&self) -> Result<(), String> {
+        self.with_sink(|sink| sink.splice())
+    }
+
+    /// Insert silence for lost audio, fading out before the gap and back in after it
+    pub fn fill_silence(&self, frames: u64) -> Result<(), String> {
+        if !self.open.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.with_sink(move |sink| {
+            sink.splice()?;
+            sink.write_silence(frames)
+        })?;
+        self.frames_written.fetch_add(frames, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Run `op` on the open sink: inline, or queued on the encoder's worker.
+    /// A queued failure is returned by the next call instead.
+    fn with_sink<F>(&self, op: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut Sink) -> Result<(), String> + Send + 'static,
+    {
+        let Some(queue) = &self.queue else {
+            return self.with_sink_inline(op);
+        };
+        self.take_failure()?;
+        queue.submit(self.sink_job(op));
+        Ok(())
+    }
+
+    /// Run `op` on the open sink on the calling thread
+    fn with_sink_inline<F>(&self, op: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut Sink) -> Result<(), String>,
+    {
+        if let Ok(mut guard) = self.sink.lock() {
+            if let Some(sink) = guard.as_mut() {
+                op(sink)?;
             }
         }
         Ok(())
     }
+
+    /// Fail with the error of an earlier queued job, if one failed
+    fn take_failure(&self) -> Result<(), String> {
+        match self.failed.lock().ok().and_then(|mut f| f.take()) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// `op` as a job for the worker, recording its failure for the next call
+    fn sink_job<F>(&self, op: F) -> Box<dyn FnOnce() + Send>
+    where
+        F: FnOnce(&mut Sink) -> Result<(), String> + Send + 'static,
+    {
+        let sink = self.sink.clone();
+        let failed = self.failed.clone();
+        Box::new(move || {
+            let Ok(mut guard) = sink.lock() else { return };
+            let Some(sink) = guard.as_mut() else { return };
+            if let Err(e) = op(sink) {
+                if let Ok(mut failed) = failed.lock() {
+                    failed.get_or_insert(e);
+                }
+            }
+        })
+    }
+
+    /// Finalize the files for good, handing them to `on_finalized`
+    pub fn finalize(&self) -> Result<(), String> {
+        self.close()?;
+        self.release();
+        Ok(())
+    }
+
+    /// Finalize the files for good without fading out, for a cut where the
+    /// next segment carries on seamlessly
+    pub fn finalize_at_cut(&self) -> Result<(), String> {
+        self.close_sink(false)?;
+        self.release();
+        Ok(())
+    }
+
+    /// Finalize the files but keep them ours, so they can be reopened
+    pub fn close(&self) -> Result<(), String> {
+        self.close_sink(true)
+    }
+
+    fn close_sink(&self, fade_out: bool) -> Result<(), String> {
+        let take = |sink: &Mutex<Option<Sink>>| sink.lock().ok().and_then(|mut s| s.take());
+        let finalized = match &self.queue {
+            // Finalized on the worker after the queued writes, so a plugin is
+            // never called from the audio thread
+            Some(queue) => {
+                let sink = self.sink.clone();
+                queue
+                    .run(move || take(&sink).map(|sink| sink.finalize(fade_out)))
+                    .flatten()
+            }
+            None => take(&self.sink).map(|sink| sink.finalize(fade_out)),
+        };
+        let pending = self.failed.lock().ok().and_then(|mut f| f.take());
+        if let Some(result) = finalized {
+            self.mark_closed();
+            result?;
+        }
+        pending.map_or(Ok(()), Err)
+    }
+
+    /// Reopen closed files and keep appending to them (e.g. after PipeWire
+    /// reconnects), writing `gap` of silence first so the timeline is kept
+    /// (at most `MAX_REOPEN_FILL` of it). Plugins can't append, and opening
+    /// their file again would overwrite it, so they continue in the file of
+    /// the output's next segment instead (`<name>_part<N>` next to the file
+    /// for an encoder opened on its own).
+    /// Returns Ok(false) if the files were still open or already finalized.
+    #[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+    pub fn reopen(&self, gap: Duration) -> Result<bool, String> {
+        let Ok(mut guard) = self.sink.lock() else {
+            return Ok(false);
+        };
+        if guard.is_some() || self.released.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let paths = self.paths();
+        let (backend, part): (Box<dyn EncoderBackend>, _) = match &self.plugin {
+            Some(plugin) => {
+                let segment = self.segment() + 1;
+                let path = match &self.output {
+                    Some(output) => output.segment_file(segment),
+                    None => segment_path(&paths[0], segment),
+                };
+                (plugin.open(self.spec, &path)?, Some((segment, path)))
+            }
+            None => {
+                let writers = paths
+                    .iter()
+                    .map(|p| {
+                        // Drops the ReplayGain tags, which are written again at finalize
+                        repair_wav(p, true)?;
+                        WavWriter::append(p)
+                            .map_err(|e| format!("Failed to reopen {:?}: {:?}", p, e))
+                    })
+                    .collect::<Result<Writers, String>>()?;
+                let backend = WavBackend {
+                    writers,
+                    paths: paths.clone(),
+                    meters: self.meters.clone(),
+                };
+                (Box::new(backend), None)
+            }
+        };
+        if let Some((segment, path)) = part {
+            self.segment.store(segment, Ordering::Relaxed);
+            if let Ok(mut paths) = self.paths.lock() {
+                *paths = vec![path];
+            }
+        }
+        let mut sink = Sink::new(backend, self.spec, self.fade);
+        for mirror in &self.mirrors {
+            sink.mirrors.push(mirror.open(self.spec, &self.path())?);
+        }
+
+        let gap = gap.min(MAX_REOPEN_FILL);
+        let frames = (gap.as_secs_f64() * f64::from(self.spec.sample_rate)) as u64;
+        sink.write_silence(frames)?;
+        self.frames_written.fetch_add(frames, Ordering::Relaxed);
+
+        *guard = Some(sink);
+        self.open.store(true, Ordering::Relaxed);
+        if let Ok(mut closed_at) = self.closed_at.lock() {
+            *closed_at = None;
+        }
+        Ok(true)
+    }
+
+    /// Treat the files as closed for `extra` longer, so reopening fills more
+    /// silence
+    #[cfg(feature = "real-audio")]
+    pub fn extend_gap(&self, extra: Duration) {
+        if let Ok(mut closed_at) = self.closed_at.lock() {
+            if let Some(at) = closed_at.as_mut() {
+                *at = at.checked_sub(extra).unwrap_or(*at);
+            }
+        }
+    }
+
+    /// How long ago the files were finalized, if they are closed
+    #[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+    pub fn closed_for(&self) -> Option<Duration> {
+        self.closed_at
+            .lock()
+            .ok()
+            .and_then(|closed_at| closed_at.map(|t| t.elapsed()))
+    }
+
+    fn release(&self) {
+        if self.released.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        if let Some(tx) = &self.on_finalized {
+            for path in self.paths() {
+                let _ = tx.send(path);
+            }
+        }
+    }
+
+    fn mark_closed(&self) {
+        self.open.store(false, Ordering::Relaxed);
+        if let Ok(mut closed_at) = self.closed_at.lock() {
+            *closed_at = Some(Instant::now());
+        }
+    }
+
+    /// Finalize without blocking on a writer that another thread is holding.
+    /// Returns Ok(false) if the writer was busy.
+    pub fn try_finalize(&self) -> Result<bool, String> {
+        let Ok(mut guard) = self.sink.try_lock() else {
+            return Ok(false);
+        };
+        if let Some(sink) = guard.take() {
+            self.mark_closed();
+            sink.finalize(true)?;
+        }
+        drop(guard);
+        self.release();
+        Ok(true)
+    }
+}
+
+fn wav_spec(sample_rate: u32, channels: u16) -> WavSpec {
+    WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    }
+}
+
+/// `dir/name.wav` -> `dir/name_ch<N>.wav`
+fn channel_path(path: &Path, channel: u16) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "wav".to_string());
+    path.with_file_name(format!("{}_ch{}.{}", stem, channel, ext))
+}
+
+/// Finalize every writer, reporting the first failure
+fn finalize_writers(writers: Writers) -> Result<(), String> {
+    let mut result = Ok(());
+    for writer in writers {
+        if let Err(e) = writer.finalize() {
+            if result.is_ok() {
+                result = Err(format!("Failed to finalize WAV file: {:?}", e));
+            }
+        }
+    }
+    result
+}
+
+/// Where a stream's audio goes and how it is laid out on disk
+#[derive(Clone, Debug)]
+pub struct OutputTarget {
+    pub path: PathBuf,
+    /// Write one mono file per channel instead of a single interleaved file
+    pub split_channels: bool,
+    /// Gain ramp length at file boundaries, pauses and splices
+    pub fade: Duration,
+    /// Write a waveform peaks file next to each WAV file
+    pub peaks: bool,
+    /// Segment the stream starts at; above 1 when resuming a session
+    pub first_segment: u32,
+    /// Names the files instead of `path` and its `_part<N>` continuations
+    pub template: Option<FileTemplate>,
+    /// Receives each file's path once it is finalized
+    pub on_finalized: Option<Sender<PathBuf>>,
+    /// Encoder to use instead of writing WAV files
+    pub plugin: Option<Arc<dyn EncoderFactory>>,
+    /// Worker to encode on instead of the audio thread
+    pub queue: Option<EncodeQueue>,
+    /// What writes do when the worker's queue is full
+    pub overflow: OverflowPolicy,
+    /// Memory budget the queued audio counts against
+    pub budget: Option<Arc<MemoryBudget>>,
+    /// Encoders that get a copy of the audio, such as live streams
+    pub mirrors: Vec<Arc<dyn EncoderFactory>>,
+    /// Receives the time spent encoding each write
+    pub encode_timer: Option<Arc<TimingWindow>>,
+    /// Start a new segment at every multiple of this since local midnight
+    pub cut_every: Option<Duration>,
+}
+
+impl OutputTarget {
+    pub fn open(&self, sample_rate: u32, channels: u16) -> Result<AudioEncoder, String> {
+        self.open_segment(sample_rate, channels, self.first_segment.max(1))
+    }
+
+    /// File of the given segment, before any split into channels
+    pub fn segment_file(&self, segment: u32) -> PathBuf {
+        match &self.template {
+            Some(template) => template.path(&self.path, segment),
+            None => segment_path(&self.path, segment),
+        }
+    }
+
+    /// The first file written for a segment (its first channel's when split)
+    pub fn first_file(&self, segment: u32) -> PathBuf {
+        let path = self.segment_file(segment);
+        if self.split_channels {
+            channel_path(&path, 0)
+        } else {
+            path
+        }
+    }
+
+    /// Files written so far, one entry per segment: the segment's file, or
+    /// its channels' files when split
+    pub fn written_files(&self) -> Vec<Vec<PathBuf>> {
+        let mut segments = Vec::new();
+        while self.first_file(segments.len() as u32 + 1).exists() {
+            let path = self.segment_file(segments.len() as u32 + 1);
+            segments.push(if self.split_channels {
+                (0..)
+                    .map(|channel| channel_path(&path, channel))
+                    .take_while(|path| path.exists())
+                    .collect()
+            } else {
+                vec![path]
+            });
+        }
+        segments
+    }
+
+    /// Prepare to continue a session left behind by a crashed process: repair
+    /// the headers of the last segment written (a killed process never
+    /// finalizes them) and return the segment to continue at.
+    pub fn resume_segment(&self) -> Result<u32, String> {
+        let mut last = 0;
+        while self.first_file(last + 1).exists() {
+            last += 1;
+        }
+        if last == 0 {
+            return Ok(1);
+        }
+
+        let path = self.segment_file(last);
+        if self.split_channels {
+            let mut channel = 0;
+            while channel_path(&path, channel).exists() {
+                repair_wav(&channel_path(&path, channel), false)?;
+                channel += 1;
+            }
+        } else {
+            repair_wav(&path, false)?;
+        }
+        Ok(last + 1)
+    }
+
+    /// Open a numbered continuation file, used when the stream format changes
+    /// mid-session. Segment 1 is the original path.
+    pub fn open_segment(
+        &self,
+        sample_rate: u32,
+        channels: u16,
+        segment: u32,
+    ) -> Result<AudioEncoder, String> {
+        let mut encoder = self.open_file(sample_rate, channels, segment)?;
+        encoder.cut_at = self.cut_every.map(|period| {
+            let now = unix_now();
+            frames_to_boundary(now, utc_offset(now), period, sample_rate)
+        });
+        Ok(encoder)
+    }
+
+    /// Write to a stream's file. With `cut_every`, the audio from the next
+    /// boundary on goes to a new segment, returned to replace `encoder`. The
+    /// cut falls on the boundary's frame, without fades, so the segments
+    /// play back to back seamlessly.
+    pub fn write_segmented(
+        &self,
+        encoder: &AudioEncoder,
+        samples: &[f32],
+    ) -> Result<Option<AudioEncoder>, String> {
+        let channels = usize::from(encoder.channels().max(1));
+        let frames = (samples.len() / channels) as u64;
+        let written = encoder.frames_written();
+        let Some(cut_at) = encoder.cut_at.filter(|&cut_at| written + frames > cut_at) else {
+            encoder.write(samples)?;
+            return Ok(None);
+        };
+        let before = (cut_at.saturating_sub(written) as usize * channels).min(samples.len());
+        encoder.write(&samples[..before])?;
+        encoder.finalize_at_cut()?;
+
+        let rate = encoder.sample_rate();
+        let mut next = self
+            .open_segment(rate, encoder.channels(), encoder.segment() + 1)?
+            .without_fade_in();
+        // A gap filled past the boundary makes the cut late; the clock then
+        // decides where the next one falls
+        if written <= cut_at {
+            next.cut_at = self.cut_every.map(|period| period_frames(period, rate));
+        }
+        next.write(&samples[before..])?;
+        Ok(Some(next))
+    }
+
+    fn open_file(
+        &self,
+        sample_rate: u32,
+        channels: u16,
+        segment: u32,
+    ) -> Result<AudioEncoder, String> {
+        let path = self.segment_file(segment);
+        if let Some(plugin) = &self.plugin {
+            // The plugin owns its output, so there is nothing to post-process
+            let mut encoder =
+                AudioEncoder::with_plugin(plugin.clone(), &path, sample_rate, channels)?
+                    .with_fade(self.fade);
+            encoder.segment = AtomicU32::new(segment);
+            encoder.output = Some(self.clone());
+            encoder.queue = self.queue.clone();
+            encoder.overflow_policy = self.overflow;
+            encoder.budget = self.budget.clone();
+            encoder.encode_timer = self.encode_timer.clone();
+            for mirror in &self.mirrors {
+                encoder.attach_mirror(mirror.clone())?;
+            }
+            return Ok(encoder);
+        }
+        let encoder = if self.split_channels {
+            AudioEncoder::new_split(&path, sample_rate, channels)?
+        } else {
+            AudioEncoder::new(&path, sample_rate, channels)?
+        }
+        .with_fade(self.fade);
+        let mut encoder = if self.peaks {
+            encoder.with_peaks()
+        } else {
+            encoder
+        };
+        encoder.segment = AtomicU32::new(segment);
+        encoder.on_finalized = self.on_finalized.clone();
+        encoder.queue = self.queue.clone();
+        encoder.overflow_policy = self.overflow;
+        encoder.budget = self.budget.clone();
+        encoder.encode_timer = self.encode_timer.clone();
+        for mirror in &self.mirrors {
+            encoder.attach_mirror(mirror.clone())?;
+        }
+        Ok(encoder)
+    }
+}
+
+/// Rewrite the RIFF and data chunk sizes of a WAV file to match its length on
+/// disk, dropping any partial frame. A file ending in the ReplayGain tags
+/// written at finalize is complete; with `strip_tags` the tags are dropped so
+/// it can be appended to. Returns whether the file changed.
+pub(crate) fn repair_wav(path: &Path, strip_tags: bool) -> Result<bool, String> {
+    let err = |e: std::io::Error| format!("Failed to repair {:?}: {}", path, e);
+    let invalid = || format!("Failed to repair {:?}: not a WAV file", path);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(err)?;
+    let len = file.metadata().map_err(err)?.len();
+
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff).map_err(err)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(invalid());
+    }
+
+    // Walk the chunks up to the data chunk, picking up the frame size from fmt
+    let mut offset = 12u64;
+    let mut block_align = 0u64;
+    loop {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(offset)).map_err(err)?;
+        file.read_exact(&mut header).map_err(|_| invalid())?;
+        let size = u64::from(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]));
+        match &header[0..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 14];
+                file.read_exact(&mut fmt).map_err(|_| invalid())?;
+                block_align = u64::from(u16::from_le_bytes([fmt[12], fmt[13]]));
+            }
+            b"data" => break,
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset += 8 + size + (size & 1);
+    }
+    if block_align == 0 {
+        return Err(invalid());
+    }
+
+    let data_start = offset + 8;
+    let mut sizes = [0u8; 4];
+    file.seek(SeekFrom::Start(offset + 4)).map_err(err)?;
+    file.read_exact(&mut sizes).map_err(err)?;
+    let declared = u64::from(u32::from_le_bytes(sizes));
+    // A finalized file may end in an ID3 chunk after the audio
+    let mut end = len;
+    let tag_at = data_start + declared + (declared & 1);
+    if declared > 0 && tag_at + 8 <= len {
+        let mut id = [0u8; 4];
+        file.seek(SeekFrom::Start(tag_at)).map_err(err)?;
+        file.read_exact(&mut id).map_err(err)?;
+        if &id == b"id3 " {
+            if !strip_tags {
+                return Ok(false);
+            }
+            end = tag_at;
+        }
+    }
+    let data_len = end.saturating_sub(data_start) / block_align * block_align;
+    let data_len = data_len.min(u64::from(u32::MAX) - data_start);
+    let riff_len = data_start + data_len - 8;
+
+    let mut riff_size = [0u8; 4];
+    riff_size.copy_from_slice(&riff[4..8]);
+    if declared == data_len
+        && u64::from(u32::from_le_bytes(riff_size)) == riff_len
+        && len == data_start + data_len
+    {
+        return Ok(false);
+    }
+
+    file.seek(SeekFrom::Start(4)).map_err(err)?;
+    file.write_all(&(riff_len as u32).to_le_bytes())
+        .map_err(err)?;
+    file.seek(SeekFrom::Start(offset + 4)).map_err(err)?;
+    file.write_all(&(data_len as u32).to_le_bytes())
+        .map_err(err)?;
+    file.set_len(data_start + data_len).map_err(err)?;
+    file.sync_all().map_err(err)?;
+    Ok(true)
+}
+
+/// `dir/name.wav` -> `dir/name_part<N>.wav` for segments after the first
+pub(crate) fn segment_path(path: &Path, segment: u32) -> PathBuf {
+    if segment <= 1 {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "wav".to_string());
+    path.with_file_name(format!("{}_part{}.{}", stem, segment, ext))
+}
+
+/// Encoders for a session's output files.
+///
+/// Shared between the audio thread and the session handle so the files can
+/// still be finalized if the audio thread has to be abandoned.
+#[derive(Default)]
+pub struct SessionEncoders {
+    /// For log lines, which Python's threads write too
+    session_id: u64,
+    pub mic: Arc<Mutex<Option<AudioEncoder>>>,
+    pub system: Arc<Mutex<Option<AudioEncoder>>>,
+    finalized: Option<Sender<PathBuf>>,
+    plugins: EncoderPlugins,
+    mic_mirrors: Vec<Arc<dyn EncoderFactory>>,
+    system_mirrors: Vec<Arc<dyn EncoderFactory>>,
+    pool: EncodePool,
+    timings: Arc<SessionTimings>,
+    budget: Option<Arc<MemoryBudget>>,
+}
+
+impl SessionEncoders {
+    /// Encoders whose files are reported to `finalized` once they are final,
+    /// using any encoder plugins given for the streams and encoding on `pool`
+    pub fn new(
+        session_id: u64,
+        finalized: Sender<PathBuf>,
+        plugins: EncoderPlugins,
+        pool: EncodePool,
+    ) -> Self {
+        Self {
+            session_id,
+            finalized: Some(finalized),
+            plugins,
+            pool,
+            ..Self::default()
+        }
+    }
+
+    /// Count audio waiting for the encoding workers against `budget`
+    pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Attach the session's finalize notifications, the stream's encoder
+    /// plugin, its encoding worker and the memory budget to an output
+    pub fn target(&self, is_mic: bool, output: OutputTarget) -> OutputTarget {
+        OutputTarget {
+            on_finalized: self.finalized.clone(),
+            plugin: self.plugins.for_stream(is_mic),
+            queue: self.pool.queue(if is_mic { 0 } else { 1 }),
+            encode_timer: Some(self.timings.stream(is_mic).encode.clone()),
+            budget: self.budget.clone(),
+            mirrors: if is_mic {
+                self.mic_mirrors.clone()
+            } else {
+                self.system_mirrors.clone()
+            },
+            ..output
+        }
+    }
+
+    /// Copy a stream's audio to `mirror` as well as its output
+    pub fn add_mirror(&mut self, is_mic: bool, mirror: Arc<dyn EncoderFactory>) {
+        if is_mic {
+            self.mic_mirrors.push(mirror);
+        } else {
+            self.system_mirrors.push(mirror);
+        }
+    }
+
+    pub fn pool_stats(&self) -> Vec<EncodeWorkerStats> {
+        self.pool.stats()
+    }
+
+    /// Processing and encoding timings of the streams
+    pub fn timings(&self) -> &Arc<SessionTimings> {
+        &self.timings
+    }
+
+    /// Close every file without finalizing it for good, so a reconnect can
+    /// reopen and append to it
+    #[cfg(feature = "real-audio")]
+    pub fn close_all(&self) {
+        for slot in [&self.mic, &self.system] {
+            if let Ok(guard) = slot.lock() {
+                if let Some(encoder) = guard.as_ref() {
+                    if let Err(e) = encoder.close() {
+                        log!(session = self.session_id; "{}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Count `extra` as part of the closed files' gap, e.g. time spent in
+    /// system suspend that `Instant` doesn't see
+    #[cfg(feature = "real-audio")]
+    pub fn extend_gaps(&self, extra: Duration) {
+        for slot in [&self.mic, &self.system] {
+            if let Ok(guard) = slot.lock() {
+                if let Some(encoder) = guard.as_ref() {
+                    encoder.extend_gap(extra);
+                }
+            }
+        }
+    }
+
+    /// Reopen files finalized when the previous connection was lost, so a
+    /// reconnect appends instead of overwriting. The time spent disconnected
+    /// is filled with silence unless `fill_gap` is false (e.g. while paused).
+    #[cfg(feature = "real-audio")]
+    pub fn reopen_all(&self, fill_gap: bool) -> Vec<String> {
+        let mut errors = Vec::new();
+        for slot in [&self.mic, &self.system] {
+            let Ok(guard) = slot.lock() else { continue };
+            let Some(encoder) = guard.as_ref() else {
+                continue;
+            };
+            let Some(gap) = encoder.closed_for() else {
+                continue;
+            };
+            let gap = if fill_gap { gap } else { Duration::ZERO };
+            if let Err(e) = encoder.reopen(gap) {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+
+    /// Splice the given stream's file at a change of source.
+    /// Returns the frame offset of the splice.
+    pub fn splice(&self, is_mic: bool) -> Option<u64> {
+        let slot = if is_mic { &self.mic } else { &self.system };
+        let guard = slot.lock().ok()?;
+        let encoder = guard.as_ref()?;
+        if let Err(e) = encoder.splice() {
+            log!(session = self.session_id; "{}", e);
+        }
+        Some(encoder.frames_written())
+    }
+
+    /// Splice both files, e.g. when the session is paused
+    pub fn splice_all(&self) {
+        for is_mic in [true, false] {
+            self.splice(is_mic);
+        }
+    }
+
+    pub fn finalize_all(&self) {
+        let mut span = trace::span("finalize");
+        for slot in [&self.mic, &self.system] {
+            if let Ok(guard) = slot.lock() {
+                if let Some(encoder) = guard.as_ref() {
+                    if let Err(e) = encoder.finalize() {
+                        log!(session = self.session_id; "{}", e);
+                        span.fail(&e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finalize whatever can be finalized without waiting on the audio thread.
+    /// Returns true if every open encoder was finalized.
+    pub fn try_finalize_all(&self) -> bool {
+        let mut all_done = true;
+        for slot in [&self.mic, &self.system] {
+            let Ok(guard) = slot.try_lock() else {
+                all_done = false;
+                continue;
+            };
+            if let Some(encoder) = guard.as_ref() {
+                match encoder.try_finalize() {
+                    Ok(done) => all_done &= done,
+                    Err(e) => {
+                        log!(session = self.session_id; "{}", e);
+                        all_done = false;
+                    }
+                }
+            }
+        }
+        all_done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_split_writes_one_mono_file_per_channel() {
+        let dir = TempDir::new("split");
+        let path = dir.join("mic.wav");
+
+        let encoder = AudioEncoder::new_split(&path, 16000, 3)
+            .unwrap()
+            .with_fade(Duration::ZERO);
+        encoder.write(&[0.0, 0.5, -0.5, 0.0, 0.5, -0.5]).unwrap();
+        encoder.finalize().unwrap();
+        assert_eq!(encoder.frames_written(), 2);
+
+        for (ch, expected) in [(0, 0), (1, 16383), (2, -16383)] {
+            let reader = hound::WavReader::open(dir.join(format!("mic_ch{}.wav", ch))).unwrap();
+            assert_eq!(reader.spec().channels, 1);
+            let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+            assert_eq!(samples, vec![expected, expected]);
+        }
+    }
+
+    #[test]
+    fn test_splice_fades_out_and_in() {
+        let dir = TempDir::new("splice");
+        let path = dir.join("mic.wav");
+
+        // 10 ms at 1 kHz = 10 fade frames
+        let encoder = AudioEncoder::new(&path, 1000, 1).unwrap();
+        encoder.write(&[1.0; 30]).unwrap();
+        encoder.splice().unwrap();
+        encoder.write(&[1.0; 30]).unwrap();
+        encoder.finalize().unwrap();
+        assert_eq!(encoder.frames_written(), 60);
+
+        let samples: Vec<i16> = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples.len(), 60);
+        let rising = |s: &[i16]| s.windows(2).all(|w| w[0] < w[1]);
+        let falling = |s: &[i16]| s.windows(2).all(|w| w[0] > w[1]);
+        // Fade in at the start of the file, out at the splice, in after it,
+        // and out at the end of the file
+        assert!(samples[0] < 3300 && rising(&samples[..10]));
+        assert_eq!(samples[10], 32767);
+        assert!(falling(&samples[20..30]) && samples[29] < 3300);
+        assert!(samples[30] < 3300 && rising(&samples[30..40]));
+        assert_eq!(samples[40], 32767);
+        assert!(falling(&samples[50..60]) && samples[59] < 3300);
+    }
+
+    #[test]
+    fn test_reopen_appends_after_gap() {
+        let dir = TempDir::new("reopen");
+        let path = dir.join("system.wav");
+
+        let encoder = AudioEncoder::new(&path, 1000, 2)
+            .unwrap()
+            .with_fade(Duration::ZERO);
+        encoder.write(&[0.5; 20]).unwrap();
+        encoder.close().unwrap();
+        assert!(encoder.closed_for().is_some());
+
+        // Writes while closed are dropped, as after a disconnect
+        encoder.write(&[0.5; 20]).unwrap();
+        assert!(encoder.reopen(Duration::from_millis(5)).unwrap());
+        assert!(!encoder.reopen(Duration::ZERO).unwrap());
+        encoder.write(&[-0.5; 20]).unwrap();
+        encoder.finalize().unwrap();
+        assert_eq!(encoder.frames_written(), 25);
+        // Finalized files are handed off and never reopened
+        assert!(!encoder.reopen(Duration::ZERO).unwrap());
+
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+        let mut expected = vec![16383; 20];
+        expected.extend([0; 10]);
+        expected.extend([-16383; 20]);
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_long_gaps_are_filled_in_chunks_and_capped() {
+        let dir = TempDir::new("long-gap");
+        let path = dir.join("system.wav");
+
+        let encoder = AudioEncoder::new(&path, 1000, 2)
+            .unwrap()
+            .with_fade(Duration::ZERO);
+        // Several chunks and a partial one
+        let filled = 2 * SILENCE_CHUNK_FRAMES as u64 + 7;
+        encoder.fill_silence(filled).unwrap();
+        encoder.close().unwrap();
+        // Two hours disconnected, of which only the cap is filled
+        assert!(encoder.reopen(Duration::from_secs(7200)).unwrap());
+        encoder.finalize().unwrap();
+
+        let capped = MAX_REOPEN_FILL.as_secs() * 1000;
+        assert_eq!(encoder.frames_written(), filled + capped);
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(u64::from(reader.duration()), filled + capped);
+        assert!(reader.into_samples::<i16>().all(|s| s.unwrap() == 0));
+    }
+
+    #[test]
+    fn test_dropped_audio_is_written_as_silence() {
+        let dir = TempDir::new("overflow");
+        let pool = EncodePool::new(1, "overflow-test");
+        let queue = pool.queue(0).unwrap();
+        let target = OutputTarget {
+            path: dir.join("microphone.wav"),
+            split_channels: false,
+            fade: Duration::ZERO,
+            peaks: false,
+            first_segment: 1,
+            template: None,
+            on_finalized: None,
+            plugin: None,
+            queue: Some(queue.clone()),
+            overflow: OverflowPolicy::Drop,
+            budget: None,
+            mirrors: Vec::new(),
+            encode_timer: None,
+            cut_every: None,
+        };
+        let encoder = target.open(1000, 1).unwrap();
+
+        // Hold the worker up and fill its queue
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        queue.submit(Box::new(move || {
+            let _ = release_rx.recv();
+        }));
+        while queue.try_submit(Box::new(|| {})) {}
+        encoder.write(&[0.5; 250]).unwrap();
+        encoder.write(&[0.5; 250]).unwrap();
+        assert_eq!(encoder.take_overflow(), None);
+
+        release_tx.send(()).unwrap();
+        assert_eq!(queue.run(|| ()), Some(()));
+        encoder.write(&[0.5; 100]).unwrap();
+        assert_eq!(
+            encoder.take_overflow(),
+            Some(Overflow {
+                dropped: Duration::from_millis(500),
+                waited: Duration::ZERO,
+            })
+        );
+        assert_eq!(encoder.frames_written(), 600);
+        encoder.finalize().unwrap();
+
+        let samples: Vec<i16> = hound::WavReader::open(&target.path)
+            .unwrap()
+            .into_samples()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples.len(), 600);
+        assert_eq!(samples[..500], [0; 500]);
+        assert_eq!(samples[599], 16383);
+    }
+
+    #[test]
+    fn test_wall_clock_cut_is_seamless() {
+        let dir = TempDir::new("cut");
+        let target = OutputTarget {
+            path: dir.join("microphone.wav"),
+            split_channels: false,
+            fade: Duration::from_millis(10),
+            peaks: false,
+            first_segment: 1,
+            template: None,
+            on_finalized: None,
+            plugin: None,
+            queue: None,
+            overflow: OverflowPolicy::Block,
+            budget: None,
+            mirrors: Vec::new(),
+            encode_timer: None,
+            cut_every: Some(Duration::from_secs(1)),
+        };
+        let mut encoder = target.open(1000, 1).unwrap();
+        encoder.cut_at = Some(30);
+        assert!(target
+            .write_segmented(&encoder, &[0.5; 20])
+            .unwrap()
+            .is_none());
+        let next = target
+            .write_segmented(&encoder, &[0.5; 30])
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.segment(), 2);
+        assert_eq!(next.cut_at, Some(1000));
+        next.finalize().unwrap();
+
+        let read = |path: &Path| -> Vec<i16> {
+            hound::WavReader::open(path)
+                .unwrap()
+                .into_samples()
+                .map(Result::unwrap)
+                .collect()
+        };
+        // Faded in where the recording starts, and at full level on both
+        // sides of the cut
+        let first = read(&target.path);
+        assert_eq!(first.len(), 30);
+        assert!(first[0] < first[29] / 5);
+        assert_eq!(first[29], 16383);
+        let second = read(&segment_path(&target.path, 2));
+        assert_eq!(second.len(), 20);
+        assert_eq!(second[0], 16383);
+    }
+
+    #[test]
+    fn test_resume_repairs_unfinalized_segment() {
+        let dir = TempDir::new("resume");
+        let target = OutputTarget {
+            path: dir.join("system.wav"),
+            split_channels: false,
+            fade: Duration::ZERO,
+            peaks: false,
+            first_segment: 1,
+            template: None,
+            on_finalized: None,
+            plugin: None,
+            queue: None,
+            overflow: OverflowPolicy::Block,
+            budget: None,
+            mirrors: Vec::new(),
+            encode_timer: None,
+            cut_every: None,
+        };
+        assert_eq!(target.resume_segment().unwrap(), 1);
+
+        let encoder = target.open(1000, 2).unwrap();
+        encoder.write(&[0.5; 40]).unwrap();
+        encoder.finalize().unwrap();
+        let encoder = target.open_segment(1000, 2, 2).unwrap();
+        encoder.write(&[0.5; 40]).unwrap();
+        encoder.finalize().unwrap();
+
+        // Simulate a crash: sizes never written, plus half a frame at the end
+        let part2 = segment_path(&target.path, 2);
+        let mut bytes = std::fs::read(&part2).unwrap();
+        bytes[4..8].copy_from_slice(&[0; 4]);
+        bytes[40..44].copy_from_slice(&[0; 4]);
+        bytes.extend([1, 2]);
+        std::fs::write(&part2, bytes).unwrap();
+        assert_eq!(hound::WavReader::open(&part2).unwrap().duration(), 0);
+
+        assert_eq!(target.resume_segment().unwrap(), 3);
+        let reader = hound::WavReader::open(&part2).unwrap();
+        assert_eq!(reader.duration(), 20);
+        assert!(!repair_wav(&part2, false).unwrap());
+    }
+
+    #[test]
+    fn test_segment_paths() {
+        let path = Path::new("/rec/microphone.wav");
+        assert_eq!(segment_path(path, 1), PathBuf::from("/rec/microphone.wav"));
+        assert_eq!(
+            segment_path(path, 3),
+            PathBuf::from("/rec/microphone_part3.wav")
+        );
+        assert_eq!(
+            channel_path(&segment_path(path, 2), 1),
+            PathBuf::from("/rec/microphone_part2_ch1.wav")
+        );
+    }
+
+    /// FNV-1a, which unlike DefaultHasher is the same on every Rust version
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x100_0000_01b3)
+        })
+    }
+
+    /// A sine at `freq` on every channel plus noise from a fixed-seed LCG
+    fn test_signal(frames: usize, channels: u16, rate: u32, freq: f32, noise: f32) -> Vec<f32> {
+        let mut seed = 0x2545_f491_u32;
+        let mut samples = Vec::with_capacity(frames * usize::from(channels));
+        for frame in 0..frames {
+            let t = frame as f32 / rate as f32;
+            let tone = 0.5 * (2.0 * std::f32::consts::PI * freq * t).sin();
+            for _ in 0..channels {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let white = (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0;
+                samples.push(tone + noise * white);
+            }
+        }
+        samples
+    }
+
+    /// Power of one channel at `freq`, by the Goertzel algorithm
+    fn power_at(samples: &[i16], rate: u32, freq: f64) -> f64 {
+        let coeff = 2.0 * (2.0 * std::f64::consts::PI * freq / f64::from(rate)).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for &sample in samples {
+            let s0 = f64::from(sample) + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    }
+
+    #[test]
+    fn test_golden_outputs() {
+        let dir = TempDir::new("golden");
+
+        // Checksums of the finished files. If a change to conversion, fades or
+        // finalization alters them on purpose, update them from the failure.
+        let cases: [(&str, u32, u16, bool, f32, f32, u64); 3] = [
+            ("mono", 48000, 1, false, 1000.0, 0.0, 0xde4769bf534237c8),
+            ("stereo", 44100, 2, false, 440.0, 0.01, 0x05e427f92d1578c9),
+            ("split", 16000, 2, true, 300.0, 0.05, 0xc94b6f155df4b41b),
+        ];
+        for (name, rate, channels, split, freq, noise, checksum) in cases {
+            let path = dir.join(format!("{}.wav", name));
+            let encoder = if split {
+                AudioEncoder::new_split(&path, rate, channels)
+            } else {
+                AudioEncoder::new(&path, rate, channels)
+            }
+            .unwrap();
+            // A second of audio in 10 ms buffers, a splice, a gap of silence
+            // and a full-scale burst that has to clip
+            let signal = test_signal(rate as usize, channels, rate, freq, noise);
+            for chunk in signal.chunks(rate as usize / 100 * usize::from(channels)) {
+                encoder.write(chunk).unwrap();
+            }
+            encoder.splice().unwrap();
+            encoder.fill_silence(u64::from(rate / 20)).unwrap();
+            encoder
+                .write(&vec![1.5; (rate / 10) as usize * usize::from(channels)])
+                .unwrap();
+            encoder.finalize().unwrap();
+
+            let files: Vec<PathBuf> = if split {
+                (0..channels).map(|ch| channel_path(&path, ch)).collect()
+            } else {
+                vec![path]
+            };
+            let actual = files.iter().fold(0u64, |hash, file| {
+                hash.rotate_left(1) ^ fnv1a(&std::fs::read(file).unwrap())
+            });
+            assert_eq!(actual, checksum, "{}: checksum {:#x}", name, actual);
+
+            for file in &files {
+                let reader = hound::WavReader::open(file).unwrap();
+                let file_channels = usize::from(reader.spec().channels);
+                let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+                let first: Vec<i16> = samples.iter().step_by(file_channels).copied().collect();
+                // The file fades in and the burst clips to full scale
+                assert!(first[0].abs() < 1000, "{}: starts at {}", name, first[0]);
+                assert!(first.contains(&32767), "{}", name);
+                // The tone dominates its spectrum despite the noise
+                let second = &first[..rate as usize];
+                let tone = power_at(second, rate, f64::from(freq));
+                let off = power_at(second, rate, f64::from(freq) * 1.7);
+                assert!(tone > 1000.0 * off, "{}: {} vs {}", name, tone, off);
+            }
+        }
+    }
 }
@@ -0,0 +1,243 @@
+//! Encryption at rest: recordings written through `age`, so the audio only
+//! ever reaches the disk encrypted
+
+use hound::WavSpec;
+use std::ffi::OsString;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::capture::encoder::{repair_wav, EncoderBackend, EncoderFactory};
+
+/// Recipient prefixes `age` accepts on its command line
+const RECIPIENT_PREFIXES: &[&str] = &["age1", "ssh-ed25519 ", "ssh-rsa "];
+
+/// `name.wav` -> `name.wav.age`
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".age");
+    PathBuf::from(name)
+}
+
+/// Encrypts a stream's files to an age recipient. Each file is a WAV file
+/// streamed to `age` as it is recorded; as the header can't be patched once
+/// the length is known, it declares the longest possible length, which
+/// `decrypt()` corrects.
+#[derive(Debug)]
+pub struct AgeEncryption {
+    recipient: String,
+    /// The `age` command
+    program: PathBuf,
+}
+
+impl AgeEncryption {
+    /// Check the recipient looks like one and `age` can be run
+    pub fn new(recipient: &str) -> Result<Self, String> {
+        let recipient = recipient.trim();
+        if !RECIPIENT_PREFIXES.iter().any(|p| recipient.starts_with(p)) {
+            return Err(format!(
+                "encryption_recipient {:?} is not an age or SSH public key",
+                recipient
+            ));
+        }
+        let program = PathBuf::from("age");
+        Command::new(&program)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("encryption_recipient needs the age command: {}", e))?;
+        Ok(Self {
+            recipient: recipient.to_string(),
+            program,
+        })
+    }
+}
+
+impl EncoderFactory for AgeEncryption {
+    fn open(&self, spec: WavSpec, path: &Path) -> Result<Box<dyn EncoderBackend>, String> {
+        let path = encrypted_path(path);
+        let mut child = Command::new(&self.program)
+            .arg("--encrypt")
+            .arg("--recipient")
+            .arg(&self.recipient)
+            .arg("--output")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run age: {}", e))?;
+        let stdin = child.stdin.take().ok_or("age has no stdin")?;
+        let mut stdin = BufWriter::new(stdin);
+        stdin
+            .write_all(&streaming_header(spec))
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+        Ok(Box::new(AgeBackend { child, stdin, path }))
+    }
+}
+
+struct AgeBackend {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    path: PathBuf,
+}
+
+impl EncoderBackend for AgeBackend {
+    fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.stdin
+            .write_all(&bytes)
+            .map_err(|e| format!("Failed to write {:?}: {}", self.path, e))
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        let Self {
+            child,
+            mut stdin,
+            path,
+        } = *self;
+        let flushed = stdin.flush();
+        // Closing stdin lets age write the last chunk
+        drop(stdin);
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Failed to wait for age: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "age failed writing {:?}: {}",
+                path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        flushed.map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+}
+
+/// Header of a 16-bit WAV file of unknown length
+fn streaming_header(spec: WavSpec) -> Vec<u8> {
+    let block_align = spec.channels * 2;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(spec.sample_rate * u32::from(block_align)).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+/// Decrypt a recording with the age identity file matching its recipient,
+/// to `output` or next to it without the `.age` extension. Returns the
+/// decrypted WAV file, its header completed.
+pub fn decrypt(path: &Path, identity: &Path, output: Option<&Path>) -> Result<PathBuf, String> {
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None if path.extension().is_some_and(|ext| ext == "age") => path.with_extension(""),
+        None => {
+            return Err(format!(
+                "{:?} has no .age extension; pass an output path",
+                path
+            ))
+        }
+    };
+    let result = Command::new("age")
+        .arg("--decrypt")
+        .arg("--identity")
+        .arg(identity)
+        .arg("--output")
+        .arg(&output)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to run age: {}", e))?;
+    if !result.status.success() {
+        return Err(format!(
+            "age failed decrypting {:?}: {}",
+            path,
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    repair_wav(&output, false)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::encoder::AudioEncoder;
+    use crate::testing::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_streaming_header_is_repaired_to_the_data_written() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let dir = TempDir::new("age");
+        let path = dir.join("microphone.wav");
+        let mut bytes = streaming_header(spec);
+        bytes.extend((0..480i16 * 2).flat_map(|s| s.to_le_bytes()));
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(repair_wav(&path, false).unwrap());
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec(), spec);
+        assert_eq!(reader.duration(), 480);
+    }
+
+    #[test]
+    fn test_reopened_encoder_continues_in_a_new_part() {
+        let dir = TempDir::new("age-reopen");
+        // Stands in for age, "encrypting" by prefixing a marker. Like age, it
+        // truncates the output it is given.
+        let program = dir.join("age");
+        std::fs::write(
+            &program,
+            "#!/bin/sh\n{ printf 'age-fake\\n'; cat; } > \"$5\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let encryption = AgeEncryption {
+            recipient: "age1example".to_string(),
+            program,
+        };
+
+        let encoder =
+            AudioEncoder::with_plugin(Arc::new(encryption), dir.join("microphone.wav"), 1000, 1)
+                .unwrap()
+                .with_fade(Duration::ZERO);
+        encoder.write(&[0.5; 100]).unwrap();
+        // A reconnect, with 20 ms of silence to fill
+        encoder.close().unwrap();
+        assert!(encoder.reopen(Duration::from_millis(20)).unwrap());
+        encoder.write(&[-0.5; 50]).unwrap();
+        encoder.finalize().unwrap();
+        assert_eq!(encoder.segment(), 2);
+        assert_eq!(encoder.path(), dir.join("microphone_part2.wav"));
+
+        let samples = |name: &str| -> Vec<i16> {
+            let bytes = std::fs::read(dir.join(name)).unwrap();
+            let wav = bytes.strip_prefix(b"age-fake\n").unwrap();
+            assert_eq!(&wav[..4], b"RIFF");
+            wav[44..]
+                .chunks(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]))
+                .collect()
+        };
+        assert_eq!(samples("microphone.wav.age"), vec![16383; 100]);
+        let mut second = vec![0; 20];
+        second.extend([-16383; 50]);
+        assert_eq!(samples("microphone_part2.wav.age"), second);
+    }
+}
@@ -0,0 +1,114 @@
+//! The session's event log: every event appended to `events.jsonl` in the
+//! output directory as it is emitted, whether or not Python polls for it, so
+//! a recording that went wrong has its timeline even after a crash
+
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use crate::capture::attribution::StreamDevices;
+use crate::capture::manifest::unix_now;
+use crate::capture::session::{AudioEvent, InternalAudioEvent};
+
+pub const EVENT_LOG_FILE: &str = "events.jsonl";
+
+/// Log the events sent to the returned sender from a thread of their own,
+/// passing each on to `events` converted and attributed. The thread exits
+/// once every sender is dropped. Without a log file, `events` is returned.
+pub fn spawn_event_log(
+    session_id: u64,
+    path: &Path,
+    devices: StreamDevices,
+    events: Sender<InternalAudioEvent>,
+) -> Sender<InternalAudioEvent> {
+    let file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            log!(session = session_id; "Failed to open event log {}: {}", path.display(), e);
+            return events;
+        }
+    };
+    let (tx, rx) = channel::<InternalAudioEvent>();
+    let forward = events.clone();
+    let spawned = thread::Builder::new()
+        .name(format!("event-log-{}", session_id))
+        .spawn(move || {
+            let mut log = EventLog {
+                file,
+                devices,
+                failed: false,
+            };
+            for internal_event in rx {
+                let mut event = AudioEvent {
+                    session_id: Some(session_id),
+                    ..AudioEvent::from(internal_event)
+                };
+                log.append(&mut event);
+                // Logging goes on if the session was dropped without a stop
+                let _ = forward.send(InternalAudioEvent::Relayed(Box::new(event)));
+            }
+        });
+    match spawned {
+        Ok(_) => tx,
+        Err(e) => {
+            log!(session = session_id; "Failed to start the event log: {}", e);
+            events
+        }
+    }
+}
+
+struct EventLog {
+    file: File,
+    devices: StreamDevices,
+    /// Set after a failed write, so a full disk is reported once
+    failed: bool,
+}
+
+impl EventLog {
+    fn append(&mut self, event: &mut AudioEvent) {
+        self.devices.attribute(event);
+        // A line per write, so a crash can cut off only the last
+        let line = format!("{}\n", log_line(event, unix_now()));
+        match self.file.write_all(line.as_bytes()) {
+            Ok(()) => self.failed = false,
+            Err(e) if !self.failed => {
+                log!("Failed to write the event log: {}", e);
+                self.failed = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// An event as a line of the log: its fields that are set, and the time it
+/// was logged in seconds since the epoch
+fn log_line(event: &AudioEvent, time: f64) -> Value {
+    let mut line = Map::new();
+    line.insert("time".to_string(), Value::from(time));
+    if let Ok(Value::Object(fields)) = serde_json::to_value(event) {
+        line.extend(fields.into_iter().filter(|(_, value)| !value.is_null()));
+    }
+    Value::Object(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_lines_leave_out_unset_fields() {
+        let event = AudioEvent {
+            stream: Some("mic".to_string()),
+            duration: Some(0.02),
+            session_id: Some(3),
+            ..AudioEvent::of_type("dropout")
+        };
+        assert_eq!(
+            log_line(&event, 1700000000.5).to_string(),
+            r#"{"duration":0.02,"session_id":3,"stream":"mic","time":1700000000.5,"type":"dropout"}"#
+        );
+    }
+}
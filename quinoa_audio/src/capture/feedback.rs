@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+
+/// A node of the PipeWire graph, as far as feedback detection cares
+#[derive(Clone, Debug)]
+struct GraphNode {
+    name: String,
+    media_class: String,
+    /// Nodes of one link group (the two halves of a loopback, our mic and
+    /// virtual microphone) pass audio to each other without a visible link
+    link_group: Option<String>,
+    /// Created by this process
+    ours: bool,
+}
+
+impl GraphNode {
+    fn is_input(&self) -> bool {
+        self.media_class.contains("Input") || self.media_class.contains("Sink")
+    }
+
+    fn is_output(&self) -> bool {
+        self.media_class.contains("Output") || self.media_class.contains("Source")
+    }
+}
+
+/// Follows nodes and links of the graph to spot cycles through our nodes,
+/// where audio we capture or publish would be fed back to itself
+#[derive(Debug, Default)]
+pub struct FeedbackDetector {
+    nodes: HashMap<u32, GraphNode>,
+    /// Link id to (output node, input node)
+    links: HashMap<u32, (u32, u32)>,
+    /// Cycles already reported, as sorted node ids
+    reported: HashSet<Vec<u32>>,
+}
+
+impl FeedbackDetector {
+    pub fn add_node(
+        &mut self,
+        id: u32,
+        name: &str,
+        media_class: &str,
+        link_group: Option<&str>,
+        ours: bool,
+    ) {
+        self.nodes.insert(
+            id,
+            GraphNode {
+                name: name.to_string(),
+                media_class: media_class.to_string(),
+                link_group: link_group.map(String::from),
+                ours,
+            },
+        );
+    }
+
+    pub fn add_link(&mut self, id: u32, output_node: u32, input_node: u32) {
+        self.links.insert(id, (output_node, input_node));
+    }
+
+    /// Forget a node or link that went away
+    pub fn remove(&mut self, id: u32) {
+        self.nodes.remove(&id);
+        // A loop that was broken may be reported again if it comes back
+        self.reported.retain(|cycle| !cycle.contains(&id));
+        if let Some((output, input)) = self.links.remove(&id) {
+            self.reported
+                .retain(|cycle| !(cycle.contains(&output) && cycle.contains(&input)));
+        }
+    }
+
+    fn successors(&self, node: u32) -> Vec<u32> {
+        let mut next: Vec<u32> = self
+            .links
+            .values()
+            .filter(|(output, _)| *output == node)
+            .map(|(_, input)| *input)
+            .collect();
+        if let Some(from) = self.nodes.get(&node).filter(|n| n.is_input()) {
+            next.extend(self.nodes.iter().filter_map(|(&id, to)| {
+                let same_group = from.link_group.is_some() && to.link_group == from.link_group;
+                (id != node && same_group && to.is_output()).then_some(id)
+            }));
+        }
+        next
+    }
+
+    /// A cycle through one of our nodes that hasn't been reported yet, as
+    /// node names in the order audio flows
+    pub fn new_cycle(&mut self) -> Option<Vec<String>> {
+        let mut ours: Vec<u32> = self
+            .nodes
+            .iter()
+            .filter(|(_, n)| n.ours)
+            .map(|(&id, _)| id)
+            .collect();
+        ours.sort_unstable();
+        for start in ours {
+            let Some(path) = self.path_back_to(start) else {
+                continue;
+            };
+            let mut key = path.clone();
+            key.sort_unstable();
+            if self.reported.insert(key) {
+                return Some(
+                    path.iter()
+                        .chain(std::iter::once(&start))
+                        .map(|id| {
+                            self.nodes
+                                .get(id)
+                                .map_or_else(|| id.to_string(), |n| n.name.clone())
+                        })
+                        .collect(),
+                );
+            }
+        }
+        None
+    }
+
+    /// Depth-first search for a path from `start` back to itself
+    fn path_back_to(&self, start: u32) -> Option<Vec<u32>> {
+        let mut stack = vec![(start, vec![start])];
+        let mut seen = HashSet::new();
+        while let Some((node, path)) = stack.pop() {
+            for next in self.successors(node) {
+                if next == start {
+                    return Some(path);
+                }
+                if seen.insert(next) {
+                    let mut longer = path.clone();
+                    longer.push(next);
+                    stack.push((next, longer));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_loop_through_our_virtual_mic() {
+        let mut graph = FeedbackDetector::default();
+        graph.add_node(1, "quinoa-mic", "Stream/Input/Audio", Some("quinoa"), true);
+        graph.add_node(
+            2,
+            "quinoa-virtual-mic",
+            "Audio/Source/Virtual",
+            Some("quinoa"),
+            true,
+        );
+        graph.add_node(3, "speakers", "Audio/Sink", None, false);
+        // Our mic records the speakers' monitor, our virtual mic plays into them
+        graph.add_link(10, 3, 1);
+        assert_eq!(graph.new_cycle(), None);
+        graph.add_link(11, 2, 3);
+        assert_eq!(
+            graph.new_cycle(),
+            Some(vec![
+                "quinoa-mic".to_string(),
+                "quinoa-virtual-mic".to_string(),
+                "speakers".to_string(),
+                "quinoa-mic".to_string(),
+            ])
+        );
+        // Reported once, and again after the loop is broken and remade
+        assert_eq!(graph.new_cycle(), None);
+        graph.remove(11);
+        assert_eq!(graph.new_cycle(), None);
+        graph.add_link(12, 2, 3);
+        assert!(graph.new_cycle().is_some());
+    }
+}
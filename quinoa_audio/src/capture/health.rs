@@ -0,0 +1,154 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+use std::time::Duration;
+
+/// A running stream that hasn't delivered a buffer for this long is stalled
+const STALL_AFTER: Duration = Duration::from_secs(2);
+
+/// State of one stream, from `RecordingSession.health()`
+#[derive(Clone, Debug, Serialize)]
+#[pyclass]
+pub struct StreamHealth {
+    /// "mic" or "system"
+    #[pyo3(get)]
+    pub stream: String,
+    /// "recording", "paused", "stalled", "reconnecting", "failed", "stopped"
+    /// or "not_recorded"
+    #[pyo3(get)]
+    pub state: String,
+    /// Seconds since the stream last delivered a buffer, None if it never has
+    #[pyo3(get)]
+    pub last_buffer_age: Option<f64>,
+}
+
+#[pymethods]
+impl StreamHealth {
+    fn __repr__(&self) -> String {
+        format!(
+            "StreamHealth(stream='{}', state='{}', last_buffer_age={})",
+            self.stream,
+            self.state,
+            self.last_buffer_age
+                .map_or_else(|| "None".to_string(), |age| format!("{:.3}", age))
+        )
+    }
+}
+
+/// What a watchdog needs to decide whether to restart capture
+#[derive(Clone, Debug, Serialize)]
+#[pyclass]
+pub struct HealthReport {
+    /// The session is running and no recorded stream is stalled or failed
+    #[pyo3(get)]
+    pub healthy: bool,
+    /// The audio thread is still running
+    #[pyo3(get)]
+    pub running: bool,
+    #[pyo3(get)]
+    pub streams: Vec<StreamHealth>,
+    /// Encoding jobs waiting on the workers
+    #[pyo3(get)]
+    pub encoder_backlog: usize,
+    /// Message of the most recent error event
+    #[pyo3(get)]
+    pub last_error: Option<String>,
+    /// Seconds since that error
+    #[pyo3(get)]
+    pub last_error_age: Option<f64>,
+}
+
+#[pymethods]
+impl HealthReport {
+    fn __repr__(&self) -> String {
+        format!(
+            "HealthReport(healthy={}, running={}, streams={}, encoder_backlog={}, last_error={})",
+            if self.healthy { "True" } else { "False" },
+            if self.running { "True" } else { "False" },
+            self.streams.len(),
+            self.encoder_backlog,
+            self.last_error
+                .as_ref()
+                .map_or_else(|| "None".to_string(), |e| format!("'{}'", e))
+        )
+    }
+}
+
+/// What is known about a stream when the health report is taken
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamProbe {
+    /// The session records this stream
+    pub recorded: bool,
+    /// The audio thread is still running
+    pub running: bool,
+    /// Its files take writes (false while closed for a reconnect)
+    pub open: bool,
+    /// Its files were finalized for good
+    pub finalized: bool,
+    pub paused: bool,
+    pub last_buffer: Option<Duration>,
+    pub uptime: Duration,
+}
+
+impl StreamProbe {
+    pub fn state(&self) -> &'static str {
+        if !self.recorded {
+            "not_recorded"
+        } else if !self.running {
+            "stopped"
+        } else if self.finalized {
+            "failed"
+        } else if !self.open {
+            "reconnecting"
+        } else if self.paused {
+            "paused"
+        } else if self.last_buffer.unwrap_or(self.uptime) > STALL_AFTER {
+            "stalled"
+        } else {
+            "recording"
+        }
+    }
+}
+
+/// Event types a health report counts as errors
+pub fn is_error_event(type_: &str) -> bool {
+    matches!(type_, "error" | "stream_error" | "disk_full")
+        || type_.ends_with("_failed")
+        || type_.ends_with("_disconnected")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_state() {
+        let secs = Duration::from_secs;
+        let running = StreamProbe {
+            recorded: true,
+            running: true,
+            open: true,
+            last_buffer: Some(Duration::from_millis(20)),
+            uptime: secs(60),
+            ..StreamProbe::default()
+        };
+        assert_eq!(running.state(), "recording");
+        let stalled = StreamProbe {
+            last_buffer: Some(secs(5)),
+            ..running
+        };
+        assert_eq!(stalled.state(), "stalled");
+        // Give a new stream time to deliver its first buffer
+        let starting = StreamProbe {
+            last_buffer: None,
+            uptime: secs(1),
+            ..running
+        };
+        assert_eq!(starting.state(), "recording");
+        let reconnecting = StreamProbe {
+            open: false,
+            ..stalled
+        };
+        assert_eq!(reconnecting.state(), "reconnecting");
+        assert_eq!(StreamProbe::default().state(), "not_recorded");
+    }
+}
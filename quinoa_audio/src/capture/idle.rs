@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::capture::levels::LEVELS_PER_SECOND;
+
+/// Peaks at or below this (-60 dBFS) count as quiet
+const QUIET_LEVEL: f32 = 0.001;
+/// How often the session's timer runs while idle, instead of every levels window
+pub const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether a session is idle, shared between its timer, its audio
+/// callbacks and the loop running them
+#[derive(Debug, Default)]
+pub struct IdleState {
+    idle: AtomicBool,
+    /// Changes the loop hasn't re-paced its timer for yet. Whoever makes a
+    /// change also quits the loop once, so each quit is accounted for.
+    changes: AtomicU32,
+}
+
+impl IdleState {
+    /// Wake an idle session if `peak` is sound. Returns true if this call
+    /// woke it, so the caller can get the timer running at full rate again.
+    pub fn wake_on(&self, peak: f32) -> bool {
+        let woke = peak > QUIET_LEVEL && self.idle.swap(false, Ordering::Relaxed);
+        if woke {
+            self.changes.fetch_add(1, Ordering::Relaxed);
+        }
+        woke
+    }
+
+    fn enter(&self) {
+        self.idle.store(true, Ordering::Relaxed);
+        self.changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    /// Account for one change, if there is one the loop hasn't re-paced for
+    #[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+    pub fn take_change(&self) -> bool {
+        self.changes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    /// How long the timer should wait between runs
+    pub fn interval(&self) -> Duration {
+        if self.is_idle() {
+            IDLE_INTERVAL
+        } else {
+            Duration::from_secs(1) / LEVELS_PER_SECOND
+        }
+    }
+}
+
+/// Puts a session that has heard nothing for a while into a low-power idle
+/// mode, where its timer runs once a second and levels aren't measured, and
+/// takes it out again as soon as there is sound. The audio callbacks see
+/// the sound first, through `IdleState::wake_on()`; the timer catches up.
+pub struct IdleWatch {
+    after: f64,
+    /// When both streams went quiet, in seconds since the session started
+    quiet_since: Option<f64>,
+    state: Arc<IdleState>,
+    /// Whether the last `observe()` left the session idle
+    was_idle: bool,
+}
+
+impl IdleWatch {
+    /// None disables idle mode
+    pub fn new(after_seconds: Option<u32>) -> Self {
+        Self {
+            after: after_seconds.map_or(0.0, f64::from),
+            quiet_since: None,
+            state: Arc::default(),
+            was_idle: false,
+        }
+    }
+
+    pub fn state(&self) -> Arc<IdleState> {
+        self.state.clone()
+    }
+
+    /// Take the loudest peak since the last call, at `now` seconds. Returns
+    /// Some(true) when the session just went idle and Some(false) when it
+    /// just woke up.
+    pub fn observe(&mut self, peak: f32, now: f64) -> Option<bool> {
+        if self.after <= 0.0 {
+            return None;
+        }
+        let woken = self.state.wake_on(peak) || (self.was_idle && !self.state.is_idle());
+        if woken || peak > QUIET_LEVEL {
+            self.quiet_since = None;
+            self.was_idle = false;
+            return woken.then_some(false);
+        }
+        let since = *self.quiet_since.get_or_insert(now);
+        if !self.was_idle && now - since >= self.after {
+            self.state.enter();
+            self.was_idle = true;
+            return Some(true);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_goes_idle_after_quiet_and_wakes_on_sound() {
+        let mut watch = IdleWatch::new(Some(2));
+        let mut changes = Vec::new();
+        for tick in 0..40 {
+            let peak = if tick < 5 { 0.3 } else { 0.0 };
+            if let Some(idle) = watch.observe(peak, f64::from(tick) * 0.1) {
+                changes.push((tick, idle));
+            }
+        }
+        assert_eq!(changes, [(25, true)]);
+        let state = watch.state();
+        assert_eq!(state.interval(), IDLE_INTERVAL);
+        assert!(state.take_change());
+        assert!(!state.take_change());
+
+        // The audio callback sees sound first; the timer reports the wake-up
+        assert!(!state.wake_on(0.0));
+        assert!(state.wake_on(0.3));
+        assert!(!state.wake_on(0.3));
+        assert!(state.take_change());
+        assert!(!state.take_change());
+        assert_eq!(watch.observe(0.0, 5.0), Some(false));
+        assert_eq!(watch.observe(0.0, 5.1), None);
+        assert!(!state.is_idle());
+
+        let mut disabled = IdleWatch::new(None);
+        assert_eq!(disabled.observe(0.0, 1000.0), None);
+    }
+}
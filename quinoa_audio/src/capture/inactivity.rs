@@ -0,0 +1,118 @@
+use crate::capture::levels::LevelSample;
+
+/// Levels at or below this (-60 dBFS) count as silence
+const SILENCE_LEVEL: f32 = 0.001;
+
+/// Notices one stream staying silent while the other has sound, e.g. a muted
+/// or wrong microphone during a call, from the levels the session measures.
+/// Each silent stretch is reported once, when it reaches `after` seconds.
+pub struct InactivityWatch {
+    after: f64,
+    /// When the mic and system streams last had sound, in
+    /// `LevelSample::elapsed` seconds
+    last_sound: [Option<f64>; 2],
+    /// When their current silence began
+    silent_since: [Option<f64>; 2],
+    reported: [bool; 2],
+}
+
+impl InactivityWatch {
+    /// `after_seconds` of 0 disables the watch
+    pub fn new(after_seconds: u32) -> Self {
+        Self {
+            after: f64::from(after_seconds),
+            last_sound: [None; 2],
+            silent_since: [None; 2],
+            reported: [false; 2],
+        }
+    }
+
+    /// Take the next levels measurement. Returns the streams (`is_mic`) that
+    /// just became inactive and how long they have been silent.
+    pub fn observe(&mut self, sample: &LevelSample, paused: bool) -> Vec<(bool, f64)> {
+        if self.after <= 0.0 {
+            return Vec::new();
+        }
+        let now = sample.elapsed;
+        let streams = [
+            (&sample.mic_channel_levels, sample.mic_level),
+            (&sample.system_channel_levels, sample.system_level),
+        ];
+        for (i, (channels, level)) in streams.into_iter().enumerate() {
+            // Levels are empty for a stream that isn't running
+            if paused || channels.is_empty() {
+                self.last_sound[i] = None;
+                self.silent_since[i] = None;
+                self.reported[i] = false;
+            } else if level > SILENCE_LEVEL {
+                self.last_sound[i] = Some(now);
+                self.silent_since[i] = None;
+                self.reported[i] = false;
+            } else if self.silent_since[i].is_none() {
+                self.silent_since[i] = Some(now);
+            }
+        }
+
+        let mut inactive = Vec::new();
+        for (i, is_mic) in [(0, true), (1, false)] {
+            let (Some(since), Some(other)) = (self.silent_since[i], self.last_sound[1 - i]) else {
+                continue;
+            };
+            let silent_for = now - since;
+            if !self.reported[i] && silent_for >= self.after && now - other < self.after {
+                self.reported[i] = true;
+                inactive.push((is_mic, silent_for));
+            }
+        }
+        inactive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(elapsed: f64, mic: f32, system: f32) -> LevelSample {
+        LevelSample {
+            elapsed,
+            mic_level: mic,
+            system_level: system,
+            mic_channel_levels: vec![mic],
+            system_channel_levels: vec![system, system],
+            mic_peak_hold: mic,
+            system_peak_hold: system,
+        }
+    }
+
+    #[test]
+    fn test_reports_silent_stream_once() {
+        let mut watch = InactivityWatch::new(10);
+        let mut reports = Vec::new();
+        for tick in 0..300 {
+            let t = f64::from(tick) * 0.1;
+            reports.extend(watch.observe(&sample(t, 0.0, 0.3), false));
+        }
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].0);
+        assert!((reports[0].1 - 10.0).abs() < 0.15);
+
+        // Both silent, or paused: nothing to compare against
+        let mut watch = InactivityWatch::new(10);
+        for tick in 0..300 {
+            let t = f64::from(tick) * 0.1;
+            assert!(watch.observe(&sample(t, 0.0, 0.0), false).is_empty());
+            assert!(watch.observe(&sample(t, 0.0, 0.3), true).is_empty());
+        }
+
+        // Sound again re-arms it
+        let mut watch = InactivityWatch::new(1);
+        let mut count = 0;
+        for tick in 0..60 {
+            let mic = if tick == 30 { 0.5 } else { 0.0 };
+            count += watch
+                .observe(&sample(f64::from(tick) * 0.1, mic, 0.3), false)
+                .len();
+        }
+        assert_eq!(count, 2);
+    }
+}
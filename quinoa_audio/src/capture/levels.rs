@@ -0,0 +1,280 @@
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::capture::memory::MemoryBudget;
+
+/// Levels events are sent every 100 ms
+pub const LEVELS_PER_SECOND: u32 = 10;
+
+/// One past levels measurement, as reported by a levels event
+#[derive(Clone, Debug, PartialEq)]
+#[pyclass]
+pub struct LevelSample {
+    /// Seconds since the session started
+    #[pyo3(get)]
+    pub elapsed: f64,
+    #[pyo3(get)]
+    pub mic_level: f32,
+    #[pyo3(get)]
+    pub system_level: f32,
+    #[pyo3(get)]
+    pub mic_channel_levels: Vec<f32>,
+    #[pyo3(get)]
+    pub system_channel_levels: Vec<f32>,
+    /// Highest peak within the last `peak_hold_ms`
+    #[pyo3(get)]
+    pub mic_peak_hold: f32,
+    #[pyo3(get)]
+    pub system_peak_hold: f32,
+}
+
+#[pymethods]
+impl LevelSample {
+    fn __repr__(&self) -> String {
+        format!(
+            "LevelSample(elapsed={:.1}, mic_level={:.3}, system_level={:.3})",
+            self.elapsed, self.mic_level, self.system_level
+        )
+    }
+}
+
+impl LevelSample {
+    /// Bytes the sample takes up in the history
+    fn footprint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + std::mem::size_of_val(self.mic_channel_levels.as_slice())
+            + std::mem::size_of_val(self.system_channel_levels.as_slice())
+    }
+}
+
+/// Meter ballistics: how fast reported levels rise and fall, and how long
+/// the peak hold stays up. Zero times give the raw window peaks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BallisticsConfig {
+    pub attack: Duration,
+    pub release: Duration,
+    pub peak_hold: Duration,
+}
+
+/// Ballistics state of one stream
+#[derive(Debug, Default)]
+struct Ballistics {
+    levels: Vec<f32>,
+    hold: f32,
+    /// When `hold` was set, in seconds since the session started
+    held_at: f64,
+    last: Option<f64>,
+}
+
+/// Fraction of the way a one-pole smoother with time constant `tau` moves in `dt`
+fn smoothing(tau: Duration, dt: f64) -> f32 {
+    if tau.is_zero() {
+        return 1.0;
+    }
+    (1.0 - (-dt / tau.as_secs_f64()).exp()) as f32
+}
+
+impl Ballistics {
+    /// Smoothed per-channel levels and the held peak for window peaks at `now`
+    fn apply(&mut self, config: BallisticsConfig, peaks: &[f32], now: f64) -> (Vec<f32>, f32) {
+        let dt = self
+            .last
+            .map_or(1.0 / f64::from(LEVELS_PER_SECOND), |last| now - last);
+        self.last = Some(now);
+        if self.levels.len() != peaks.len() {
+            self.levels = vec![0.0; peaks.len()];
+        }
+        let attack = smoothing(config.attack, dt);
+        let release = smoothing(config.release, dt);
+        for (level, &peak) in self.levels.iter_mut().zip(peaks) {
+            let rate = if peak > *level { attack } else { release };
+            *level += (peak - *level) * rate;
+        }
+
+        let peak = overall_peak(peaks);
+        if peak >= self.hold || now - self.held_at >= config.peak_hold.as_secs_f64() {
+            self.hold = peak;
+            self.held_at = now;
+        }
+        (self.levels.clone(), self.hold)
+    }
+}
+
+/// Turns the window peaks measured on the audio thread into the levels a
+/// session reports, and keeps the most recent ones so a UI that stopped
+/// polling (e.g. while backgrounded) can redraw its meters
+pub struct LevelMeter {
+    started: Instant,
+    config: BallisticsConfig,
+    mic: Mutex<Ballistics>,
+    system: Mutex<Ballistics>,
+    capacity: usize,
+    samples: Mutex<VecDeque<LevelSample>>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl LevelMeter {
+    pub fn new(config: BallisticsConfig, history_seconds: u32) -> Self {
+        Self {
+            started: Instant::now(),
+            config,
+            mic: Mutex::default(),
+            system: Mutex::default(),
+            capacity: (history_seconds * LEVELS_PER_SECOND) as usize,
+            samples: Mutex::new(VecDeque::new()),
+            budget: Arc::default(),
+        }
+    }
+
+    /// Count the history against `budget`, dropping the oldest samples
+    /// rather than going over it
+    pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Apply the ballistics to one window's per-channel peaks and record the result
+    pub fn measure(&self, mic: &[f32], system: &[f32]) -> LevelSample {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let apply = |stream: &Mutex<Ballistics>, peaks: &[f32]| {
+            stream
+                .lock()
+                .map(|mut b| b.apply(self.config, peaks, elapsed))
+                .unwrap_or_else(|_| (peaks.to_vec(), overall_peak(peaks)))
+        };
+        let (mic_channel_levels, mic_peak_hold) = apply(&self.mic, mic);
+        let (system_channel_levels, system_peak_hold) = apply(&self.system, system);
+        let sample = LevelSample {
+            elapsed,
+            mic_level: overall_peak(&mic_channel_levels),
+            system_level: overall_peak(&system_channel_levels),
+            mic_channel_levels,
+            system_channel_levels,
+            mic_peak_hold,
+            system_peak_hold,
+        };
+        if self.capacity > 0 {
+            if let Ok(mut samples) = self.samples.lock() {
+                if samples.len() == self.capacity {
+                    if let Some(oldest) = samples.pop_front() {
+                        self.budget.release(oldest.footprint());
+                    }
+                }
+                let bytes = sample.footprint();
+                while !self
+                    .budget
+                    .try_reserve(bytes, "dropping the oldest level history")
+                {
+                    match samples.pop_front() {
+                        Some(oldest) => self.budget.release(oldest.footprint()),
+                        None => return sample,
+                    }
+                }
+                samples.push_back(sample.clone());
+            }
+        }
+        sample
+    }
+
+    /// Measurements from the last `seconds`, oldest first
+    pub fn since(&self, seconds: f64) -> Vec<LevelSample> {
+        let cutoff = self.started.elapsed().as_secs_f64() - seconds;
+        self.samples
+            .lock()
+            .map(|samples| {
+                samples
+                    .iter()
+                    .filter(|s| s.elapsed >= cutoff)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Peak absolute sample value per channel of an interleaved buffer
+pub fn channel_peaks(samples: &[f32], channels: usize) -> Vec<f32> {
+    let mut peaks = vec![0.0f32; channels];
+    if channels == 0 {
+        return peaks;
+    }
+    for frame in samples.chunks_exact(channels) {
+        for (peak, sample) in peaks.iter_mut().zip(frame) {
+            *peak = peak.max(sample.abs());
+        }
+    }
+    peaks
+}
+
+/// Fold per-channel peaks into an accumulator covering the current level window.
+/// The accumulator takes the new channel count if the stream was renegotiated.
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub fn merge_peaks(acc: &mut Vec<f32>, peaks: &[f32]) {
+    if acc.len() != peaks.len() {
+        acc.clear();
+        acc.resize(peaks.len(), 0.0);
+    }
+    for (a, p) in acc.iter_mut().zip(peaks) {
+        *a = a.max(*p);
+    }
+}
+
+/// Overall level of a stream: the loudest channel
+pub fn overall_peak(peaks: &[f32]) -> f32 {
+    peaks.iter().copied().fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_peaks_deinterleave() {
+        let samples = [0.1, -0.8, 0.0, 0.3, 0.5, -0.2, 0.0, 0.9];
+        assert_eq!(channel_peaks(&samples, 4), vec![0.5, 0.8, 0.0, 0.9]);
+
+        let mut acc = vec![0.6];
+        merge_peaks(&mut acc, &[0.2, 0.4]);
+        assert_eq!(acc, vec![0.2, 0.4]);
+        merge_peaks(&mut acc, &[0.3, 0.1]);
+        assert_eq!(acc, vec![0.3, 0.4]);
+        assert_eq!(overall_peak(&acc), 0.4);
+    }
+
+    #[test]
+    fn test_level_history_is_bounded() {
+        let meter = LevelMeter::new(BallisticsConfig::default(), 1);
+        for i in 0..25 {
+            meter.measure(&[i as f32], &[]);
+        }
+        let samples = meter.since(60.0);
+        assert_eq!(samples.len(), 10);
+        assert_eq!(samples[0].mic_level, 15.0);
+        assert_eq!(samples[9].system_level, 0.0);
+        assert!(LevelMeter::new(BallisticsConfig::default(), 1)
+            .since(60.0)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_ballistics_smooth_and_hold() {
+        let config = BallisticsConfig {
+            attack: Duration::ZERO,
+            release: Duration::from_millis(300),
+            peak_hold: Duration::from_millis(1000),
+        };
+        let mut stream = Ballistics::default();
+        assert_eq!(stream.apply(config, &[0.8], 0.0), (vec![0.8], 0.8));
+        // Falls by 1 - e^(-1/3) per 100 ms, while the peak is held
+        let (levels, hold) = stream.apply(config, &[0.0], 0.1);
+        assert!((levels[0] - 0.8 * (-1.0f32 / 3.0).exp()).abs() < 1e-4);
+        assert_eq!(hold, 0.8);
+        // Instant attack; a higher peak restarts the hold
+        assert_eq!(stream.apply(config, &[0.9], 0.5), (vec![0.9], 0.9));
+        assert_eq!(stream.apply(config, &[0.1], 1.0).1, 0.9);
+        // ...which drops once it has been up for a second
+        assert_eq!(stream.apply(config, &[0.1], 1.6).1, 0.1);
+    }
+}
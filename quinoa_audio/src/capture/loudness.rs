@@ -0,0 +1,239 @@
+use std::f64::consts::PI;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Loudness ReplayGain 2.0 brings tracks to, in LUFS
+const REFERENCE_LUFS: f64 = -18.0;
+/// Gating blocks are 400 ms, overlapping by 75%
+const SUBBLOCKS_PER_BLOCK: usize = 4;
+const SUBBLOCK_SECONDS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Second-order IIR filter, transposed direct form II
+#[derive(Clone, Debug)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two K-weighting stages of ITU-R BS.1770 (a high shelf, then a
+/// high-pass), with coefficients derived for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = f64::from(sample_rate.max(1));
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+fn lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Integrated loudness (EBU R128 / BS.1770) and sample peak of everything
+/// written to a file, measured as it is written.
+#[derive(Clone, Debug)]
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    subblock_frames: usize,
+    /// Sum of squared weighted samples in the current 100 ms
+    energy: f64,
+    frames: usize,
+    /// Mean square of each completed 100 ms
+    subblocks: Vec<f64>,
+    peak: u16,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = usize::from(channels.max(1));
+        Self {
+            channels,
+            filters: (0..channels).map(|_| k_weighting(sample_rate)).collect(),
+            subblock_frames: ((f64::from(sample_rate) * SUBBLOCK_SECONDS) as usize).max(1),
+            energy: 0.0,
+            frames: 0,
+            subblocks: Vec::new(),
+            peak: 0,
+        }
+    }
+
+    /// Add interleaved samples
+    pub fn add(&mut self, samples: &[i16]) {
+        let scale = 1.0 / 32768.0;
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, filters) in frame.iter().zip(&mut self.filters) {
+                self.peak = self.peak.max(sample.unsigned_abs());
+                let x = f64::from(*sample) * scale;
+                let [shelf, high_pass] = filters;
+                let y = high_pass.process(shelf.process(x));
+                // Front channels all weigh 1.0; surround weights are ignored
+                self.energy += y * y;
+            }
+            self.frames += 1;
+            if self.frames == self.subblock_frames {
+                self.subblocks.push(self.energy / self.frames as f64);
+                self.energy = 0.0;
+                self.frames = 0;
+            }
+        }
+    }
+
+    /// Gated integrated loudness in LUFS, or None if everything was silence
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        let blocks: Vec<f64> = self
+            .subblocks
+            .windows(SUBBLOCKS_PER_BLOCK)
+            .map(|w| w.iter().sum::<f64>() / SUBBLOCKS_PER_BLOCK as f64)
+            .filter(|&energy| energy > 0.0 && lufs(energy) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+        let mean = blocks.iter().sum::<f64>() / blocks.len() as f64;
+        let gate = lufs(mean) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = blocks.into_iter().filter(|&e| lufs(e) > gate).collect();
+        Some(lufs(gated.iter().sum::<f64>() / gated.len() as f64))
+    }
+
+    /// Highest absolute sample, 1.0 being full scale
+    pub fn peak(&self) -> f64 {
+        f64::from(self.peak) / 32768.0
+    }
+
+    /// ReplayGain 2.0 track gain in dB, or None if everything was silence
+    pub fn track_gain(&self) -> Option<f64> {
+        self.integrated_lufs()
+            .map(|loudness| REFERENCE_LUFS - loudness)
+    }
+}
+
+/// ID3v2.3 user text frame
+fn txxx_frame(description: &str, value: &str) -> Vec<u8> {
+    let mut body = vec![0u8];
+    body.extend_from_slice(description.as_bytes());
+    body.push(0);
+    body.extend_from_slice(value.as_bytes());
+    let mut frame = b"TXXX".to_vec();
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(&body);
+    frame
+}
+
+/// Append the meter's ReplayGain tags to a finalized WAV file, as the ID3
+/// chunk most players read them from. Silent files are left untagged.
+pub fn tag_wav(path: &Path, meter: &LoudnessMeter) -> Result<(), String> {
+    let Some(gain) = meter.track_gain() else {
+        return Ok(());
+    };
+    let mut frames = txxx_frame("REPLAYGAIN_TRACK_GAIN", &format!("{:.2} dB", gain));
+    frames.extend(txxx_frame(
+        "REPLAYGAIN_TRACK_PEAK",
+        &format!("{:.6}", meter.peak()),
+    ));
+    let size = frames.len() as u32;
+    // Tag size is a syncsafe integer: 7 bits per byte
+    let syncsafe = [
+        (size >> 21) as u8 & 0x7F,
+        (size >> 14) as u8 & 0x7F,
+        (size >> 7) as u8 & 0x7F,
+        size as u8 & 0x7F,
+    ];
+    let mut tag = b"ID3\x03\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe);
+    tag.extend_from_slice(&frames);
+
+    let mut chunk = b"id3 ".to_vec();
+    chunk.extend_from_slice(&(tag.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(&tag);
+    if tag.len() % 2 == 1 {
+        chunk.push(0);
+    }
+
+    let err = |e: std::io::Error| format!("Failed to tag {:?}: {}", path, e);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(err)?;
+    let mut riff = [0u8; 4];
+    file.read_exact(&mut riff).map_err(err)?;
+    if &riff != b"RIFF" {
+        return Err(format!("Failed to tag {:?}: not a WAV file", path));
+    }
+    let end = file.seek(SeekFrom::End(0)).map_err(err)?;
+    file.write_all(&chunk).map_err(err)?;
+    let riff_size = end + chunk.len() as u64 - 8;
+    file.seek(SeekFrom::Start(4)).map_err(err)?;
+    file.write_all(&(riff_size.min(u64::from(u32::MAX)) as u32).to_le_bytes())
+        .map_err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, freq: f64, amplitude: f64, seconds: f64) -> Vec<i16> {
+        (0..(f64::from(sample_rate) * seconds) as usize)
+            .map(|i| {
+                let t = i as f64 / f64::from(sample_rate);
+                ((2.0 * PI * freq * t).sin() * amplitude * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_full_scale_1khz_sine_measures_about_minus_3_lufs() {
+        // BS.1770: a 0 dBFS 1 kHz sine in one channel reads -3.01 LUFS
+        let mut meter = LoudnessMeter::new(48000, 1);
+        meter.add(&sine(48000, 1000.0, 1.0, 5.0));
+        let loudness = meter.integrated_lufs().unwrap();
+        assert!((loudness + 3.01).abs() < 0.1, "{}", loudness);
+        assert!((meter.peak() - 1.0).abs() < 0.001);
+
+        // 20 dB quieter reads 20 LU lower, and the pause is gated out (only the
+        // blocks straddling the end of the tone pull it down a little)
+        let mut meter = LoudnessMeter::new(44100, 1);
+        meter.add(&sine(44100, 1000.0, 0.1, 3.0));
+        meter.add(&vec![0; 44100 * 3]);
+        let gain = meter.track_gain().unwrap();
+        assert!((gain - (-18.0 + 23.01)).abs() < 0.3, "{}", gain);
+
+        assert!(LoudnessMeter::new(48000, 2).track_gain().is_none());
+    }
+}
@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::capture::checksum::sha256_file;
+use crate::capture::redact::Redaction;
+use crate::capture::session::RecordingConfig;
+use crate::capture::trim::TrimmedSilence;
+
+/// File name of the manifest inside the output directory
+pub const MANIFEST_FILE: &str = "session.json";
+
+/// A change of microphone during the session
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MicSwitch {
+    pub device_id: String,
+    /// Frame offset in the mic file where the new device starts
+    pub frame: u64,
+    /// Seconds since the session started
+    pub elapsed: f64,
+}
+
+/// A stream recorded in a different format than the config asked for
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfigMismatch {
+    /// "mic" or "system"
+    pub stream: String,
+    pub requested_rate: u32,
+    pub requested_channels: Option<u32>,
+    /// What the file is written at
+    pub rate: u32,
+    pub channels: u32,
+}
+
+/// The format a session's config asks its streams to be written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestedFormat {
+    pub rate: u32,
+    /// None for whatever the device delivers
+    pub channels: Option<u32>,
+}
+
+impl RequestedFormat {
+    pub fn of(config: &RecordingConfig) -> Self {
+        Self {
+            rate: config.sample_rate,
+            channels: config.channels,
+        }
+    }
+
+    /// How a stream written at `rate` and `channels` differs, if it does
+    pub fn mismatch(&self, stream: &str, rate: u32, channels: u32) -> Option<ConfigMismatch> {
+        let channels_differ = self.channels.is_some_and(|c| c != channels);
+        (rate != self.rate || channels_differ).then(|| ConfigMismatch {
+            stream: stream.to_string(),
+            requested_rate: self.rate,
+            requested_channels: self.channels,
+            rate,
+            channels,
+        })
+    }
+}
+
+/// Description of a recording session, kept next to its output files
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionManifest {
+    pub version: u32,
+    /// Unix time the session started, in seconds
+    pub started_at: f64,
+    pub sample_rate: u32,
+    pub mic_device_id: Option<String>,
+    pub system_audio: bool,
+    #[serde(default)]
+    pub mic_switches: Vec<MicSwitch>,
+    /// Settings the session was started with, used to resume it. Secrets
+    /// such as upload header values are redacted.
+    #[serde(default)]
+    pub config: Option<RecordingConfig>,
+    /// Unix times the session was resumed by a new process, in seconds
+    #[serde(default)]
+    pub resumed_at: Vec<f64>,
+    /// Streams written in a different format than requested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub config_mismatches: Vec<ConfigMismatch>,
+    /// Ranges struck with `redact()`, silenced in the files once final
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<Redaction>,
+    /// SHA-256 of each output file once final, by path relative to the
+    /// manifest's directory
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+    /// Checksum of each file as it was when uploaded, so a rewritten file
+    /// is sent again
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub uploaded: BTreeMap<String, String>,
+    /// Silence cut from the start and end of each file by `trim_silence_db`,
+    /// by path relative to the manifest's directory
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trimmed: BTreeMap<String, TrimmedSilence>,
+}
+
+impl SessionManifest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read manifest {:?}: {}", path, e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Invalid manifest {:?}: {}", path, e))
+    }
+}
+
+/// A file's name in the manifest: its path relative to `dir`
+fn relative_name(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub(crate) fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Keeps `session.json` in the output directory up to date as the session runs
+pub struct ManifestWriter {
+    /// None for an analysis-only session, whose manifest stays in memory
+    path: Option<PathBuf>,
+    started: Instant,
+    manifest: Mutex<SessionManifest>,
+}
+
+impl ManifestWriter {
+    pub fn create(config: &RecordingConfig) -> Result<Self, String> {
+        let manifest = SessionManifest {
+            version: 1,
+            started_at: config.started_at,
+            sample_rate: config.sample_rate,
+            mic_device_id: config.mic_device_id.clone(),
+            system_audio: config.system_audio,
+            mic_switches: Vec::new(),
+            config: Some(config.redacted()),
+            resumed_at: Vec::new(),
+            config_mismatches: Vec::new(),
+            redactions: Vec::new(),
+            checksums: BTreeMap::new(),
+            uploaded: BTreeMap::new(),
+            trimmed: BTreeMap::new(),
+        };
+        let writer = Self {
+            path: (!config.analysis_only()).then(|| config.output_path(MANIFEST_FILE)),
+            started: Instant::now(),
+            manifest: Mutex::new(manifest),
+        };
+        writer.save()?;
+        Ok(writer)
+    }
+
+    /// Take over the manifest of a session started by an earlier process
+    pub fn resume(path: &Path, mut manifest: SessionManifest) -> Result<Self, String> {
+        let now = unix_now();
+        // Keep elapsed times relative to the original start
+        let since_start =
+            Duration::try_from_secs_f64(now - manifest.started_at).unwrap_or_default();
+        manifest.resumed_at.push(now);
+        let writer = Self {
+            path: Some(path.to_path_buf()),
+            started: Instant::now()
+                .checked_sub(since_start)
+                .unwrap_or_else(Instant::now),
+            manifest: Mutex::new(manifest),
+        };
+        writer.save()?;
+        Ok(writer)
+    }
+
+    pub fn record_mic_switch(&self, device_id: &str, frame: u64) {
+        if let Ok(mut manifest) = self.manifest.lock() {
+            manifest.mic_switches.push(MicSwitch {
+                device_id: device_id.to_string(),
+                frame,
+                elapsed: self.started.elapsed().as_secs_f64(),
+            });
+        }
+        if let Err(e) = self.save() {
+            log!("{}", e);
+        }
+    }
+
+    /// Record a format mismatch; false if the same one was recorded before
+    pub fn record_config_mismatch(&self, mismatch: &ConfigMismatch) -> bool {
+        if let Ok(mut manifest) = self.manifest.lock() {
+            if manifest.config_mismatches.contains(mismatch) {
+                return false;
+            }
+            manifest.config_mismatches.push(mismatch.clone());
+        }
+        if let Err(e) = self.save() {
+            log!("{}", e);
+        }
+        true
+    }
+
+    pub fn record_redaction(&self, redaction: Redaction) {
+        if let Ok(mut manifest) = self.manifest.lock() {
+            manifest.redactions.push(redaction);
+        }
+        if let Err(e) = self.save() {
+            log!("{}", e);
+        }
+    }
+
+    /// Ranges struck so far, including by earlier runs of a resumed session
+    pub fn redactions(&self) -> Vec<Redaction> {
+        self.manifest
+            .lock()
+            .map(|m| m.redactions.clone())
+            .unwrap_or_default()
+    }
+
+    /// Record the checksums of files that are final, replacing any recorded
+    /// before (e.g. when post-processing or a redaction rewrote them)
+    pub fn record_checksums(&self, paths: &[PathBuf]) {
+        self.update_checksums(paths, true);
+    }
+
+    /// Record the checksums of files that have none yet
+    pub fn record_missing_checksums(&self, paths: &[PathBuf]) {
+        self.update_checksums(paths, false);
+    }
+
+    fn update_checksums(&self, paths: &[PathBuf], replace: bool) {
+        let Some(dir) = self.dir() else {
+            return;
+        };
+        let mut changed = false;
+        for path in paths {
+            let name = relative_name(dir, path);
+            let known = self
+                .manifest
+                .lock()
+                .is_ok_and(|m| m.checksums.contains_key(&name));
+            if (known && !replace) || !path.exists() {
+                continue;
+            }
+            // Hashed without the lock, so other updates don't wait on it
+            match sha256_file(path) {
+                Ok(checksum) => {
+                    if let Ok(mut manifest) = self.manifest.lock() {
+                        manifest.checksums.insert(name, checksum);
+                        changed = true;
+                    }
+                }
+                Err(e) => log!("Failed to checksum {:?}: {}", path, e),
+            }
+        }
+        if changed {
+            if let Err(e) = self.save() {
+                log!("{}", e);
+            }
+        }
+    }
+
+    /// Silence trimmed from files so far, by path
+    pub fn trims(&self) -> HashMap<PathBuf, TrimmedSilence> {
+        let (Some(dir), Ok(manifest)) = (self.dir(), self.manifest.lock()) else {
+            return HashMap::new();
+        };
+        manifest
+            .trimmed
+            .iter()
+            .map(|(name, trimmed)| (dir.join(name), *trimmed))
+            .collect()
+    }
+
+    pub fn record_trim(&self, path: &Path, trimmed: TrimmedSilence) {
+        let Some(dir) = self.dir() else {
+            return;
+        };
+        if let Ok(mut manifest) = self.manifest.lock() {
+            manifest.trimmed.insert(relative_name(dir, path), trimmed);
+        }
+        if let Err(e) = self.save() {
+            log!("{}", e);
+        }
+    }
+
+    /// Directory the manifest's file names are relative to; None for an
+    /// analysis-only session
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_deref().and_then(Path::parent)
+    }
+
+    /// Files with a checksum that haven't been uploaded as they are now, as
+    /// (name, checksum)
+    pub fn pending_uploads(&self) -> Vec<(String, String)> {
+        let Ok(manifest) = self.manifest.lock() else {
+            return Vec::new();
+        };
+        manifest
+            .checksums
+            .iter()
+            .filter(|(name, checksum)| manifest.uploaded.get(*name) != Some(*checksum))
+            .map(|(name, checksum)| (name.clone(), checksum.clone()))
+            .collect()
+    }
+
+    pub fn record_upload(&self, name: &str, checksum: &str) {
+        if let Ok(mut manifest) = self.manifest.lock() {
+            manifest
+                .uploaded
+                .insert(name.to_string(), checksum.to_string());
+        }
+        if let Err(e) = self.save() {
+            log!("{}", e);
+        }
+    }
+
+    /// Write the manifest via a temp file so readers never see a partial file
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = {
+            let manifest = self
+                .manifest
+                .lock()
+                .map_err(|_| "Manifest mutex poisoned".to_string())?;
+            serde_json::to_string_pretty(&*manifest)
+                .map_err(|e| format!("Failed to serialize manifest: {}", e))?
+        };
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Failed to write manifest {:?}: {}", path, e))
+    }
+}
@@ -0,0 +1,120 @@
+//! The session's memory budget, shared by the buffers that grow while it
+//! records: the level history, queued Opus packets and audio waiting for the
+//! encoder
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::capture::session::InternalAudioEvent;
+
+/// Share of the limit usage has to fall below before another refusal is
+/// reported
+const REARM_PERCENT: usize = 90;
+
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    /// Bytes; None for no limit
+    limit: Option<usize>,
+    used: AtomicUsize,
+    /// A refusal was reported and usage hasn't fallen back since
+    reached: AtomicBool,
+    event_tx: Option<Mutex<Sender<InternalAudioEvent>>>,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes that reports the first refusal to `event_tx`
+    pub fn new(limit: Option<usize>, event_tx: Sender<InternalAudioEvent>) -> Self {
+        Self {
+            limit,
+            event_tx: Some(Mutex::new(event_tx)),
+            ..Self::default()
+        }
+    }
+
+    /// Account for `bytes` more, or refuse if that would exceed the limit.
+    /// `action` says what the refused buffer does instead, for the event.
+    pub fn try_reserve(&self, bytes: usize, action: &'static str) -> bool {
+        let Some(limit) = self.limit else {
+            self.used.fetch_add(bytes, Ordering::Relaxed);
+            return true;
+        };
+        let reserved = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used + bytes <= limit).then_some(used + bytes)
+            })
+            .is_ok();
+        if !reserved && !self.reached.swap(true, Ordering::Relaxed) {
+            log!("Memory limit of {} bytes reached, {}", limit, action);
+            if let Some(tx) = self.event_tx.as_ref().and_then(|tx| tx.lock().ok()) {
+                let _ = tx.send(InternalAudioEvent::MemoryLimitReached { limit, action });
+            }
+        }
+        reserved
+    }
+
+    /// Like `try_reserve()`, with a guard that releases the bytes when dropped
+    pub fn reserve(self: &Arc<Self>, bytes: usize, action: &'static str) -> Option<Reservation> {
+        self.try_reserve(bytes, action).then(|| Reservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    pub fn release(&self, bytes: usize) {
+        let used = self
+            .used
+            .fetch_sub(bytes, Ordering::Relaxed)
+            .saturating_sub(bytes);
+        if let Some(limit) = self.limit {
+            if used < limit / 100 * REARM_PERCENT {
+                self.reached.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Bytes reserved from a budget until dropped
+pub struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_refusals_are_reported_once_until_usage_falls() {
+        let (tx, rx) = channel();
+        let budget = Arc::new(MemoryBudget::new(Some(1000), tx));
+        assert!(budget.try_reserve(600, "dropping"));
+        let held = budget.reserve(350, "dropping").unwrap();
+        assert!(!budget.try_reserve(100, "dropping"));
+        assert!(!budget.try_reserve(100, "dropping"));
+        assert_eq!(rx.try_iter().count(), 1);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 950);
+
+        // Still above 90% of the limit, so no new report yet
+        budget.release(40);
+        assert!(!budget.try_reserve(100, "dropping"));
+        assert_eq!(rx.try_iter().count(), 0);
+
+        drop(held);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 560);
+        assert!(budget.try_reserve(400, "dropping"));
+        assert!(!budget.try_reserve(100, "dropping"));
+        assert_eq!(rx.try_iter().count(), 1);
+
+        let unlimited = MemoryBudget::default();
+        assert!(unlimited.try_reserve(usize::MAX / 2, "dropping"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Capture metrics for monitoring recorders: counters kept for the whole
+//! process and, with the `metrics` feature, rendered in the Prometheus text
+//! format by `collect_metrics()` or served over HTTP by `serve_metrics()`
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A count that only goes up, for the life of the process
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub static SESSIONS_STARTED: Counter = Counter::new();
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub static DROPOUTS: Counter = Counter::new();
+/// Audio lost to dropouts, in microseconds
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub static DROPOUT_MICROS: Counter = Counter::new();
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub static RECONNECTS: Counter = Counter::new();
+
+#[cfg(feature = "metrics")]
+pub use exporter::{collect_metrics, serve_metrics};
+
+#[cfg(feature = "metrics")]
+mod exporter {
+    use std::fmt::Write as _;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::capture::registry::SessionEntry;
+    use crate::capture::session::RecordingSession;
+
+    /// A session as the gauges see it
+    pub(super) struct SessionSample {
+        pub id: u64,
+        pub state: &'static str,
+        /// Audio chunks waiting for the session's encoding workers
+        pub backlog: Option<usize>,
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn collect_metrics() -> String {
+        let sessions: Vec<SessionSample> = SessionEntry::all()
+            .iter()
+            .map(|entry| SessionSample {
+                id: entry.id,
+                state: entry.state(),
+                backlog: SessionEntry::attach(entry.id)
+                    .map(|state| RecordingSession::attached(state).encoder_backlog()),
+            })
+            .collect();
+        render(&sessions)
+    }
+
+    pub(super) fn render(sessions: &[SessionSample]) -> String {
+        let mut out = String::new();
+        let states = ["recording", "paused", "stopping"].map(|state| {
+            let count = sessions.iter().filter(|s| s.state == state).count();
+            (format!("{{state=\"{}\"}}", state), count as f64)
+        });
+        family(
+            &mut out,
+            "quinoa_active_sessions",
+            "gauge",
+            "Sessions of this process that are running, by state",
+            &states,
+        );
+        let total = |counter: &Counter| [(String::new(), counter.get() as f64)];
+        family(
+            &mut out,
+            "quinoa_sessions_started_total",
+            "counter",
+            "Sessions started by this process",
+            &total(&SESSIONS_STARTED),
+        );
+        family(
+            &mut out,
+            "quinoa_dropouts_total",
+            "counter",
+            "Gaps in the audio a stream delivered, filled with silence",
+            &total(&DROPOUTS),
+        );
+        family(
+            &mut out,
+            "quinoa_dropout_seconds_total",
+            "counter",
+            "Audio lost to dropouts",
+            &[(String::new(), DROPOUT_MICROS.get() as f64 / 1e6)],
+        );
+        family(
+            &mut out,
+            "quinoa_reconnects_total",
+            "counter",
+            "Attempts at reconnecting to PipeWire",
+            &total(&RECONNECTS),
+        );
+        let backlog: Vec<(String, f64)> = sessions
+            .iter()
+            .filter_map(|s| {
+                let backlog = s.backlog? as f64;
+                Some((format!("{{session_id=\"{}\"}}", s.id), backlog))
+            })
+            .collect();
+        family(
+            &mut out,
+            "quinoa_encoder_backlog",
+            "gauge",
+            "Audio chunks waiting for a session's encoding workers",
+            &backlog,
+        );
+        out
+    }
+
+    fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    }
+
+    /// Serve the metrics at `/metrics` from a thread of its own until the
+    /// process exits. Returns the port, which the system picks for port 0.
+    pub fn serve_metrics(host: &str, port: u16) -> Result<u16, String> {
+        let listener = TcpListener::bind((host, port))
+            .map_err(|e| format!("Failed to listen on {}:{}: {}", host, port, e))?;
+        let port = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to listen on {}:{}: {}", host, port, e))?
+            .port();
+        thread::Builder::new()
+            .name("metrics-http".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    // A thread per connection, so a client that is slow to
+                    // send its request holds up no other scrape
+                    let spawned = thread::Builder::new()
+                        .name("metrics-client".to_string())
+                        .spawn(move || {
+                            if let Err(e) = respond(stream) {
+                                log!("Metrics request failed: {}", e);
+                            }
+                        });
+                    if let Err(e) = spawned {
+                        log!("Failed to answer a metrics request: {}", e);
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to start the metrics server: {}", e))?;
+        Ok(port)
+    }
+
+    fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Read the headers, unused, so closing doesn't reset the connection
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+            header.clear();
+        }
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        let (status, body) = match path.split('?').next() {
+            Some("/metrics") => ("200 OK", collect_metrics()),
+            _ => ("404 Not Found", "Not found\n".to_string()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::exporter::{render, SessionSample};
+    use super::*;
+
+    #[test]
+    fn test_render_exposition_format() {
+        RECONNECTS.add(2);
+        let text = render(&[
+            SessionSample {
+                id: 3,
+                state: "recording",
+                backlog: Some(4),
+            },
+            SessionSample {
+                id: 5,
+                state: "paused",
+                backlog: None,
+            },
+        ]);
+        assert!(text.contains("# TYPE quinoa_active_sessions gauge\n"));
+        assert!(text.contains("quinoa_active_sessions{state=\"recording\"} 1\n"));
+        assert!(text.contains("quinoa_active_sessions{state=\"stopping\"} 0\n"));
+        assert!(text.contains("quinoa_encoder_backlog{session_id=\"3\"} 4\n"));
+        assert!(!text.contains("session_id=\"5\""));
+        // Other tests may count too
+        let reconnects = text
+            .lines()
+            .find_map(|line| line.strip_prefix("quinoa_reconnects_total "))
+            .unwrap();
+        assert!(reconnects.parse::<u64>().unwrap() >= 2);
+    }
+}
@@ -0,0 +1,306 @@
+//! The "meeting playback" file: a session's mic and system recordings mixed
+//! into one stereo file, each brought to the same loudness first
+
+use hound::{WavIntoSamples, WavReader, WavSpec, WavWriter};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::capture::ducking::{Ducker, DuckingOptions};
+use crate::capture::loudness::LoudnessMeter;
+use crate::capture::opus::OpusOptions;
+use crate::capture::resample::Resampler;
+
+/// Accepted `export_mixdown()` formats; all but "wav" need ffmpeg
+pub const MIXDOWN_FORMATS: &[&str] = &["wav", "flac", "mp3", "opus"];
+/// Highest peak the mix may reach (-1 dBFS)
+const PEAK_CEILING: f64 = 0.891;
+/// Frames read from each track at a time
+const BLOCK_FRAMES: usize = 4096;
+
+/// The recordings of a session to mix
+pub struct Mixdown {
+    /// The mic's files, one entry per segment (several files when its
+    /// channels were split)
+    pub mic: Vec<Vec<PathBuf>>,
+    pub system: Vec<Vec<PathBuf>>,
+    /// Rate of the mix; segments at other rates are resampled
+    pub sample_rate: u32,
+    /// Lower the system audio under the mic
+    pub ducking: Option<DuckingOptions>,
+}
+
+impl Mixdown {
+    /// Write the mix to `path` in `format`, one of `MIXDOWN_FORMATS`
+    pub fn export(&self, path: &Path, format: &str, opus: &OpusOptions) -> Result<(), String> {
+        if self.mic.is_empty() && self.system.is_empty() {
+            return Err("No recordings to mix".to_string());
+        }
+        let (mic_gain, system_gain) = mix_gains(
+            &self.measure(&self.mic, 1)?,
+            &self.measure(&self.system, 2)?,
+        );
+
+        let tmp = path.with_extension("mixdown.tmp");
+        self.write_wav(&tmp, mic_gain, system_gain)?;
+        let result = match format {
+            "wav" => std::fs::rename(&tmp, path)
+                .map_err(|e| format!("Failed to write {:?}: {}", path, e)),
+            _ => transcode(&tmp, path, format, opus),
+        };
+        let _ = std::fs::remove_file(&tmp);
+        result
+    }
+
+    fn measure(&self, segments: &[Vec<PathBuf>], channels: usize) -> Result<LoudnessMeter, String> {
+        let mut meter = LoudnessMeter::new(self.sample_rate, channels as u16);
+        let mut track = Track::new(segments, self.sample_rate, channels);
+        while let Some(block) = track.next_block()? {
+            let samples: Vec<i16> = block.iter().map(|&s| to_i16(s)).collect();
+            meter.add(&samples);
+        }
+        Ok(meter)
+    }
+
+    fn write_wav(&self, path: &Path, mic_gain: f32, system_gain: f32) -> Result<(), String> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let write_error = |e: hound::Error| format!("Failed to write {:?}: {}", path, e);
+        let mut writer = WavWriter::create(path, spec).map_err(write_error)?;
+        let mut ducker = self
+            .ducking
+            .map(|options| Ducker::new(options, self.sample_rate));
+        let mut mic = Buffered::new(Track::new(&self.mic, self.sample_rate, 1));
+        let mut system = Buffered::new(Track::new(&self.system, self.sample_rate, 2));
+        loop {
+            mic.fill()?;
+            system.fill()?;
+            let frames = match (mic.done, system.done) {
+                (true, true) => mic.frames().max(system.frames()),
+                (true, false) => system.frames(),
+                (false, true) => mic.frames(),
+                (false, false) => mic.frames().min(system.frames()),
+            };
+            if frames == 0 {
+                break;
+            }
+            for _ in 0..frames {
+                let voice = mic.pop() * mic_gain;
+                let duck = ducker.as_mut().map_or(1.0, |d| d.gain(voice.abs()));
+                for _ in 0..2 {
+                    let sample = system.pop() * system_gain * duck + voice;
+                    writer.write_sample(to_i16(sample)).map_err(write_error)?;
+                }
+            }
+        }
+        writer.finalize().map_err(write_error)
+    }
+}
+
+/// Gains bringing each track to the same loudness, lowered together if the
+/// mix could then clip
+fn mix_gains(mic: &LoudnessMeter, system: &LoudnessMeter) -> (f32, f32) {
+    let gain = |meter: &LoudnessMeter| meter.track_gain().map_or(1.0, |db| 10f64.powf(db / 20.0));
+    let (mic_gain, system_gain) = (gain(mic), gain(system));
+    let peak = mic.peak() * mic_gain + system.peak() * system_gain;
+    let scale = if peak > PEAK_CEILING {
+        PEAK_CEILING / peak
+    } else {
+        1.0
+    };
+    ((mic_gain * scale) as f32, (system_gain * scale) as f32)
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample * 32768.0)
+        .round()
+        .clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16
+}
+
+/// A stream's segments read back as one track with `channels` channels at
+/// `sample_rate`
+struct Track {
+    segments: VecDeque<Vec<PathBuf>>,
+    /// The files of the segment being read and their channel counts
+    readers: Vec<(WavIntoSamples<BufReader<File>, i16>, usize)>,
+    resampler: Option<Resampler>,
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl Track {
+    fn new(segments: &[Vec<PathBuf>], sample_rate: u32, channels: usize) -> Self {
+        Self {
+            segments: segments.iter().cloned().collect(),
+            readers: Vec::new(),
+            resampler: None,
+            sample_rate,
+            channels,
+        }
+    }
+
+    /// Open the next segment; false once there are none left
+    fn open_segment(&mut self) -> Result<bool, String> {
+        let Some(files) = self.segments.pop_front() else {
+            return Ok(false);
+        };
+        let mut rate = self.sample_rate;
+        for file in files {
+            let reader =
+                WavReader::open(&file).map_err(|e| format!("Failed to read {:?}: {}", file, e))?;
+            let spec = reader.spec();
+            rate = spec.sample_rate;
+            self.readers
+                .push((reader.into_samples(), usize::from(spec.channels)));
+        }
+        self.resampler = (rate != self.sample_rate)
+            .then(|| Resampler::new(rate, self.sample_rate, self.channels));
+        Ok(true)
+    }
+
+    /// Up to `BLOCK_FRAMES` frames of interleaved audio; None at the end
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, String> {
+        loop {
+            if self.readers.is_empty() && !self.open_segment()? {
+                return Ok(None);
+            }
+            let mut block = Vec::with_capacity(BLOCK_FRAMES * self.channels);
+            let mut frame = Vec::new();
+            'frames: for _ in 0..BLOCK_FRAMES {
+                frame.clear();
+                for (samples, channels) in &mut self.readers {
+                    for _ in 0..*channels {
+                        match samples.next() {
+                            Some(sample) => {
+                                let sample = sample.map_err(|e| e.to_string())?;
+                                frame.push(f32::from(sample) / 32768.0);
+                            }
+                            None => break 'frames,
+                        }
+                    }
+                }
+                remix(&frame, self.channels, &mut block);
+            }
+            if block.is_empty() {
+                // This segment is done
+                self.readers.clear();
+                continue;
+            }
+            let block = match &mut self.resampler {
+                Some(resampler) => resampler.process(&block),
+                None => block,
+            };
+            if !block.is_empty() {
+                return Ok(Some(block));
+            }
+        }
+    }
+}
+
+/// Append `frame` as `channels` channels: averaged to mono, or the first two
+/// channels (a mono frame twice) for stereo
+fn remix(frame: &[f32], channels: usize, out: &mut Vec<f32>) {
+    match (channels, frame) {
+        (_, []) => {}
+        (1, _) => out.push(frame.iter().sum::<f32>() / frame.len() as f32),
+        (_, [mono]) => out.extend([*mono, *mono]),
+        _ => out.extend_from_slice(&frame[..2]),
+    }
+}
+
+/// A track read ahead a block at a time, giving silence once it has ended
+struct Buffered {
+    track: Track,
+    samples: VecDeque<f32>,
+    done: bool,
+}
+
+impl Buffered {
+    fn new(track: Track) -> Self {
+        Self {
+            track,
+            samples: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Read ahead until a block's worth is buffered or the track has ended
+    fn fill(&mut self) -> Result<(), String> {
+        while !self.done && self.frames() < BLOCK_FRAMES {
+            match self.track.next_block()? {
+                Some(block) => self.samples.extend(block),
+                None => self.done = true,
+            }
+        }
+        Ok(())
+    }
+
+    fn frames(&self) -> usize {
+        self.samples.len() / self.track.channels
+    }
+
+    fn pop(&mut self) -> f32 {
+        self.samples.pop_front().unwrap_or(0.0)
+    }
+}
+
+/// Encode the mix at `source` to `path` with ffmpeg
+fn transcode(source: &Path, path: &Path, format: &str, opus: &OpusOptions) -> Result<(), String> {
+    let codec: Vec<String> = match format {
+        "flac" => vec!["-c:a".to_string(), "flac".to_string()],
+        "mp3" => ["-c:a", "libmp3lame", "-q:a", "2"]
+            .map(String::from)
+            .to_vec(),
+        _ => opus.ffmpeg_args(),
+    };
+    let result = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(source)
+        .args(codec)
+        .args(["-f", format])
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if !result.status.success() {
+        return Err(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracks_are_matched_in_loudness_without_clipping() {
+        let tone = |amplitude: f64, channels: u16| {
+            let mut meter = LoudnessMeter::new(48000, channels);
+            let samples: Vec<i16> = (0..48000 * 2)
+                .flat_map(|n| {
+                    let value =
+                        amplitude * (n as f64 * 1000.0 * std::f64::consts::TAU / 48000.0).sin();
+                    vec![(value * 32767.0) as i16; usize::from(channels)]
+                })
+                .collect();
+            meter.add(&samples);
+            meter
+        };
+        // A quiet voice and loud system audio meet in the middle
+        let (mic, system) = mix_gains(&tone(0.05, 1), &tone(0.5, 2));
+        assert!(mic > 1.0 && system < 1.0, "{} {}", mic, system);
+        assert!(f64::from(mic) * 0.05 + f64::from(system) * 0.5 <= PEAK_CEILING + 1e-3);
+
+        // Silence is left alone
+        let silent = LoudnessMeter::new(48000, 1);
+        assert_eq!(mix_gains(&silent, &silent), (1.0, 1.0));
+    }
+}
@@ -0,0 +1,556 @@
+use hound::WavSpec;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::capture::encoder::{segment_path, EncoderBackend, EncoderFactory};
+use crate::capture::session::stream_name;
+
+/// File name of the Matroska archive inside the output directory
+pub const MKA_FILE: &str = "session.mka";
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const NAME: u32 = 0x536E;
+const CODEC_ID: u32 = 0x86;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const BIT_DEPTH: u32 = 0x6264;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Every size is written as an 8-byte vint so it can be patched in place
+const SIZE_LENGTH: u64 = 8;
+/// Size of a Segment still being written, so players can follow it live
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+/// Longest block and cluster, in milliseconds (timestamps are in ms)
+const BLOCK_MS: u64 = 100;
+const CLUSTER_MS: i64 = 1000;
+/// Audio buffered while waiting for every stream to open before the
+/// track list is written without the missing ones
+const HEADER_WAIT_MS: u64 = 2000;
+
+fn element_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn element_size(out: &mut Vec<u8>, size: u64) {
+    out.push(0x01);
+    out.extend_from_slice(&size.to_be_bytes()[1..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+    element_id(out, id);
+    element_size(out, body.len() as u64);
+    out.extend_from_slice(body);
+}
+
+fn uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(out, id, &bytes[skip..]);
+}
+
+fn float(out: &mut Vec<u8>, id: u32, value: f64) {
+    element(out, id, &value.to_be_bytes());
+}
+
+fn string(out: &mut Vec<u8>, id: u32, value: &str) {
+    element(out, id, value.as_bytes());
+}
+
+/// One stream's track. Its position carries over format changes and
+/// reconnects, so both tracks stay on the session's timeline.
+#[derive(Debug)]
+struct Track {
+    spec: WavSpec,
+    open: bool,
+    /// Number in the current file, once its track list is written
+    number: Option<u8>,
+    /// Session time of the start of the current spec, in ms
+    base_ms: u64,
+    /// Frames written since `base_ms`
+    frames: u64,
+}
+
+impl Track {
+    fn position_ms(&self) -> u64 {
+        self.base_ms + self.frames * 1000 / u64::from(self.spec.sample_rate.max(1))
+    }
+}
+
+#[derive(Debug)]
+struct Block {
+    stream: usize,
+    timestamp_ms: u64,
+    data: Vec<u8>,
+}
+
+/// The file currently being written
+#[derive(Debug)]
+struct MkaFile {
+    file: BufWriter<File>,
+    /// Offset of the first byte inside the Segment element
+    segment_start: u64,
+    /// Offset of the Duration value in the Info element
+    duration_at: u64,
+    cluster: Vec<u8>,
+    cluster_ms: Option<u64>,
+    /// The Segment size is set, and must be cleared before it grows again
+    sized: bool,
+}
+
+#[derive(Debug)]
+struct MkaState {
+    path: PathBuf,
+    started: Instant,
+    /// Streams the session records, indexed like `tracks` (mic, system)
+    expected: [bool; 2],
+    tracks: [Option<Track>; 2],
+    file: Option<MkaFile>,
+    /// Blocks waiting for the track list to be written
+    pending: Vec<Block>,
+    /// 1 for `session.mka`, 2 for `session_part2.mka`, ...
+    part: u32,
+}
+
+impl MkaState {
+    fn open_track(&mut self, stream: usize, spec: WavSpec) -> Result<(), String> {
+        let now_ms = self.started.elapsed().as_millis() as u64;
+        let restart = match self.tracks[stream].as_mut() {
+            Some(track) => {
+                let changed = track.spec != spec;
+                if changed {
+                    track.base_ms = track.position_ms();
+                    track.frames = 0;
+                    track.spec = spec;
+                }
+                track.open = true;
+                changed
+            }
+            None => {
+                self.tracks[stream] = Some(Track {
+                    spec,
+                    open: true,
+                    number: None,
+                    base_ms: now_ms,
+                    frames: 0,
+                });
+                true
+            }
+        };
+        // A file's track list can't change once written, so continue in a new part
+        if self.file.is_some() {
+            if restart {
+                self.finish_file()?;
+                self.part += 1;
+                self.start_file()?;
+            }
+        } else if self.expected_open() {
+            self.start_file()?;
+        }
+        Ok(())
+    }
+
+    fn expected_open(&self) -> bool {
+        self.expected
+            .iter()
+            .zip(&self.tracks)
+            .all(|(expected, track)| !expected || track.is_some())
+    }
+
+    fn write(&mut self, stream: usize, samples: &[i16]) -> Result<(), String> {
+        let Some(track) = self.tracks[stream].as_mut() else {
+            return Err("Matroska track written before it was opened".to_string());
+        };
+        let channels = usize::from(track.spec.channels.max(1));
+        let rate = u64::from(track.spec.sample_rate.max(1));
+        let block_frames = (rate * BLOCK_MS / 1000).max(1) as usize;
+        let mut blocks = Vec::new();
+        for chunk in samples.chunks(block_frames * channels) {
+            blocks.push(Block {
+                stream,
+                timestamp_ms: track.position_ms(),
+                data: chunk.iter().flat_map(|s| s.to_le_bytes()).collect(),
+            });
+            track.frames += (chunk.len() / channels) as u64;
+        }
+
+        if self.file.is_none() {
+            self.pending.extend(blocks);
+            let span = match (self.pending.first(), self.pending.last()) {
+                (Some(first), Some(last)) => last.timestamp_ms.saturating_sub(first.timestamp_ms),
+                _ => 0,
+            };
+            if span >= HEADER_WAIT_MS {
+                self.start_file()?;
+            }
+            return Ok(());
+        }
+        blocks
+            .into_iter()
+            .try_for_each(|block| self.write_block(block))
+    }
+
+    fn close_track(&mut self, stream: usize) -> Result<(), String> {
+        if let Some(track) = self.tracks[stream].as_mut() {
+            track.open = false;
+        }
+        if self.tracks.iter().flatten().any(|track| track.open) {
+            return Ok(());
+        }
+        // Everything is closed (stopped or reconnecting): leave a complete file
+        // behind, which later writes simply extend
+        if self.file.is_none() {
+            self.start_file()?;
+        }
+        self.patch_file()
+    }
+
+    fn start_file(&mut self) -> Result<(), String> {
+        let path = segment_path(&self.path, self.part);
+        let file =
+            File::create(&path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+
+        let mut header = Vec::new();
+        let mut ebml = Vec::new();
+        uint(&mut ebml, EBML_VERSION, 1);
+        uint(&mut ebml, EBML_READ_VERSION, 1);
+        uint(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        uint(&mut ebml, EBML_MAX_SIZE_LENGTH, SIZE_LENGTH);
+        string(&mut ebml, DOC_TYPE, "matroska");
+        uint(&mut ebml, DOC_TYPE_VERSION, 4);
+        uint(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        element(&mut header, EBML, &ebml);
+
+        // Segment size is patched whenever the file is completed
+        element_id(&mut header, SEGMENT);
+        header.extend_from_slice(&UNKNOWN_SIZE);
+        let segment_start = header.len() as u64;
+
+        let mut info = Vec::new();
+        uint(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        string(&mut info, MUXING_APP, "quinoa_audio");
+        string(&mut info, WRITING_APP, "quinoa_audio");
+        let duration_in_info = info.len() as u64;
+        float(&mut info, DURATION, 0.0);
+        element_id(&mut header, INFO);
+        element_size(&mut header, info.len() as u64);
+        // ID (2 bytes) and size in front of the value
+        let duration_at = header.len() as u64 + duration_in_info + 2 + SIZE_LENGTH;
+        header.extend_from_slice(&info);
+
+        let mut tracks = Vec::new();
+        let mut number = 0u8;
+        for (stream, track) in self.tracks.iter_mut().enumerate() {
+            let Some(track) = track else {
+                continue;
+            };
+            number += 1;
+            track.number = Some(number);
+            let mut audio = Vec::new();
+            float(
+                &mut audio,
+                SAMPLING_FREQUENCY,
+                f64::from(track.spec.sample_rate),
+            );
+            uint(&mut audio, CHANNELS, u64::from(track.spec.channels));
+            uint(&mut audio, BIT_DEPTH, u64::from(track.spec.bits_per_sample));
+            let mut entry = Vec::new();
+            uint(&mut entry, TRACK_NUMBER, u64::from(number));
+            uint(&mut entry, TRACK_UID, u64::from(number));
+            uint(&mut entry, TRACK_TYPE, 2);
+            uint(&mut entry, FLAG_LACING, 0);
+            string(&mut entry, NAME, stream_name(stream == 0));
+            string(&mut entry, CODEC_ID, "A_PCM/INT/LIT");
+            element(&mut entry, AUDIO, &audio);
+            element(&mut tracks, TRACK_ENTRY, &entry);
+        }
+        element(&mut header, TRACKS, &tracks);
+
+        let mut file = BufWriter::new(file);
+        file.write_all(&header)
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+        self.file = Some(MkaFile {
+            file,
+            segment_start,
+            duration_at,
+            cluster: Vec::new(),
+            cluster_ms: None,
+            sized: false,
+        });
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .try_for_each(|block| self.write_block(block))
+    }
+
+    fn write_block(&mut self, block: Block) -> Result<(), String> {
+        let Some(number) = self.tracks[block.stream].as_ref().and_then(|t| t.number) else {
+            // Opened after this file's track list was written; it is in the next part
+            return Ok(());
+        };
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let relative = file
+            .cluster_ms
+            .map(|start| block.timestamp_ms as i64 - start as i64);
+        if !relative.is_some_and(|r| (-CLUSTER_MS..CLUSTER_MS).contains(&r)) {
+            file.flush_cluster()?;
+            file.cluster_ms = Some(block.timestamp_ms);
+        }
+        let relative = block.timestamp_ms as i64 - file.cluster_ms.unwrap_or(0) as i64;
+
+        let mut body = Vec::with_capacity(block.data.len() + 4);
+        body.push(0x80 | number);
+        body.extend_from_slice(&(relative as i16).to_be_bytes());
+        // Keyframe: every PCM block can be decoded on its own
+        body.push(0x80);
+        body.extend_from_slice(&block.data);
+        element(&mut file.cluster, SIMPLE_BLOCK, &body);
+        Ok(())
+    }
+
+    fn duration_ms(&self) -> u64 {
+        self.tracks
+            .iter()
+            .flatten()
+            .map(Track::position_ms)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Flush and fix up the Segment size and Duration so the file is playable
+    fn patch_file(&mut self) -> Result<(), String> {
+        let duration = self.duration_ms() as f64;
+        let path = segment_path(&self.path, self.part);
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        file.flush_cluster()?;
+        file.cluster_ms = None;
+        let err = |e: std::io::Error| format!("Failed to update {:?}: {}", path, e);
+        let out = &mut file.file;
+        let end = out.seek(SeekFrom::End(0)).map_err(err)?;
+        let mut size = Vec::new();
+        element_size(&mut size, end - file.segment_start);
+        out.seek(SeekFrom::Start(file.segment_start - SIZE_LENGTH))
+            .map_err(err)?;
+        out.write_all(&size).map_err(err)?;
+        out.seek(SeekFrom::Start(file.duration_at)).map_err(err)?;
+        out.write_all(&duration.to_be_bytes()).map_err(err)?;
+        out.seek(SeekFrom::End(0)).map_err(err)?;
+        file.sized = true;
+        out.flush().map_err(err)
+    }
+
+    fn finish_file(&mut self) -> Result<(), String> {
+        let result = self.patch_file();
+        self.file = None;
+        for track in self.tracks.iter_mut().flatten() {
+            track.number = None;
+        }
+        result
+    }
+}
+
+impl MkaFile {
+    fn flush_cluster(&mut self) -> Result<(), String> {
+        let Some(start) = self.cluster_ms else {
+            return Ok(());
+        };
+        let err = |e: std::io::Error| format!("Failed to write Matroska cluster: {}", e);
+        if self.sized {
+            self.file
+                .seek(SeekFrom::Start(self.segment_start - SIZE_LENGTH))
+                .and_then(|_| self.file.write_all(&UNKNOWN_SIZE))
+                .and_then(|_| self.file.seek(SeekFrom::End(0)))
+                .map_err(err)?;
+            self.sized = false;
+        }
+        let mut cluster = Vec::new();
+        uint(&mut cluster, TIMESTAMP, start);
+        cluster.append(&mut self.cluster);
+        let mut out = Vec::with_capacity(cluster.len() + 12);
+        element(&mut out, CLUSTER, &cluster);
+        self.file.write_all(&out).map_err(err)
+    }
+}
+
+/// Writes the session's streams as PCM tracks of one Matroska file,
+/// `session.mka` in the output directory, with each track placed on the
+/// session timeline.
+///
+/// Matroska can't add or change tracks after its track list, so when a
+/// stream renegotiates its format (or opens more than two seconds after the
+/// others) the archive continues in `session_part2.mka`, and so on. The file
+/// is completed whenever every stream is closed, so it stays playable across
+/// reconnects and after a crash loses at most the audio since the last one.
+#[derive(Clone, Debug)]
+pub struct MkaWriter {
+    state: Arc<Mutex<MkaState>>,
+}
+
+impl MkaWriter {
+    /// A resumed session continues in the first part that doesn't exist yet
+    pub fn new(path: &Path, mic: bool, system: bool) -> Self {
+        let path = path.to_path_buf();
+        let mut part = 1;
+        while segment_path(&path, part).exists() {
+            part += 1;
+        }
+        Self {
+            state: Arc::new(Mutex::new(MkaState {
+                path,
+                started: Instant::now(),
+                expected: [mic, system],
+                tracks: [None, None],
+                file: None,
+                pending: Vec::new(),
+                part,
+            })),
+        }
+    }
+
+    /// Mirror feeding one stream's track
+    pub fn track(&self, is_mic: bool) -> Arc<dyn EncoderFactory> {
+        Arc::new(MkaTrack {
+            writer: self.clone(),
+            stream: if is_mic { 0 } else { 1 },
+        })
+    }
+
+    fn with_state<T>(
+        &self,
+        op: impl FnOnce(&mut MkaState) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "Matroska writer mutex poisoned".to_string())?;
+        op(&mut state)
+    }
+}
+
+#[derive(Debug)]
+struct MkaTrack {
+    writer: MkaWriter,
+    stream: usize,
+}
+
+impl EncoderFactory for MkaTrack {
+    fn open(&self, spec: WavSpec, _path: &Path) -> Result<Box<dyn EncoderBackend>, String> {
+        self.writer
+            .with_state(|state| state.open_track(self.stream, spec))?;
+        Ok(Box::new(MkaBackend {
+            writer: self.writer.clone(),
+            stream: self.stream,
+        }))
+    }
+}
+
+struct MkaBackend {
+    writer: MkaWriter,
+    stream: usize,
+}
+
+impl EncoderBackend for MkaBackend {
+    fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        self.writer
+            .with_state(|state| state.write(self.stream, samples))
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), String> {
+        self.writer
+            .with_state(|state| state.close_track(self.stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn spec(sample_rate: u32, channels: u16) -> WavSpec {
+        WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        }
+    }
+
+    fn count(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .filter(|w| *w == needle)
+            .count()
+    }
+
+    #[test]
+    fn test_tracks_share_one_file_until_a_format_change() {
+        let dir = TempDir::new("mka");
+        let writer = MkaWriter::new(&dir.join(MKA_FILE), true, true);
+        let mic = writer.track(true);
+        let system = writer.track(false);
+
+        let mut mic_out = mic.open(spec(48000, 1), &dir).unwrap();
+        let mut system_out = system.open(spec(48000, 2), &dir).unwrap();
+        for _ in 0..30 {
+            mic_out.write(&[100; 4800]).unwrap();
+            system_out.write(&[-100; 9600]).unwrap();
+        }
+        mic_out.finalize().unwrap();
+        system_out.finalize().unwrap();
+
+        let first = std::fs::read(dir.join(MKA_FILE)).unwrap();
+        assert_eq!(&first[..4], &EBML.to_be_bytes());
+        assert_eq!(count(&first, b"A_PCM/INT/LIT"), 2);
+        // 3 s of audio per track in 100 ms blocks, after the EBML ID (which ends in 0xA3)
+        assert_eq!(count(&first[4..], &[SIMPLE_BLOCK as u8, 0x01]), 60);
+        let segment = first
+            .windows(4)
+            .position(|w| w == SEGMENT.to_be_bytes())
+            .unwrap()
+            + 4;
+        let mut size = [0u8; 8];
+        size[1..].copy_from_slice(&first[segment + 1..segment + 8]);
+        assert_eq!(u64::from_be_bytes(size) as usize, first.len() - segment - 8);
+
+        // The mic comes back at a different rate: a new part with both tracks
+        let mut system_out = system.open(spec(48000, 2), &dir).unwrap();
+        let mut mic_out = mic.open(spec(16000, 1), &dir).unwrap();
+        mic_out.write(&[100; 1600]).unwrap();
+        system_out.write(&[-100; 9600]).unwrap();
+        mic_out.finalize().unwrap();
+        system_out.finalize().unwrap();
+
+        let second = std::fs::read(dir.join("session_part2.mka")).unwrap();
+        assert_eq!(count(&second, b"A_PCM/INT/LIT"), 2);
+        assert_eq!(std::fs::read(dir.join(MKA_FILE)).unwrap(), first);
+    }
+}
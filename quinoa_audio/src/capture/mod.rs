@@ -1,2 +1,56 @@
+pub mod attribution;
+pub mod builder;
+pub mod callback;
+pub mod checksum;
+pub mod clock;
+pub mod collision;
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub mod convert;
+pub mod detached;
+pub mod diagnostics;
+pub mod disk;
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub mod dropout;
+pub mod ducking;
 pub mod encoder;
+pub mod encrypt;
+pub mod eventlog;
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub mod feedback;
+pub mod health;
+pub mod idle;
+pub mod inactivity;
+pub mod levels;
+pub mod live;
+pub mod loudness;
+pub mod manifest;
+pub mod memory;
+pub mod metrics;
+pub mod mixdown;
+pub mod mka;
+pub mod naming;
+pub mod options;
+pub mod opus;
+pub mod packets;
+pub mod peaks;
+pub mod plugin;
+pub mod pool;
+pub mod postprocess;
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub mod reconnect;
+pub mod redact;
+pub mod registry;
+pub mod resample;
+pub mod secret;
 pub mod session;
+pub mod speaking;
+pub mod stats;
+pub mod suspend;
+pub mod trace;
+pub mod trim;
+pub mod upload;
+pub mod validate;
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub mod virtual_mic;
+pub mod wallclock;
+pub mod webhook;
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use crate::capture::encoder::segment_path;
+use crate::capture::wallclock::local_tm;
+
+/// Placeholders `filename_template` may use
+pub const PLACEHOLDERS: &[&str] = &["stream", "start_time", "date", "segment", "ext"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Stream,
+    StartTime,
+    Date,
+    /// Zero-padded to this width
+    Segment(usize),
+    Ext,
+}
+
+/// A parsed `filename_template`, e.g. `{stream}_{start_time}_{segment:03}.{ext}`.
+///
+/// `{stream}` is the name the file has by default without its extension
+/// ("microphone", "system", "session" for the manifest and Matroska file),
+/// `{start_time}` the local time the session started (20240131-142500),
+/// `{date}` its day (2024-01-31), `{segment}` the segment number starting
+/// at 1 and `{ext}` the default extension. Braces are doubled to be taken
+/// literally. Without `{segment}`, later segments get `_part<N>` appended
+/// as usual.
+#[derive(Clone, Debug)]
+pub struct FileTemplate {
+    parts: Vec<Part>,
+    start_time: String,
+    date: String,
+}
+
+impl FileTemplate {
+    /// `started_at` is the session's start in Unix seconds
+    pub fn parse(template: &str, started_at: f64) -> Result<Self, String> {
+        if template.contains(['/', '\0']) {
+            return Err(format!(
+                "filename_template must be a file name, without '/', got {:?}",
+                template
+            ));
+        }
+
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let rest = chars.as_str();
+                    if let Some(escaped) = rest.strip_prefix('{') {
+                        text.push('{');
+                        chars = escaped.chars();
+                        continue;
+                    }
+                    let end = rest.find('}').ok_or_else(|| {
+                        format!("Unclosed '{{' in filename_template {:?}", template)
+                    })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(placeholder(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    let rest = chars.as_str();
+                    let Some(escaped) = rest.strip_prefix('}') else {
+                        return Err(format!(
+                            "Unmatched '}}' in filename_template {:?}",
+                            template
+                        ));
+                    };
+                    text.push('}');
+                    chars = escaped.chars();
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if !parts.contains(&Part::Stream) {
+            return Err(format!(
+                "filename_template must contain {{stream}} so the streams' files differ, got {:?}",
+                template
+            ));
+        }
+
+        let (date, start_time) = local_time(started_at);
+        Ok(Self {
+            parts,
+            start_time,
+            date,
+        })
+    }
+
+    fn render(&self, stream: &str, segment: u32, ext: &str) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Stream => name.push_str(stream),
+                Part::StartTime => name.push_str(&self.start_time),
+                Part::Date => name.push_str(&self.date),
+                Part::Segment(width) => name.push_str(&format!("{:0width$}", segment)),
+                Part::Ext => name.push_str(ext),
+            }
+        }
+        name
+    }
+
+    /// The file `default` (e.g. `dir/microphone.wav`) is written to instead,
+    /// for the given segment
+    pub fn path(&self, default: &Path, segment: u32) -> PathBuf {
+        let stem = default
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let ext = default
+            .extension()
+            .map(|e| e.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let dir = default.parent().unwrap_or(Path::new(""));
+        if self.parts.iter().any(|p| matches!(p, Part::Segment(_))) {
+            dir.join(self.render(&stem, segment, &ext))
+        } else {
+            segment_path(&dir.join(self.render(&stem, 1, &ext)), segment)
+        }
+    }
+}
+
+fn placeholder(spec: &str) -> Result<Part, String> {
+    let (name, format) = match spec.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (spec, None),
+    };
+    let part = match name {
+        "stream" => Part::Stream,
+        "start_time" => Part::StartTime,
+        "date" => Part::Date,
+        "ext" => Part::Ext,
+        "segment" => {
+            let width = match format {
+                None => 0,
+                Some(f) => f
+                    .strip_prefix('0')
+                    .and_then(|w| w.parse().ok())
+                    .filter(|w| (1..=9).contains(w))
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid segment format {:?} (expected a zero-padded width like :03)",
+                            f
+                        )
+                    })?,
+            };
+            return Ok(Part::Segment(width));
+        }
+        _ => {
+            return Err(format!(
+                "Unknown placeholder {{{}}} in filename_template (expected one of {:?})",
+                name, PLACEHOLDERS
+            ))
+        }
+    };
+    match format {
+        Some(f) => Err(format!("{{{}}} takes no format, got {:?}", name, f)),
+        None => Ok(part),
+    }
+}
+
+/// `(date, start_time)` of a Unix time in the local time zone
+fn local_time(unix: f64) -> (String, String) {
+    let tm = local_tm(unix);
+    let (year, month, day) = (tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday);
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!(
+            "{:04}{:02}{:02}-{:02}{:02}{:02}",
+            year, month, day, tm.tm_hour, tm.tm_min, tm.tm_sec
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_paths() {
+        let template = FileTemplate::parse("{stream}_{segment:03}.{ext}", 0.0).unwrap();
+        let mic = Path::new("/rec/microphone.wav");
+        assert_eq!(
+            template.path(mic, 1),
+            PathBuf::from("/rec/microphone_001.wav")
+        );
+        assert_eq!(
+            template.path(mic, 12),
+            PathBuf::from("/rec/microphone_012.wav")
+        );
+
+        // Without {segment}, later segments are numbered as usual
+        let template = FileTemplate::parse("{{meeting}} {stream}.{ext}", 0.0).unwrap();
+        assert_eq!(
+            template.path(Path::new("/rec/system.wav"), 2),
+            PathBuf::from("/rec/{meeting} system_part2.wav")
+        );
+
+        for bad in [
+            "{segment}.wav",
+            "{stream}_{time}.wav",
+            "{stream}_{segment:3}.wav",
+            "{stream.wav",
+            "dir/{stream}.wav",
+        ] {
+            assert!(FileTemplate::parse(bad, 0.0).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Highest `compression_level`
+pub const MAX_COMPRESSION_LEVEL: u32 = 10;
+
+/// Encoder settings applied to whichever encoder a stream goes through: the
+/// Opus post-processing step, the Icecast and HLS live streams, and encoder
+/// plugins (which get them in their `open()` spec). WAV and Matroska output
+/// is uncompressed and ignores them.
+///
+/// Each setting left as None keeps the format's own default
+/// (`opus_bitrate_kbps`, `icecast_bitrate_kbps`, ...).
+#[pyclass]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EncoderOptions {
+    /// Target bitrate
+    #[pyo3(get, set)]
+    pub bitrate_kbps: Option<u32>,
+    /// 0-10: higher spends more CPU for better quality at the same size
+    /// (Opus complexity, LAME algorithm quality)
+    #[pyo3(get, set)]
+    pub compression_level: Option<u32>,
+    /// 0.0-1.0: variable-bitrate quality, used by MP3 and Vorbis when no
+    /// bitrate is set
+    #[pyo3(get, set)]
+    pub quality: Option<f32>,
+}
+
+#[pymethods]
+impl EncoderOptions {
+    #[new]
+    #[pyo3(signature = (bitrate_kbps=None, compression_level=None, quality=None))]
+    fn new(
+        bitrate_kbps: Option<u32>,
+        compression_level: Option<u32>,
+        quality: Option<f32>,
+    ) -> Self {
+        Self {
+            bitrate_kbps,
+            compression_level,
+            quality,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        let show = |value: Option<String>| value.unwrap_or_else(|| "None".to_string());
+        format!(
+            "EncoderOptions(bitrate_kbps={}, compression_level={}, quality={})",
+            show(self.bitrate_kbps.map(|v| v.to_string())),
+            show(self.compression_level.map(|v| v.to_string())),
+            show(self.quality.map(|v| v.to_string())),
+        )
+    }
+}
+
+impl EncoderOptions {
+    /// ffmpeg `-q:a` for a codec whose VBR scale runs from `worst` to `best`
+    pub fn vbr_quality(&self, worst: f32, best: f32) -> Option<String> {
+        self.quality
+            .map(|q| format!("{}", (worst + (best - worst) * q).round()))
+    }
+}
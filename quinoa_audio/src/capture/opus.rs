@@ -0,0 +1,50 @@
+/// Values accepted for `opus_vbr`
+pub const OPUS_VBR_MODES: [&str; 3] = ["on", "off", "constrained"];
+
+/// Bitrates libopus supports, in kbit/s
+pub const OPUS_BITRATE_RANGE: std::ops::RangeInclusive<u32> = 6..=510;
+
+/// Highest libopus complexity (slowest, best quality)
+pub const MAX_OPUS_COMPLEXITY: u32 = 10;
+
+/// Settings for everything the session encodes to Opus (the `opus`
+/// post-processing step and Opus HLS segments)
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpusOptions {
+    pub bitrate_kbps: u32,
+    /// 0-10, trading CPU for quality
+    pub complexity: u32,
+    /// "on", "off" (CBR) or "constrained"
+    pub vbr: String,
+    /// Discontinuous transmission: send almost nothing during silence
+    pub dtx: bool,
+}
+
+impl Default for OpusOptions {
+    fn default() -> Self {
+        Self {
+            bitrate_kbps: 96,
+            complexity: MAX_OPUS_COMPLEXITY,
+            vbr: "on".to_string(),
+            dtx: false,
+        }
+    }
+}
+
+impl OpusOptions {
+    /// ffmpeg options selecting libopus with these settings
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        vec![
+            "-c:a".to_string(),
+            "libopus".to_string(),
+            "-b:a".to_string(),
+            format!("{}k", self.bitrate_kbps),
+            "-compression_level".to_string(),
+            self.complexity.to_string(),
+            "-vbr".to_string(),
+            self.vbr.clone(),
+            "-dtx".to_string(),
+            if self.dtx { "1" } else { "0" }.to_string(),
+        ]
+    }
+}
@@ -0,0 +1,268 @@
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+use crate::capture::memory::MemoryBudget;
+
+/// Opus frame length the packets are encoded with
+pub const PACKET_MS: u32 = 20;
+/// RTP clock rate of Opus, whatever the input sample rate
+pub const OPUS_CLOCK_RATE: u32 = 48000;
+/// Packets kept for the application; older ones are dropped (10 s)
+const MAX_QUEUED_PACKETS: usize = 500;
+
+/// One encoded Opus packet, ready to be sent as an RTP payload
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct OpusPacket {
+    pub data: Vec<u8>,
+    /// Increments by one per packet for the whole session (take the low 16
+    /// bits for an RTP sequence number)
+    #[pyo3(get)]
+    pub sequence: u64,
+    /// Position of the packet's first sample, in 48 kHz units
+    #[pyo3(get)]
+    pub timestamp: u64,
+    /// Samples in the packet, in 48 kHz units (960 for 20 ms)
+    #[pyo3(get)]
+    pub duration: u32,
+}
+
+#[pymethods]
+impl OpusPacket {
+    #[getter(data)]
+    fn py_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "OpusPacket(sequence={}, timestamp={}, duration={}, {} bytes)",
+            self.sequence,
+            self.timestamp,
+            self.duration,
+            self.data.len()
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    packets: VecDeque<OpusPacket>,
+    next_sequence: u64,
+    next_timestamp: u64,
+}
+
+/// Packets of a stream waiting to be read by the application. Sequence
+/// numbers and timestamps continue across the files of the stream, so a
+/// WebRTC track fed from it stays continuous.
+#[derive(Debug, Default)]
+pub struct OpusPacketQueue {
+    state: Mutex<QueueState>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl OpusPacketQueue {
+    /// Count the queued packets against `budget`, dropping the oldest ones
+    /// rather than going over it
+    pub fn with_budget(budget: Arc<MemoryBudget>) -> Self {
+        Self {
+            budget,
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&self, data: Vec<u8>) {
+        let duration = packet_samples(&data);
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let packet = OpusPacket {
+            data,
+            sequence: state.next_sequence,
+            timestamp: state.next_timestamp,
+            duration,
+        };
+        state.next_sequence += 1;
+        state.next_timestamp += u64::from(duration);
+        if state.packets.len() == MAX_QUEUED_PACKETS {
+            if let Some(oldest) = state.packets.pop_front() {
+                self.budget.release(oldest.data.len());
+            }
+        }
+        while !self
+            .budget
+            .try_reserve(packet.data.len(), "dropping the oldest Opus packets")
+        {
+            match state.packets.pop_front() {
+                Some(oldest) => self.budget.release(oldest.data.len()),
+                None => return,
+            }
+        }
+        state.packets.push_back(packet);
+    }
+
+    /// Take up to `max` packets (all if None), oldest first
+    pub fn take(&self, max: Option<usize>) -> Vec<OpusPacket> {
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+        let count = max.map_or(state.packets.len(), |m| m.min(state.packets.len()));
+        let packets: Vec<_> = state.packets.drain(..count).collect();
+        self.budget
+            .release(packets.iter().map(|p| p.data.len()).sum());
+        packets
+    }
+
+    /// Read an Ogg Opus stream (as written by ffmpeg) until it ends, queueing
+    /// its audio packets
+    pub fn read_ogg<R: Read>(&self, reader: R) -> io::Result<()> {
+        let mut ogg = OggPacketReader::new(reader);
+        let mut headers = 0;
+        while let Some(packet) = ogg.next_packet()? {
+            // OpusHead and OpusTags come first
+            if headers < 2 {
+                headers += 1;
+                continue;
+            }
+            self.push(packet);
+        }
+        Ok(())
+    }
+}
+
+/// Samples in an Opus packet at 48 kHz, from its TOC byte (RFC 6716 3.1)
+pub fn packet_samples(packet: &[u8]) -> u32 {
+    let Some(&toc) = packet.first() else {
+        return 0;
+    };
+    let config = toc >> 3;
+    let frame = match config {
+        // SILK: 10, 20, 40, 60 ms
+        0..=11 => [480, 960, 1920, 2880][usize::from(config % 4)],
+        // Hybrid: 10, 20 ms
+        12..=15 => [480, 960][usize::from(config % 2)],
+        // CELT: 2.5, 5, 10, 20 ms
+        _ => [120, 240, 480, 960][usize::from(config % 4)],
+    };
+    let frames = match toc & 0x3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |count| u32::from(count & 0x3F)),
+    };
+    frame * frames
+}
+
+/// Splits an Ogg bitstream into packets, which may span pages
+struct OggPacketReader<R> {
+    reader: R,
+    /// Packets completed on the current page
+    ready: VecDeque<Vec<u8>>,
+    /// Start of a packet continued on the next page
+    partial: Vec<u8>,
+}
+
+impl<R: Read> OggPacketReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            ready: VecDeque::new(),
+            partial: Vec::new(),
+        }
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while self.ready.is_empty() {
+            if !self.read_page()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.ready.pop_front())
+    }
+
+    /// Returns false at the end of the stream
+    fn read_page(&mut self) -> io::Result<bool> {
+        // capture pattern, version, header type, granule position, serial
+        // number, page sequence number, checksum, segment count
+        let mut header = [0u8; 27];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if &header[..4] != b"OggS" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "lost Ogg page sync",
+            ));
+        }
+        let mut lacing = vec![0u8; usize::from(header[26])];
+        self.reader.read_exact(&mut lacing)?;
+        let mut body = vec![0u8; lacing.iter().map(|&l| usize::from(l)).sum()];
+        self.reader.read_exact(&mut body)?;
+
+        let mut offset = 0;
+        for &len in &lacing {
+            let len = usize::from(len);
+            self.partial.extend_from_slice(&body[offset..offset + len]);
+            offset += len;
+            // A lacing value under 255 ends the packet
+            if len < 255 {
+                self.ready.push_back(std::mem::take(&mut self.partial));
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(lacing: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0u8; 22]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(lacing);
+        page.extend_from_slice(body);
+        page
+    }
+
+    #[test]
+    fn test_ogg_packets_are_numbered_and_timed() {
+        // 20 ms CELT packet (config 31, one frame)
+        let audio = |fill: u8| {
+            let mut p = vec![31 << 3];
+            p.resize(10, fill);
+            p
+        };
+        let mut stream = page(&[8], b"OpusHead");
+        stream.extend(page(&[8], b"OpusTags"));
+        // Two packets on one page, then a 300-byte packet spanning two pages
+        let mut body = audio(1);
+        body.extend(audio(2));
+        stream.extend(page(&[10, 10], &body));
+        let mut long = vec![31 << 3 | 1];
+        long.resize(300, 3);
+        stream.extend(page(&[255], &long[..255]));
+        stream.extend(page(&[45], &long[255..]));
+
+        let queue = OpusPacketQueue::default();
+        queue.read_ogg(stream.as_slice()).unwrap();
+        let packets = queue.take(None);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[1].data, audio(2));
+        assert_eq!(packets[2].data, long);
+        let timing: Vec<(u64, u64, u32)> = packets
+            .iter()
+            .map(|p| (p.sequence, p.timestamp, p.duration))
+            .collect();
+        assert_eq!(timing, vec![(0, 0, 960), (1, 960, 960), (2, 1920, 1920)]);
+
+        // Numbering continues with the next stream
+        queue.push(audio(4));
+        let next = queue.take(Some(5));
+        assert_eq!((next[0].sequence, next[0].timestamp), (3, 3840));
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Waveform resolution: min/max pairs per second of audio
+pub const PEAKS_PER_SECOND: u32 = 100;
+
+/// Waveform data in the audiowaveform JSON format (version 2), as read by
+/// peaks.js and similar players. `data` holds, for each pixel, a min and max
+/// per channel, scaled to 8 bits.
+#[derive(Serialize)]
+struct PeaksFile<'a> {
+    version: u32,
+    channels: usize,
+    sample_rate: u32,
+    samples_per_pixel: u32,
+    bits: u32,
+    length: usize,
+    data: &'a [i8],
+}
+
+/// Accumulates min/max pairs of a file's samples as they are written
+#[derive(Clone, Debug)]
+pub struct PeaksBuilder {
+    sample_rate: u32,
+    channels: usize,
+    samples_per_pixel: usize,
+    /// Frames in the current pixel
+    frames: usize,
+    /// Running (min, max) per channel for the current pixel
+    current: Vec<(i16, i16)>,
+    data: Vec<i8>,
+}
+
+fn to_8_bit(sample: i16) -> i8 {
+    (sample >> 8) as i8
+}
+
+impl PeaksBuilder {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = usize::from(channels.max(1));
+        Self {
+            sample_rate,
+            channels,
+            samples_per_pixel: (sample_rate / PEAKS_PER_SECOND).max(1) as usize,
+            frames: 0,
+            current: vec![(i16::MAX, i16::MIN); channels],
+            data: Vec::new(),
+        }
+    }
+
+    /// Add interleaved samples
+    pub fn add(&mut self, samples: &[i16]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (&sample, (min, max)) in frame.iter().zip(&mut self.current) {
+                *min = (*min).min(sample);
+                *max = (*max).max(sample);
+            }
+            self.frames += 1;
+            if self.frames == self.samples_per_pixel {
+                self.push_pixel();
+            }
+        }
+    }
+
+    fn push_pixel(&mut self) {
+        for (min, max) in &mut self.current {
+            self.data.push(to_8_bit(*min));
+            self.data.push(to_8_bit(*max));
+            (*min, *max) = (i16::MAX, i16::MIN);
+        }
+        self.frames = 0;
+    }
+
+    /// `<stem>.peaks.json` next to `audio`
+    pub fn path_for(audio: &Path) -> PathBuf {
+        audio.with_extension("peaks.json")
+    }
+
+    /// Write the waveform so far, including a partial last pixel, next to `audio`
+    pub fn write(&self, audio: &Path) -> Result<(), String> {
+        let mut peaks = self.clone();
+        if peaks.frames > 0 {
+            peaks.push_pixel();
+        }
+        let file = PeaksFile {
+            version: 2,
+            channels: peaks.channels,
+            sample_rate: peaks.sample_rate,
+            samples_per_pixel: peaks.samples_per_pixel as u32,
+            bits: 8,
+            length: peaks.data.len() / (2 * peaks.channels),
+            data: &peaks.data,
+        };
+        let path = Self::path_for(audio);
+        let json = serde_json::to_string(&file)
+            .map_err(|e| format!("Failed to serialize {:?}: {}", path, e))?;
+        // Via a temp file so a player never loads a partial waveform
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peaks_are_min_max_per_pixel_and_channel() {
+        // Two frames per pixel
+        let mut peaks = PeaksBuilder::new(200, 2);
+        let mut samples = Vec::new();
+        for i in 0..5i16 {
+            samples.extend([i * 1000, -i * 2000]);
+        }
+        peaks.add(&samples);
+        assert_eq!(peaks.data, vec![0, 3, -8, 0, 7, 11, -24, -16]);
+
+        // A pixel can span writes
+        peaks.add(&[i16::MAX, i16::MIN]);
+        assert_eq!(peaks.data[8..], [15, 127, -128, -32]);
+    }
+}
@@ -0,0 +1,194 @@
+use hound::WavSpec;
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::capture::callback::with_gil;
+use crate::capture::encoder::{EncoderBackend, EncoderFactory};
+use crate::capture::options::EncoderOptions;
+
+/// Methods a Python object needs to be used as an encoder
+const PLUGIN_METHODS: [&str; 3] = ["open", "write", "finalize"];
+
+/// A Python object used as a stream's encoder instead of WAV files.
+///
+/// For each output (the first file, and every segment after a format change
+/// or reconnect) it is called as `open(spec)`, then `write(data)` with
+/// interleaved 16-bit little-endian PCM, then `finalize()`. `spec` is a dict
+/// with `sample_rate`, `channels`, `bits_per_sample`, `path` (the file the
+/// stream would otherwise have written) and the session's `bitrate_kbps`,
+/// `compression_level` and `quality` encoder options (None when unset).
+/// Calls are made from the stream's encoding worker while holding the GIL, so
+/// they should return quickly. `open()` is deferred until the first write,
+/// so nothing is called from the audio thread itself.
+#[derive(Debug)]
+pub struct EncoderPlugin {
+    object: Arc<Py<PyAny>>,
+    /// Shared with `EncoderPlugins`, which sets it once the config is known
+    options: Arc<Mutex<EncoderOptions>>,
+}
+
+impl EncoderPlugin {
+    pub fn from_py(
+        object: &Bound<'_, PyAny>,
+        options: Arc<Mutex<EncoderOptions>>,
+    ) -> PyResult<Self> {
+        for method in PLUGIN_METHODS {
+            if !object.hasattr(method)? {
+                return Err(PyTypeError::new_err(format!(
+                    "Encoder plugin must implement open(spec), write(data) and finalize(); missing {}()",
+                    method
+                )));
+            }
+        }
+        Ok(Self {
+            object: Arc::new(object.clone().unbind()),
+            options,
+        })
+    }
+}
+
+impl EncoderFactory for EncoderPlugin {
+    fn open(&self, spec: WavSpec, path: &Path) -> Result<Box<dyn EncoderBackend>, String> {
+        let options = self.options.lock().map(|o| o.clone()).unwrap_or_default();
+        Ok(Box::new(PluginBackend {
+            object: self.object.clone(),
+            pending: Some(PendingOpen {
+                spec,
+                path: path.to_path_buf(),
+                options,
+            }),
+        }))
+    }
+}
+
+/// What `open()` is called with, kept until the plugin is first used
+struct PendingOpen {
+    spec: WavSpec,
+    path: PathBuf,
+    options: EncoderOptions,
+}
+
+struct PluginBackend {
+    object: Arc<Py<PyAny>>,
+    /// Set until `open()` has been called
+    pending: Option<PendingOpen>,
+}
+
+impl PluginBackend {
+    fn ensure_open(&mut self) -> Result<(), String> {
+        let Some(open) = &self.pending else {
+            return Ok(());
+        };
+        with_gil("Encoder plugin open()", |py| {
+            let dict = PyDict::new(py);
+            dict.set_item("sample_rate", open.spec.sample_rate)?;
+            dict.set_item("channels", open.spec.channels)?;
+            dict.set_item("bits_per_sample", open.spec.bits_per_sample)?;
+            dict.set_item("path", &open.path)?;
+            dict.set_item("bitrate_kbps", open.options.bitrate_kbps)?;
+            dict.set_item("compression_level", open.options.compression_level)?;
+            dict.set_item("quality", open.options.quality)?;
+            self.object.call_method1(py, "open", (dict,)).map(|_| ())
+        })?;
+        self.pending = None;
+        Ok(())
+    }
+}
+
+impl EncoderBackend for PluginBackend {
+    fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        self.ensure_open()?;
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        with_gil("Encoder plugin write()", |py| {
+            self.object
+                .call_method1(py, "write", (PyBytes::new(py, &bytes),))
+                .map(|_| ())
+        })
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), String> {
+        // An output that never received audio is still opened, so the
+        // plugin sees every open() matched by a finalize()
+        self.ensure_open()?;
+        with_gil("Encoder plugin finalize()", |py| {
+            self.object.call_method0(py, "finalize").map(|_| ())
+        })
+    }
+}
+
+/// Encoder plugins chosen for a session's streams
+#[derive(Debug, Default)]
+pub struct EncoderPlugins {
+    pub mic: Option<Arc<dyn EncoderFactory>>,
+    pub system: Option<Arc<dyn EncoderFactory>>,
+    options: Arc<Mutex<EncoderOptions>>,
+}
+
+impl EncoderPlugins {
+    pub fn from_py(
+        mic: Option<&Bound<'_, PyAny>>,
+        system: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<Self> {
+        let options = Arc::new(Mutex::new(EncoderOptions::default()));
+        let wrap = |object: Option<&Bound<'_, PyAny>>| {
+            object
+                .map(|o| {
+                    EncoderPlugin::from_py(o, options.clone())
+                        .map(|p| Arc::new(p) as Arc<dyn EncoderFactory>)
+                })
+                .transpose()
+        };
+        Ok(Self {
+            mic: wrap(mic)?,
+            system: wrap(system)?,
+            options,
+        })
+    }
+
+    /// Encoder options passed to the plugins' `open()`
+    pub fn set_options(&self, options: EncoderOptions) {
+        if let Ok(mut current) = self.options.lock() {
+            *current = options;
+        }
+    }
+
+    /// Check plugins can be kept off the audio thread: they are called from
+    /// the encoding workers, so there must be some
+    pub fn check_threads(&self, encoder_threads: u32) -> Result<(), String> {
+        if encoder_threads == 0 && (self.mic.is_some() || self.system.is_some()) {
+            return Err(
+                "Encoder plugins need encoder_threads of at least 1, as Python is never called from the audio thread"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Encrypt both streams' files with `encryption` instead. Plugins write
+    /// their own output, so they can't be combined with it.
+    pub fn encrypt(&mut self, encryption: Arc<dyn EncoderFactory>) -> Result<(), String> {
+        if self.mic.is_some() || self.system.is_some() {
+            return Err(
+                "encryption_recipient can't be combined with encoder plugins, which write their own output"
+                    .to_string(),
+            );
+        }
+        self.mic = Some(encryption.clone());
+        self.system = Some(encryption);
+        Ok(())
+    }
+
+    pub fn for_stream(&self, is_mic: bool) -> Option<Arc<dyn EncoderFactory>> {
+        if is_mic {
+            self.mic.clone()
+        } else {
+            self.system.clone()
+        }
+    }
+}
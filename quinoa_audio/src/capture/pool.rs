@@ -0,0 +1,230 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Jobs a worker can have pending before submitting blocks the audio thread
+const QUEUE_CAPACITY: usize = 64;
+
+/// Accepted `encoder_overflow` values
+pub const OVERFLOW_POLICIES: &[&str] = &["block", "drop"];
+
+/// What writing audio does when the stream's worker queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    /// Wait for the worker on the audio thread. Nothing is paused: the
+    /// callback overruns its cycle, and PipeWire counts xruns meanwhile.
+    #[default]
+    Block,
+    /// Discard the audio, to be written as silence once the queue has room
+    Drop,
+}
+
+impl OverflowPolicy {
+    /// Policy named by `encoder_overflow`, one of `OVERFLOW_POLICIES`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "block" => Some(Self::Block),
+            "drop" => Some(Self::Drop),
+            _ => None,
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Default)]
+struct QueueCounters {
+    queued: AtomicUsize,
+    max_queued: AtomicUsize,
+    completed: AtomicU64,
+    stalls: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Backpressure counters for one encoding worker
+#[derive(Clone, Debug, Serialize)]
+#[pyclass]
+pub struct EncodeWorkerStats {
+    #[pyo3(get)]
+    pub worker: usize,
+    /// Jobs waiting or running right now
+    #[pyo3(get)]
+    pub queued: usize,
+    /// Deepest the queue has been
+    #[pyo3(get)]
+    pub max_queued: usize,
+    #[pyo3(get)]
+    pub capacity: usize,
+    #[pyo3(get)]
+    pub completed: u64,
+    /// Submissions that found the queue full and had to wait for the worker
+    #[pyo3(get)]
+    pub stalls: u64,
+    /// Writes discarded because the queue was full (`encoder_overflow="drop"`)
+    #[pyo3(get)]
+    pub dropped: u64,
+}
+
+#[pymethods]
+impl EncodeWorkerStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "EncodeWorkerStats(worker={}, queued={}, max_queued={}, completed={}, stalls={}, dropped={})",
+            self.worker, self.queued, self.max_queued, self.completed, self.stalls, self.dropped
+        )
+    }
+}
+
+/// Handle to one worker's queue. Jobs submitted to the same queue run in order.
+#[derive(Clone, Debug)]
+pub struct EncodeQueue {
+    tx: SyncSender<Job>,
+    counters: Arc<QueueCounters>,
+}
+
+impl EncodeQueue {
+    /// Queue `job`, waiting for room if the queue is full. Returns how long
+    /// it waited.
+    pub fn submit(&self, job: Job) -> Duration {
+        let depth = self.counters.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters.max_queued.fetch_max(depth, Ordering::Relaxed);
+        match self.tx.try_send(job) {
+            Ok(()) => Duration::ZERO,
+            Err(TrySendError::Full(job)) => {
+                self.counters.stalls.fetch_add(1, Ordering::Relaxed);
+                let started = Instant::now();
+                if let Err(e) = self.tx.send(job) {
+                    self.run_inline(e.0);
+                }
+                started.elapsed()
+            }
+            Err(TrySendError::Disconnected(job)) => {
+                self.run_inline(job);
+                Duration::ZERO
+            }
+        }
+    }
+
+    /// Queue `job` unless the queue is full; false if it was discarded
+    pub fn try_submit(&self, job: Job) -> bool {
+        let depth = self.counters.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.tx.try_send(job) {
+            Ok(()) => {
+                self.counters.max_queued.fetch_max(depth, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Disconnected(job)) => {
+                self.run_inline(job);
+                true
+            }
+        }
+    }
+
+    /// Run `job` after every job submitted so far and wait for its result.
+    /// None if the worker panicked.
+    pub fn run<R: Send + 'static>(&self, job: impl FnOnce() -> R + Send + 'static) -> Option<R> {
+        let (done_tx, done_rx) = channel();
+        self.submit(Box::new(move || {
+            let _ = done_tx.send(job());
+        }));
+        done_rx.recv().ok()
+    }
+
+    fn run_inline(&self, job: Job) {
+        job();
+        self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Worker threads that encode a session's streams off the audio thread.
+/// Each stream is pinned to one worker so its writes stay in order; with no
+/// workers the streams are encoded inline.
+#[derive(Default)]
+pub struct EncodePool {
+    queues: Vec<EncodeQueue>,
+}
+
+impl EncodePool {
+    /// Start `threads` workers, named `<name>-<worker>`
+    pub fn new(threads: usize, name: &str) -> Self {
+        let queues = (0..threads)
+            .map(|worker| {
+                let (tx, rx) = sync_channel::<Job>(QUEUE_CAPACITY);
+                let counters = Arc::new(QueueCounters::default());
+                let worker_counters = counters.clone();
+                // Exits once every queue handle has been dropped
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, worker))
+                    .spawn(move || {
+                        for job in rx {
+                            job();
+                            worker_counters.queued.fetch_sub(1, Ordering::Relaxed);
+                            worker_counters.completed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    })
+                    .expect("failed to spawn encode worker");
+                EncodeQueue { tx, counters }
+            })
+            .collect();
+        Self { queues }
+    }
+
+    /// Queue for the `stream`-th stream, or None to encode inline
+    pub fn queue(&self, stream: usize) -> Option<EncodeQueue> {
+        if self.queues.is_empty() {
+            return None;
+        }
+        Some(self.queues[stream % self.queues.len()].clone())
+    }
+
+    pub fn stats(&self) -> Vec<EncodeWorkerStats> {
+        self.queues
+            .iter()
+            .enumerate()
+            .map(|(worker, queue)| EncodeWorkerStats {
+                worker,
+                queued: queue.counters.queued.load(Ordering::Relaxed),
+                max_queued: queue.counters.max_queued.load(Ordering::Relaxed),
+                capacity: QUEUE_CAPACITY,
+                completed: queue.counters.completed.load(Ordering::Relaxed),
+                stalls: queue.counters.stalls.load(Ordering::Relaxed),
+                dropped: queue.counters.dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_jobs_on_one_queue_run_in_order() {
+        let pool = EncodePool::new(2, "writer");
+        let queue = pool.queue(1).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..200 {
+            let seen = seen.clone();
+            queue.submit(Box::new(move || seen.lock().unwrap().push(i)));
+        }
+        assert_eq!(queue.run(|| 7), Some(7));
+
+        assert_eq!(*seen.lock().unwrap(), (0..200).collect::<Vec<_>>());
+        let stats = pool.stats();
+        // The last job itself may still be finishing
+        assert!(stats[1].completed >= 200);
+        assert!(stats[1].max_queued >= 1);
+        assert_eq!(stats[0].completed, 0);
+        assert!(EncodePool::new(0, "writer").queue(0).is_none());
+    }
+}
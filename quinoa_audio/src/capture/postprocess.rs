@@ -0,0 +1,323 @@
+use hound::{WavReader, WavWriter};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::capture::manifest::ManifestWriter;
+use crate::capture::opus::OpusOptions;
+use crate::capture::redact::Redactions;
+use crate::capture::session::InternalAudioEvent;
+use crate::capture::webhook::Notifier;
+use crate::errors::ConfigError;
+
+/// RMS level `normalize` aims for (-20 dBFS)
+const TARGET_RMS: f32 = 0.1;
+/// Highest peak `normalize` allows (-1 dBFS)
+const PEAK_CEILING: f32 = 0.891;
+
+/// One step run on each finished output file
+pub enum PostStep {
+    /// Scale the file towards -20 dBFS RMS without letting peaks exceed -1 dBFS
+    Normalize,
+    /// Transcode to `<stem>.opus` next to the WAV with ffmpeg, using the session's Opus settings
+    Opus,
+    /// Python callable taking the file path; may return a new path for later steps
+    Callback(Py<PyAny>),
+}
+
+impl PostStep {
+    /// A built-in step name ("normalize", "opus") or a callable
+    pub fn from_py(step: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(name) = step.extract::<String>() {
+            return match name.as_str() {
+                "normalize" => Ok(PostStep::Normalize),
+                "opus" => Ok(PostStep::Opus),
+                _ => Err(ConfigError::new_err(format!(
+                    "Unknown post-processing step {:?} (expected \"normalize\", \"opus\" or a callable)",
+                    name
+                ))),
+            };
+        }
+        if step.is_callable() {
+            return Ok(PostStep::Callback(step.clone().unbind()));
+        }
+        Err(PyTypeError::new_err(
+            "Post-processing step must be a step name or a callable",
+        ))
+    }
+
+    fn clone_ref(&self, py: Python<'_>) -> Self {
+        match self {
+            PostStep::Normalize => PostStep::Normalize,
+            PostStep::Opus => PostStep::Opus,
+            PostStep::Callback(callback) => PostStep::Callback(callback.clone_ref(py)),
+        }
+    }
+
+    /// Run the step on `path`, returning the file later steps should work on
+    fn run(&self, path: &Path, opus: &OpusOptions) -> Result<PathBuf, String> {
+        match self {
+            PostStep::Normalize => normalize(path).map(|_| path.to_path_buf()),
+            PostStep::Opus => transcode_opus(path, opus),
+            PostStep::Callback(callback) => Python::with_gil(|py| {
+                let result = callback
+                    .call1(py, (path.to_path_buf(),))
+                    .map_err(|e| e.to_string())?;
+                if result.is_none(py) {
+                    Ok(path.to_path_buf())
+                } else {
+                    result.extract::<PathBuf>(py).map_err(|e| e.to_string())
+                }
+            }),
+        }
+    }
+}
+
+/// Runs the registered steps on each file a session finalizes, on a worker
+/// thread so that stopping a session never waits on them.
+pub struct PostProcessor {
+    steps: Arc<Mutex<Vec<PostStep>>>,
+    tx: Sender<PathBuf>,
+    barriers: Barriers,
+}
+
+/// Callbacks waiting for the files sent before them, in order; each is
+/// marked in the queue of files by an empty path
+type Barriers = Arc<Mutex<VecDeque<Box<dyn FnOnce() + Send>>>>;
+
+/// Runs a callback once the post-processing worker has handled every file
+/// reported before it
+pub struct PostBarrier {
+    tx: Sender<PathBuf>,
+    barriers: Barriers,
+}
+
+impl PostBarrier {
+    pub fn then(self, callback: impl FnOnce() + Send + 'static) {
+        // Queued under the lock so markers and callbacks stay in step
+        if let Ok(mut barriers) = self.barriers.lock() {
+            barriers.push_back(Box::new(callback));
+            let _ = self.tx.send(PathBuf::new());
+        }
+    }
+}
+
+impl PostProcessor {
+    /// Start the worker. It exits once every sender (the session's encoders
+    /// and this handle) has been dropped. Each file has `redactions`
+    /// silenced and, given `trim_silence_db`, its silent start and end cut
+    /// before any step runs, and its checksum recorded in `manifest` after
+    /// the last, when `notifier` is told it is final.
+    pub fn spawn(
+        session_id: u64,
+        event_tx: Sender<InternalAudioEvent>,
+        opus: OpusOptions,
+        trim_silence_db: Option<f32>,
+        redactions: Arc<Redactions>,
+        manifest: Option<Arc<ManifestWriter>>,
+        notifier: Option<Arc<Notifier>>,
+    ) -> Self {
+        let steps: Arc<Mutex<Vec<PostStep>>> = Arc::new(Mutex::new(Vec::new()));
+        let barriers = Barriers::default();
+        let (tx, rx) = channel::<PathBuf>();
+
+        let worker_steps = steps.clone();
+        let worker_barriers = barriers.clone();
+        thread::Builder::new()
+            .name(format!("post-process-{}", session_id))
+            .spawn(move || {
+                for source in rx {
+                    if source.as_os_str().is_empty() {
+                        let callback = worker_barriers.lock().ok().and_then(|mut b| b.pop_front());
+                        if let Some(callback) = callback {
+                            callback();
+                        }
+                        continue;
+                    }
+                    // Files whose contents are now final
+                    let mut finished = match redactions.apply(&source) {
+                        Ok(rewritten) => rewritten,
+                        Err(e) => {
+                            log!("Redacting {:?} failed: {}", source, e);
+                            let _ = event_tx.send(InternalAudioEvent::Error(e));
+                            Vec::new()
+                        }
+                    };
+                    if let Some(threshold_db) = trim_silence_db {
+                        match redactions.trim(&source, threshold_db) {
+                            Ok(Some(trimmed)) => {
+                                if let Some(manifest) = &manifest {
+                                    manifest.record_trim(&source, trimmed);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                log!("Trimming {:?} failed: {}", source, e);
+                                let _ = event_tx.send(InternalAudioEvent::Error(e));
+                            }
+                        }
+                    }
+                    finished.push(source.clone());
+                    let record = |mut finished: Vec<PathBuf>| {
+                        finished.dedup();
+                        if let Some(manifest) = &manifest {
+                            manifest.record_checksums(&finished);
+                        }
+                        if let Some(notifier) = &notifier {
+                            notifier.segment_finalized(&source);
+                            // A post-processed copy, e.g. the .opus file
+                            if let Some(output) = finished.last().filter(|o| **o != source) {
+                                notifier.segment_finalized(output);
+                            }
+                        }
+                    };
+                    let registered = worker_steps.lock().map(|s| !s.is_empty());
+                    if !registered.unwrap_or(false) {
+                        record(finished);
+                        continue;
+                    }
+                    // Snapshot the steps so registering more never waits on a running step
+                    let steps: Vec<PostStep> = Python::with_gil(|py| {
+                        worker_steps
+                            .lock()
+                            .map(|s| s.iter().map(|step| step.clone_ref(py)).collect())
+                            .unwrap_or_default()
+                    });
+                    let event = match run_steps(&steps, &source, &opus) {
+                        Ok(output) => {
+                            finished.push(output.clone());
+                            record(finished);
+                            InternalAudioEvent::PostProcessed { source, output }
+                        }
+                        Err(message) => {
+                            log!("Post-processing {:?} failed: {}", source, message);
+                            record(finished);
+                            InternalAudioEvent::PostProcessFailed {
+                                path: source,
+                                message,
+                            }
+                        }
+                    };
+                    let _ = event_tx.send(event);
+                }
+            })
+            .expect("failed to spawn post-processing worker");
+
+        Self {
+            steps,
+            tx,
+            barriers,
+        }
+    }
+
+    /// Sender the session's encoders report finalized files to
+    pub fn sender(&self) -> Sender<PathBuf> {
+        self.tx.clone()
+    }
+
+    /// A barrier behind the files reported so far
+    pub fn barrier(&self) -> PostBarrier {
+        PostBarrier {
+            tx: self.tx.clone(),
+            barriers: self.barriers.clone(),
+        }
+    }
+
+    pub fn add(&self, step: PostStep) {
+        if let Ok(mut steps) = self.steps.lock() {
+            steps.push(step);
+        }
+    }
+}
+
+fn run_steps(steps: &[PostStep], source: &Path, opus: &OpusOptions) -> Result<PathBuf, String> {
+    steps
+        .iter()
+        .try_fold(source.to_path_buf(), |path, step| step.run(&path, opus))
+}
+
+/// Gain that brings `samples` to TARGET_RMS without peaks above PEAK_CEILING
+fn normalize_gain(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return 1.0;
+    }
+    let scale = 1.0 / f32::from(i16::MAX);
+    let mut peak = 0.0f32;
+    let mut sum_squares = 0.0f64;
+    for &sample in samples {
+        let value = f32::from(sample) * scale;
+        peak = peak.max(value.abs());
+        sum_squares += f64::from(value * value);
+    }
+    let rms = (sum_squares / samples.len() as f64).sqrt() as f32;
+    if peak == 0.0 || rms == 0.0 {
+        return 1.0;
+    }
+    (TARGET_RMS / rms).min(PEAK_CEILING / peak)
+}
+
+/// Apply `normalize_gain` to a 16-bit WAV file in place
+fn normalize(path: &Path) -> Result<(), String> {
+    let reader = WavReader::open(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let spec = reader.spec();
+    let samples = reader
+        .into_samples::<i16>()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let gain = normalize_gain(&samples);
+
+    let tmp = path.with_extension("wav.tmp");
+    let write = || -> Result<(), hound::Error> {
+        let mut writer = WavWriter::create(&tmp, spec)?;
+        for &sample in &samples {
+            let scaled = (f32::from(sample) * gain).round();
+            writer.write_sample(scaled.clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16)?;
+        }
+        writer.finalize()
+    };
+    write().map_err(|e| format!("Failed to write {:?}: {}", tmp, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Failed to replace {:?}: {}", path, e))
+}
+
+/// Encode `path` to Opus next to it with ffmpeg, returning the new file
+fn transcode_opus(path: &Path, opus: &OpusOptions) -> Result<PathBuf, String> {
+    let output = path.with_extension("opus");
+    let result = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(path)
+        .args(opus.ffmpeg_args())
+        .arg(&output)
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if !result.status.success() {
+        return Err(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_gain_targets_rms_under_peak_ceiling() {
+        // Quiet square wave: RMS == peak, so the RMS target decides
+        let quiet = [3277i16, -3277, 3277, -3277];
+        assert!((normalize_gain(&quiet) - 1.0).abs() < 0.01);
+
+        // A single loud click in silence: the peak ceiling decides
+        let mut click = vec![0i16; 1000];
+        click[500] = i16::MAX / 2;
+        assert!((normalize_gain(&click) - PEAK_CEILING * 2.0).abs() < 0.01);
+
+        assert_eq!(normalize_gain(&[0; 16]), 1.0);
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Exponential backoff with jitter for reconnecting to PipeWire
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// Give up after this many consecutive failed attempts (None = retry forever)
+    pub max_attempts: Option<u32>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// Whether another attempt is allowed after `attempt` consecutive failures
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt < max)
+    }
+
+    /// Backoff before the given attempt (1-based), without jitter
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        self.initial_delay
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_delay)
+    }
+
+    /// Backoff before the given attempt (1-based), randomized to the upper half
+    /// of the base delay so that several clients don't reconnect in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        let jitter = jitter_fraction(attempt);
+        base / 2 + base.mul_f64(jitter / 2.0)
+    }
+}
+
+/// Cheap pseudo-random value in [0, 1) seeded from the clock (xorshift)
+fn jitter_fraction(salt: u32) -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut x = u64::from(nanos) ^ (u64::from(salt) << 32) | 1;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    (x % 1_000_000) as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+        };
+
+        assert_eq!(policy.base_delay(1), Duration::from_secs(1));
+        assert_eq!(policy.base_delay(2), Duration::from_secs(2));
+        assert_eq!(policy.base_delay(3), Duration::from_secs(4));
+        assert_eq!(policy.base_delay(10), Duration::from_secs(5));
+
+        let jittered = policy.delay(3);
+        assert!(jittered >= Duration::from_secs(2) && jittered <= Duration::from_secs(4));
+
+        assert!(policy.should_retry(2));
+        assert!(!policy.should_retry(3));
+    }
+}
@@ -0,0 +1,206 @@
+//! Redaction: ranges struck from a recording, overwritten with silence in
+//! every stream's files once they are finalized
+
+use hound::{WavReader, WavWriter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::capture::encoder::OutputTarget;
+use crate::capture::trim::{trim_silence, TrimmedSilence};
+
+/// A range struck from the recording, in seconds of recorded audio (the
+/// position in the stream's files, its segments played back to back)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Redaction {
+    pub from: f64,
+    pub to: f64,
+}
+
+/// The ranges of a session to strike and the outputs to strike them from
+#[derive(Default)]
+pub struct Redactions {
+    ranges: Mutex<Vec<Redaction>>,
+    outputs: Vec<OutputTarget>,
+    /// Silence cut from files, which moves the ranges within them
+    trims: Mutex<HashMap<PathBuf, TrimmedSilence>>,
+    /// Held while rewriting files, which the post-processing worker and
+    /// stop() may both do
+    scrubbing: Mutex<()>,
+}
+
+impl Redactions {
+    /// `ranges` and `trims` from an earlier run of the session, if it is
+    /// resumed
+    pub fn new(
+        outputs: Vec<OutputTarget>,
+        ranges: Vec<Redaction>,
+        trims: HashMap<PathBuf, TrimmedSilence>,
+    ) -> Self {
+        Self {
+            ranges: Mutex::new(ranges),
+            outputs,
+            trims: Mutex::new(trims),
+            scrubbing: Mutex::new(()),
+        }
+    }
+
+    pub fn add(&self, redaction: Redaction) {
+        if let Ok(mut ranges) = self.ranges.lock() {
+            ranges.push(redaction);
+        }
+    }
+
+    fn ranges(&self) -> Vec<Redaction> {
+        self.ranges.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+
+    fn trims(&self) -> HashMap<PathBuf, TrimmedSilence> {
+        self.trims.lock().map(|t| t.clone()).unwrap_or_default()
+    }
+
+    /// Cut the silence from the start and end of a finalized file (see
+    /// `trim_silence`), keeping track of it so later ranges still land
+    pub fn trim(&self, path: &Path, threshold_db: f32) -> Result<Option<TrimmedSilence>, String> {
+        let _scrubbing = self.scrubbing.lock();
+        let trimmed = trim_silence(path, threshold_db)?;
+        if let (Some(trimmed), Ok(mut trims)) = (trimmed, self.trims.lock()) {
+            trims.insert(path.to_path_buf(), trimmed);
+        }
+        Ok(trimmed)
+    }
+
+    /// Silence the ranges in the stream `finalized` belongs to, in its
+    /// segments up to and including the finalized one, so ranges added
+    /// after an earlier segment was finalized reach it too. Returns the
+    /// files rewritten.
+    pub fn apply(&self, finalized: &Path) -> Result<Vec<PathBuf>, String> {
+        let ranges = self.ranges();
+        if ranges.is_empty() {
+            return Ok(Vec::new());
+        }
+        let _scrubbing = self.scrubbing.lock();
+        for output in &self.outputs {
+            let mut segments = output.written_files();
+            // Later segments are still being written
+            if let Some(last) = segments
+                .iter()
+                .position(|files| files.iter().any(|file| file == finalized))
+            {
+                segments.truncate(last + 1);
+                return scrub(&segments, &ranges, &self.trims());
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// Silence the ranges in every stream, for a session that has stopped
+    pub fn apply_all(&self) -> Result<Vec<PathBuf>, String> {
+        let ranges = self.ranges();
+        if ranges.is_empty() {
+            return Ok(Vec::new());
+        }
+        let _scrubbing = self.scrubbing.lock();
+        let trims = self.trims();
+        let mut rewritten = Vec::new();
+        for output in &self.outputs {
+            rewritten.extend(scrub(&output.written_files(), &ranges, &trims)?);
+        }
+        Ok(rewritten)
+    }
+}
+
+/// Silence `ranges` in a stream's segments, each a list of files of the
+/// same length before any was trimmed (one per channel when split). Only
+/// files overlapping a range are rewritten.
+fn scrub(
+    segments: &[Vec<PathBuf>],
+    ranges: &[Redaction],
+    trims: &HashMap<PathBuf, TrimmedSilence>,
+) -> Result<Vec<PathBuf>, String> {
+    let mut rewritten = Vec::new();
+    let mut start = 0.0;
+    let cut_start = |file: &PathBuf| trims.get(file).map_or(0.0, |t| t.start);
+    for files in segments {
+        let Some(first) = files.first() else {
+            continue;
+        };
+        let reader =
+            WavReader::open(first).map_err(|e| format!("Failed to read {:?}: {}", first, e))?;
+        let rate = reader.spec().sample_rate;
+        let cut = trims.get(first).map_or(0.0, |t| t.start + t.end);
+        let length = f64::from(reader.duration()) / f64::from(rate.max(1)) + cut;
+        let end = start + length;
+        if ranges.iter().any(|r| r.from < end && r.to > start) {
+            for file in files {
+                silence_file(file, start + cut_start(file), ranges)?;
+                rewritten.push(file.clone());
+            }
+        }
+        start = end;
+    }
+    Ok(rewritten)
+}
+
+/// Rewrite a 16-bit WAV file that starts `start` seconds into its stream
+/// with `ranges` silenced, copying it a sample at a time
+fn silence_file(path: &Path, start: f64, ranges: &[Redaction]) -> Result<(), String> {
+    let mut reader =
+        WavReader::open(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let spec = reader.spec();
+    let channels = usize::from(spec.channels).max(1);
+    let silenced = silenced_frames(reader.duration() as usize, spec.sample_rate, start, ranges);
+
+    let tmp = path.with_extension("wav.tmp");
+    let mut write = || -> Result<(), hound::Error> {
+        let mut writer = WavWriter::create(&tmp, spec)?;
+        for (i, sample) in reader.samples::<i16>().enumerate() {
+            let sample = sample?;
+            let frame = i / channels;
+            let silent = silenced.iter().any(|range| range.contains(&frame));
+            writer.write_sample(if silent { 0 } else { sample })?;
+        }
+        writer.finalize()
+    };
+    write().map_err(|e| format!("Failed to rewrite {:?}: {}", path, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Failed to replace {:?}: {}", path, e))
+}
+
+/// The frames inside `ranges` of a file `frames` long, given that its first
+/// frame is `start` seconds into the stream
+fn silenced_frames(
+    frames: usize,
+    rate: u32,
+    start: f64,
+    ranges: &[Redaction],
+) -> Vec<Range<usize>> {
+    let frame_at = |seconds: f64| {
+        (((seconds - start) * f64::from(rate)).round().max(0.0) as usize).min(frames)
+    };
+    ranges
+        .iter()
+        .map(|range| frame_at(range.from)..frame_at(range.to))
+        .filter(|frames| !frames.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silenced_frames_are_the_ranges_within_the_segment() {
+        // One second at 10 Hz, starting 5 s into the stream
+        let ranges = [
+            Redaction { from: 5.2, to: 5.5 },
+            // Ends before this segment, or starts after it
+            Redaction { from: 1.0, to: 4.0 },
+            Redaction { from: 6.5, to: 9.0 },
+            // Runs past the end, so stops at the last frame
+            Redaction { from: 5.8, to: 7.0 },
+        ];
+        assert_eq!(silenced_frames(10, 10, 5.0, &ranges), [2..5, 8..10]);
+    }
+}
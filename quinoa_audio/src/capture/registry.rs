@@ -0,0 +1,178 @@
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::capture::session::{AudioCommand, SessionState};
+
+/// Sessions of this process whose audio thread is still running
+static SESSIONS: Mutex<Vec<Arc<SessionEntry>>> = Mutex::new(Vec::new());
+
+/// What the registry knows about a running session. The session's handle and
+/// audio thread update it; `SessionHandle`s read it.
+pub struct SessionEntry {
+    pub id: u64,
+    /// None for an analysis-only session
+    pub output_dir: Option<String>,
+    started: Instant,
+    commands: Sender<AudioCommand>,
+    paused: AtomicBool,
+    stopping: AtomicBool,
+    /// What the session's handles share, so `attach_session()` can make one
+    /// after the `RecordingSession` that started it was dropped
+    session: Mutex<Option<Arc<SessionState>>>,
+}
+
+impl SessionEntry {
+    /// Add a session to the registry until `unregister()`
+    pub fn register(
+        id: u64,
+        output_dir: Option<String>,
+        commands: Sender<AudioCommand>,
+    ) -> Arc<Self> {
+        let entry = Self::unlisted(id, output_dir, commands);
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            sessions.push(entry.clone());
+        }
+        entry
+    }
+
+    /// An entry for a session that isn't this process's, left out of the registry
+    pub fn unlisted(
+        id: u64,
+        output_dir: Option<String>,
+        commands: Sender<AudioCommand>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            id,
+            output_dir,
+            started: Instant::now(),
+            commands,
+            paused: AtomicBool::new(false),
+            stopping: AtomicBool::new(false),
+            session: Mutex::new(None),
+        })
+    }
+
+    /// Remove the session once its audio thread has exited
+    pub fn unregister(&self) {
+        if let Ok(mut sessions) = SESSIONS.lock() {
+            sessions.retain(|entry| entry.id != self.id);
+        }
+        // Breaks the cycle with the state, which holds the entry
+        if let Ok(mut session) = self.session.lock() {
+            session.take();
+        }
+    }
+
+    pub fn set_session(&self, state: Arc<SessionState>) {
+        if let Ok(mut session) = self.session.lock() {
+            *session = Some(state);
+        }
+    }
+
+    /// Every running session of this process, oldest first
+    pub fn all() -> Vec<Arc<SessionEntry>> {
+        SESSIONS
+            .lock()
+            .map(|sessions| sessions.clone())
+            .unwrap_or_default()
+    }
+
+    /// The shared state of a running session of this process
+    pub fn attach(id: u64) -> Option<Arc<SessionState>> {
+        let sessions = SESSIONS.lock().ok()?;
+        let entry = sessions.iter().find(|entry| entry.id == id)?;
+        let session = entry.session.lock().ok()?;
+        session.clone()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn set_stopping(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    pub fn state(&self) -> &'static str {
+        if self.stopping.load(Ordering::Relaxed) {
+            "stopping"
+        } else if self.paused.load(Ordering::Relaxed) {
+            "paused"
+        } else {
+            "recording"
+        }
+    }
+}
+
+/// A running session as seen from anywhere in the process, from
+/// `active_sessions()`. Unlike the `RecordingSession` that started it, it
+/// can't wait for the session to finish.
+#[derive(Clone)]
+#[pyclass]
+pub struct SessionHandle {
+    entry: Arc<SessionEntry>,
+}
+
+#[pymethods]
+impl SessionHandle {
+    #[getter]
+    fn session_id(&self) -> u64 {
+        self.entry.id
+    }
+
+    #[getter]
+    fn output_dir(&self) -> Option<&str> {
+        self.entry.output_dir.as_deref()
+    }
+
+    /// "recording", "paused" or "stopping"
+    #[getter]
+    fn state(&self) -> &'static str {
+        self.entry.state()
+    }
+
+    /// Seconds since the session started
+    #[getter]
+    fn uptime(&self) -> f64 {
+        self.entry.uptime().as_secs_f64()
+    }
+
+    /// Ask the session to stop and finalize its files. Returns without
+    /// waiting; the session leaves `active_sessions()` once it has stopped.
+    fn stop(&self) {
+        self.entry.set_stopping();
+        let _ = self.entry.commands.send(AudioCommand::Stop);
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "SessionHandle(session_id={}, state='{}', output_dir={}, uptime={:.1})",
+            self.entry.id,
+            self.entry.state(),
+            self.entry
+                .output_dir
+                .as_ref()
+                .map_or_else(|| "None".to_string(), |dir| format!("'{}'", dir)),
+            self.uptime()
+        )
+    }
+}
+
+/// Sessions started by this process that are still running, oldest first
+pub fn active_sessions() -> Vec<SessionHandle> {
+    SessionEntry::all()
+        .into_iter()
+        .map(|entry| SessionHandle { entry })
+        .collect()
+}
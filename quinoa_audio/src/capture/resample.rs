@@ -0,0 +1,123 @@
+/// Mic rates at or below this are treated as a narrowband voice link, like a
+/// Bluetooth headset in its headset profile (8 kHz CVSD, 16 kHz mSBC)
+pub const NARROWBAND_MAX_RATE: u32 = 16000;
+
+/// Whether audio at `rate` is narrowband and would be upsampled to a session
+/// recording at `session_rate`
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub fn is_narrowband(rate: u32, session_rate: u32) -> bool {
+    rate > 0 && rate <= NARROWBAND_MAX_RATE && rate < session_rate
+}
+
+/// Streaming sample rate converter for interleaved audio, interpolating each
+/// channel with a Catmull-Rom spline. Good enough to bring a voice link up
+/// to the session rate; not meant for music.
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub struct Resampler {
+    channels: usize,
+    from_rate: u64,
+    to_rate: u64,
+    /// Input not yet consumed, starting one frame before `position`
+    pending: Vec<f32>,
+    /// Position of the next output frame in `pending`, in 1/`to_rate` frames
+    /// so it never drifts
+    position: u64,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            from_rate: u64::from(from_rate.max(1)),
+            to_rate: u64::from(to_rate.max(1)),
+            // A silent frame before the first, for the spline's left side
+            pending: vec![0.0; channels],
+            position: u64::from(to_rate.max(1)),
+        }
+    }
+
+    /// Convert the next block of input. The output lags by two input frames.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        self.pending
+            .extend_from_slice(&input[..input.len() - input.len() % channels]);
+        let frames = self.pending.len() / channels;
+        let sample = |frame: usize, channel: usize| self.pending[frame * channels + channel];
+
+        let mut output = Vec::with_capacity(
+            (input.len() as u64 * self.to_rate / self.from_rate) as usize + channels,
+        );
+        // Each output frame needs the input frame before it and two after
+        while (self.position / self.to_rate) as usize + 2 < frames {
+            let index = (self.position / self.to_rate) as usize;
+            let t = (self.position % self.to_rate) as f32 / self.to_rate as f32;
+            for channel in 0..channels {
+                output.push(catmull_rom(
+                    sample(index - 1, channel),
+                    sample(index, channel),
+                    sample(index + 1, channel),
+                    sample(index + 2, channel),
+                    t,
+                ));
+            }
+            self.position += self.from_rate;
+        }
+
+        // Keep the frames the next output still needs
+        let keep_from = ((self.position / self.to_rate) as usize)
+            .saturating_sub(1)
+            .min(frames);
+        self.pending.drain(..keep_from * channels);
+        self.position -= keep_from as u64 * self.to_rate;
+        output
+    }
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsamples_in_blocks() {
+        assert!(is_narrowband(16000, 48000));
+        assert!(is_narrowband(8000, 16000));
+        assert!(!is_narrowband(16000, 16000));
+        assert!(!is_narrowband(44100, 48000));
+
+        // A 16 kHz stereo sine, fed in uneven blocks
+        let frames = 1600;
+        let input: Vec<f32> = (0..frames)
+            .flat_map(|n| {
+                let v = (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 16000.0).sin();
+                [v, -v]
+            })
+            .collect();
+        let mut resampler = Resampler::new(16000, 48000, 2);
+        let mut output = Vec::new();
+        for block in input.chunks(2 * 97) {
+            output.extend(resampler.process(block));
+        }
+
+        // Three times the frames, less the two frames of latency
+        assert_eq!(output.len(), 2 * (3 * frames - 6));
+        // Past the first input frame, whose left neighbour is the silent pad,
+        // output frame n is at input frame n/3
+        for (n, frame) in output.chunks(2).enumerate().skip(3) {
+            let t = n as f32 / 3.0 / 16000.0;
+            let expected = (2.0 * std::f32::consts::PI * 440.0 * t).sin();
+            assert!((frame[0] - expected).abs() < 0.01, "frame {}", n);
+            assert_eq!(frame[0], -frame[1]);
+        }
+    }
+}
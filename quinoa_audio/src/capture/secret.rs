@@ -0,0 +1,61 @@
+//! Secrets handed to the commands we run (curl, ffmpeg) in files only this
+//! user can read, rather than on their command lines, which any user can
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A private temporary file, removed when dropped
+#[derive(Debug)]
+pub struct SecretFile {
+    path: PathBuf,
+}
+
+impl SecretFile {
+    pub fn write(contents: &str) -> io::Result<Self> {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "quinoa-secret-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        // Removes a file left half written
+        let secret = Self { path };
+        file.write_all(contents.as_bytes())?;
+        Ok(secret)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_only_we_can_read_it_until_it_is_dropped() {
+        let secret = SecretFile::write("hunter2").unwrap();
+        let path = secret.path().to_path_buf();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hunter2");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(secret);
+        assert!(!path.exists());
+    }
+}
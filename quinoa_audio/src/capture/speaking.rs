@@ -0,0 +1,104 @@
+use crate::capture::levels::LevelSample;
+
+/// Levels windows in a row above the threshold before a stream counts as
+/// speaking, so a click or a knock on the desk doesn't
+const ONSET_WINDOWS: u32 = 2;
+
+#[derive(Default)]
+struct StreamActivity {
+    /// Windows in a row above the threshold
+    loud_windows: u32,
+    /// When the stream was last above the threshold, in
+    /// `LevelSample::elapsed` seconds
+    last_loud: f64,
+    speaking: bool,
+}
+
+/// Tells from the levels the session measures whether someone is talking
+/// on the mic and on the system audio: a stream starts speaking once it
+/// stays above the threshold for `ONSET_WINDOWS` windows and stops once it
+/// has been below it for the hold time, so pauses between words don't
+/// flicker.
+pub struct SpeakingDetector {
+    /// Linear level; None disables the detector
+    threshold: Option<f32>,
+    hold: f64,
+    streams: [StreamActivity; 2],
+}
+
+impl SpeakingDetector {
+    pub fn new(threshold_db: Option<f64>, hold_ms: u32) -> Self {
+        Self {
+            threshold: threshold_db.map(|db| 10f64.powf(db / 20.0) as f32),
+            hold: f64::from(hold_ms) / 1000.0,
+            streams: Default::default(),
+        }
+    }
+
+    /// Take the next levels measurement. Returns whether the mic and the
+    /// system audio are speaking when either of them just changed.
+    pub fn observe(&mut self, sample: &LevelSample, paused: bool) -> Option<(bool, bool)> {
+        let threshold = self.threshold?;
+        let now = sample.elapsed;
+        let levels = [
+            (&sample.mic_channel_levels, sample.mic_level),
+            (&sample.system_channel_levels, sample.system_level),
+        ];
+        let mut changed = false;
+        for (stream, (channels, level)) in self.streams.iter_mut().zip(levels) {
+            // Levels are empty for a stream that isn't running
+            let loud = !paused && !channels.is_empty() && level > threshold;
+            if loud {
+                stream.loud_windows += 1;
+                stream.last_loud = now;
+            } else {
+                stream.loud_windows = 0;
+            }
+            let speaking = if paused || channels.is_empty() {
+                false
+            } else if stream.speaking {
+                now - stream.last_loud < self.hold
+            } else {
+                stream.loud_windows >= ONSET_WINDOWS
+            };
+            changed |= speaking != stream.speaking;
+            stream.speaking = speaking;
+        }
+        changed.then_some((self.streams[0].speaking, self.streams[1].speaking))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(elapsed: f64, mic: f32, system: f32) -> LevelSample {
+        LevelSample {
+            elapsed,
+            mic_level: mic,
+            system_level: system,
+            mic_channel_levels: vec![mic],
+            system_channel_levels: vec![system, system],
+            mic_peak_hold: mic,
+            system_peak_hold: system,
+        }
+    }
+
+    #[test]
+    fn test_speaking_transitions_ignore_clicks_and_short_pauses() {
+        let mut detector = SpeakingDetector::new(Some(-40.0), 500);
+        let mut transitions = Vec::new();
+        // A click, then speech with a 300 ms pause, then silence
+        let mic = [0.0, 0.5, 0.0, 0.2, 0.2, 0.2, 0.0, 0.0, 0.0, 0.2, 0.2];
+        for (tick, &level) in mic.iter().chain(&[0.0; 10]).enumerate() {
+            let t = tick as f64 * 0.1;
+            if let Some(change) = detector.observe(&sample(t, level, 0.0), false) {
+                transitions.push((tick, change));
+            }
+        }
+        assert_eq!(transitions, [(4, (true, false)), (15, (false, false))]);
+
+        let mut disabled = SpeakingDetector::new(None, 500);
+        assert_eq!(disabled.observe(&sample(0.0, 0.5, 0.5), false), None);
+    }
+}
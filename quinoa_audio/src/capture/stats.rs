@@ -0,0 +1,223 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::capture::pool::EncodeWorkerStats;
+use crate::capture::session::stream_name;
+
+/// Snapshot of a session's runtime counters, from `RecordingSession.stats()`
+#[derive(Clone, Debug, Serialize)]
+#[pyclass]
+pub struct SessionStats {
+    /// One entry per encoding worker (empty when encoding inline)
+    #[pyo3(get)]
+    pub encode_workers: Vec<EncodeWorkerStats>,
+    /// Timings of the mic and system streams, in that order (None for a
+    /// stream that isn't recorded)
+    #[pyo3(get)]
+    pub streams: Vec<StreamTimings>,
+}
+
+/// Length of the window timings are aggregated over
+const TIMING_WINDOW: Duration = Duration::from_secs(1);
+
+/// Time spent per call over one window, in microseconds
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[pyclass]
+pub struct TimingStats {
+    #[pyo3(get)]
+    pub min_us: f64,
+    #[pyo3(get)]
+    pub avg_us: f64,
+    #[pyo3(get)]
+    pub max_us: f64,
+    /// Calls in the window
+    #[pyo3(get)]
+    pub count: u64,
+}
+
+#[pymethods]
+impl TimingStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "TimingStats(min_us={:.1}, avg_us={:.1}, max_us={:.1}, count={})",
+            self.min_us, self.avg_us, self.max_us, self.count
+        )
+    }
+}
+
+/// Processing cost of one stream over the last complete second. `process`
+/// covers the capture callback, `encode` the writes to the output (on the
+/// encoding worker when there is one). None before a second has elapsed.
+#[derive(Clone, Debug, Serialize)]
+#[pyclass]
+pub struct StreamTimings {
+    /// "mic" or "system"
+    #[pyo3(get)]
+    pub stream: String,
+    #[pyo3(get)]
+    pub process: Option<TimingStats>,
+    #[pyo3(get)]
+    pub encode: Option<TimingStats>,
+}
+
+#[pymethods]
+impl StreamTimings {
+    fn __repr__(&self) -> String {
+        let repr = |stats: &Option<TimingStats>| {
+            stats
+                .as_ref()
+                .map_or_else(|| "None".to_string(), TimingStats::__repr__)
+        };
+        format!(
+            "StreamTimings(stream='{}', process={}, encode={})",
+            self.stream,
+            repr(&self.process),
+            repr(&self.encode)
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct WindowState {
+    started: Option<Instant>,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    count: u64,
+    /// The last complete window
+    last: Option<TimingStats>,
+    /// When the latest duration was recorded
+    recorded_at: Option<Instant>,
+}
+
+/// Min/avg/max of durations, aggregated per `TIMING_WINDOW`
+#[derive(Debug, Default)]
+pub struct TimingWindow {
+    state: Mutex<WindowState>,
+}
+
+impl TimingWindow {
+    pub fn record(&self, elapsed: Duration) {
+        self.record_at(elapsed, Instant::now());
+    }
+
+    fn record_at(&self, elapsed: Duration, now: Instant) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let started = *state.started.get_or_insert(now);
+        if now.duration_since(started) >= TIMING_WINDOW {
+            let micros = |d: Duration| d.as_secs_f64() * 1e6;
+            state.last = (state.count > 0).then(|| TimingStats {
+                min_us: micros(state.min),
+                avg_us: micros(state.total) / state.count as f64,
+                max_us: micros(state.max),
+                count: state.count,
+            });
+            *state = WindowState {
+                started: Some(now),
+                last: state.last.take(),
+                ..WindowState::default()
+            };
+        }
+        state.min = if state.count == 0 {
+            elapsed
+        } else {
+            state.min.min(elapsed)
+        };
+        state.max = state.max.max(elapsed);
+        state.total += elapsed;
+        state.count += 1;
+        state.recorded_at = Some(now);
+    }
+
+    pub fn last(&self) -> Option<TimingStats> {
+        self.state.lock().ok().and_then(|state| state.last.clone())
+    }
+
+    /// Time since a duration was last recorded
+    pub fn since_last(&self) -> Option<Duration> {
+        let recorded_at = self.state.lock().ok()?.recorded_at?;
+        Some(recorded_at.elapsed())
+    }
+
+    /// Time from now until the returned guard is dropped
+    pub fn time(self: &Arc<Self>) -> TimingGuard {
+        TimingGuard {
+            window: self.clone(),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Records its lifetime into a `TimingWindow`, however the timed scope exits
+pub struct TimingGuard {
+    window: Arc<TimingWindow>,
+    started: Instant,
+}
+
+impl Drop for TimingGuard {
+    fn drop(&mut self) {
+        self.window.record(self.started.elapsed());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StreamTimers {
+    pub process: Arc<TimingWindow>,
+    pub encode: Arc<TimingWindow>,
+}
+
+/// Timings of both streams, shared between the audio thread, the encoding
+/// workers and the session handle
+#[derive(Debug, Default)]
+pub struct SessionTimings {
+    pub mic: StreamTimers,
+    pub system: StreamTimers,
+}
+
+impl SessionTimings {
+    pub fn stream(&self, is_mic: bool) -> &StreamTimers {
+        if is_mic {
+            &self.mic
+        } else {
+            &self.system
+        }
+    }
+
+    pub fn snapshot(&self, is_mic: bool) -> StreamTimings {
+        let timers = self.stream(is_mic);
+        StreamTimings {
+            stream: stream_name(is_mic).to_string(),
+            process: timers.process.last(),
+            encode: timers.encode.last(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_window_reports_last_complete_second() {
+        let window = TimingWindow::default();
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        window.record_at(ms(2), start);
+        window.record_at(ms(4), start + ms(500));
+        assert_eq!(window.last(), None);
+
+        window.record_at(ms(1), start + ms(1000));
+        let last = window.last().unwrap();
+        assert_eq!(last.count, 2);
+        assert_eq!(last.min_us, 2000.0);
+        assert_eq!(last.avg_us, 3000.0);
+        assert_eq!(last.max_us, 4000.0);
+
+        window.record_at(ms(1), start + ms(2000));
+        assert_eq!(window.last().unwrap().max_us, 1000.0);
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+/// Shortest sleep reported as a suspend; smaller differences are clock noise
+const MIN_SUSPEND: Duration = Duration::from_secs(1);
+
+fn read_clock(id: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid timespec to write to
+    unsafe { libc::clock_gettime(id, &mut ts) };
+    Duration::new(
+        ts.tv_sec.max(0) as u64,
+        ts.tv_nsec.clamp(0, 999_999_999) as u32,
+    )
+}
+
+/// Notices system suspends. CLOCK_BOOTTIME keeps counting while the machine
+/// sleeps and CLOCK_MONOTONIC doesn't, so the difference between them grows
+/// by the time spent suspended.
+#[derive(Debug)]
+pub struct SuspendDetector {
+    /// CLOCK_BOOTTIME minus CLOCK_MONOTONIC at the last poll
+    offset: Duration,
+}
+
+impl Default for SuspendDetector {
+    fn default() -> Self {
+        let mut detector = Self {
+            offset: Duration::ZERO,
+        };
+        detector.poll();
+        detector
+    }
+}
+
+impl SuspendDetector {
+    /// How long the system was suspended since the last poll, if it was
+    pub fn poll(&mut self) -> Option<Duration> {
+        self.observe(
+            read_clock(libc::CLOCK_MONOTONIC),
+            read_clock(libc::CLOCK_BOOTTIME),
+        )
+    }
+
+    fn observe(&mut self, monotonic: Duration, boottime: Duration) -> Option<Duration> {
+        let offset = boottime.saturating_sub(monotonic);
+        let slept = offset.saturating_sub(self.offset);
+        self.offset = offset;
+        (slept >= MIN_SUSPEND).then_some(slept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_growth_of_boottime_offset() {
+        let secs = Duration::from_secs;
+        let mut detector = SuspendDetector { offset: secs(5) };
+        assert_eq!(detector.observe(secs(100), secs(105)), None);
+        // Slept for 30 s between polls
+        assert_eq!(detector.observe(secs(101), secs(136)), Some(secs(30)));
+        assert_eq!(detector.observe(secs(102), secs(137)), None);
+        // Drift below the threshold is not a suspend
+        assert_eq!(
+            detector.observe(secs(103), secs(138) + Duration::from_millis(5)),
+            None
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! Tracing spans of a session's lifecycle (connect, negotiate, reconnect,
+//! finalize), one trace per session under a root "session" span. With the
+//! `otel` feature, `enable_tracing()` exports them to an OpenTelemetry
+//! collector over OTLP/HTTP; until then no span is recorded.
+//!
+//! The export is OTLP/JSON written here rather than the opentelemetry-otlp
+//! client: each batch of spans is POSTed by running curl, so batches are
+//! kept as far apart as the OpenTelemetry SDK's own.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Set once an exporter is running
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The trace of the session whose thread this is
+    static CURRENT: RefCell<SessionTrace> = RefCell::new(SessionTrace::default());
+}
+
+/// Check a W3C `traceparent` header value: version-trace_id-parent_id-flags
+pub fn check_traceparent(traceparent: &str) -> Result<(), String> {
+    parse_traceparent(traceparent).map(|_| ()).ok_or_else(|| {
+        format!(
+            "traceparent must look like 00-<32 hex digits>-<16 hex digits>-<2 hex digits>, got {:?}",
+            traceparent
+        )
+    })
+}
+
+fn parse_traceparent(traceparent: &str) -> Option<([u8; 16], [u8; 8])> {
+    let parts: Vec<&str> = traceparent.trim().split('-').collect();
+    let [version, trace_id, parent_id, flags] = parts[..] else {
+        return None;
+    };
+    if version.len() != 2 || flags.len() != 2 || u8::from_str_radix(flags, 16).is_err() {
+        return None;
+    }
+    let trace_id = from_hex::<16>(trace_id)?;
+    let parent_id = from_hex::<8>(parent_id)?;
+    // All zeros is invalid
+    (trace_id != [0; 16] && parent_id != [0; 8]).then_some((trace_id, parent_id))
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Random bytes for ids, from a counter and the clock (splitmix64)
+fn random_id<const N: usize>() -> [u8; N] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let mut state = nanos
+        ^ COUNTER.fetch_add(1, Ordering::Relaxed).rotate_left(32)
+        ^ u64::from(std::process::id());
+    let mut bytes = [0u8; N];
+    for chunk in bytes.chunks_mut(8) {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+    }
+    bytes
+}
+
+/// Value of a span attribute
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttrValue {
+    fn from(value: &str) -> Self {
+        AttrValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for AttrValue {
+    fn from(value: String) -> Self {
+        AttrValue::Str(value)
+    }
+}
+
+impl From<u32> for AttrValue {
+    fn from(value: u32) -> Self {
+        AttrValue::Int(i64::from(value))
+    }
+}
+
+impl From<u64> for AttrValue {
+    fn from(value: u64) -> Self {
+        AttrValue::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<bool> for AttrValue {
+    fn from(value: bool) -> Self {
+        AttrValue::Bool(value)
+    }
+}
+
+/// A finished span, as exported
+#[derive(Debug)]
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+pub struct SpanRecord {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    name: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, AttrValue)>,
+    /// Set when the operation failed
+    error: Option<String>,
+}
+
+/// An operation being timed; it ends when dropped. Does nothing when
+/// tracing isn't enabled.
+#[derive(Default)]
+pub struct Span {
+    record: Option<SpanRecord>,
+}
+
+impl Span {
+    pub fn attr(&mut self, key: &'static str, value: impl Into<AttrValue>) -> &mut Self {
+        if let Some(record) = &mut self.record {
+            record.attributes.push((key, value.into()));
+        }
+        self
+    }
+
+    /// Mark the operation failed
+    pub fn fail(&mut self, message: &str) -> &mut Self {
+        if let Some(record) = &mut self.record {
+            record.error = Some(message.to_string());
+        }
+        self
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut record) = self.record.take() {
+            record.end = SystemTime::now();
+            export(record);
+        }
+    }
+}
+
+struct TraceIds {
+    trace_id: [u8; 16],
+    /// The session's root span
+    root_id: [u8; 8],
+}
+
+/// A session's trace, whose spans are children of its root span. Empty
+/// (every span a no-op) when tracing wasn't enabled as the session started.
+#[derive(Clone, Default)]
+pub struct SessionTrace {
+    ids: Option<Arc<TraceIds>>,
+}
+
+impl SessionTrace {
+    /// Start a session's trace and its root span, which ends the trace when
+    /// dropped. A `traceparent` from the caller makes the session part of
+    /// the caller's trace.
+    pub fn start(traceparent: Option<&str>) -> (Self, Span) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return (Self::default(), Span::default());
+        }
+        let (trace_id, parent_id) = match traceparent.and_then(parse_traceparent) {
+            Some((trace_id, parent_id)) => (trace_id, Some(parent_id)),
+            None => (random_id(), None),
+        };
+        let ids = Arc::new(TraceIds {
+            trace_id,
+            root_id: random_id(),
+        });
+        let root = Span {
+            record: Some(SpanRecord {
+                trace_id,
+                span_id: ids.root_id,
+                parent_id,
+                name: "session",
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: Vec::new(),
+                error: None,
+            }),
+        };
+        (Self { ids: Some(ids) }, root)
+    }
+
+    /// The trace id as 32 hex digits, None when not traced
+    pub fn trace_id(&self) -> Option<String> {
+        self.ids.as_ref().map(|ids| to_hex(&ids.trace_id))
+    }
+
+    /// A span under the session's root span
+    pub fn span(&self, name: &'static str) -> Span {
+        Span {
+            record: self.ids.as_ref().map(|ids| SpanRecord {
+                trace_id: ids.trace_id,
+                span_id: random_id(),
+                parent_id: Some(ids.root_id),
+                name,
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    /// Make this the trace `span()` adds to on the current thread
+    pub fn enter(&self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.clone());
+    }
+}
+
+/// A span in the trace of the session whose thread this is, so code deep in
+/// the audio thread needn't be handed the trace
+pub fn span(name: &'static str) -> Span {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Span::default();
+    }
+    CURRENT.with(|current| current.borrow().span(name))
+}
+
+#[cfg(feature = "otel")]
+fn export(record: SpanRecord) {
+    exporter::send(record);
+}
+
+#[cfg(not(feature = "otel"))]
+fn export(_record: SpanRecord) {}
+
+#[cfg(feature = "otel")]
+pub use exporter::enable_tracing;
+
+#[cfg(feature = "otel")]
+mod exporter {
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::capture::upload::{check_curl, CurlHeaders};
+
+    /// Longest a finished span waits to be sent, the SDK's BatchSpanProcessor
+    /// default
+    const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+    const MAX_BATCH: usize = 512;
+
+    static SPANS: Mutex<Option<Sender<SpanRecord>>> = Mutex::new(None);
+
+    pub(super) fn send(record: SpanRecord) {
+        if let Some(tx) = SPANS.lock().ok().and_then(|tx| tx.clone()) {
+            let _ = tx.send(record);
+        }
+    }
+
+    /// Export spans to the OTLP/HTTP collector at `endpoint` (e.g.
+    /// `http://localhost:4318`), replacing any earlier exporter
+    pub fn enable_tracing(
+        endpoint: &str,
+        service_name: &str,
+        headers: HashMap<String, String>,
+    ) -> Result<(), String> {
+        if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+            return Err(format!(
+                "OTLP endpoint must be an http:// or https:// URL, got {:?}",
+                endpoint
+            ));
+        }
+        check_curl("enable_tracing")?;
+        let url = if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+        };
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", &AttrValue::from(service_name)),
+                attribute("service.version", &AttrValue::from(env!("CARGO_PKG_VERSION"))),
+            ]
+        });
+        let (tx, rx) = channel::<SpanRecord>();
+        thread::Builder::new()
+            .name("otlp-export".to_string())
+            .spawn(move || {
+                // Exits once replaced, after sending what it has
+                let mut batch = Vec::new();
+                let mut oldest = Instant::now();
+                loop {
+                    let wait = BATCH_INTERVAL.saturating_sub(oldest.elapsed());
+                    let disconnected = match rx.recv_timeout(wait) {
+                        Ok(record) => {
+                            if batch.is_empty() {
+                                oldest = Instant::now();
+                            }
+                            batch.push(record);
+                            false
+                        }
+                        Err(RecvTimeoutError::Timeout) => false,
+                        Err(RecvTimeoutError::Disconnected) => true,
+                    };
+                    let due = oldest.elapsed() >= BATCH_INTERVAL || batch.len() >= MAX_BATCH;
+                    if !batch.is_empty() && (due || disconnected) {
+                        let body = request(&resource, &batch);
+                        if let Err(e) = post(&url, &headers, &body.to_string()) {
+                            log!("Exporting {} spans failed: {}", batch.len(), e);
+                        }
+                        batch.clear();
+                    }
+                    if disconnected {
+                        return;
+                    }
+                    if batch.is_empty() {
+                        oldest = Instant::now();
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to start the span exporter: {}", e))?;
+        if let Ok(mut spans) = SPANS.lock() {
+            *spans = Some(tx);
+        }
+        ENABLED.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn attribute(key: &str, value: &AttrValue) -> Value {
+        let value = match value {
+            AttrValue::Str(s) => json!({ "stringValue": s }),
+            // 64-bit integers are strings in OTLP/JSON
+            AttrValue::Int(i) => json!({ "intValue": i.to_string() }),
+            AttrValue::Bool(b) => json!({ "boolValue": b }),
+        };
+        json!({ "key": key, "value": value })
+    }
+
+    fn unix_nanos(time: SystemTime) -> String {
+        time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0)
+            .to_string()
+    }
+
+    /// An ExportTraceServiceRequest in OTLP/JSON
+    pub(super) fn request(resource: &Value, spans: &[SpanRecord]) -> Value {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                // Status codes: 1 ok, 2 error
+                let status = match &span.error {
+                    Some(message) => json!({ "code": 2, "message": message }),
+                    None => json!({ "code": 1 }),
+                };
+                json!({
+                    "traceId": to_hex(&span.trace_id),
+                    "spanId": to_hex(&span.span_id),
+                    "parentSpanId": span.parent_id.map(|id| to_hex(&id)).unwrap_or_default(),
+                    "name": span.name,
+                    // Internal
+                    "kind": 1,
+                    "startTimeUnixNano": unix_nanos(span.start),
+                    "endTimeUnixNano": unix_nanos(span.end),
+                    "attributes": span
+                        .attributes
+                        .iter()
+                        .map(|(key, value)| attribute(key, value))
+                        .collect::<Vec<_>>(),
+                    "status": status,
+                })
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{
+                    "scope": { "name": "quinoa_audio", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }]
+        })
+    }
+
+    fn post(url: &str, headers: &HashMap<String, String>, body: &str) -> Result<(), String> {
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--fail", "--max-time", "10"])
+            .args(["--request", "POST", "--data-binary", "@-"])
+            .args(["--header", "Content-Type: application/json"]);
+        let headers = CurlHeaders::write(headers)?;
+        headers.add_to(&mut command);
+        let mut child = command
+            .arg(url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run curl: {}", e))?;
+        let written = child
+            .stdin
+            .take()
+            .ok_or_else(|| "curl has no stdin".to_string())
+            .and_then(|mut stdin| stdin.write_all(body.as_bytes()).map_err(|e| e.to_string()));
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Failed to wait for curl: {}", e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent_parsing() {
+        let (trace_id, parent_id) =
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(to_hex(&trace_id), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(to_hex(&parent_id), "00f067aa0ba902b7");
+
+        for invalid in [
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902bz-01",
+        ] {
+            assert!(check_traceparent(invalid).is_err(), "{}", invalid);
+        }
+        assert_ne!(random_id::<16>(), random_id::<16>());
+    }
+}
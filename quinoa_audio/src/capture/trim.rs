@@ -0,0 +1,184 @@
+//! Silence trimming: dead air cut from the start and end of each file once
+//! it is final, with the lengths cut kept in the manifest
+
+use hound::{SampleFormat, WavReader, WavWriter};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::Path;
+
+/// Length of the windows whose level decides what is silent
+const WINDOW_SECONDS: f64 = 0.02;
+/// Audio kept either side of the sound, so it doesn't start or end abruptly
+const PADDING_SECONDS: f64 = 0.25;
+
+/// Silence cut from a file, in seconds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrimmedSilence {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Frames from the first window at or above `threshold` (RMS, full scale
+/// 1.0) to the end of the last, padded; None if every window is quieter.
+/// Takes the interleaved samples as they are read, holding one window's
+/// energy at a time.
+fn sound_range(
+    samples: impl IntoIterator<Item = i16>,
+    channels: usize,
+    rate: u32,
+    threshold: f64,
+) -> Option<Range<usize>> {
+    let channels = channels.max(1);
+    let window = ((f64::from(rate) * WINDOW_SECONDS) as usize).max(1);
+    let threshold = threshold * f64::from(i16::MAX);
+    // First and last loud window, by their first frame
+    let mut loud: Option<(usize, usize)> = None;
+    let mut mark = |start: usize, energy: f64, count: usize| {
+        if (energy / count.max(1) as f64).sqrt() >= threshold {
+            loud = Some((loud.map_or(start, |(first, _)| first), start));
+        }
+    };
+    let (mut total, mut energy, mut count, mut start) = (0, 0.0, 0, 0);
+    for sample in samples {
+        energy += f64::from(sample) * f64::from(sample);
+        count += 1;
+        total += 1;
+        if count == window * channels {
+            mark(start, energy, count);
+            (energy, count, start) = (0.0, 0, start + window);
+        }
+    }
+    if count > 0 {
+        mark(start, energy, count);
+    }
+    let frames = total / channels;
+    let (first, last) = loud?;
+    let padding = (f64::from(rate) * PADDING_SECONDS) as usize;
+    Some(first.saturating_sub(padding)..(last + window + padding).min(frames))
+}
+
+/// Cut the audio quieter than `threshold_db` (dBFS) from the start and end
+/// of a 16-bit WAV file in place. Files in other formats, files that are
+/// silent throughout and files with nothing to cut are left alone (None).
+///
+/// The file is read twice, once to find the sound and once to copy it, so
+/// memory use doesn't grow with its length.
+pub fn trim_silence(path: &Path, threshold_db: f32) -> Result<Option<TrimmedSilence>, String> {
+    if path.extension().is_none_or(|ext| ext != "wav") {
+        return Ok(None);
+    }
+    let read_err = |e: hound::Error| format!("Failed to read {:?}: {}", path, e);
+    let mut reader = WavReader::open(path).map_err(read_err)?;
+    let spec = reader.spec();
+    if spec.sample_format != SampleFormat::Int || spec.bits_per_sample != 16 {
+        return Ok(None);
+    }
+    let channels = usize::from(spec.channels).max(1);
+    let frames = reader.duration() as usize;
+    let threshold = 10f64.powf(f64::from(threshold_db) / 20.0);
+    let mut failed = None;
+    let samples = reader
+        .samples::<i16>()
+        .map_while(|sample| sample.map_err(|e| failed = Some(e)).ok());
+    let keep = sound_range(samples, channels, spec.sample_rate, threshold);
+    if let Some(e) = failed {
+        return Err(read_err(e));
+    }
+    let Some(keep) = keep else {
+        return Ok(None);
+    };
+    if keep.start == 0 && keep.end == frames {
+        return Ok(None);
+    }
+
+    let tmp = path.with_extension("wav.tmp");
+    let mut write = || -> Result<(), hound::Error> {
+        reader.seek(keep.start as u32)?;
+        let mut writer = WavWriter::create(&tmp, spec)?;
+        for sample in reader.samples::<i16>().take(keep.len() * channels) {
+            writer.write_sample(sample?)?;
+        }
+        writer.finalize()
+    };
+    write().map_err(|e| format!("Failed to trim {:?}: {}", path, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Failed to replace {:?}: {}", path, e))?;
+    let rate = f64::from(spec.sample_rate.max(1));
+    Ok(Some(TrimmedSilence {
+        start: keep.start as f64 / rate,
+        end: (frames - keep.end) as f64 / rate,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::manifest::{ManifestWriter, SessionManifest, MANIFEST_FILE};
+    use crate::capture::session::RecordingConfig;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_sound_range_keeps_padding_around_the_sound() {
+        // 100 Hz mono: 2 s of silence, 1 s of sound, 3 s of silence
+        let mut samples = vec![0i16; 600];
+        samples[200..300].fill(8000);
+        let range = sound_range(samples, 1, 100, 0.01).unwrap();
+        assert_eq!(range, 175..325);
+
+        // Stereo frames count once; a quiet hiss stays below the threshold
+        let mut stereo = vec![30i16; 1200];
+        stereo[1000..1002].fill(8000);
+        assert_eq!(sound_range(stereo, 2, 100, 0.01).unwrap(), 475..527);
+
+        assert_eq!(sound_range([0; 600], 1, 100, 0.01), None);
+    }
+
+    #[test]
+    fn test_trims_a_wav_file_and_records_the_cut() {
+        let dir = TempDir::new("trim");
+        let path = dir.join("system.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 1000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        // 1 s of silence, 2 s of a square wave at -12 dBFS, 1.5 s of silence
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for frame in 0..4500 {
+            let level = if (1000..3000).contains(&frame) {
+                8000
+            } else {
+                0
+            };
+            let sample = if frame % 2 == 0 { level } else { -level };
+            writer.write_sample(sample as i16).unwrap();
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let trimmed = trim_silence(&path, -40.0).unwrap().unwrap();
+        assert_eq!(
+            trimmed,
+            TrimmedSilence {
+                start: 0.75,
+                end: 1.25
+            }
+        );
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec(), spec);
+        assert_eq!(reader.duration(), 2500);
+        let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+        assert!(samples[..500].iter().all(|&s| s == 0));
+        assert_eq!(&samples[500..504], [8000, 8000, -8000, -8000]);
+        // Nothing left to cut the second time
+        assert_eq!(trim_silence(&path, -40.0).unwrap(), None);
+
+        let mut config = RecordingConfig::default();
+        config.output_dir = Some(dir.to_string_lossy().into_owned());
+        let manifest = ManifestWriter::create(&config).unwrap();
+        manifest.record_trim(&path, trimmed);
+        let saved = SessionManifest::load(&dir.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(saved.trimmed.get("system.wav"), Some(&trimmed));
+        assert_eq!(manifest.trims().get(&path), Some(&trimmed));
+    }
+}
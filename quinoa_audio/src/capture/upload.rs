@@ -0,0 +1,586 @@
+//! Upload sink: each file a session finishes is sent to an HTTP endpoint
+//! (a presigned URL, an S3-compatible bucket) with `curl`, retried with
+//! backoff. The manifest records what was uploaded, so a resumed session
+//! or a new process only sends what's missing. A retry sends the whole file
+//! again, unless `upload_resume` says the server takes the rest of a file
+//! as a PUT with a Content-Range header; see `resume_point()`.
+//!
+//! Requests are made by running curl rather than with an HTTP client crate
+//! such as reqwest. Sessions run on plain threads, and reqwest would bring an
+//! async runtime, hyper and a TLS stack into a module every Python process
+//! loads, where curl already follows the system's TLS, CA and proxy
+//! settings. The webhook notifier and the trace exporter run curl the same
+//! way, with `CurlHeaders` keeping secrets off its command line.
+
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::capture::manifest::ManifestWriter;
+use crate::capture::reconnect::ReconnectPolicy;
+use crate::capture::secret::SecretFile;
+use crate::capture::session::InternalAudioEvent;
+use crate::errors::ConfigError;
+
+/// How often the worker looks for newly finished files
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Shortest time between progress events for a file
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// An upload sending nothing for this long is abandoned and retried
+const STALL_SECONDS: u32 = 60;
+
+/// Check an upload URL is one curl will PUT to
+pub fn check_upload_url(url: &str) -> Result<(), String> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(format!(
+            "upload_url must be an http:// or https:// URL, got {:?}",
+            url
+        ))
+    }
+}
+
+/// Check the `curl` command `setting` needs can be run
+pub fn check_curl(setting: &str) -> Result<(), String> {
+    Command::new("curl")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|_| ())
+        .map_err(|e| format!("{} needs the curl command: {}", setting, e))
+}
+
+/// Extra headers for a curl command, kept out of its argv
+pub struct CurlHeaders {
+    file: Option<SecretFile>,
+}
+
+impl CurlHeaders {
+    /// Write `headers` to a new file, or to none if there aren't any
+    pub fn write(headers: &HashMap<String, String>) -> Result<Self, String> {
+        if headers.is_empty() {
+            return Ok(Self { file: None });
+        }
+        let lines: String = headers
+            .iter()
+            .map(|(key, value)| format!("{}: {}\n", key, value))
+            .collect();
+        let file = SecretFile::write(&lines)
+            .map_err(|e| format!("Failed to write the request headers: {}", e))?;
+        Ok(Self { file: Some(file) })
+    }
+
+    /// Have `command` send the headers
+    pub fn add_to(&self, command: &mut Command) {
+        if let Some(file) = &self.file {
+            command
+                .arg("--header")
+                .arg(format!("@{}", file.path().display()));
+        }
+    }
+}
+
+/// Where a file is uploaded to
+pub enum UploadUrl {
+    /// URL with `{name}` standing for the file's name, or a base URL the
+    /// name is appended to
+    Template(String),
+    /// Python callable taking the file's name and returning its URL, e.g.
+    /// one presigned for that file
+    Presign(Py<PyAny>),
+}
+
+impl UploadUrl {
+    /// A URL string or a callable
+    pub fn from_py(url: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(url) = url.extract::<String>() {
+            check_upload_url(&url).map_err(ConfigError::new_err)?;
+            return Ok(UploadUrl::Template(url));
+        }
+        if url.is_callable() {
+            return Ok(UploadUrl::Presign(url.clone().unbind()));
+        }
+        Err(PyTypeError::new_err(
+            "Upload URL must be a string or a callable",
+        ))
+    }
+
+    /// The URL for the file named `name`, relative to the output directory
+    fn resolve(&self, name: &str) -> Result<String, String> {
+        match self {
+            UploadUrl::Template(template) => Ok(fill_template(template, name)),
+            UploadUrl::Presign(callback) => Python::with_gil(|py| {
+                let url = callback
+                    .call1(py, (name,))
+                    .and_then(|url| url.extract::<String>(py))
+                    .map_err(|e| e.to_string())?;
+                check_upload_url(&url).map(|_| url)
+            }),
+        }
+    }
+}
+
+fn fill_template(template: &str, name: &str) -> String {
+    if template.contains("{name}") {
+        template.replace("{name}", &encode_path(name))
+    } else {
+        format!("{}/{}", template.trim_end_matches('/'), encode_path(name))
+    }
+}
+
+/// Percent-encode a relative path for a URL, keeping its slashes
+fn encode_path(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(char::from(byte))
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[derive(Default)]
+struct UploadState {
+    /// A file is being uploaded
+    busy: bool,
+    stopped: bool,
+    /// Files given up on, by name and the checksum they had then; a file
+    /// rewritten since is tried again
+    failed: HashSet<(String, String)>,
+}
+
+#[derive(Default)]
+struct UploadShared {
+    url: Mutex<Option<UploadUrl>>,
+    state: Mutex<UploadState>,
+    /// Signalled when the worker goes idle or the uploader is dropped
+    changed: Condvar,
+}
+
+/// What the worker needs, held until it is started
+struct UploadWorker {
+    session_id: u64,
+    headers: HashMap<String, String>,
+    /// See `RecordingConfig.upload_resume`
+    resume: bool,
+    policy: ReconnectPolicy,
+    event_tx: Sender<InternalAudioEvent>,
+}
+
+/// Uploads the files listed in a session's manifest checksums from a
+/// thread of its own, started once there is a URL to upload to
+pub struct Uploader {
+    shared: Arc<UploadShared>,
+    manifest: Arc<ManifestWriter>,
+    worker: Mutex<Option<UploadWorker>>,
+}
+
+impl Uploader {
+    pub fn new(
+        session_id: u64,
+        manifest: Arc<ManifestWriter>,
+        headers: HashMap<String, String>,
+        resume: bool,
+        policy: ReconnectPolicy,
+        event_tx: Sender<InternalAudioEvent>,
+    ) -> Self {
+        Self {
+            shared: Arc::default(),
+            manifest,
+            worker: Mutex::new(Some(UploadWorker {
+                session_id,
+                headers,
+                resume,
+                policy,
+                event_tx,
+            })),
+        }
+    }
+
+    /// Upload to `url` from now on, starting the worker if needed. Files
+    /// given up on are tried again.
+    pub fn set_url(&self, url: UploadUrl) {
+        if let Ok(mut current) = self.shared.url.lock() {
+            *current = Some(url);
+        }
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.failed.clear();
+        }
+        let Some(worker) = self.worker.lock().ok().and_then(|mut w| w.take()) else {
+            return;
+        };
+        let shared = self.shared.clone();
+        let manifest = self.manifest.clone();
+        let session_id = worker.session_id;
+        let spawned = thread::Builder::new()
+            .name(format!("upload-{}", session_id))
+            .spawn(move || worker.run(&shared, &manifest));
+        if let Err(e) = spawned {
+            log!(session = session_id; "Failed to start uploads: {}", e);
+        }
+    }
+
+    /// Wait until every file finished so far is uploaded or given up on.
+    /// Returns true if they were all uploaded, false on a timeout or if
+    /// any were given up on or there's no URL to upload to.
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let has_url = self.shared.url.lock().is_ok_and(|url| url.is_some());
+        let Ok(mut state) = self.shared.state.lock() else {
+            return false;
+        };
+        while has_url && !state.stopped {
+            if !state.busy && pending(&self.manifest, &state).is_empty() {
+                break;
+            }
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) => left.min(POLL_INTERVAL),
+                    None => return false,
+                },
+                None => POLL_INTERVAL,
+            };
+            state = match self.shared.changed.wait_timeout(state, wait) {
+                Ok((state, _)) => state,
+                Err(_) => return false,
+            };
+        }
+        self.manifest.pending_uploads().is_empty()
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.stopped = true;
+        }
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Files to upload, leaving out those given up on
+fn pending(manifest: &ManifestWriter, state: &UploadState) -> Vec<(String, String)> {
+    manifest
+        .pending_uploads()
+        .into_iter()
+        .filter(|file| !state.failed.contains(file))
+        .collect()
+}
+
+impl UploadWorker {
+    fn run(self, shared: &UploadShared, manifest: &ManifestWriter) {
+        let Some(dir) = manifest.dir().map(Path::to_path_buf) else {
+            return;
+        };
+        loop {
+            let (name, checksum) = {
+                let Ok(mut state) = shared.state.lock() else {
+                    return;
+                };
+                loop {
+                    if state.stopped {
+                        return;
+                    }
+                    if let Some(file) = pending(manifest, &state).into_iter().next() {
+                        state.busy = true;
+                        break file;
+                    }
+                    state.busy = false;
+                    shared.changed.notify_all();
+                    state = match shared.changed.wait_timeout(state, POLL_INTERVAL) {
+                        Ok((state, _)) => state,
+                        Err(_) => return,
+                    };
+                }
+            };
+            let path = dir.join(&name);
+            if self.upload_with_retries(shared, &name, &path) {
+                manifest.record_upload(&name, &checksum);
+                let _ = self.event_tx.send(InternalAudioEvent::Uploaded(path));
+            } else if let Ok(mut state) = shared.state.lock() {
+                state.failed.insert((name, checksum));
+            }
+        }
+    }
+
+    /// Upload a file, retrying as the policy allows. Returns false once it
+    /// gave up or the uploader was dropped.
+    fn upload_with_retries(&self, shared: &UploadShared, name: &str, path: &Path) -> bool {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let url = match shared.url.lock() {
+                Ok(url) => url.as_ref().map(|url| url.resolve(name)),
+                Err(_) => return false,
+            };
+            let result = match url {
+                Some(Ok(url)) => self.upload(&url, path, attempt > 1),
+                Some(Err(e)) => Err(format!("No upload URL for {:?}: {}", name, e)),
+                None => return false,
+            };
+            let Err(message) = result else {
+                return true;
+            };
+            log!("Uploading {:?} failed: {}", path, message);
+            let retry = self
+                .policy
+                .should_retry(attempt)
+                .then(|| self.policy.delay(attempt));
+            let _ = self.event_tx.send(InternalAudioEvent::UploadFailed {
+                path: path.to_path_buf(),
+                message,
+                attempt,
+                retry,
+            });
+            let Some(delay) = retry else {
+                return false;
+            };
+            if sleep_unless_stopped(shared, delay) {
+                return false;
+            }
+        }
+    }
+
+    /// PUT a file to `url`, fed to curl through its stdin so progress can
+    /// be reported as it goes. A `retry` with `resume` set sends only what
+    /// the server is missing.
+    fn upload(&self, url: &str, path: &Path, retry: bool) -> Result<(), String> {
+        let err = |e: std::io::Error| format!("Failed to read {:?}: {}", path, e);
+        let mut file = File::open(path).map_err(err)?;
+        let total = file.metadata().map_err(err)?.len();
+        let headers = CurlHeaders::write(&self.headers)?;
+        let start = if self.resume && retry {
+            resume_point(url, &headers, &mut file, total)
+        } else {
+            0
+        };
+        file.seek(SeekFrom::Start(start)).map_err(err)?;
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--fail"])
+            .args(["--request", "PUT", "--upload-file", "-"])
+            // A known length instead of chunked encoding, which presigned
+            // S3 URLs refuse
+            .arg("--header")
+            .arg(format!("Content-Length: {}", total - start))
+            .args(["--header", "Transfer-Encoding:", "--header", "Expect:"])
+            .args(["--connect-timeout", "30", "--speed-limit", "1"])
+            .arg("--speed-time")
+            .arg(STALL_SECONDS.to_string());
+        if start > 0 {
+            command.arg("--header").arg(format!(
+                "Content-Range: bytes {}-{}/{}",
+                start,
+                total - 1,
+                total
+            ));
+        }
+        headers.add_to(&mut command);
+        let mut child = command
+            .arg(url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run curl: {}", e))?;
+        let mut stdin = child.stdin.take().ok_or("curl has no stdin")?;
+
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut sent = start;
+        let mut reported = Instant::now();
+        let fed = loop {
+            let n = match file.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(n) => n,
+                Err(e) => break Err(err(e)),
+            };
+            // curl exiting early (e.g. on an error response) closes the pipe
+            if let Err(e) = stdin.write_all(&buffer[..n]) {
+                break Err(format!("curl stopped reading: {}", e));
+            }
+            sent += n as u64;
+            if reported.elapsed() >= PROGRESS_INTERVAL {
+                reported = Instant::now();
+                self.progress(path, sent, total);
+            }
+        };
+        drop(stdin);
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Failed to wait for curl: {}", e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        fed?;
+        self.progress(path, sent, total);
+        Ok(())
+    }
+
+    fn progress(&self, path: &Path, sent: u64, total: u64) {
+        let _ = self.event_tx.send(InternalAudioEvent::UploadProgress {
+            path: PathBuf::from(path),
+            sent,
+            total,
+        });
+    }
+}
+
+/// Where to carry on uploading `file` (`total` bytes long) to `url`: the
+/// length of the copy there if it is the start of the file, otherwise 0. A
+/// copy from an earlier recording under the same name doesn't match.
+fn resume_point(url: &str, headers: &CurlHeaders, file: &mut File, total: u64) -> u64 {
+    let Some(len) = remote_size(url, headers).filter(|&len| len > 0 && len < total) else {
+        return 0;
+    };
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--fail", "--max-time", "300", "--range"])
+        .arg(format!("0-{}", len - 1));
+    headers.add_to(&mut command);
+    let Ok(mut child) = command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return 0;
+    };
+    let matches = child
+        .stdout
+        .take()
+        .is_some_and(|remote| is_prefix(remote, &mut *file, len));
+    if !matches {
+        let _ = child.kill();
+    }
+    match child.wait() {
+        Ok(status) if matches && status.success() => len,
+        _ => 0,
+    }
+}
+
+/// Whether `remote` reads as exactly the first `len` bytes of `local`
+fn is_prefix(mut remote: impl Read, local: impl Read, len: u64) -> bool {
+    let mut local = local.take(len);
+    let mut theirs = vec![0u8; 64 * 1024];
+    let mut ours = vec![0u8; 64 * 1024];
+    loop {
+        let n = match remote.read(&mut theirs) {
+            Ok(0) => return local.read(&mut ours).is_ok_and(|n| n == 0),
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        if local.read_exact(&mut ours[..n]).is_err() || theirs[..n] != ours[..n] {
+            return false;
+        }
+    }
+}
+
+/// Size of the copy of a file at `url`, if the server answers a HEAD request
+/// for it
+fn remote_size(url: &str, headers: &CurlHeaders) -> Option<u64> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--fail", "--head", "--max-time", "30"]);
+    headers.add_to(&mut command);
+    let output = command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    content_length(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The Content-Length of a response's headers; the last one, after redirects
+fn content_length(head: &str) -> Option<u64> {
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .filter_map(|(_, value)| value.trim().parse().ok())
+        .next_back()
+}
+
+/// Sleep for `delay`, waking early if the uploader is dropped. Returns true
+/// if it was.
+fn sleep_unless_stopped(shared: &UploadShared, delay: Duration) -> bool {
+    let Ok(state) = shared.state.lock() else {
+        return true;
+    };
+    shared
+        .changed
+        .wait_timeout_while(state, delay, |state| !state.stopped)
+        .map_or(true, |(state, _)| state.stopped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_urls_for_file_names() {
+        let name = "2024-05-01 call/microphone.wav";
+        let url = |template: &str| fill_template(template, name);
+        assert_eq!(
+            url("https://bucket.example.com/rec/{name}?x-id=PutObject"),
+            "https://bucket.example.com/rec/2024-05-01%20call/microphone.wav?x-id=PutObject"
+        );
+        assert_eq!(
+            url("https://bucket.example.com/rec/"),
+            "https://bucket.example.com/rec/2024-05-01%20call/microphone.wav"
+        );
+        assert!(check_upload_url("ftp://example.com/").is_err());
+    }
+
+    #[test]
+    fn test_content_length_of_the_final_response() {
+        let head = "HTTP/1.1 301 Moved\r\nContent-Length: 0\r\nLocation: /b\r\n\r\n\
+                    HTTP/1.1 200 OK\r\ncontent-length: 1234\r\n\r\n";
+        assert_eq!(content_length(head), Some(1234));
+        assert_eq!(content_length("HTTP/1.1 200 OK\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn test_only_the_start_of_the_file_is_a_prefix() {
+        let local = b"RIFF....WAVEfmt ";
+        assert!(is_prefix(&local[..8], &local[..], 8));
+        // Too short, too long or different
+        assert!(!is_prefix(&local[..7], &local[..], 8));
+        assert!(!is_prefix(&local[..9], &local[..], 8));
+        assert!(!is_prefix(&b"RIFX...."[..], &local[..], 8));
+    }
+
+    #[test]
+    fn test_headers_are_sent_from_a_file() {
+        let headers = HashMap::from([("Authorization".to_string(), "Bearer abc".to_string())]);
+        let file = CurlHeaders::write(&headers).unwrap();
+        let mut command = Command::new("curl");
+        file.add_to(&mut command);
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args[0], "--header");
+        let path = args[1].to_str().unwrap().strip_prefix('@').unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "Authorization: Bearer abc\n"
+        );
+        // No headers, no file
+        let mut command = Command::new("curl");
+        CurlHeaders::write(&HashMap::new())
+            .unwrap()
+            .add_to(&mut command);
+        assert_eq!(command.get_args().count(), 0);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Format of the virtual microphone node: mono at this rate, whatever the
+/// recorded mic delivers
+pub const VIRTUAL_MIC_RATE: u32 = 48000;
+
+/// Audio held for the virtual microphone before the oldest is dropped; a
+/// reader that falls behind hears a skip rather than growing latency
+const MAX_BUFFERED_FRAMES: usize = VIRTUAL_MIC_RATE as usize / 5;
+
+#[derive(Debug)]
+struct TapState {
+    buffer: VecDeque<f32>,
+    /// Position of the next output sample between `previous` (0.0) and the
+    /// next input sample (1.0)
+    position: f64,
+    previous: f32,
+}
+
+/// Copy of the mic stream for the virtual microphone, downmixed to mono and
+/// resampled to `VIRTUAL_MIC_RATE`. The capture callback pushes, the
+/// virtual microphone's callback pulls.
+#[derive(Debug)]
+pub struct MicTap {
+    state: Mutex<TapState>,
+}
+
+impl Default for MicTap {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(TapState {
+                buffer: VecDeque::with_capacity(MAX_BUFFERED_FRAMES),
+                position: 0.0,
+                previous: 0.0,
+            }),
+        }
+    }
+}
+
+impl MicTap {
+    /// Add interleaved samples captured at `rate`
+    pub fn push(&self, samples: &[f32], channels: usize, rate: u32) {
+        let channels = channels.max(1);
+        let step = f64::from(rate) / f64::from(VIRTUAL_MIC_RATE);
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        for frame in samples.chunks_exact(channels) {
+            let sample = frame.iter().sum::<f32>() / channels as f32;
+            // Linear interpolation between the previous and this sample
+            while state.position < 1.0 {
+                let t = state.position as f32;
+                let value = state.previous + (sample - state.previous) * t;
+                if state.buffer.len() == MAX_BUFFERED_FRAMES {
+                    state.buffer.pop_front();
+                }
+                state.buffer.push_back(value);
+                state.position += step;
+            }
+            state.position -= 1.0;
+            state.previous = sample;
+        }
+    }
+
+    /// Fill `out` with the oldest samples, padding with silence when the mic
+    /// hasn't delivered enough. Returns how many samples came from the mic.
+    pub fn pull(&self, out: &mut [f32]) -> usize {
+        let Ok(mut state) = self.state.lock() else {
+            out.fill(0.0);
+            return 0;
+        };
+        let available = state.buffer.len().min(out.len());
+        for (slot, sample) in out.iter_mut().zip(state.buffer.drain(..available)) {
+            *slot = sample;
+        }
+        out[available..].fill(0.0);
+        available
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tap_downmixes_and_resamples() {
+        // Same rate: stereo averaged, one sample of latency
+        let tap = MicTap::default();
+        tap.push(&[0.2, 0.4, 0.6, 0.8], 2, 48000);
+        let mut out = [1.0; 4];
+        assert_eq!(tap.pull(&mut out), 2);
+        assert_eq!(out, [0.0, 0.3, 0.0, 0.0]);
+
+        // 24 kHz doubles the sample count, interpolating between samples
+        let tap = MicTap::default();
+        tap.push(&[0.5, 1.0], 1, 24000);
+        let mut out = [0.0; 4];
+        assert_eq!(tap.pull(&mut out), 4);
+        assert_eq!(out, [0.0, 0.25, 0.5, 0.75]);
+    }
+}
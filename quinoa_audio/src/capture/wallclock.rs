@@ -0,0 +1,68 @@
+//! Cutting recordings at wall-clock boundaries, e.g. the top of each hour
+
+use std::time::Duration;
+
+/// Broken-down local time of Unix time `unix`
+pub fn local_tm(unix: f64) -> libc::tm {
+    let secs = unix as libc::time_t;
+    // SAFETY: tm is plain old data, for which all zeros is a valid value,
+    // and localtime_r only writes to the out pointer it is given (unlike
+    // localtime, it shares no static buffer between threads)
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    }
+}
+
+/// Seconds east of UTC of the local time zone at Unix time `unix`
+pub fn utc_offset(unix: f64) -> i64 {
+    local_tm(unix).tm_gmtoff
+}
+
+/// Frames in `period` at `rate`
+pub fn period_frames(period: Duration, rate: u32) -> u64 {
+    (period.as_secs_f64() * f64::from(rate)).round() as u64
+}
+
+/// Frames at `rate` from Unix time `now` to the next multiple of `period`
+/// since local midnight. A time right on a boundary is a whole period away
+/// from the next one.
+pub fn frames_to_boundary(now: f64, utc_offset: i64, period: Duration, rate: u32) -> u64 {
+    let period_secs = period.as_secs_f64();
+    let into = (now + utc_offset as f64).rem_euclid(period_secs);
+    match ((period_secs - into) * f64::from(rate)).round() as u64 {
+        0 => period_frames(period, rate),
+        frames => frames,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_to_boundary() {
+        let minute = Duration::from_secs(60);
+        let hour = Duration::from_secs(3600);
+        // 2024-01-31 14:25:00.5 UTC
+        let now = 1706711100.5;
+        assert_eq!(
+            frames_to_boundary(now, 0, minute, 48000),
+            59 * 48000 + 24000
+        );
+        assert_eq!(
+            frames_to_boundary(now, 0, hour, 1000),
+            (34 * 60 + 59) * 1000 + 500
+        );
+        // UTC+05:30 is 19:55:00.5 local
+        assert_eq!(
+            frames_to_boundary(now, 19800, hour, 1000),
+            (4 * 60 + 59) * 1000 + 500
+        );
+        assert_eq!(
+            frames_to_boundary(1706711100.0, 0, minute, 48000),
+            60 * 48000
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Webhook notifications: session lifecycle events POSTed as JSON to a
+//! configured URL with `curl`, for services that keep track of recordings
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use crate::capture::manifest::unix_now;
+use crate::capture::reconnect::ReconnectPolicy;
+use crate::capture::upload::CurlHeaders;
+
+/// Version of the notification format, sent as "schema"
+const WEBHOOK_SCHEMA: u32 = 1;
+/// Attempts at delivering a notification before dropping it
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Check a webhook URL is one curl will POST to
+pub fn check_webhook_url(url: &str) -> Result<(), String> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(format!(
+            "webhook_url must be an http:// or https:// URL, got {:?}",
+            url
+        ))
+    }
+}
+
+/// Sends a session's notifications in order from a thread of its own. The
+/// thread delivers what is queued and exits once every handle is dropped,
+/// so the last notification goes out after the session is gone.
+pub struct Notifier {
+    tx: Sender<Value>,
+    session_id: u64,
+    output_dir: Option<PathBuf>,
+}
+
+impl Notifier {
+    pub fn start(
+        session_id: u64,
+        url: String,
+        headers: HashMap<String, String>,
+        output_dir: Option<PathBuf>,
+        policy: ReconnectPolicy,
+    ) -> Self {
+        let (tx, rx) = channel::<Value>();
+        let policy = ReconnectPolicy {
+            max_attempts: Some(MAX_ATTEMPTS),
+            ..policy
+        };
+        let spawned = thread::Builder::new()
+            .name(format!("webhook-{}", session_id))
+            .spawn(move || {
+                for notification in rx {
+                    deliver(&url, &headers, &notification, &policy);
+                }
+            });
+        if let Err(e) = spawned {
+            log!(session = session_id; "Failed to start webhook notifications: {}", e);
+        }
+        Self {
+            tx,
+            session_id,
+            output_dir,
+        }
+    }
+
+    /// Queue a notification of `event` with the fields every one has
+    fn send(&self, event: &str, fields: Value) {
+        let mut notification = json!({
+            "schema": WEBHOOK_SCHEMA,
+            "event": event,
+            "session_id": self.session_id,
+            "output_dir": self.output_dir,
+            "time": unix_now(),
+        });
+        if let (Some(notification), Value::Object(fields)) = (notification.as_object_mut(), fields)
+        {
+            notification.extend(fields);
+        }
+        let _ = self.tx.send(notification);
+    }
+
+    pub fn started(&self, mic_device_id: Option<&str>, system_audio: bool) {
+        self.send(
+            "started",
+            json!({ "mic_device_id": mic_device_id, "system_audio": system_audio }),
+        );
+    }
+
+    /// A file is final: redacted and post-processed
+    pub fn segment_finalized(&self, path: &Path) {
+        self.send(
+            "segment_finalized",
+            json!({
+                "path": path,
+                "name": self.name(path),
+                "size": std::fs::metadata(path).map(|m| m.len()).ok(),
+            }),
+        );
+    }
+
+    /// The session ended, on an error if `failure` is given, with these
+    /// files
+    pub fn ended(&self, failure: Option<&str>, files: &[PathBuf]) {
+        let names: Vec<String> = files.iter().map(|path| self.name(path)).collect();
+        match failure {
+            Some(message) => self.send("failed", json!({ "message": message, "files": names })),
+            None => self.send("stopped", json!({ "files": names })),
+        }
+    }
+
+    /// Path relative to the output directory
+    fn name(&self, path: &Path) -> String {
+        self.output_dir
+            .as_deref()
+            .and_then(|dir| path.strip_prefix(dir).ok())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// POST a notification, retrying as the policy allows
+fn deliver(
+    url: &str,
+    headers: &HashMap<String, String>,
+    notification: &Value,
+    policy: &ReconnectPolicy,
+) {
+    let body = notification.to_string();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let Err(e) = post(url, headers, &body) else {
+            return;
+        };
+        if !policy.should_retry(attempt) {
+            log!(
+                "Webhook {} notification dropped after {} attempts: {}",
+                notification["event"],
+                attempt,
+                e
+            );
+            return;
+        }
+        let delay = policy.delay(attempt);
+        log!(
+            "Webhook {} notification failed: {}, retrying in {:.1}s",
+            notification["event"],
+            e,
+            delay.as_secs_f64()
+        );
+        thread::sleep(delay);
+    }
+}
+
+fn post(url: &str, headers: &HashMap<String, String>, body: &str) -> Result<(), String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--fail", "--max-time", "30"])
+        .args(["--request", "POST", "--data-binary", "@-"])
+        .args(["--header", "Content-Type: application/json"]);
+    let headers = CurlHeaders::write(headers)?;
+    headers.add_to(&mut command);
+    let mut child = command
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    let written = child
+        .stdin
+        .take()
+        .ok_or_else(|| "curl has no stdin".to_string())
+        .and_then(|mut stdin| stdin.write_all(body.as_bytes()).map_err(|e| e.to_string()));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to wait for curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications_carry_the_session_fields() {
+        let (tx, rx) = channel();
+        let notifier = Notifier {
+            tx,
+            session_id: 7,
+            output_dir: Some(PathBuf::from("/rec/call")),
+        };
+        notifier.ended(
+            None,
+            &[
+                PathBuf::from("/rec/call/microphone.wav"),
+                PathBuf::from("/elsewhere/system.wav"),
+            ],
+        );
+        let notification = rx.recv().unwrap();
+        assert_eq!(notification["schema"], 1);
+        assert_eq!(notification["event"], "stopped");
+        assert_eq!(notification["session_id"], 7);
+        assert_eq!(notification["output_dir"], "/rec/call");
+        assert_eq!(
+            notification["files"],
+            json!(["microphone.wav", "/elsewhere/system.wav"])
+        );
+
+        notifier.ended(Some("PipeWire went away"), &[]);
+        let notification = rx.recv().unwrap();
+        assert_eq!(notification["event"], "failed");
+        assert_eq!(notification["message"], "PipeWire went away");
+    }
+}
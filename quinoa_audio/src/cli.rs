@@ -0,0 +1,466 @@
+//! The `quinoa-audio` companion binary: list devices, follow hotplug and
+//! record from a shell, running the same code as the Python module
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::capture::checksum::{verify_session, SessionVerification};
+use crate::capture::manifest::unix_now;
+use crate::capture::plugin::EncoderPlugins;
+use crate::capture::session::{start_recording_impl, AudioEvent, RecordingConfig};
+use crate::{list_devices, subscribe_device_changes, Device, DeviceEvent, DeviceType};
+
+const USAGE: &str = "\
+Usage: quinoa-audio [--json] <command> [options]
+
+  --json                  Print JSON lines instead of text, for supervisors
+                          driving the CLI over a pipe
+
+Commands:
+  list                    List audio devices, the defaults marked with *
+  monitor                 Print device events until interrupted
+  record --output DIR     Record until interrupted
+         [--mic ID]           Microphone to record (default: the default one)
+         [--system]           Also record system audio
+         [--config FILE]      RecordingConfig as TOML or JSON (.json)
+         [--duration SECS]    Stop after this long
+  verify MANIFEST         Check a recording's files against the checksums
+                          in its session.json; exits 1 if any don't match
+";
+
+/// How often the monitor and sessions are polled for events
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a stop waits for the capture thread
+const STOP_TIMEOUT: f64 = 5.0;
+
+/// Version of the `--json` line format. Fields may be added without a bump;
+/// it changes when one is renamed, removed or changes meaning.
+const JSON_SCHEMA: u32 = 1;
+
+/// Set by SIGINT and SIGTERM, to stop cleanly
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, PartialEq)]
+enum CliError {
+    /// Bad arguments; the usage is printed
+    Usage(String),
+    Failed(String),
+}
+
+impl From<PyErr> for CliError {
+    fn from(err: PyErr) -> Self {
+        CliError::Failed(err.to_string())
+    }
+}
+
+/// Run the command given on the command line; returns the exit code
+pub fn main() -> i32 {
+    // Errors are Python exceptions, which need an interpreter to format
+    pyo3::prepare_freethreaded_python();
+
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let out = match args.iter().position(|arg| arg == "--json") {
+        Some(index) => {
+            args.remove(index);
+            Output::Json
+        }
+        None => Output::Text,
+    };
+    let (command, options) = match args.split_first() {
+        Some((command, options)) => (command.as_str(), options),
+        None => ("help", &[][..]),
+    };
+    let result = match command {
+        "list" => no_options(options).and_then(|()| list(out)),
+        "monitor" => no_options(options).and_then(|()| monitor(out)),
+        "record" => RecordArgs::parse(options).and_then(|args| record(args, out)),
+        "verify" => match options {
+            [manifest] => verify(Path::new(manifest), out),
+            _ => Err(CliError::Usage("verify needs a session.json".into())),
+        },
+        "help" | "-h" | "--help" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(CliError::Usage(format!("unknown command {:?}", other))),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(CliError::Usage(message)) => {
+            out.error(&message, || {
+                eprintln!("quinoa-audio: {}\n\n{}", message, USAGE)
+            });
+            2
+        }
+        Err(CliError::Failed(message)) => {
+            out.error(&message, || eprintln!("quinoa-audio: {}", message));
+            1
+        }
+    }
+}
+
+fn no_options(options: &[String]) -> Result<(), CliError> {
+    match options.first() {
+        Some(option) => Err(CliError::Usage(format!("unexpected argument {:?}", option))),
+        None => Ok(()),
+    }
+}
+
+fn catch_signals() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic, which is
+    // async-signal-safe, and is a valid extern "C" fn for the whole process
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// How results and events are printed
+#[derive(Clone, Copy)]
+enum Output {
+    Text,
+    Json,
+}
+
+/// A line of `--json` output: the fields of the device or event, under the
+/// version of the format, where it came from and when
+#[derive(Serialize)]
+struct JsonLine<'a, T: Serialize> {
+    schema: u32,
+    /// "list" for devices, "monitor" and "session" for their events, "cli"
+    /// for errors of the CLI itself
+    source: &'a str,
+    /// Unix time in seconds
+    time: f64,
+    #[serde(flatten)]
+    body: T,
+}
+
+#[derive(Serialize)]
+struct CliErrorLine<'a> {
+    #[serde(rename = "type")]
+    type_: &'a str,
+    message: &'a str,
+}
+
+impl Output {
+    fn json<T: Serialize>(source: &str, body: T) {
+        let line = JsonLine {
+            schema: JSON_SCHEMA,
+            source,
+            time: unix_now(),
+            body,
+        };
+        match serde_json::to_string(&line) {
+            Ok(line) => println!("{}", line),
+            Err(e) => log!("Can't serialize output: {}", e),
+        }
+    }
+
+    fn device(self, device: &Device) {
+        match self {
+            Output::Json => Self::json("list", device),
+            Output::Text => {
+                let kind = match device.device_type {
+                    DeviceType::Microphone => "mic",
+                    DeviceType::Speaker => "speaker",
+                    DeviceType::Monitor => "monitor",
+                };
+                println!(
+                    "{} {:<8} {}  {} ({} Hz, {} ch)",
+                    if device.is_default { "*" } else { " " },
+                    kind,
+                    device.id,
+                    device.name,
+                    device.sample_rate,
+                    device.channels
+                );
+            }
+        }
+    }
+
+    fn device_event(self, event: &DeviceEvent) {
+        match self {
+            Output::Json => Self::json("monitor", event),
+            Output::Text => println!(
+                "{}",
+                describe(
+                    &event.type_,
+                    [&event.device_id, &event.device_name, &event.state]
+                )
+            ),
+        }
+    }
+
+    fn audio_event(self, event: &AudioEvent) {
+        match self {
+            Output::Json => Self::json("session", event),
+            // Too many to read
+            Output::Text if event.type_ == "levels" => {}
+            Output::Text => println!(
+                "{}",
+                describe(
+                    &event.type_,
+                    [&event.stream, &event.device_id, &event.message]
+                )
+            ),
+        }
+    }
+
+    fn verification(self, result: &SessionVerification) {
+        match self {
+            Output::Json => Self::json("verify", result),
+            Output::Text => {
+                for name in &result.missing {
+                    println!("missing   {}", name);
+                }
+                for name in &result.mismatched {
+                    println!("mismatch  {}", name);
+                }
+                println!("{} files verified", result.verified.len());
+            }
+        }
+    }
+
+    /// Report an error: as a JSON line on stdout, or with `text`
+    fn error(self, message: &str, text: impl FnOnce()) {
+        match self {
+            Output::Json => Self::json(
+                "cli",
+                CliErrorLine {
+                    type_: "error",
+                    message,
+                },
+            ),
+            Output::Text => text(),
+        }
+    }
+}
+
+/// The type of an event followed by the details it has
+fn describe<const N: usize>(type_: &str, details: [&Option<String>; N]) -> String {
+    let mut line = type_.to_string();
+    for detail in details.into_iter().flatten() {
+        line.push(' ');
+        line.push_str(detail);
+    }
+    line
+}
+
+fn list(out: Output) -> Result<(), CliError> {
+    for device in list_devices()? {
+        out.device(&device);
+    }
+    Ok(())
+}
+
+fn monitor(out: Output) -> Result<(), CliError> {
+    catch_signals();
+    let mut monitor = subscribe_device_changes()?;
+    while !INTERRUPTED.load(Ordering::SeqCst) && monitor.is_running() {
+        for event in monitor.poll()? {
+            out.device_event(&event);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Python::with_gil(|py| monitor.stop(py, Some(STOP_TIMEOUT)))?;
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct RecordArgs {
+    output: String,
+    mic: Option<String>,
+    system: bool,
+    config: Option<String>,
+    duration: Option<Duration>,
+}
+
+impl RecordArgs {
+    fn parse(options: &[String]) -> Result<Self, CliError> {
+        let mut args = RecordArgs::default();
+        let mut output = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let mut value = || {
+                options
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| CliError::Usage(format!("{} needs a value", option)))
+            };
+            match option.as_str() {
+                "--output" => output = Some(value()?),
+                "--mic" => args.mic = Some(value()?),
+                "--system" => args.system = true,
+                "--config" => args.config = Some(value()?),
+                "--duration" => {
+                    let value = value()?;
+                    let seconds = value
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                        .ok_or_else(|| {
+                            CliError::Usage(format!("invalid --duration {:?}", value))
+                        })?;
+                    args.duration = Some(seconds);
+                }
+                other => return Err(CliError::Usage(format!("unknown option {:?}", other))),
+            }
+        }
+        args.output = output.ok_or_else(|| CliError::Usage("record needs --output".into()))?;
+        Ok(args)
+    }
+
+    fn config(&self) -> Result<RecordingConfig, CliError> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| CliError::Failed(format!("Can't read {}: {}", path, e)))?;
+                if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+                    RecordingConfig::from_json(&text)?
+                } else {
+                    RecordingConfig::from_toml(&text)?
+                }
+            }
+            None => RecordingConfig::default(),
+        };
+        config.output_dir = Some(self.output.clone());
+        config.mic_device_id = match (&self.mic, config.mic_device_id.take()) {
+            (Some(mic), _) => Some(mic.clone()),
+            (None, Some(mic)) => Some(mic),
+            (None, None) => list_devices()?
+                .into_iter()
+                .find(|d| d.device_type == DeviceType::Microphone && d.is_default)
+                .map(|d| d.id),
+        };
+        config.system_audio |= self.system;
+        Ok(config)
+    }
+}
+
+fn record(args: RecordArgs, out: Output) -> Result<(), CliError> {
+    let config = args.config()?;
+    catch_signals();
+    let session = start_recording_impl(config, EncoderPlugins::default())?;
+    let started = Instant::now();
+    let mut finished = false;
+    while !finished
+        && !INTERRUPTED.load(Ordering::SeqCst)
+        && args
+            .duration
+            .is_none_or(|duration| started.elapsed() < duration)
+    {
+        for event in session.poll_events()? {
+            out.audio_event(&event);
+            finished |= matches!(
+                event.type_.as_str(),
+                "stopped" | "error" | "reconnect_failed"
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    if !Python::with_gil(|py| session.stop(py, Some(STOP_TIMEOUT)))? {
+        return Err(CliError::Failed(
+            "The capture thread did not stop in time".to_string(),
+        ));
+    }
+    for event in session.poll_events()? {
+        out.audio_event(&event);
+    }
+    Ok(())
+}
+
+fn verify(manifest: &Path, out: Output) -> Result<(), CliError> {
+    let result = verify_session(manifest)?;
+    out.verification(&result);
+    if !result.ok {
+        return Err(CliError::Failed(format!(
+            "{} files missing, {} don't match their checksums",
+            result.missing.len(),
+            result.mismatched.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_lines_flatten_the_event() {
+        let event = DeviceEvent {
+            type_: "added".to_string(),
+            device_id: Some("alsa_input.usb-mic".to_string()),
+            device_name: Some("USB Mic".to_string()),
+            state: None,
+            initial: false,
+        };
+        let line = JsonLine {
+            schema: JSON_SCHEMA,
+            source: "monitor",
+            time: 1700000000.5,
+            body: &event,
+        };
+        assert_eq!(
+            serde_json::to_value(&line).unwrap(),
+            serde_json::json!({
+                "schema": 1,
+                "source": "monitor",
+                "time": 1700000000.5,
+                "type": "added",
+                "device_id": "alsa_input.usb-mic",
+                "device_name": "USB Mic",
+                "state": null,
+                "initial": false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_record_args() {
+        let args = |args: &[&str]| {
+            RecordArgs::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            args(&[
+                "--mic",
+                "alsa_input.usb",
+                "--output",
+                "/tmp/rec",
+                "--system"
+            ]),
+            Ok(RecordArgs {
+                output: "/tmp/rec".to_string(),
+                mic: Some("alsa_input.usb".to_string()),
+                system: true,
+                ..RecordArgs::default()
+            })
+        );
+        assert_eq!(
+            args(&["--output", "out", "--duration", "1.5"]).map(|a| a.duration),
+            Ok(Some(Duration::from_millis(1500)))
+        );
+        assert!(matches!(args(&["--system"]), Err(CliError::Usage(_))));
+        assert!(matches!(args(&["--output"]), Err(CliError::Usage(_))));
+        assert!(matches!(
+            args(&["--output", "out", "--duration", "-1"]),
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["--output", "out", "--loud"]),
+            Err(CliError::Usage(_))
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+//! Bluetooth headset profiles. A headset on A2DP plays high-quality audio but
+//! has no microphone; recording from it makes the session manager switch it
+//! to the headset profile, which degrades playback for the whole desktop.
+
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::capture::session::InternalAudioEvent;
+use crate::device::cards::{list_cards, Card};
+use crate::device::ports::device_card;
+
+/// How long after the mic starts a profile switch is attributed to it
+const SWITCH_WINDOW: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// "a2dp-sink", "a2dp-sink-sbc", "a2dp-sink-aac", ...
+pub fn is_a2dp_profile(profile: &str) -> bool {
+    profile.starts_with("a2dp")
+}
+
+/// "headset-head-unit", "headset-head-unit-msbc", and the older
+/// BlueZ/PulseAudio names
+pub fn is_headset_profile(profile: &str) -> bool {
+    profile.starts_with("headset-head-unit") || matches!(profile, "hsp_hs" | "hfp_hf")
+}
+
+/// The Bluetooth card behind a device, None for anything else or if it can't
+/// be looked up
+pub fn bluetooth_card(device_id: &str) -> Option<Card> {
+    let (_, card) = device_card(device_id).ok()?;
+    card.map(|(card, _)| card)
+        .filter(|card| card.api.as_deref() == Some("bluez5"))
+}
+
+/// Watch the card of a Bluetooth mic while its capture starts, and report a
+/// switch from A2DP to the headset profile with a `BtProfileSwitched` event
+pub fn watch_profile_switch(mic_id: &str, event_tx: Sender<InternalAudioEvent>) {
+    let Some(card) = bluetooth_card(mic_id) else {
+        return;
+    };
+    let Some(before) = card.active_profile.filter(|p| is_a2dp_profile(p)) else {
+        return;
+    };
+    let mic_id = mic_id.to_string();
+    thread::spawn(move || {
+        let started = Instant::now();
+        while started.elapsed() < SWITCH_WINDOW {
+            thread::sleep(POLL_INTERVAL);
+            let Some(now) = list_cards()
+                .ok()
+                .and_then(|cards| cards.into_iter().find(|c| c.id == card.id))
+                .and_then(|c| c.active_profile)
+            else {
+                continue;
+            };
+            if is_headset_profile(&now) {
+                let _ = event_tx.send(InternalAudioEvent::BtProfileSwitched {
+                    device_id: mic_id,
+                    card: card.name,
+                    from: before,
+                    to: now,
+                });
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_kinds() {
+        for a2dp in ["a2dp-sink", "a2dp-sink-sbc_xq", "a2dp-sink-aac"] {
+            assert!(is_a2dp_profile(a2dp));
+            assert!(!is_headset_profile(a2dp));
+        }
+        for headset in ["headset-head-unit", "headset-head-unit-msbc", "hfp_hf"] {
+            assert!(is_headset_profile(headset));
+            assert!(!is_a2dp_profile(headset));
+        }
+        assert!(!is_a2dp_profile("off"));
+        assert!(!is_headset_profile("off"));
+    }
+}
@@ -0,0 +1,455 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::device::cards::CardProfile;
+use crate::device::ports::CardPort;
+use crate::device::resolve::resolve_device;
+use crate::errors::DeviceNotFoundError;
+use crate::Device;
+
+/// Rates listed for a device that accepts any rate in a range
+const COMMON_RATES: &[u32] = &[
+    8000, 11025, 16000, 22050, 24000, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+
+/// SPA channel position names, indexed by `spa_audio_channel`
+const CHANNEL_NAMES: &[&str] = &[
+    "UNK", "NA", "MONO", "FL", "FR", "FC", "LFE", "SL", "SR", "FLC", "FRC", "RC", "RL", "RR", "TC",
+    "TFL", "TFC", "TFR", "TRL", "TRC", "TRR", "RLC", "RRC", "FLW", "FRW", "LFE2", "FLH", "FCH",
+    "FRH", "TFLC", "TFRC", "TSL", "TSR", "LLFE", "RLFE", "BC", "BLC", "BRC",
+];
+
+/// First of the auxiliary channel positions, AUX0
+const CHANNEL_AUX0: u32 = 0x1000;
+
+/// Short SPA name of a channel position, e.g. "FL" or "AUX3"
+pub fn channel_name(position: u32) -> String {
+    match CHANNEL_NAMES.get(position as usize) {
+        Some(name) => name.to_string(),
+        None if position >= CHANNEL_AUX0 => format!("AUX{}", position - CHANNEL_AUX0),
+        None => format!("UNKNOWN{}", position),
+    }
+}
+
+/// What a device can be opened with, from `get_device_capabilities()`
+#[derive(Clone, Debug, Default, Serialize)]
+#[pyclass]
+pub struct DeviceCapabilities {
+    #[pyo3(get)]
+    pub device_id: String,
+    /// Rates the device offers. For a device taking any rate in a range, the
+    /// common rates inside it.
+    #[pyo3(get)]
+    pub sample_rates: Vec<u32>,
+    /// Sample formats as SPA names ("S16LE", "F32LE", ...)
+    #[pyo3(get)]
+    pub formats: Vec<String>,
+    /// Channel counts the device can be opened with
+    #[pyo3(get)]
+    pub channels: Vec<u32>,
+    /// Channel layouts the device reports, as position names (["FL", "FR"])
+    #[pyo3(get)]
+    pub channel_maps: Vec<Vec<String>>,
+    /// Profiles of the device's card in which the device exists
+    #[pyo3(get)]
+    pub profiles: Vec<String>,
+    /// Inclusive rate ranges the device accepts, for `supports()`
+    #[serde(skip)]
+    rate_ranges: Vec<(u32, u32)>,
+}
+
+impl DeviceCapabilities {
+    pub fn new(device_id: &str) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn add_rates(&mut self, rates: &[u32]) {
+        self.sample_rates.extend(rates);
+        self.rate_ranges
+            .extend(rates.iter().map(|&rate| (rate, rate)));
+    }
+
+    pub fn add_rate_range(&mut self, min: u32, max: u32) {
+        self.sample_rates.extend(
+            COMMON_RATES
+                .iter()
+                .filter(|&&rate| rate >= min && rate <= max),
+        );
+        self.rate_ranges.push((min, max));
+    }
+
+    pub fn add_channel_range(&mut self, min: u32, max: u32) {
+        self.channels.extend(min.max(1)..=max);
+    }
+
+    pub fn add_channel_map(&mut self, positions: &[u32]) {
+        let map: Vec<String> = positions.iter().map(|&p| channel_name(p)).collect();
+        if !self.channel_maps.contains(&map) {
+            self.channel_maps.push(map);
+        }
+    }
+
+    /// Sort and deduplicate what the params listed
+    pub fn finish(mut self) -> Self {
+        self.sample_rates.sort_unstable();
+        self.sample_rates.dedup();
+        self.channels.sort_unstable();
+        self.channels.dedup();
+        let mut seen = Vec::new();
+        self.formats.retain(|format| {
+            let new = !seen.contains(format);
+            seen.push(format.clone());
+            new
+        });
+        self
+    }
+}
+
+#[pymethods]
+impl DeviceCapabilities {
+    /// Whether the device can be opened with all of the given settings
+    #[pyo3(signature = (sample_rate=None, channels=None, format=None))]
+    pub fn supports(
+        &self,
+        sample_rate: Option<u32>,
+        channels: Option<u32>,
+        format: Option<&str>,
+    ) -> bool {
+        sample_rate.is_none_or(|rate| {
+            self.rate_ranges
+                .iter()
+                .any(|&(min, max)| rate >= min && rate <= max)
+        }) && channels.is_none_or(|count| self.channels.contains(&count))
+            && format.is_none_or(|f| {
+                self.formats
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(f))
+            })
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "DeviceCapabilities(device_id='{}', sample_rates={:?}, formats={:?}, channels={:?}, profiles={:?})",
+            self.device_id, self.sample_rates, self.formats, self.channels, self.profiles
+        )
+    }
+}
+
+/// Names of the card profiles with a route in the device's direction. A
+/// card that reports no ports gets all its profiles but "off".
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub fn device_profiles(profiles: &[CardProfile], ports: &[CardPort], input: bool) -> Vec<String> {
+    let ports: Vec<&CardPort> = ports.iter().filter(|p| p.is_input() == input).collect();
+    profiles
+        .iter()
+        .filter(|p| p.name != "off")
+        .filter(|p| ports.is_empty() || ports.iter().any(|r| r.profiles.contains(&p.index)))
+        .map(|p| p.name.clone())
+        .collect()
+}
+
+/// Capabilities of a device given by node name, global id or description
+pub fn get_device_capabilities(ident: &str) -> PyResult<DeviceCapabilities> {
+    #[cfg(feature = "real-audio")]
+    let devices = crate::device::enumerate::list_devices_pw()
+        .map_err(pyo3::exceptions::PyRuntimeError::new_err)?;
+    #[cfg(not(feature = "real-audio"))]
+    let devices = crate::list_devices()?;
+
+    let device = resolve_device(&devices, ident).map_err(DeviceNotFoundError::new_err)?;
+
+    #[cfg(feature = "real-audio")]
+    {
+        host::query(device).map_err(pyo3::exceptions::PyRuntimeError::new_err)
+    }
+    #[cfg(not(feature = "real-audio"))]
+    {
+        Ok(mock_capabilities(device))
+    }
+}
+
+#[cfg(not(feature = "real-audio"))]
+fn mock_capabilities(device: &Device) -> DeviceCapabilities {
+    let mut caps = DeviceCapabilities::new(&device.id);
+    if device.is_bluetooth {
+        caps.add_rates(&[device.sample_rate]);
+        caps.formats.push("S16LE".to_string());
+        caps.profiles = vec!["headset-head-unit".to_string(), "a2dp-sink".to_string()];
+    } else {
+        caps.add_rate_range(8000, 192000);
+        caps.formats = vec![
+            "S16LE".to_string(),
+            "S32LE".to_string(),
+            "F32LE".to_string(),
+        ];
+        caps.profiles = vec!["output:analog-stereo+input:analog-stereo".to_string()];
+    }
+    let channels = u32::from(device.channels);
+    caps.add_channel_range(1, channels);
+    caps.add_channel_map(if channels == 1 { &[2] } else { &[3, 4] });
+    caps.finish()
+}
+
+#[cfg(feature = "real-audio")]
+mod host {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use pipewire as pw;
+    use pw::spa::param::ParamType;
+    use pw::spa::pod::{ChoiceValue, Pod, Value, ValueArray};
+    use pw::spa::utils::{Choice, ChoiceEnum, Id};
+    use pw::types::ObjectType;
+
+    use super::{device_profiles, DeviceCapabilities};
+    use crate::device::params::{object_properties, parse_profile, parse_route, Connection};
+    use crate::{Device, DeviceType};
+
+    /// Values a format property allows
+    enum Allowed {
+        List(Vec<u32>),
+        Range(u32, u32),
+    }
+
+    fn allowed_ints(value: &Value) -> Option<Allowed> {
+        let to_u32 = |v: &i32| u32::try_from(*v).unwrap_or(0);
+        match value {
+            Value::Int(v) => Some(Allowed::List(vec![to_u32(v)])),
+            Value::Choice(ChoiceValue::Int(Choice(_, choice))) => match choice {
+                ChoiceEnum::None(v) => Some(Allowed::List(vec![to_u32(v)])),
+                ChoiceEnum::Range { min, max, .. } | ChoiceEnum::Step { min, max, .. } => {
+                    Some(Allowed::Range(to_u32(min), to_u32(max)))
+                }
+                ChoiceEnum::Enum {
+                    default,
+                    alternatives,
+                } => Some(Allowed::List(
+                    std::iter::once(default)
+                        .chain(alternatives)
+                        .map(to_u32)
+                        .collect(),
+                )),
+                ChoiceEnum::Flags { .. } => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn allowed_ids(value: &Value) -> Vec<u32> {
+        match value {
+            Value::Id(Id(id)) => vec![*id],
+            Value::Choice(ChoiceValue::Id(Choice(_, choice))) => match choice {
+                ChoiceEnum::None(Id(id)) => vec![*id],
+                ChoiceEnum::Enum {
+                    default,
+                    alternatives,
+                } => std::iter::once(default)
+                    .chain(alternatives)
+                    .map(|Id(id)| *id)
+                    .collect(),
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Add what an EnumFormat param of the node allows
+    fn add_format(caps: &mut DeviceCapabilities, pod: &Pod) {
+        use pw::spa::sys::{
+            SPA_FORMAT_AUDIO_channels, SPA_FORMAT_AUDIO_format, SPA_FORMAT_AUDIO_position,
+            SPA_FORMAT_AUDIO_rate,
+        };
+        for prop in object_properties(pod) {
+            match prop.key {
+                SPA_FORMAT_AUDIO_format => {
+                    caps.formats
+                        .extend(allowed_ids(&prop.value).into_iter().map(|id| {
+                            crate::capture::session::format_name(
+                                pw::spa::param::audio::AudioFormat::from_raw(id),
+                            )
+                        }))
+                }
+                SPA_FORMAT_AUDIO_rate => match allowed_ints(&prop.value) {
+                    Some(Allowed::List(rates)) => caps.add_rates(&rates),
+                    Some(Allowed::Range(min, max)) => caps.add_rate_range(min, max),
+                    None => {}
+                },
+                SPA_FORMAT_AUDIO_channels => match allowed_ints(&prop.value) {
+                    Some(Allowed::List(counts)) => caps.channels.extend(counts),
+                    Some(Allowed::Range(min, max)) => caps.add_channel_range(min, max),
+                    None => {}
+                },
+                SPA_FORMAT_AUDIO_position => {
+                    if let Value::ValueArray(ValueArray::Id(ids)) = &prop.value {
+                        let positions: Vec<u32> = ids.iter().map(|Id(id)| *id).collect();
+                        caps.add_channel_map(&positions);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Ask the device's node for its EnumFormat params and its card for
+    /// EnumProfile and EnumRoute
+    pub fn query(device: &Device) -> Result<DeviceCapabilities, String> {
+        let node_id = device
+            .node_id
+            .ok_or_else(|| format!("Device '{}' has no PipeWire node", device.id))?;
+
+        let conn = Connection::new()?;
+
+        // Find the node's version and its card, then bind both
+        let node_version = Rc::new(RefCell::new(None::<u32>));
+        let card_id = Rc::new(RefCell::new(None::<u32>));
+        let cards = Rc::new(RefCell::new(HashMap::<u32, u32>::new()));
+        let _registry_listener = conn
+            .registry
+            .add_listener_local()
+            .global({
+                let node_version = node_version.clone();
+                let card_id = card_id.clone();
+                let cards = cards.clone();
+                move |global| {
+                    if global.type_ == ObjectType::Device {
+                        cards.borrow_mut().insert(global.id, global.version);
+                    } else if global.type_ == ObjectType::Node && global.id == node_id {
+                        *node_version.borrow_mut() = Some(global.version);
+                        *card_id.borrow_mut() = global
+                            .props
+                            .and_then(|props| props.get("device.id"))
+                            .and_then(|id| id.parse().ok());
+                    }
+                }
+            })
+            .register();
+        conn.roundtrip()?;
+
+        let version = node_version
+            .borrow()
+            .ok_or_else(|| format!("Device '{}' is gone", device.id))?;
+        let node: pw::node::Node = conn.bind(node_id, ObjectType::Node, version)?;
+        let caps = Rc::new(RefCell::new(DeviceCapabilities::new(&device.id)));
+        let _node_listener = node
+            .add_listener_local()
+            .param({
+                let caps = caps.clone();
+                move |_seq, id, _index, _next, param| {
+                    if let (ParamType::EnumFormat, Some(pod)) = (id, param) {
+                        add_format(&mut caps.borrow_mut(), pod);
+                    }
+                }
+            })
+            .register();
+        node.enum_params(0, Some(ParamType::EnumFormat), 0, u32::MAX);
+
+        let profiles = Rc::new(RefCell::new(Vec::new()));
+        let routes = Rc::new(RefCell::new(Vec::new()));
+        let card = card_id
+            .borrow()
+            .and_then(|id| Some((id, *cards.borrow().get(&id)?)));
+        let card = card
+            .map(|(id, version)| conn.bind::<pw::device::Device>(id, ObjectType::Device, version))
+            .transpose()?;
+        let _card_listener = card.as_ref().map(|card| {
+            let listener = card
+                .add_listener_local()
+                .param({
+                    let profiles = profiles.clone();
+                    let routes = routes.clone();
+                    move |_seq, id, _index, _next, param| match (id, param) {
+                        (ParamType::EnumProfile, Some(pod)) => {
+                            profiles.borrow_mut().extend(parse_profile(pod))
+                        }
+                        (ParamType::EnumRoute, Some(pod)) => {
+                            routes.borrow_mut().extend(parse_route(pod))
+                        }
+                        _ => {}
+                    }
+                })
+                .register();
+            card.enum_params(0, Some(ParamType::EnumProfile), 0, u32::MAX);
+            card.enum_params(0, Some(ParamType::EnumRoute), 0, u32::MAX);
+            listener
+        });
+        conn.roundtrip()?;
+
+        let mut caps = caps.replace(DeviceCapabilities::default());
+        caps.profiles = device_profiles(
+            &profiles.borrow(),
+            &routes.borrow(),
+            device.device_type == DeviceType::Microphone,
+        );
+        Ok(caps.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_from_ranges_and_lists() {
+        let mut caps = DeviceCapabilities::new("usb-mic");
+        caps.add_rate_range(16000, 48000);
+        caps.add_rates(&[44100, 96000]);
+        caps.add_channel_range(1, 2);
+        caps.formats = vec!["S16LE".into(), "S32LE".into(), "S16LE".into()];
+        caps.add_channel_map(&[3, 4]);
+        caps.add_channel_map(&[3, 4]);
+        caps.add_channel_map(&[CHANNEL_AUX0 + 1]);
+        let caps = caps.finish();
+
+        assert_eq!(
+            caps.sample_rates,
+            vec![16000, 22050, 24000, 32000, 44100, 48000, 96000]
+        );
+        assert_eq!(caps.formats, vec!["S16LE", "S32LE"]);
+        assert_eq!(caps.channels, vec![1, 2]);
+        assert_eq!(caps.channel_maps, vec![vec!["FL", "FR"], vec!["AUX1"]]);
+        // Any rate inside a range is supported, not just the listed ones
+        assert!(caps.supports(Some(37800), Some(2), Some("s16le")));
+        assert!(!caps.supports(Some(88200), None, None));
+        assert!(!caps.supports(None, Some(6), None));
+        assert!(!caps.supports(None, None, Some("F32LE")));
+    }
+
+    #[test]
+    fn test_device_profiles_follow_routes() {
+        let profile = |index, name: &str| CardProfile {
+            index,
+            name: name.to_string(),
+            ..CardProfile::default()
+        };
+        let profiles = [
+            profile(0, "off"),
+            profile(1, "output:analog-stereo"),
+            profile(2, "output:analog-stereo+input:analog-stereo"),
+            profile(3, "input:analog-stereo"),
+        ];
+        let routes = [
+            CardPort {
+                direction: "input".to_string(),
+                profiles: vec![2, 3],
+                ..CardPort::default()
+            },
+            CardPort {
+                direction: "output".to_string(),
+                profiles: vec![1, 2],
+                ..CardPort::default()
+            },
+        ];
+        assert_eq!(
+            device_profiles(&profiles, &routes, true),
+            vec![
+                "output:analog-stereo+input:analog-stereo",
+                "input:analog-stereo"
+            ]
+        );
+        assert_eq!(device_profiles(&profiles, &[], true).len(), 3);
+    }
+}
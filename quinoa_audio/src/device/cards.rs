@@ -0,0 +1,449 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::device::ports::CardPort;
+use crate::errors::{ConfigError, DeviceNotFoundError};
+
+/// One configuration of a card's inputs and outputs, e.g.
+/// "output:analog-stereo+input:analog-stereo"
+#[derive(Clone, Debug, Default, Serialize)]
+#[pyclass]
+pub struct CardProfile {
+    #[pyo3(get)]
+    pub index: i32,
+    #[pyo3(get)]
+    pub name: String,
+    /// Human-readable name, e.g. "Analog Stereo Duplex"
+    #[pyo3(get)]
+    pub description: String,
+    /// False when the hardware can't use it right now (e.g. nothing plugged
+    /// into the HDMI port)
+    #[pyo3(get)]
+    pub available: bool,
+    #[pyo3(get)]
+    pub priority: i32,
+}
+
+#[pymethods]
+impl CardProfile {
+    fn __repr__(&self) -> String {
+        format!(
+            "CardProfile(name='{}', description='{}', available={})",
+            self.name,
+            self.description,
+            if self.available { "True" } else { "False" }
+        )
+    }
+}
+
+/// A sound card, USB interface or Bluetooth device. Its active profile
+/// decides which microphones and outputs exist, so a "missing microphone"
+/// is often a card on an output-only profile.
+#[derive(Clone, Debug, Default, Serialize)]
+#[pyclass]
+pub struct Card {
+    /// The card's `device.name`, stable across restarts
+    #[pyo3(get)]
+    pub id: String,
+    #[pyo3(get)]
+    pub name: String,
+    /// "alsa", "bluez5", ...
+    #[pyo3(get)]
+    pub api: Option<String>,
+    #[pyo3(get)]
+    pub profiles: Vec<CardProfile>,
+    #[pyo3(get)]
+    pub active_profile: Option<String>,
+    /// Connectors of the card, for every profile
+    #[pyo3(get)]
+    pub ports: Vec<CardPort>,
+    /// PipeWire global id and interface version, when known
+    pub(crate) global_id: Option<u32>,
+    #[serde(skip)]
+    #[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+    pub(crate) version: u32,
+}
+
+impl Card {
+    /// Match a profile by name, or by description ignoring case
+    pub fn find_profile(&self, ident: &str) -> Result<&CardProfile, String> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == ident)
+            .or_else(|| {
+                self.profiles
+                    .iter()
+                    .find(|p| p.description.eq_ignore_ascii_case(ident))
+            })
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.profiles.iter().map(|p| p.name.as_str()).collect();
+                format!(
+                    "Card '{}' has no profile '{}'. Available profiles: {}",
+                    self.id,
+                    ident,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[pymethods]
+impl Card {
+    fn __repr__(&self) -> String {
+        format!(
+            "Card(id='{}', name='{}', active_profile={}, profiles={})",
+            self.id,
+            self.name,
+            self.active_profile
+                .as_ref()
+                .map_or_else(|| "None".to_string(), |p| format!("'{}'", p)),
+            self.profiles.len()
+        )
+    }
+}
+
+/// Match a user-supplied identifier against cards, like `resolve_device`:
+/// the `device.name`, the numeric global id, or the description
+pub fn resolve_card<'a>(cards: &'a [Card], ident: &str) -> Result<&'a Card, String> {
+    cards
+        .iter()
+        .find(|c| c.id == ident)
+        .or_else(|| {
+            let global_id = ident.parse::<u32>().ok()?;
+            cards.iter().find(|c| c.global_id == Some(global_id))
+        })
+        .or_else(|| cards.iter().find(|c| c.name.eq_ignore_ascii_case(ident)))
+        .ok_or_else(|| {
+            let names: Vec<String> = cards
+                .iter()
+                .map(|c| format!("{} ({})", c.name, c.id))
+                .collect();
+            format!(
+                "Card '{}' not found. Available cards: {}",
+                ident,
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+        })
+}
+
+/// Global id of the mock backend's card
+#[cfg(not(feature = "real-audio"))]
+pub const MOCK_CARD_ID: u32 = 40;
+
+/// Record a port chosen on the mock card
+#[cfg(not(feature = "real-audio"))]
+pub fn set_mock_port(profile_device: i32, port: &str) -> Result<(), String> {
+    host::set_port(profile_device, port)
+}
+
+pub fn list_cards() -> PyResult<Vec<Card>> {
+    host::list().map_err(pyo3::exceptions::PyRuntimeError::new_err)
+}
+
+/// Switch a card to another profile, given by name or description
+pub fn set_card_profile(card_id: &str, profile: &str) -> PyResult<()> {
+    let cards = list_cards()?;
+    let card = resolve_card(&cards, card_id).map_err(DeviceNotFoundError::new_err)?;
+    let profile = card.find_profile(profile).map_err(ConfigError::new_err)?;
+    if !profile.available {
+        log!(
+            "Profile '{}' of card '{}' is marked unavailable; its devices may not work",
+            profile.name,
+            card.id
+        );
+    }
+    host::set_profile(card, profile).map_err(pyo3::exceptions::PyRuntimeError::new_err)
+}
+
+#[cfg(feature = "real-audio")]
+mod host {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use pipewire as pw;
+    use pw::spa::param::ParamType;
+    use pw::spa::pod::{Object, Pod, Property, Value};
+    use pw::types::ObjectType;
+
+    use super::{Card, CardProfile};
+    use crate::device::params::{parse_profile, parse_route, Connection};
+    use crate::device::ports::CardPort;
+
+    /// What a card's params say about it
+    #[derive(Default)]
+    struct CardParams {
+        profiles: Vec<CardProfile>,
+        active_profile: Option<String>,
+        ports: Vec<CardPort>,
+        /// Indexes of the ports in use
+        active_ports: Vec<i32>,
+    }
+
+    pub fn list() -> Result<Vec<Card>, String> {
+        let conn = Connection::new()?;
+        let cards = Rc::new(RefCell::new(Vec::new()));
+        let _registry_listener = conn
+            .registry
+            .add_listener_local()
+            .global({
+                let cards = cards.clone();
+                move |global| {
+                    let Some(props) = global.props else {
+                        return;
+                    };
+                    if global.type_ != ObjectType::Device
+                        || props.get("media.class") != Some("Audio/Device")
+                    {
+                        return;
+                    }
+                    let id = props
+                        .get("device.name")
+                        .map(|s| s.to_string())
+                        .unwrap_or_else(|| global.id.to_string());
+                    let name = props
+                        .get("device.description")
+                        .or_else(|| props.get("device.nick"))
+                        .unwrap_or(id.as_str())
+                        .to_string();
+                    cards.borrow_mut().push(Card {
+                        id,
+                        name,
+                        api: props.get("device.api").map(|s| s.to_string()),
+                        profiles: Vec::new(),
+                        active_profile: None,
+                        ports: Vec::new(),
+                        global_id: Some(global.id),
+                        version: global.version,
+                    });
+                }
+            })
+            .register();
+        conn.roundtrip()?;
+
+        // Card global id to its params
+        let found = Rc::new(RefCell::new(HashMap::<u32, CardParams>::new()));
+        let mut bound = Vec::new();
+        for card in cards.borrow().iter() {
+            let Some(id) = card.global_id else {
+                continue;
+            };
+            let proxy: pw::device::Device = conn.bind(id, ObjectType::Device, card.version)?;
+            let listener = proxy
+                .add_listener_local()
+                .param({
+                    let found = found.clone();
+                    move |_seq, param_id, _index, _next, param| {
+                        let Some(pod) = param else {
+                            return;
+                        };
+                        let mut found = found.borrow_mut();
+                        let params = found.entry(id).or_default();
+                        match param_id {
+                            ParamType::EnumProfile => params.profiles.extend(parse_profile(pod)),
+                            ParamType::Profile => {
+                                params.active_profile = parse_profile(pod).map(|p| p.name)
+                            }
+                            ParamType::EnumRoute => params.ports.extend(parse_route(pod)),
+                            ParamType::Route => params
+                                .active_ports
+                                .extend(parse_route(pod).map(|p| p.index)),
+                            _ => {}
+                        }
+                    }
+                })
+                .register();
+            proxy.enum_params(0, Some(ParamType::EnumProfile), 0, u32::MAX);
+            proxy.enum_params(0, Some(ParamType::Profile), 0, u32::MAX);
+            proxy.enum_params(0, Some(ParamType::EnumRoute), 0, u32::MAX);
+            proxy.enum_params(0, Some(ParamType::Route), 0, u32::MAX);
+            bound.push((proxy, listener));
+        }
+        conn.roundtrip()?;
+
+        let mut found = found.borrow_mut();
+        let mut cards = cards.take();
+        for card in &mut cards {
+            if let Some(params) = card.global_id.and_then(|id| found.remove(&id)) {
+                card.profiles = params.profiles;
+                card.active_profile = params.active_profile;
+                card.ports = params.ports;
+                for port in &mut card.ports {
+                    port.active = params.active_ports.contains(&port.index);
+                }
+            }
+        }
+        Ok(cards)
+    }
+
+    pub fn set_profile(card: &Card, profile: &CardProfile) -> Result<(), String> {
+        use pw::spa::sys::{SPA_PARAM_PROFILE_index, SPA_PARAM_PROFILE_save};
+
+        let id = card
+            .global_id
+            .ok_or_else(|| format!("Card '{}' has no PipeWire object", card.id))?;
+        let conn = Connection::new()?;
+        let proxy: pw::device::Device = conn.bind(id, ObjectType::Device, card.version)?;
+        let obj = Object {
+            type_: pw::spa::utils::SpaTypes::ObjectParamProfile.as_raw(),
+            id: ParamType::Profile.as_raw(),
+            // Saved, so the session manager restores it like a choice made
+            // in the desktop's sound settings
+            properties: vec![
+                Property::new(SPA_PARAM_PROFILE_index, Value::Int(profile.index)),
+                Property::new(SPA_PARAM_PROFILE_save, Value::Bool(true)),
+            ],
+        };
+        let bytes = pw::spa::pod::serialize::PodSerializer::serialize(
+            std::io::Cursor::new(Vec::new()),
+            &Value::Object(obj),
+        )
+        .map_err(|e| format!("Failed to serialize profile param: {:?}", e))?
+        .0
+        .into_inner();
+        let pod = Pod::from_bytes(&bytes).expect("serialized pod bytes should be valid");
+        proxy.set_param(ParamType::Profile, 0, pod);
+        conn.roundtrip()
+    }
+}
+
+#[cfg(not(feature = "real-audio"))]
+mod host {
+    use std::sync::Mutex;
+
+    use super::{Card, CardProfile, MOCK_CARD_ID};
+    use crate::device::ports::CardPort;
+
+    /// Profile of the mock card once `set_card_profile()` changed it
+    static ACTIVE_PROFILE: Mutex<Option<String>> = Mutex::new(None);
+
+    /// Ports chosen with `set_device_port()`, by card device. Devices
+    /// without an entry use their first port.
+    static ACTIVE_PORTS: Mutex<Vec<(i32, String)>> = Mutex::new(Vec::new());
+
+    fn ports() -> Result<Vec<CardPort>, String> {
+        let active = ACTIVE_PORTS
+            .lock()
+            .map_err(|_| "mock card ports poisoned".to_string())?;
+        let port = |index, name: &str, description: &str, device: i32| CardPort {
+            index,
+            name: name.to_string(),
+            description: description.to_string(),
+            direction: if device == 0 { "input" } else { "output" }.to_string(),
+            // Nothing is plugged into the headphone jack
+            available: name != "analog-output-headphones",
+            active: match active.iter().find(|(d, _)| *d == device) {
+                Some((_, chosen)) => chosen == name,
+                None => index == 0 || index == 2,
+            },
+            profiles: vec![2],
+            devices: vec![device],
+        };
+        Ok(vec![
+            port(0, "analog-input-internal-mic", "Internal Microphone", 0),
+            port(1, "analog-input-headset-mic", "Headset Microphone", 0),
+            port(2, "analog-output-speaker", "Speakers", 1),
+            port(3, "analog-output-headphones", "Headphones", 1),
+        ])
+    }
+
+    pub fn set_port(device: i32, name: &str) -> Result<(), String> {
+        let mut active = ACTIVE_PORTS
+            .lock()
+            .map_err(|_| "mock card ports poisoned".to_string())?;
+        active.retain(|(d, _)| *d != device);
+        active.push((device, name.to_string()));
+        Ok(())
+    }
+
+    pub fn list() -> Result<Vec<Card>, String> {
+        let profile = |index, name: &str, description: &str| CardProfile {
+            index,
+            name: name.to_string(),
+            description: description.to_string(),
+            available: true,
+            priority: 0,
+        };
+        let active = ACTIVE_PROFILE
+            .lock()
+            .map_err(|_| "mock card profile poisoned".to_string())?;
+        Ok(vec![Card {
+            id: "alsa_card.mock".to_string(),
+            name: "Mock Audio".to_string(),
+            api: Some("alsa".to_string()),
+            profiles: vec![
+                profile(0, "off", "Off"),
+                profile(1, "output:analog-stereo", "Analog Stereo Output"),
+                profile(
+                    2,
+                    "output:analog-stereo+input:analog-stereo",
+                    "Analog Stereo Duplex",
+                ),
+                profile(3, "output:hdmi-stereo", "Digital Stereo (HDMI) Output"),
+            ],
+            active_profile: Some(
+                active
+                    .clone()
+                    .unwrap_or_else(|| "output:analog-stereo+input:analog-stereo".to_string()),
+            ),
+            ports: ports()?,
+            global_id: Some(MOCK_CARD_ID),
+            version: 0,
+        }])
+    }
+
+    pub fn set_profile(_card: &Card, profile: &CardProfile) -> Result<(), String> {
+        let mut active = ACTIVE_PROFILE
+            .lock()
+            .map_err(|_| "mock card profile poisoned".to_string())?;
+        *active = Some(profile.name.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_card_and_profile() {
+        let card = |id: &str, name: &str, global_id| Card {
+            id: id.to_string(),
+            name: name.to_string(),
+            api: Some("alsa".to_string()),
+            profiles: vec![CardProfile {
+                index: 2,
+                name: "output:analog-stereo+input:analog-stereo".to_string(),
+                description: "Analog Stereo Duplex".to_string(),
+                available: true,
+                priority: 6565,
+            }],
+            active_profile: None,
+            ports: Vec::new(),
+            global_id: Some(global_id),
+            version: 3,
+        };
+        let cards = vec![
+            card("alsa_card.pci-0000_00_1f.3", "Built-in Audio", 47),
+            card("alsa_card.usb-Blue_Yeti", "Yeti Stereo Microphone", 52),
+        ];
+
+        assert_eq!(
+            resolve_card(&cards, "52").unwrap().name,
+            "Yeti Stereo Microphone"
+        );
+        let builtin = resolve_card(&cards, "built-in audio").unwrap();
+        assert_eq!(builtin.id, "alsa_card.pci-0000_00_1f.3");
+        assert_eq!(
+            builtin.find_profile("analog stereo duplex").unwrap().index,
+            2
+        );
+        let missing = builtin.find_profile("pro-audio").unwrap_err();
+        assert!(missing.contains("output:analog-stereo+input:analog-stereo"));
+        assert!(resolve_card(&cards, "HDMI").is_err());
+    }
+}
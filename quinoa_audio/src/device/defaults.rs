@@ -0,0 +1,169 @@
+//! Parsing of the `default.audio.source` / `default.audio.sink` values in
+//! PipeWire's "default" metadata
+
+use serde::Deserialize;
+
+/// Longest node name accepted from the metadata
+const MAX_NAME_LEN: usize = 1024;
+
+#[derive(Deserialize)]
+struct DefaultDevice {
+    name: String,
+}
+
+/// The node name in a default device value: `{"name": "<node.name>"}` as
+/// session managers write it, a JSON string, or (from older ones) the bare
+/// name. None for anything malformed, so a broken value never becomes a
+/// device name.
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+pub fn parse_default_device(value: &str) -> Option<String> {
+    let value = value.trim();
+    let name = if value.starts_with('{') {
+        serde_json::from_str::<DefaultDevice>(value).ok()?.name
+    } else if value.starts_with('"') {
+        serde_json::from_str::<String>(value).ok()?
+    } else if value.contains(['{', '}', '"']) {
+        // Part of a JSON value
+        return None;
+    } else {
+        value.to_string()
+    };
+    valid_name(&name).then_some(name)
+}
+
+/// Follows whether the device a stream records is the default, to report
+/// when it becomes or stops being the default. Moving to another device
+/// starts over without a report.
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+#[derive(Default)]
+pub struct DefaultStatus {
+    device: Option<String>,
+    is_default: bool,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl DefaultStatus {
+    /// Take the recorded device and the current default, if known. Returns
+    /// the new status when it changed.
+    pub fn observe(&mut self, recorded: &str, default: Option<&str>) -> Option<bool> {
+        let is_default = default? == recorded;
+        if self.device.as_deref() != Some(recorded) {
+            self.device = Some(recorded.to_string());
+            self.is_default = is_default;
+            return None;
+        }
+        if self.is_default == is_default {
+            return None;
+        }
+        self.is_default = is_default;
+        Some(is_default)
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.trim() == name
+        && !name.chars().any(char::is_control)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixed-seed generator, so a failure reproduces
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((self.0 >> 33) % n as u64) as usize
+        }
+
+        fn string(&mut self, alphabet: &[char], max_len: usize) -> String {
+            let len = self.below(max_len + 1);
+            (0..len)
+                .map(|_| alphabet[self.below(alphabet.len())])
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_default_status_changes() {
+        let mut status = DefaultStatus::default();
+        assert_eq!(status.observe("usb-mic", None), None);
+        assert_eq!(status.observe("usb-mic", Some("usb-mic")), None);
+        assert_eq!(status.observe("usb-mic", Some("usb-mic")), None);
+        assert_eq!(status.observe("usb-mic", Some("headset")), Some(false));
+        assert_eq!(status.observe("usb-mic", None), None);
+        assert_eq!(status.observe("usb-mic", Some("headset")), None);
+        assert_eq!(status.observe("usb-mic", Some("usb-mic")), Some(true));
+
+        // Switching to another device isn't a change of status
+        assert_eq!(status.observe("headset", Some("usb-mic")), None);
+        assert_eq!(status.observe("headset", Some("headset")), Some(true));
+    }
+
+    #[test]
+    fn test_parse_default_device_properties() {
+        assert_eq!(
+            parse_default_device(r#"{ "name": "alsa_input.pci-0000_00_1f.3.analog-stereo" }"#),
+            Some("alsa_input.pci-0000_00_1f.3.analog-stereo".to_string())
+        );
+        assert_eq!(
+            parse_default_device("bluez_output.AA_BB_CC_DD_EE_FF.1"),
+            Some("bluez_output.AA_BB_CC_DD_EE_FF.1".to_string())
+        );
+        assert_eq!(
+            parse_default_device(r#"{"name":"say \"hi\""}"#),
+            Some("say \"hi\"".to_string())
+        );
+        for malformed in [
+            "",
+            "   ",
+            "{",
+            r#"{"name":"#,
+            r#"{"name":5}"#,
+            r#"{"name":""}"#,
+            r#"{"nick":"mic"}"#,
+            r#"{"name":"a\nb"}"#,
+            r#"{"name":" mic "}"#,
+            r#""unterminated"#,
+            r#"name":"mic"}"#,
+            "mic\0",
+        ] {
+            assert_eq!(parse_default_device(malformed), None, "{:?}", malformed);
+        }
+
+        let mut rng = Lcg(0x5eed);
+        let name_chars: Vec<char> = "abcXYZ019._-:@/ é€🎤\"\\'".chars().collect();
+        let noise_chars: Vec<char> = "{}[]\":,\\ \t\n\0nameé\u{7f}\u{2028}".chars().collect();
+        for _ in 0..5000 {
+            // Any name survives being written as JSON, with other fields
+            let name = rng.string(&name_chars, 40);
+            let json = serde_json::json!({ "name": name, "priority": rng.below(100) }).to_string();
+            let expected = valid_name(&name).then(|| name.clone());
+            assert_eq!(parse_default_device(&json), expected, "{:?}", json);
+
+            // A truncated value is rejected or parses to a valid name
+            let cut = json
+                .char_indices()
+                .map(|(i, _)| i)
+                .nth(rng.below(json.chars().count()))
+                .unwrap_or(0);
+            if let Some(parsed) = parse_default_device(&json[..cut]) {
+                assert!(valid_name(&parsed), "{:?} -> {:?}", &json[..cut], parsed);
+            }
+
+            // Arbitrary input never panics or yields an invalid name
+            let noise = rng.string(&noise_chars, 30);
+            if let Some(parsed) = parse_default_device(&noise) {
+                assert!(valid_name(&parsed), "{:?} -> {:?}", noise, parsed);
+                assert!(!parsed.contains(['{', '}']) || noise.starts_with(['{', '"']));
+            }
+        }
+    }
+}
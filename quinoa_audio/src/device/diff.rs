@@ -0,0 +1,147 @@
+use pyo3::prelude::*;
+
+use crate::Device;
+
+/// A device present in both listings whose fields differ
+#[derive(Clone, Debug)]
+#[pyclass]
+pub struct DeviceChange {
+    #[pyo3(get)]
+    pub old: Device,
+    #[pyo3(get)]
+    pub new: Device,
+    /// Names of the fields that differ, e.g. ["is_default", "state"]
+    #[pyo3(get)]
+    pub fields: Vec<String>,
+}
+
+#[pymethods]
+impl DeviceChange {
+    fn __repr__(&self) -> String {
+        format!(
+            "DeviceChange(id='{}', fields=[{}])",
+            self.new.id,
+            self.fields.join(", ")
+        )
+    }
+}
+
+/// What changed between two `list_devices()` results, from `diff_devices()`
+#[derive(Clone, Debug, Default)]
+#[pyclass]
+pub struct DeviceDiff {
+    /// In the new listing only, in its order
+    #[pyo3(get)]
+    pub added: Vec<Device>,
+    /// In the old listing only, in its order
+    #[pyo3(get)]
+    pub removed: Vec<Device>,
+    #[pyo3(get)]
+    pub changed: Vec<DeviceChange>,
+}
+
+#[pymethods]
+impl DeviceDiff {
+    /// True when the listings hold the same devices
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn __bool__(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "DeviceDiff(added={}, removed={}, changed={})",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+}
+
+/// Devices are matched by `id`, the node name that stays the same while a
+/// device is connected
+pub fn diff_devices(old: &[Device], new: &[Device]) -> DeviceDiff {
+    let find = |devices: &[Device], id: &str| devices.iter().find(|d| d.id == id).cloned();
+    let mut diff = DeviceDiff::default();
+    for device in new {
+        match find(old, &device.id) {
+            None => diff.added.push(device.clone()),
+            Some(before) if before != *device => diff.changed.push(DeviceChange {
+                fields: changed_fields(&before, device),
+                old: before,
+                new: device.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .iter()
+        .filter(|d| find(new, &d.id).is_none())
+        .cloned()
+        .collect();
+    diff
+}
+
+/// Names of the serialized fields that differ
+fn changed_fields(old: &Device, new: &Device) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    new.iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    fn mic(id: &str) -> Device {
+        Device {
+            id: id.to_string(),
+            name: id.to_uppercase(),
+            device_type: DeviceType::Microphone,
+            is_bluetooth: false,
+            sample_rate: 48000,
+            channels: 1,
+            is_default: false,
+            bluetooth_profile: None,
+            node_id: Some(40),
+            object_serial: None,
+            persistent_key: None,
+            icon_name: None,
+            vendor_name: None,
+            product_name: None,
+            bus: None,
+            bus_path: None,
+            state: Some("suspended".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_diff_devices() {
+        let old = vec![mic("a"), mic("b"), mic("c")];
+        let mut b = mic("b");
+        b.is_default = true;
+        b.state = Some("running".to_string());
+        let new = vec![mic("d"), b, mic("a")];
+
+        let diff = diff_devices(&old, &new);
+        let ids = |devices: &[Device]| devices.iter().map(|d| d.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&diff.added), ["d"]);
+        assert_eq!(ids(&diff.removed), ["c"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].old.id, "b");
+        assert!(!diff.changed[0].old.is_default);
+        assert_eq!(diff.changed[0].fields, ["is_default", "state"]);
+
+        assert!(diff_devices(&new, &new).is_empty());
+    }
+}
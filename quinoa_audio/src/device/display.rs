@@ -0,0 +1,90 @@
+use crate::Device;
+
+/// How a device can be shown to the user, from the properties of its node
+/// and card. Two identical USB interfaces share every name but `bus_path`.
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisplayInfo {
+    /// `device.icon-name`, a freedesktop icon name like "audio-card-usb"
+    pub icon_name: Option<String>,
+    /// `device.vendor.name`
+    pub vendor_name: Option<String>,
+    /// `device.product.name`
+    pub product_name: Option<String>,
+    /// `device.bus`: "usb", "pci", "bluetooth", ...
+    pub bus: Option<String>,
+    /// `device.bus-path`, the port the device is plugged into
+    pub bus_path: Option<String>,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl DisplayInfo {
+    /// Read the properties; `get` looks one up
+    pub fn from_props<'a>(get: impl Fn(&str) -> Option<&'a str>) -> Self {
+        let text = |key: &str| {
+            get(key)
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+        };
+        Self {
+            icon_name: text("device.icon-name").or_else(|| text("device.icon_name")),
+            vendor_name: text("device.vendor.name"),
+            product_name: text("device.product.name"),
+            bus: text("device.bus"),
+            bus_path: text("device.bus-path"),
+        }
+    }
+
+    /// Fill what these properties lack from `fallback`, e.g. a node's from
+    /// its card's
+    pub fn or(self, fallback: &DisplayInfo) -> Self {
+        Self {
+            icon_name: self.icon_name.or_else(|| fallback.icon_name.clone()),
+            vendor_name: self.vendor_name.or_else(|| fallback.vendor_name.clone()),
+            product_name: self.product_name.or_else(|| fallback.product_name.clone()),
+            bus: self.bus.or_else(|| fallback.bus.clone()),
+            bus_path: self.bus_path.or_else(|| fallback.bus_path.clone()),
+        }
+    }
+
+    pub fn apply(self, device: &mut Device) {
+        device.icon_name = self.icon_name;
+        device.vendor_name = self.vendor_name;
+        device.product_name = self.product_name;
+        device.bus = self.bus;
+        device.bus_path = self.bus_path;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_props_fall_back_to_card() {
+        let props = |pairs: &'static [(&'static str, &'static str)]| {
+            move |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+        };
+        let card = DisplayInfo::from_props(props(&[
+            ("device.icon-name", "audio-card-usb"),
+            ("device.vendor.name", "C-Media Electronics, Inc."),
+            ("device.product.name", "USB Audio Device"),
+            ("device.bus", "usb"),
+            ("device.bus-path", "pci-0000:00:14.0-usb-0:2:1.0"),
+        ]));
+        let node = DisplayInfo::from_props(props(&[
+            ("device.icon_name", "audio-input-microphone"),
+            ("device.product.name", " "),
+        ]));
+        assert_eq!(
+            node.or(&card),
+            DisplayInfo {
+                icon_name: Some("audio-input-microphone".to_string()),
+                product_name: Some("USB Audio Device".to_string()),
+                ..card.clone()
+            }
+        );
+        assert_eq!(DisplayInfo::from_props(props(&[])), DisplayInfo::default());
+    }
+}
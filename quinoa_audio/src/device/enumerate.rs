@@ -1,4 +1,12 @@
 #[cfg(feature = "real-audio")]
+use crate::device::defaults::parse_default_device;
+#[cfg(feature = "real-audio")]
+use crate::device::display::DisplayInfo;
+#[cfg(feature = "real-audio")]
+use crate::device::identity::DeviceIdentity;
+#[cfg(feature = "real-audio")]
+use crate::device::state::state_name;
+#[cfg(feature = "real-audio")]
 use crate::{Device, DeviceType};
 #[cfg(feature = "real-audio")]
 use pipewire as pw;
@@ -7,28 +15,13 @@ use pipewire::context::Context;
 #[cfg(feature = "real-audio")]
 use pipewire::main_loop::MainLoop;
 #[cfg(feature = "real-audio")]
-use serde::Deserialize;
+use std::cell::Cell;
 #[cfg(feature = "real-audio")]
-use std::sync::{Arc, Mutex};
-
-/// Helper struct for parsing PipeWire default device JSON
+use std::collections::{BTreeMap, HashMap};
 #[cfg(feature = "real-audio")]
-#[derive(Deserialize)]
-struct DefaultDevice {
-    name: String,
-}
-
-/// Parse a PipeWire default device value, which may be JSON or a plain string
+use std::rc::Rc;
 #[cfg(feature = "real-audio")]
-fn parse_default_device(json_val: &str) -> Option<String> {
-    if json_val.starts_with('{') {
-        serde_json::from_str::<DefaultDevice>(json_val)
-            .ok()
-            .map(|d| d.name)
-    } else {
-        Some(json_val.to_string())
-    }
-}
+use std::sync::{Arc, Mutex};
 
 #[cfg(feature = "real-audio")]
 pub fn list_devices_pw() -> Result<Vec<Device>, String> {
@@ -58,15 +51,43 @@ pub fn list_devices_pw() -> Result<Vec<Device>, String> {
     let default_source_clone = default_source.clone();
     let default_sink_clone = default_sink.clone();
 
+    // Hardware identity and display info of cards by global id, and of
+    // nodes by global id with their card, joined once everything is in
+    let card_identities = Arc::new(Mutex::new(
+        HashMap::<u32, (DeviceIdentity, DisplayInfo)>::new(),
+    ));
+    let node_identities = Arc::new(Mutex::new(HashMap::<
+        u32,
+        (Option<u32>, DeviceIdentity, DisplayInfo),
+    >::new()));
+    let card_identities_clone = card_identities.clone();
+    let node_identities_clone = node_identities.clone();
+
     // We need to hold the metadata listener alive
     let metadata_listener_holder = Arc::new(Mutex::new(None));
     let metadata_listener_holder_clone = metadata_listener_holder.clone();
 
+    // Nodes are bound for their state, which is in their info rather than
+    // their properties
+    let node_states = Arc::new(Mutex::new(HashMap::<u32, &'static str>::new()));
+    let node_states_clone = node_states.clone();
+    let node_proxies = Arc::new(Mutex::new(Vec::new()));
+    let node_proxies_clone = node_proxies.clone();
+
     // Listener for registry events
     let _listener = registry
         .add_listener_local()
         .global(move |global| {
             if let Some(props) = global.props {
+                if global.type_ == pipewire::types::ObjectType::Device {
+                    let mut identity = DeviceIdentity::default();
+                    identity.add_card_props(|key| props.get(key));
+                    let display = DisplayInfo::from_props(|key| props.get(key));
+                    if let Ok(mut guard) = card_identities_clone.lock() {
+                        guard.insert(global.id, (identity, display));
+                    }
+                }
+
                 // Check for Metadata interface to find defaults
                 if global.type_ == pipewire::types::ObjectType::Metadata
                     && props.get("metadata.name") == Some("default")
@@ -156,8 +177,44 @@ pub fn list_devices_pw() -> Result<Vec<Device>, String> {
                             channels,
                             is_default: false, // Will be updated after collection
                             bluetooth_profile,
+                            node_id: Some(global.id),
+                            object_serial: props.get("object.serial").and_then(|s| s.parse().ok()),
+                            persistent_key: None, // Needs the card, set after collection
+                            icon_name: None,
+                            vendor_name: None,
+                            product_name: None,
+                            bus: None,
+                            bus_path: None,
+                            state: None,
                         };
 
+                        let identity = DeviceIdentity {
+                            bluez_address: props.get("api.bluez5.address").map(String::from),
+                            profile: props.get("device.profile.name").map(String::from),
+                            ..DeviceIdentity::default()
+                        };
+                        let display = DisplayInfo::from_props(|key| props.get(key));
+                        let card = props.get("device.id").and_then(|id| id.parse().ok());
+                        if let Ok(mut guard) = node_identities_clone.lock() {
+                            guard.insert(global.id, (card, identity, display));
+                        }
+
+                        if let Ok(node) = registry_binding.bind::<pw::node::Node, _>(&global) {
+                            let node_states = node_states_clone.clone();
+                            let node_id = global.id;
+                            let listener = node
+                                .add_listener_local()
+                                .info(move |info| {
+                                    if let Ok(mut guard) = node_states.lock() {
+                                        guard.insert(node_id, state_name(&info.state()));
+                                    }
+                                })
+                                .register();
+                            if let Ok(mut guard) = node_proxies_clone.lock() {
+                                guard.push((node, listener));
+                            }
+                        }
+
                         if let Ok(mut guard) = devices_clone.lock() {
                             guard.push(device);
                         }
@@ -167,20 +224,26 @@ pub fn list_devices_pw() -> Result<Vec<Device>, String> {
         })
         .register();
 
-    // Perform a roundtrip to ensure we receive all initial globals
-    let pending = core.sync(0).map_err(|e| format!("Sync failed: {:?}", e))?;
+    // Perform a roundtrip to ensure we receive all initial globals, and a
+    // second one for the info of the nodes bound during the first
+    let pending = Rc::new(Cell::new(
+        core.sync(0).map_err(|e| format!("Sync failed: {:?}", e))?,
+    ));
+    let pending_clone = pending.clone();
     let mainloop_clone = mainloop.clone();
 
     let _core_listener = core
         .add_listener_local()
         .done(move |id, seq| {
-            if id == pipewire::core::PW_ID_CORE && seq == pending {
+            if id == pipewire::core::PW_ID_CORE && seq == pending_clone.get() {
                 mainloop_clone.quit();
             }
         })
         .register();
 
     mainloop.run();
+    pending.set(core.sync(0).map_err(|e| format!("Sync failed: {:?}", e))?);
+    mainloop.run();
 
     // Post-process to set is_default
     let mut result = devices.lock().expect("devices mutex poisoned").clone();
@@ -193,6 +256,35 @@ pub fn list_devices_pw() -> Result<Vec<Device>, String> {
         .expect("default_sink mutex poisoned")
         .clone();
 
+    let card_identities = card_identities
+        .lock()
+        .expect("card identities mutex poisoned");
+    let mut node_identities = node_identities
+        .lock()
+        .expect("node identities mutex poisoned");
+    for device in &mut result {
+        let Some((card, mut identity, mut display)) =
+            device.node_id.and_then(|id| node_identities.remove(&id))
+        else {
+            continue;
+        };
+        if let Some((card, card_display)) = card.and_then(|card| card_identities.get(&card)) {
+            identity.usb = card.usb.clone();
+            identity.alsa_card = card.alsa_card.clone();
+            display = display.or(card_display);
+        }
+        device.persistent_key = identity.key(device.device_type == DeviceType::Microphone);
+        display.apply(device);
+    }
+
+    let node_states = node_states.lock().expect("node states mutex poisoned");
+    for device in &mut result {
+        device.state = device
+            .node_id
+            .and_then(|id| node_states.get(&id))
+            .map(|state| state.to_string());
+    }
+
     for device in &mut result {
         if device.device_type == DeviceType::Microphone {
             if let Some(ref def) = def_source {
@@ -211,3 +303,49 @@ pub fn list_devices_pw() -> Result<Vec<Device>, String> {
 
     Ok(result)
 }
+
+/// Name, version and properties of the PipeWire daemon we connect to
+#[cfg(feature = "real-audio")]
+pub fn server_info_pw() -> Result<BTreeMap<String, String>, String> {
+    pw::init();
+
+    let mainloop =
+        MainLoop::new(None).map_err(|e| format!("Failed to create main loop: {:?}", e))?;
+    let context =
+        Context::new(&mainloop).map_err(|e| format!("Failed to create context: {:?}", e))?;
+    let core = context
+        .connect(None)
+        .map_err(|e| format!("Failed to connect to core: {:?}", e))?;
+
+    let info = Arc::new(Mutex::new(BTreeMap::new()));
+    let info_clone = info.clone();
+    let pending = core.sync(0).map_err(|e| format!("Sync failed: {:?}", e))?;
+    let mainloop_clone = mainloop.clone();
+    let _core_listener = core
+        .add_listener_local()
+        .info(move |core_info| {
+            let Ok(mut info) = info_clone.lock() else {
+                return;
+            };
+            if let Some(props) = core_info.props() {
+                for (key, value) in props.iter() {
+                    info.insert(key.to_string(), value.to_string());
+                }
+            }
+            info.insert("name".to_string(), core_info.name().to_string());
+            info.insert("version".to_string(), core_info.version().to_string());
+            info.insert("host_name".to_string(), core_info.host_name().to_string());
+            info.insert("user_name".to_string(), core_info.user_name().to_string());
+        })
+        .done(move |id, seq| {
+            if id == pipewire::core::PW_ID_CORE && seq == pending {
+                mainloop_clone.quit();
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let info = info.lock().map(|info| info.clone());
+    info.map_err(|_| "server info mutex poisoned".to_string())
+}
@@ -0,0 +1,109 @@
+/// What identifies the hardware behind a node, gathered from the node's and
+/// its card's properties. Node names are made from some of these too, but
+/// session managers are free to rename nodes, and card numbers (hw:0, hw:1)
+/// change with the order devices are detected in.
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+#[derive(Clone, Debug, Default)]
+pub struct DeviceIdentity {
+    /// `api.bluez5.address` of a Bluetooth device
+    pub bluez_address: Option<String>,
+    /// `device.vendor.id`, `device.product.id` and `device.serial` of a USB card
+    pub usb: Option<(String, String, String)>,
+    /// `device.bus-path` of another card (its PCI slot), or its ALSA card
+    /// name when there is none
+    pub alsa_card: Option<String>,
+    /// `device.profile.name` of the node, telling the outputs of one card
+    /// apart (analog-stereo, hdmi-stereo, ...)
+    pub profile: Option<String>,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl DeviceIdentity {
+    /// Add what the card's properties say; `get` looks up a property
+    pub fn add_card_props<'a>(&mut self, get: impl Fn(&str) -> Option<&'a str>) {
+        if get("device.bus") == Some("usb") {
+            if let (Some(vendor), Some(product), Some(serial)) = (
+                get("device.vendor.id"),
+                get("device.product.id"),
+                get("device.serial"),
+            ) {
+                self.usb = Some((vendor.to_string(), product.to_string(), serial.to_string()));
+            }
+        }
+        self.alsa_card = get("device.bus-path")
+            .or_else(|| get("api.alsa.card.name"))
+            .map(String::from);
+    }
+
+    /// A key naming the same hardware after a reboot or re-plug, or None
+    /// when nothing better than the node name is known
+    pub fn key(&self, is_input: bool) -> Option<String> {
+        let direction = if is_input { "in" } else { "out" };
+        let profile = self.profile.as_deref().unwrap_or("default");
+        if let Some(address) = &self.bluez_address {
+            return Some(format!("bluez5:{}:{}", address, direction));
+        }
+        if let Some((vendor, product, serial)) = &self.usb {
+            return Some(format!(
+                "usb:{}:{}:{}:{}:{}",
+                vendor, product, serial, direction, profile
+            ));
+        }
+        self.alsa_card
+            .as_ref()
+            .map(|card| format!("alsa:{}:{}:{}", card, direction, profile))
+    }
+}
+
+/// The persistent key of a device without better identity: its node name
+pub fn fallback_key(node_name: &str) -> String {
+    format!("node:{}", node_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_prefer_hardware_identity() {
+        let props = |pairs: &'static [(&'static str, &'static str)]| {
+            move |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+        };
+
+        let mut usb = DeviceIdentity {
+            profile: Some("analog-stereo".to_string()),
+            ..DeviceIdentity::default()
+        };
+        usb.add_card_props(props(&[
+            ("device.bus", "usb"),
+            ("device.bus-path", "pci-0000:00:14.0-usb-0:2:1.0"),
+            ("device.vendor.id", "0x046d"),
+            ("device.product.id", "0x0825"),
+            ("device.serial", "046d_0825_A1B2C3"),
+        ]));
+        assert_eq!(
+            usb.key(true).as_deref(),
+            Some("usb:0x046d:0x0825:046d_0825_A1B2C3:in:analog-stereo")
+        );
+
+        let mut builtin = DeviceIdentity::default();
+        builtin.add_card_props(props(&[
+            ("device.bus", "pci"),
+            ("device.bus-path", "pci-0000:00:1f.3"),
+        ]));
+        assert_eq!(
+            builtin.key(false).as_deref(),
+            Some("alsa:pci-0000:00:1f.3:out:default")
+        );
+
+        let headset = DeviceIdentity {
+            bluez_address: Some("00:1B:66:AA:BB:CC".to_string()),
+            ..builtin
+        };
+        assert_eq!(
+            headset.key(true).as_deref(),
+            Some("bluez5:00:1B:66:AA:BB:CC:in")
+        );
+        assert_eq!(DeviceIdentity::default().key(true), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! What the device monitor has reported about each node
+
+use std::collections::HashMap;
+
+/// A node as the monitor reports it
+#[derive(Clone, Debug, PartialEq)]
+pub struct KnownNode {
+    /// `node.name`, the `Device.id`
+    pub id: String,
+    /// Description shown to the user
+    pub name: String,
+}
+
+/// How an announcement of a node relates to what was reported before
+#[derive(Debug, PartialEq)]
+pub enum Announcement {
+    Added,
+    Changed,
+    Unchanged,
+}
+
+/// The audio nodes reported, by global id, so a global announced again (or
+/// a node whose properties were updated without touching the name) isn't
+/// reported as a new device
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+#[derive(Default)]
+pub struct KnownNodes {
+    nodes: HashMap<u32, KnownNode>,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl KnownNodes {
+    pub fn announce(&mut self, global_id: u32, node: KnownNode) -> Announcement {
+        match self.nodes.insert(global_id, node.clone()) {
+            None => Announcement::Added,
+            Some(previous) if previous == node => Announcement::Unchanged,
+            Some(_) => Announcement::Changed,
+        }
+    }
+
+    pub fn get(&self, global_id: u32) -> Option<&KnownNode> {
+        self.nodes.get(&global_id)
+    }
+
+    /// Forget a node; None if it wasn't an audio node we reported
+    pub fn remove(&mut self, global_id: u32) -> Option<KnownNode> {
+        self.nodes.remove(&global_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reannounced_nodes_are_not_added_again() {
+        let node = |name: &str| KnownNode {
+            id: "alsa_input.usb-mic".to_string(),
+            name: name.to_string(),
+        };
+        let mut known = KnownNodes::default();
+        assert_eq!(known.announce(40, node("USB Mic")), Announcement::Added);
+        assert_eq!(known.announce(40, node("USB Mic")), Announcement::Unchanged);
+        assert_eq!(known.announce(40, node("Desk Mic")), Announcement::Changed);
+        assert_eq!(known.get(40), Some(&node("Desk Mic")));
+
+        assert_eq!(known.remove(40), Some(node("Desk Mic")));
+        assert_eq!(known.remove(40), None);
+        assert_eq!(known.announce(40, node("USB Mic")), Announcement::Added);
+    }
+}
@@ -1,2 +1,18 @@
+pub mod bluetooth;
+pub mod capabilities;
+pub mod cards;
+pub mod defaults;
+pub mod diff;
+pub mod display;
 pub mod enumerate;
+pub mod identity;
+pub mod known;
+pub mod modules;
 pub mod monitor;
+pub mod mute;
+pub mod params;
+pub mod ports;
+pub mod preferences;
+pub mod rank;
+pub mod resolve;
+pub mod state;
@@ -0,0 +1,484 @@
+use pyo3::prelude::*;
+use std::sync::Mutex;
+
+use crate::errors::{ConfigError, DeviceNotFoundError};
+use crate::{Device, DeviceType};
+
+/// A device this process created, with the modules backing it
+struct VirtualDevice {
+    device: Device,
+    /// "echo_cancel", ...; destroy functions only accept their own kind
+    kind: &'static str,
+    parts: Vec<u32>,
+}
+
+/// What a virtual device is made of
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+enum Part {
+    /// `libpipewire-module-*` loaded with SPA-JSON arguments
+    Module(&'static str, String),
+    /// Node made by the server's `adapter` factory, with these properties
+    Node(Vec<(&'static str, String)>),
+}
+
+/// Virtual devices this process created. They go away with the process:
+/// PipeWire removes a client's modules and nodes when it disconnects.
+static VIRTUAL_DEVICES: Mutex<Vec<VirtualDevice>> = Mutex::new(Vec::new());
+
+/// Quote a string for PipeWire's SPA-JSON module arguments
+pub fn spa_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `{ key = value ... }` with every value quoted
+fn spa_object(entries: &[(&str, Option<&str>)]) -> String {
+    let body: Vec<String> = entries
+        .iter()
+        .filter_map(|(key, value)| value.map(|v| format!("{} = {}", key, spa_string(v))))
+        .collect();
+    format!("{{ {} }}", body.join(" "))
+}
+
+/// Arguments for `libpipewire-module-echo-cancel`: `source` (the default
+/// microphone if None) is cleaned of what plays on `sink` (the default
+/// output if None), which is taken from the sink's monitor so applications
+/// keep playing to their usual output.
+pub fn echo_cancel_args(name: &str, source: Option<&str>, sink: Option<&str>) -> String {
+    let description = format!("Echo-cancelled {}", source.unwrap_or("microphone"));
+    format!(
+        "{{ library.name = {} monitor.mode = true capture.props = {} source.props = {} sink.props = {} playback.props = {} }}",
+        spa_string("aec/libspa-aec-webrtc"),
+        spa_object(&[
+            ("node.name", Some(&format!("{}.capture", name))),
+            ("node.passive", Some("true")),
+            ("target.object", source),
+        ]),
+        spa_object(&[
+            ("node.name", Some(name)),
+            ("node.description", Some(&description)),
+            ("media.class", Some("Audio/Source")),
+        ]),
+        spa_object(&[
+            ("node.name", Some(&format!("{}.reference", name))),
+            ("node.passive", Some("true")),
+            ("target.object", sink),
+        ]),
+        spa_object(&[
+            ("node.name", Some(&format!("{}.playback", name))),
+            ("node.passive", Some("true")),
+        ]),
+    )
+}
+
+/// Virtual devices created by this process, for the mock `list_devices()`
+#[cfg_attr(feature = "real-audio", allow(dead_code))]
+pub fn virtual_devices() -> Vec<Device> {
+    VIRTUAL_DEVICES
+        .lock()
+        .map(|devices| devices.iter().map(|v| v.device.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// Create the parts making up a virtual device and remember them under its id
+fn create(device: Device, kind: &'static str, parts: &[Part]) -> PyResult<Device> {
+    let mut devices = VIRTUAL_DEVICES
+        .lock()
+        .map_err(|_| pyo3::exceptions::PyRuntimeError::new_err("virtual device list poisoned"))?;
+    if device.id.is_empty() {
+        return Err(ConfigError::new_err("Virtual device name can't be empty"));
+    }
+    if devices.iter().any(|v| v.device.id == device.id) {
+        return Err(ConfigError::new_err(format!(
+            "A virtual device named {:?} already exists",
+            device.id
+        )));
+    }
+    let mut handles = Vec::new();
+    for part in parts {
+        match host::create(part) {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                for handle in handles.into_iter().rev() {
+                    let _ = host::destroy(handle);
+                }
+                return Err(pyo3::exceptions::PyRuntimeError::new_err(e));
+            }
+        }
+    }
+    devices.push(VirtualDevice {
+        device: device.clone(),
+        kind,
+        parts: handles,
+    });
+    Ok(device)
+}
+
+/// Remove the parts of a virtual device created with `create`, last first
+fn destroy(device_id: &str, kind: &'static str) -> PyResult<()> {
+    let handles = {
+        let mut devices = VIRTUAL_DEVICES.lock().map_err(|_| {
+            pyo3::exceptions::PyRuntimeError::new_err("virtual device list poisoned")
+        })?;
+        let index = devices
+            .iter()
+            .position(|v| v.device.id == device_id && v.kind == kind)
+            .ok_or_else(|| {
+                DeviceNotFoundError::new_err(format!(
+                    "No {} device {:?} was created by this process",
+                    kind.replace('_', "-"),
+                    device_id
+                ))
+            })?;
+        devices.remove(index).parts
+    };
+    for handle in handles.into_iter().rev() {
+        host::destroy(handle).map_err(pyo3::exceptions::PyRuntimeError::new_err)?;
+    }
+    Ok(())
+}
+
+pub fn create_echo_cancel(
+    name: &str,
+    source: Option<&str>,
+    sink: Option<&str>,
+) -> PyResult<Device> {
+    let device = Device {
+        id: name.to_string(),
+        name: format!("Echo-cancelled {}", source.unwrap_or("microphone")),
+        device_type: DeviceType::Microphone,
+        is_bluetooth: false,
+        sample_rate: 48000,
+        channels: 1,
+        is_default: false,
+        bluetooth_profile: None,
+        node_id: None,
+        object_serial: None,
+        persistent_key: None,
+        icon_name: None,
+        vendor_name: None,
+        product_name: None,
+        bus: None,
+        bus_path: None,
+        state: None,
+    };
+    create(
+        device,
+        "echo_cancel",
+        &[Part::Module(
+            "libpipewire-module-echo-cancel",
+            echo_cancel_args(name, source, sink),
+        )],
+    )
+}
+
+pub fn destroy_echo_cancel(device_id: &str) -> PyResult<()> {
+    destroy(device_id, "echo_cancel")
+}
+
+/// Properties of a null sink: a sink that discards what is played to it,
+/// except through its monitor, which can be recorded
+fn null_sink_props(name: &str, description: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("factory.name", "support.null-audio-sink".to_string()),
+        ("node.name", name.to_string()),
+        ("node.description", description.to_string()),
+        ("media.class", "Audio/Sink".to_string()),
+        ("audio.position", "FL,FR".to_string()),
+        ("monitor.channel-volumes", "true".to_string()),
+    ]
+}
+
+/// Arguments for `libpipewire-module-loopback` playing the monitor of sink
+/// `name` on `output` (the default output if None)
+pub fn loopback_args(name: &str, output: Option<&str>) -> String {
+    format!(
+        "{{ capture.props = {} playback.props = {} }}",
+        spa_object(&[
+            ("node.name", Some(&format!("{}.loopback", name))),
+            ("target.object", Some(name)),
+            ("stream.capture.sink", Some("true")),
+            ("node.passive", Some("true")),
+        ]),
+        spa_object(&[
+            ("node.name", Some(&format!("{}.playback", name))),
+            ("target.object", output),
+            ("node.passive", Some("true")),
+        ]),
+    )
+}
+
+pub fn create_virtual_sink(
+    name: &str,
+    description: Option<&str>,
+    playback: bool,
+    output: Option<&str>,
+) -> PyResult<Device> {
+    let description = description.unwrap_or(name);
+    let device = Device {
+        id: name.to_string(),
+        name: description.to_string(),
+        device_type: DeviceType::Speaker,
+        is_bluetooth: false,
+        sample_rate: 48000,
+        channels: 2,
+        is_default: false,
+        bluetooth_profile: None,
+        node_id: None,
+        object_serial: None,
+        persistent_key: None,
+        icon_name: None,
+        vendor_name: None,
+        product_name: None,
+        bus: None,
+        bus_path: None,
+        state: None,
+    };
+    let mut parts = vec![Part::Node(null_sink_props(name, description))];
+    if playback {
+        parts.push(Part::Module(
+            "libpipewire-module-loopback",
+            loopback_args(name, output),
+        ));
+    }
+    create(device, "virtual_sink", &parts)
+}
+
+pub fn destroy_virtual_sink(device_id: &str) -> PyResult<()> {
+    destroy(device_id, "virtual_sink")
+}
+
+/// Modules are loaded into a PipeWire context kept on a thread of its own,
+/// since a context's objects may only be touched from its loop
+#[cfg(feature = "real-audio")]
+mod host {
+    use super::Part;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::ffi::{c_char, c_void, CString};
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use pipewire as pw;
+    use pipewire::context::Context;
+    use pipewire::main_loop::MainLoop;
+    use pipewire::node::Node;
+    use pipewire::properties::Properties;
+
+    #[repr(C)]
+    struct PwImplModule {
+        _private: [u8; 0],
+    }
+
+    extern "C" {
+        fn pw_context_load_module(
+            context: *mut pw::sys::pw_context,
+            name: *const c_char,
+            args: *const c_char,
+            properties: *mut c_void,
+        ) -> *mut PwImplModule;
+        fn pw_impl_module_destroy(module: *mut PwImplModule);
+    }
+
+    enum HostCommand {
+        Load {
+            name: CString,
+            args: CString,
+            reply: Sender<Result<u32, String>>,
+        },
+        CreateNode {
+            props: Vec<(&'static str, String)>,
+            reply: Sender<Result<u32, String>>,
+        },
+        Destroy {
+            handle: u32,
+            reply: Sender<Result<(), String>>,
+        },
+    }
+
+    static HOST: Mutex<Option<Sender<HostCommand>>> = Mutex::new(None);
+
+    /// Send a command to the host thread, starting it if needed
+    fn send(command: HostCommand) -> Result<(), String> {
+        let mut host = HOST
+            .lock()
+            .map_err(|_| "module host poisoned".to_string())?;
+        let tx = host.get_or_insert_with(|| {
+            let (tx, rx) = channel();
+            thread::Builder::new()
+                .name("pipewire-modules".to_string())
+                .spawn(move || {
+                    if let Err(e) = run_host(rx) {
+                        log!("PipeWire module host error: {}", e);
+                    }
+                })
+                .expect("failed to spawn PipeWire module host");
+            tx
+        });
+        if tx.send(command).is_err() {
+            // The thread died; start a new one next time
+            *host = None;
+            return Err("PipeWire module host is not running".to_string());
+        }
+        Ok(())
+    }
+
+    /// Objects the host keeps alive
+    enum HostObject {
+        Module(*mut PwImplModule),
+        Node(Node),
+    }
+
+    pub fn create(part: &Part) -> Result<u32, String> {
+        let (reply, rx) = channel();
+        send(match part {
+            Part::Module(name, args) => HostCommand::Load {
+                name: CString::new(*name).map_err(|e| e.to_string())?,
+                args: CString::new(args.as_str()).map_err(|e| e.to_string())?,
+                reply,
+            },
+            Part::Node(props) => HostCommand::CreateNode {
+                props: props.clone(),
+                reply,
+            },
+        })?;
+        rx.recv()
+            .map_err(|_| "PipeWire module host exited".to_string())?
+    }
+
+    pub fn destroy(handle: u32) -> Result<(), String> {
+        let (reply, rx) = channel();
+        send(HostCommand::Destroy { handle, reply })?;
+        rx.recv()
+            .map_err(|_| "PipeWire module host exited".to_string())?
+    }
+
+    fn run_host(commands: std::sync::mpsc::Receiver<HostCommand>) -> Result<(), String> {
+        pw::init();
+
+        let mainloop =
+            MainLoop::new(None).map_err(|e| format!("Failed to create main loop: {:?}", e))?;
+        let context =
+            Context::new(&mainloop).map_err(|e| format!("Failed to create context: {:?}", e))?;
+        // Modules export their nodes through the context's connection
+        let core = context
+            .connect(None)
+            .map_err(|e| format!("Failed to connect to core: {:?}", e))?;
+        let context_ptr = context.as_raw_ptr();
+
+        let objects: RefCell<HashMap<u32, HostObject>> = RefCell::new(HashMap::new());
+        let next_handle = Cell::new(1u32);
+        let timer_core = core.clone();
+        let timer = mainloop.loop_().add_timer(move |_| {
+            let keep = |object: HostObject| {
+                let handle = next_handle.get();
+                next_handle.set(handle + 1);
+                objects.borrow_mut().insert(handle, object);
+                handle
+            };
+            while let Ok(command) = commands.try_recv() {
+                match command {
+                    HostCommand::Load { name, args, reply } => {
+                        let module = unsafe {
+                            pw_context_load_module(
+                                context_ptr,
+                                name.as_ptr(),
+                                args.as_ptr(),
+                                std::ptr::null_mut(),
+                            )
+                        };
+                        let result = if module.is_null() {
+                            Err(format!(
+                                "Failed to load {}: {}",
+                                name.to_string_lossy(),
+                                std::io::Error::last_os_error()
+                            ))
+                        } else {
+                            Ok(keep(HostObject::Module(module)))
+                        };
+                        let _ = reply.send(result);
+                    }
+                    HostCommand::CreateNode { props, reply } => {
+                        let mut properties = Properties::new();
+                        for (key, value) in props {
+                            properties.insert(key, value);
+                        }
+                        let result = timer_core
+                            .create_object::<Node>("adapter", &properties)
+                            .map(|node| keep(HostObject::Node(node)))
+                            .map_err(|e| format!("Failed to create node: {:?}", e));
+                        let _ = reply.send(result);
+                    }
+                    HostCommand::Destroy { handle, reply } => {
+                        let result = match objects.borrow_mut().remove(&handle) {
+                            Some(HostObject::Module(module)) => {
+                                unsafe { pw_impl_module_destroy(module) };
+                                Ok(())
+                            }
+                            Some(HostObject::Node(node)) => timer_core
+                                .destroy_object(node)
+                                .map(|_| ())
+                                .map_err(|e| format!("Failed to destroy node: {:?}", e)),
+                            None => Err(format!("Object {} does not exist", handle)),
+                        };
+                        let _ = reply.send(result);
+                    }
+                }
+            }
+        });
+        let interval = Duration::from_millis(50);
+        timer.update_timer(Some(interval), Some(interval));
+
+        mainloop.run();
+        Ok(())
+    }
+}
+
+/// Without PipeWire, parts are only counted so the API can be exercised
+#[cfg(not(feature = "real-audio"))]
+mod host {
+    use super::Part;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
+
+    pub fn create(_part: &Part) -> Result<u32, String> {
+        Ok(NEXT_HANDLE.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn destroy(_handle: u32) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_args_quote_targets() {
+        let args = echo_cancel_args("aec", Some("alsa_input.\"usb\""), None);
+        assert!(args.contains(r#"source.props = { node.name = "aec" "#));
+        assert!(args.contains(r#"target.object = "alsa_input.\"usb\"" }"#));
+        // No sink given: the reference follows the default output
+        assert!(
+            args.contains(r#"sink.props = { node.name = "aec.reference" node.passive = "true" }"#)
+        );
+
+        let args = loopback_args("apps", Some("speakers"));
+        assert!(args.contains(r#"target.object = "apps" stream.capture.sink = "true""#));
+        assert!(args.contains(
+            r#"playback.props = { node.name = "apps.playback" target.object = "speakers""#
+        ));
+    }
+}
@@ -1,107 +1,335 @@
+#![cfg(feature = "real-audio")]
+
 use pyo3::prelude::*;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::Mutex;
 use std::thread;
+use std::time::Duration;
 
-#[cfg(feature = "real-audio")]
 use pipewire as pw;
-#[cfg(feature = "real-audio")]
 use pipewire::context::Context;
-#[cfg(feature = "real-audio")]
 use pipewire::main_loop::MainLoop;
+use pipewire::node::NodeChangeMask;
 
+use crate::capture::reconnect::ReconnectPolicy;
+use crate::device::known::{Announcement, KnownNode, KnownNodes};
+use crate::device::state::{state_name, StateTracker};
 use crate::{DeviceEvent, DeviceMonitor};
 
 pub fn start_monitoring() -> PyResult<DeviceMonitor> {
     let (event_tx, event_rx) = channel();
     let (stop_tx, stop_rx) = channel();
 
-    let handle = thread::spawn(move || {
-        #[cfg(feature = "real-audio")]
-        {
+    let handle = thread::Builder::new()
+        .name("device-monitor".to_string())
+        .spawn(move || {
             if let Err(e) = run_monitor_thread(event_tx, stop_rx) {
-                eprintln!("Device monitor thread error: {}", e);
+                log!("Device monitor thread error: {}", e);
             }
-        }
-        #[cfg(not(feature = "real-audio"))]
-        {
-            // Mock implementation
-            let _ = stop_rx.recv();
-        }
-    });
+        })
+        .map_err(|e| {
+            pyo3::exceptions::PyRuntimeError::new_err(format!(
+                "Failed to start device monitor: {}",
+                e
+            ))
+        })?;
 
     Ok(DeviceMonitor {
         event_rx: Some(Mutex::new(event_rx)),
         thread_handle: Some(handle),
         stop_tx: Some(stop_tx),
+        inject_tx: None,
+        stopped: false,
     })
 }
 
-#[cfg(feature = "real-audio")]
+enum MonitorError {
+    Fatal(String),
+    /// Failed to connect to PipeWire
+    Recoverable(String),
+    /// The connection was lost, e.g. the daemon restarted
+    Disconnected(String),
+}
+
+/// Follow the devices until stopped, reconnecting whenever PipeWire goes
+/// away: "disconnected", then "reconnecting" before each attempt, then a
+/// fresh snapshot of the devices that ends with "resynced"
 fn run_monitor_thread(event_tx: Sender<DeviceEvent>, stop_rx: Receiver<()>) -> Result<(), String> {
     pw::init();
 
-    let mainloop =
-        MainLoop::new(None).map_err(|e| format!("Failed to create main loop: {:?}", e))?;
-    let context =
-        Context::new(&mainloop).map_err(|e| format!("Failed to create context: {:?}", e))?;
+    let stop_rx = Rc::new(stop_rx);
+    let policy = ReconnectPolicy {
+        max_attempts: None,
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(30),
+    };
+    let send = |type_: &str| {
+        let _ = event_tx.send(DeviceEvent {
+            type_: type_.to_string(),
+            device_id: None,
+            device_name: None,
+            state: None,
+            initial: false,
+        });
+    };
+
+    // Whether a connection was established before, so the next snapshot is
+    // a resync
+    let mut resync = false;
+    // Consecutive failed attempts since the last established connection
+    let mut attempt = 0u32;
+
+    loop {
+        let error = match watch_devices(&event_tx, &stop_rx, resync) {
+            Ok(()) => return Ok(()),
+            Err(MonitorError::Fatal(e)) => return Err(e),
+            Err(MonitorError::Disconnected(e)) => {
+                send("disconnected");
+                resync = true;
+                attempt = 0;
+                e
+            }
+            Err(MonitorError::Recoverable(e)) => e,
+        };
+
+        attempt += 1;
+        let delay = policy.delay(attempt);
+        log!(
+            "Device monitor lost PipeWire: {}. Reconnecting in {:.1}s",
+            error,
+            delay.as_secs_f64()
+        );
+        send("reconnecting");
+        match stop_rx.recv_timeout(delay) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// One connection's worth of monitoring. The devices present are reported
+/// first, ending with "snapshot_complete" (or "resynced" after a reconnect).
+fn watch_devices(
+    event_tx: &Sender<DeviceEvent>,
+    stop_rx: &Rc<Receiver<()>>,
+    resync: bool,
+) -> Result<(), MonitorError> {
+    let mainloop = MainLoop::new(None)
+        .map_err(|e| MonitorError::Fatal(format!("Failed to create main loop: {:?}", e)))?;
+    let context = Context::new(&mainloop)
+        .map_err(|e| MonitorError::Fatal(format!("Failed to create context: {:?}", e)))?;
     let core = context
         .connect(None)
-        .map_err(|e| format!("Failed to connect to core: {:?}", e))?;
+        .map_err(|e| MonitorError::Recoverable(format!("Failed to connect to core: {:?}", e)))?;
     let registry = core
         .get_registry()
-        .map_err(|e| format!("Failed to get registry: {:?}", e))?;
+        .map_err(|e| MonitorError::Recoverable(format!("Failed to get registry: {:?}", e)))?;
+    // A second registry proxy for binding nodes from inside the listener
+    let registry_binding = core.get_registry().map_err(|e| {
+        MonitorError::Recoverable(format!("Failed to get registry binding: {:?}", e))
+    })?;
 
+    let event_tx = event_tx.clone();
     let event_tx_clone = event_tx.clone();
     let event_tx_remove = event_tx.clone();
 
+    // Audio nodes are bound to follow their state, reported as "state_changed",
+    // and their properties, reported as "changed"
+    let nodes = Rc::new(RefCell::new(HashMap::new()));
+    let states = Rc::new(RefCell::new(StateTracker::default()));
+    let known = Rc::new(RefCell::new(KnownNodes::default()));
+    let nodes_remove = nodes.clone();
+    let states_remove = states.clone();
+    let known_remove = known.clone();
+
+    // Set while the devices already present are reported, until
+    // "snapshot_complete"
+    let initial = Rc::new(Cell::new(true));
+    let initial_global = initial.clone();
+    let initial_remove = initial.clone();
+
     // Listener for registry events
     let _listener = registry
         .add_listener_local()
         .global(move |global| {
-            if let Some(props) = global.props {
-                if let Some(media_class) = props.get("media.class") {
-                    if media_class == "Audio/Source" || media_class == "Audio/Sink" {
-                        let name = props
-                            .get("node.description")
-                            .or_else(|| props.get("node.nick"))
-                            .or_else(|| props.get("node.name"))
-                            .unwrap_or("Unknown Device");
-
-                        let id = props
-                            .get("node.name")
-                            .map(|s| s.to_string())
-                            .unwrap_or_else(|| global.id.to_string());
-
-                        let _ = event_tx_clone.send(DeviceEvent {
-                            type_: "added".to_string(),
-                            device_id: Some(id),
-                            device_name: Some(name.to_string()),
+            let Some(props) = global.props else {
+                return;
+            };
+            if !matches!(
+                props.get("media.class"),
+                Some("Audio/Source" | "Audio/Sink")
+            ) {
+                return;
+            }
+            let node = known_node(global.id, |key| props.get(key));
+            let type_ = match known.borrow_mut().announce(global.id, node.clone()) {
+                Announcement::Added => "added",
+                Announcement::Changed => "changed",
+                Announcement::Unchanged => return,
+            };
+            let _ = event_tx_clone.send(DeviceEvent {
+                type_: type_.to_string(),
+                device_id: Some(node.id),
+                device_name: Some(node.name),
+                state: None,
+                initial: initial_global.get(),
+            });
+            if nodes.borrow().contains_key(&global.id) {
+                return;
+            }
+
+            let Ok(proxy) = registry_binding.bind::<pw::node::Node, _>(&global) else {
+                return;
+            };
+            let node_id = global.id;
+            let event_tx = event_tx_clone.clone();
+            let states = states.clone();
+            let known = known.clone();
+            let initial = initial_global.clone();
+            let listener = proxy
+                .add_listener_local()
+                .info(move |info| {
+                    let send = |type_: &str, node: KnownNode, state: Option<&str>| {
+                        let _ = event_tx.send(DeviceEvent {
+                            type_: type_.to_string(),
+                            device_id: Some(node.id),
+                            device_name: Some(node.name),
+                            state: state.map(String::from),
+                            initial: initial.get(),
                         });
+                    };
+                    // Property updates, e.g. the user renaming the device
+                    let props = info
+                        .props()
+                        .filter(|_| info.change_mask().contains(NodeChangeMask::PROPS));
+                    if let Some(props) = props {
+                        let node = known_node(node_id, |key| props.get(key));
+                        let announced = known.borrow_mut().announce(node_id, node.clone());
+                        if announced == Announcement::Changed {
+                            send("changed", node, None);
+                        }
                     }
-                }
-            }
+                    let state = state_name(&info.state());
+                    if states.borrow_mut().update(node_id, state) {
+                        let node = known.borrow().get(node_id).cloned();
+                        if let Some(node) = node {
+                            send("state_changed", node, Some(state));
+                        }
+                    }
+                })
+                .register();
+            nodes.borrow_mut().insert(node_id, (proxy, listener));
         })
         .global_remove(move |id| {
+            nodes_remove.borrow_mut().remove(&id);
+            states_remove.borrow_mut().remove(id);
+            // Links, clients and other objects come and go too
+            let Some(node) = known_remove.borrow_mut().remove(id) else {
+                return;
+            };
             let _ = event_tx_remove.send(DeviceEvent {
                 type_: "removed".to_string(),
-                device_id: Some(id.to_string()),
-                device_name: None,
+                device_id: Some(node.id),
+                device_name: Some(node.name),
+                state: None,
+                initial: initial_remove.get(),
             });
         })
         .register();
 
+    // The first roundtrip brings the existing nodes, the second their state
+    let pending =
+        Rc::new(Cell::new(core.sync(0).map_err(|e| {
+            MonitorError::Recoverable(format!("Sync failed: {:?}", e))
+        })?));
+    let snapshot_rounds = Cell::new(0);
+    // Set when the daemon goes away (EPIPE on the core)
+    let disconnected = Rc::new(RefCell::new(None));
+    let _core_listener = core
+        .add_listener_local()
+        .error({
+            let mainloop = mainloop.clone();
+            let disconnected = disconnected.clone();
+            move |id, _seq, res, message| {
+                if id == pw::core::PW_ID_CORE && res == -libc::EPIPE {
+                    *disconnected.borrow_mut() = Some(message.to_string());
+                    mainloop.quit();
+                }
+            }
+        })
+        .done({
+            let core = core.clone();
+            let pending = pending.clone();
+            move |id, seq| {
+                if id != pw::core::PW_ID_CORE || seq != pending.get() || !initial.get() {
+                    return;
+                }
+                snapshot_rounds.set(snapshot_rounds.get() + 1);
+                if snapshot_rounds.get() < 2 {
+                    if let Ok(seq) = core.sync(0) {
+                        pending.set(seq);
+                        return;
+                    }
+                }
+                initial.set(false);
+                let type_ = if resync {
+                    "resynced"
+                } else {
+                    "snapshot_complete"
+                };
+                let _ = event_tx.send(DeviceEvent {
+                    type_: type_.to_string(),
+                    device_id: None,
+                    device_name: None,
+                    state: None,
+                    initial: true,
+                });
+            }
+        })
+        .register();
+
     // Watchdog/Stop check
     let loop_clone = mainloop.clone();
+    let stop_rx = stop_rx.clone();
+    let stopped = Rc::new(Cell::new(false));
+    let stopped_clone = stopped.clone();
     let timer = mainloop.loop_().add_timer(move |_| {
-        if let Ok(_) = stop_rx.try_recv() {
-            loop_clone.quit();
+        match stop_rx.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            // Dropped with the monitor
+            Ok(()) | Err(TryRecvError::Disconnected) => {
+                stopped_clone.set(true);
+                loop_clone.quit();
+            }
         }
     });
 
-    let timeout = std::time::Duration::from_millis(200);
+    let timeout = Duration::from_millis(200);
     timer.update_timer(Some(timeout), Some(timeout));
 
     mainloop.run();
-    Ok(())
+    if stopped.get() {
+        return Ok(());
+    }
+    let message = disconnected.borrow_mut().take();
+    Err(MonitorError::Disconnected(message.unwrap_or_else(|| {
+        "PipeWire mainloop exited unexpectedly".to_string()
+    })))
+}
+
+/// The id and name a device of an audio node is listed with
+fn known_node<'a>(global_id: u32, get: impl Fn(&str) -> Option<&'a str>) -> KnownNode {
+    KnownNode {
+        id: get("node.name")
+            .map(String::from)
+            .unwrap_or_else(|| global_id.to_string()),
+        name: get("node.description")
+            .or_else(|| get("node.nick"))
+            .or_else(|| get("node.name"))
+            .unwrap_or("Unknown Device")
+            .to_string(),
+    }
 }
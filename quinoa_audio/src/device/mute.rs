@@ -0,0 +1,224 @@
+//! Microphone mute as the system sees it: the node's mute control, which the
+//! desktop's mute button and hardware mute switches (when the driver reports
+//! them) both set. Muting this way silences every app, not just ours.
+
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const UNMUTED: u8 = 0;
+const MUTED: u8 = 1;
+
+/// Whether a device is muted, given by node name, global id or description
+pub fn get_device_mute(ident: &str) -> PyResult<bool> {
+    host::is_muted(ident)
+}
+
+#[derive(Default)]
+struct MuteShared {
+    /// The mic to watch, which changes with mic switches
+    device: Mutex<Option<String>>,
+    /// Last state read from the device
+    state: AtomicU8,
+    stopped: AtomicBool,
+}
+
+/// Follows the mute control of a session's mic from a thread of its own,
+/// so the audio loop only reads an atomic. A mic that starts out muted is
+/// reported like one muted during the session.
+pub struct MuteMonitor {
+    shared: Arc<MuteShared>,
+    /// Last state `poll()` reported
+    reported: AtomicU8,
+}
+
+impl MuteMonitor {
+    /// Watch `device_id`; None watches nothing until `set_device()`
+    pub fn start(device_id: Option<&str>) -> Self {
+        let shared = Arc::new(MuteShared {
+            device: Mutex::new(device_id.map(str::to_string)),
+            ..MuteShared::default()
+        });
+        let watched = shared.clone();
+        let spawned = thread::Builder::new()
+            .name("mute-watch".to_string())
+            .spawn(move || {
+                while !watched.stopped.load(Ordering::Relaxed) {
+                    let device = watched.device.lock().ok().and_then(|d| d.clone());
+                    // The device may be gone for a moment, e.g. while reconnecting
+                    if let Some(Ok(muted)) = device.map(|device| host::is_muted(&device)) {
+                        watched.state.store(u8::from(muted), Ordering::Relaxed);
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            });
+        if let Err(e) = spawned {
+            log!("Failed to start mute watch: {}", e);
+        }
+        Self {
+            shared,
+            reported: AtomicU8::new(UNMUTED),
+        }
+    }
+
+    /// Follow a mic switch
+    pub fn set_device(&self, device_id: &str) {
+        if let Ok(mut device) = self.shared.device.lock() {
+            if device.as_deref() != Some(device_id) {
+                *device = Some(device_id.to_string());
+            }
+        }
+    }
+
+    /// Some(true) once the mic was muted, Some(false) once it was unmuted
+    pub fn poll(&self) -> Option<bool> {
+        let state = self.shared.state.load(Ordering::Relaxed);
+        (self.reported.swap(state, Ordering::Relaxed) != state).then_some(state == MUTED)
+    }
+
+    /// Whether the mic was muted as of the last `poll()`
+    pub fn is_muted(&self) -> bool {
+        self.reported.load(Ordering::Relaxed) == MUTED
+    }
+}
+
+impl Drop for MuteMonitor {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "real-audio")]
+mod host {
+    use pyo3::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use pipewire as pw;
+    use pw::spa::param::ParamType;
+    use pw::spa::pod::Value;
+    use pw::types::ObjectType;
+
+    use crate::device::params::{object_properties, Connection};
+    use crate::device::resolve::resolve_device;
+    use crate::errors::DeviceNotFoundError;
+
+    /// The device's node, bound on a new connection
+    fn bind_node(ident: &str) -> PyResult<(Connection, pw::node::Node)> {
+        let devices = crate::device::enumerate::list_devices_pw()
+            .map_err(pyo3::exceptions::PyRuntimeError::new_err)?;
+        let device = resolve_device(&devices, ident).map_err(DeviceNotFoundError::new_err)?;
+        let node_id = device.node_id.ok_or_else(|| {
+            DeviceNotFoundError::new_err(format!("Device '{}' has no PipeWire node", device.id))
+        })?;
+        let id = device.id.clone();
+        let bound = (|| {
+            let conn = Connection::new()?;
+            let version = Rc::new(RefCell::new(None));
+            let _listener = conn
+                .registry
+                .add_listener_local()
+                .global({
+                    let version = version.clone();
+                    move |global| {
+                        if global.type_ == ObjectType::Node && global.id == node_id {
+                            *version.borrow_mut() = Some(global.version);
+                        }
+                    }
+                })
+                .register();
+            conn.roundtrip()?;
+            let version = version
+                .take()
+                .ok_or_else(|| format!("Device '{}' is gone", id))?;
+            let node = conn.bind(node_id, ObjectType::Node, version)?;
+            Ok::<_, String>((conn, node))
+        })();
+        bound.map_err(pyo3::exceptions::PyRuntimeError::new_err)
+    }
+
+    /// The node's mute or soft mute, from its Props param
+    pub fn is_muted(ident: &str) -> PyResult<bool> {
+        use pw::spa::sys::{SPA_PROP_mute, SPA_PROP_softMute};
+
+        let (conn, node) = bind_node(ident)?;
+        let muted = Rc::new(RefCell::new(false));
+        let _listener = node
+            .add_listener_local()
+            .param({
+                let muted = muted.clone();
+                move |_seq, id, _index, _next, param| {
+                    let (ParamType::Props, Some(pod)) = (id, param) else {
+                        return;
+                    };
+                    for prop in object_properties(pod) {
+                        if let (SPA_PROP_mute | SPA_PROP_softMute, Value::Bool(true)) =
+                            (prop.key, prop.value)
+                        {
+                            *muted.borrow_mut() = true;
+                        }
+                    }
+                }
+            })
+            .register();
+        node.enum_params(0, Some(ParamType::Props), 0, u32::MAX);
+        conn.roundtrip()
+            .map_err(pyo3::exceptions::PyRuntimeError::new_err)?;
+        let muted = *muted.borrow();
+        Ok(muted)
+    }
+}
+
+#[cfg(not(feature = "real-audio"))]
+mod host {
+    use pyo3::prelude::*;
+    use std::ops::Range;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    /// The mock mic whose mute button gets pressed
+    const MUTE_MIC: &str = "mock_mute_mic";
+    /// When it is muted, counted from when it was first asked about
+    const MUTED: Range<Duration> = Duration::from_millis(500)..Duration::from_millis(2000);
+
+    static FIRST_ASKED: Mutex<Option<Instant>> = Mutex::new(None);
+
+    /// `mock_mute_mic` is muted for a while soon after it is first asked
+    /// about; the other mock devices never are
+    pub fn is_muted(ident: &str) -> PyResult<bool> {
+        if ident != MUTE_MIC {
+            return Ok(false);
+        }
+        let mut first = FIRST_ASKED
+            .lock()
+            .map_err(|_| pyo3::exceptions::PyRuntimeError::new_err("mock mute state poisoned"))?;
+        let since = first.get_or_insert_with(Instant::now).elapsed();
+        Ok(MUTED.contains(&since))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_reported_once() {
+        let monitor = MuteMonitor {
+            shared: Arc::default(),
+            reported: AtomicU8::new(UNMUTED),
+        };
+        // Starting out unmuted is nothing to report
+        assert_eq!(monitor.poll(), None);
+        monitor.shared.state.store(MUTED, Ordering::Relaxed);
+        assert_eq!(monitor.poll(), Some(true));
+        assert_eq!(monitor.poll(), None);
+        assert!(monitor.is_muted());
+        monitor.shared.state.store(UNMUTED, Ordering::Relaxed);
+        assert_eq!(monitor.poll(), Some(false));
+        assert!(!monitor.is_muted());
+    }
+}
@@ -0,0 +1,156 @@
+#![cfg(feature = "real-audio")]
+
+//! Short-lived PipeWire connections for querying and setting object params
+
+use pipewire as pw;
+use pw::context::Context;
+use pw::main_loop::MainLoop;
+use pw::proxy::ProxyT;
+use pw::registry::{GlobalObject, Registry};
+use pw::spa::pod::deserialize::PodDeserializer;
+use pw::spa::pod::{Pod, Property, Value, ValueArray};
+use pw::spa::utils::Id;
+use pw::types::ObjectType;
+
+use crate::device::cards::CardProfile;
+use crate::device::ports::CardPort;
+
+/// A connection to the server that lives for one query. Fields drop in
+/// declaration order, so the registry goes before the core and the loop.
+pub struct Connection {
+    pub registry: Registry,
+    pub core: pw::core::Core,
+    _context: Context,
+    pub mainloop: MainLoop,
+}
+
+impl Connection {
+    pub fn new() -> Result<Self, String> {
+        pw::init();
+        let mainloop =
+            MainLoop::new(None).map_err(|e| format!("Failed to create main loop: {:?}", e))?;
+        let context =
+            Context::new(&mainloop).map_err(|e| format!("Failed to create context: {:?}", e))?;
+        let core = context
+            .connect(None)
+            .map_err(|e| format!("Failed to connect to core: {:?}", e))?;
+        let registry = core
+            .get_registry()
+            .map_err(|e| format!("Failed to get registry: {:?}", e))?;
+        Ok(Self {
+            registry,
+            core,
+            _context: context,
+            mainloop,
+        })
+    }
+
+    /// Run the loop until the server has answered everything sent so far
+    pub fn roundtrip(&self) -> Result<(), String> {
+        let pending = self
+            .core
+            .sync(0)
+            .map_err(|e| format!("Sync failed: {:?}", e))?;
+        let mainloop = self.mainloop.clone();
+        let _listener = self
+            .core
+            .add_listener_local()
+            .done(move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            })
+            .register();
+        self.mainloop.run();
+        Ok(())
+    }
+
+    /// Bind a global seen in an earlier roundtrip
+    pub fn bind<T: ProxyT>(&self, id: u32, type_: ObjectType, version: u32) -> Result<T, String> {
+        let global: GlobalObject<&pw::spa::utils::dict::DictRef> = GlobalObject {
+            id,
+            permissions: pw::permissions::PermissionFlags::R,
+            type_,
+            version,
+            props: None,
+        };
+        self.registry
+            .bind(&global)
+            .map_err(|e| format!("Failed to bind object {}: {:?}", id, e))
+    }
+}
+
+/// Properties of an object param, empty if the pod isn't an object
+pub fn object_properties(pod: &Pod) -> Vec<Property> {
+    match PodDeserializer::deserialize_any_from(pod.as_bytes()) {
+        Ok((_, Value::Object(object))) => object.properties,
+        _ => Vec::new(),
+    }
+}
+
+/// A card profile from an EnumProfile or Profile param
+pub fn parse_profile(pod: &Pod) -> Option<CardProfile> {
+    use pw::spa::sys::{
+        SPA_PARAM_AVAILABILITY_no, SPA_PARAM_PROFILE_available, SPA_PARAM_PROFILE_description,
+        SPA_PARAM_PROFILE_index, SPA_PARAM_PROFILE_name, SPA_PARAM_PROFILE_priority,
+    };
+    let mut index = None;
+    let mut profile = CardProfile {
+        available: true,
+        ..CardProfile::default()
+    };
+    for prop in object_properties(pod) {
+        match (prop.key, prop.value) {
+            (SPA_PARAM_PROFILE_index, Value::Int(i)) => index = Some(i),
+            (SPA_PARAM_PROFILE_name, Value::String(s)) => profile.name = s,
+            (SPA_PARAM_PROFILE_description, Value::String(s)) => profile.description = s,
+            (SPA_PARAM_PROFILE_priority, Value::Int(p)) => profile.priority = p,
+            (SPA_PARAM_PROFILE_available, Value::Id(Id(a))) => {
+                profile.available = a != SPA_PARAM_AVAILABILITY_no
+            }
+            _ => {}
+        }
+    }
+    profile.index = index?;
+    Some(profile)
+}
+
+/// A card port from an EnumRoute or Route param
+pub fn parse_route(pod: &Pod) -> Option<CardPort> {
+    use pw::spa::sys::{
+        SPA_PARAM_AVAILABILITY_no, SPA_PARAM_ROUTE_available, SPA_PARAM_ROUTE_description,
+        SPA_PARAM_ROUTE_device, SPA_PARAM_ROUTE_devices, SPA_PARAM_ROUTE_direction,
+        SPA_PARAM_ROUTE_index, SPA_PARAM_ROUTE_name, SPA_PARAM_ROUTE_profiles, SPA_DIRECTION_INPUT,
+    };
+    let mut index = None;
+    let mut direction = None;
+    let mut port = CardPort {
+        available: true,
+        ..CardPort::default()
+    };
+    for prop in object_properties(pod) {
+        match (prop.key, prop.value) {
+            (SPA_PARAM_ROUTE_index, Value::Int(i)) => index = Some(i),
+            (SPA_PARAM_ROUTE_direction, Value::Id(Id(d))) => {
+                direction = Some(if d == SPA_DIRECTION_INPUT {
+                    "input"
+                } else {
+                    "output"
+                })
+            }
+            (SPA_PARAM_ROUTE_name, Value::String(s)) => port.name = s,
+            (SPA_PARAM_ROUTE_description, Value::String(s)) => port.description = s,
+            (SPA_PARAM_ROUTE_available, Value::Id(Id(a))) => {
+                port.available = a != SPA_PARAM_AVAILABILITY_no
+            }
+            (SPA_PARAM_ROUTE_profiles, Value::ValueArray(ValueArray::Int(p))) => port.profiles = p,
+            (SPA_PARAM_ROUTE_devices, Value::ValueArray(ValueArray::Int(d))) => port.devices = d,
+            // A Route param names the one device using it
+            (SPA_PARAM_ROUTE_device, Value::Int(d)) => port.devices = vec![d],
+            _ => {}
+        }
+    }
+    port.index = index?;
+    port.direction = direction?.to_string();
+    Some(port)
+}
@@ -0,0 +1,289 @@
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::device::cards::{list_cards, Card};
+use crate::device::resolve::resolve_device;
+use crate::errors::{ConfigError, DeviceNotFoundError};
+use crate::{Device, DeviceType};
+
+/// A physical connector of a card (PipeWire calls them routes): the
+/// internal microphone, the headset jack, a rear line input, ...
+#[derive(Clone, Debug, Default, Serialize)]
+#[pyclass]
+pub struct CardPort {
+    #[pyo3(get)]
+    pub index: i32,
+    /// e.g. "analog-input-rear-mic"
+    #[pyo3(get)]
+    pub name: String,
+    /// e.g. "Rear Microphone"
+    #[pyo3(get)]
+    pub description: String,
+    /// "input" or "output"
+    #[pyo3(get)]
+    pub direction: String,
+    /// False when the jack is known to be unplugged
+    #[pyo3(get)]
+    pub available: bool,
+    /// A device of the card currently uses this port
+    #[pyo3(get)]
+    pub active: bool,
+    /// Indexes of the card profiles the port exists in
+    #[serde(skip)]
+    pub(crate) profiles: Vec<i32>,
+    /// Card devices (`card.profile.device` of a node) that can use the port
+    #[serde(skip)]
+    pub(crate) devices: Vec<i32>,
+}
+
+impl CardPort {
+    pub fn is_input(&self) -> bool {
+        self.direction == "input"
+    }
+}
+
+#[pymethods]
+impl CardPort {
+    fn __repr__(&self) -> String {
+        format!(
+            "CardPort(name='{}', description='{}', direction='{}', available={}, active={})",
+            self.name,
+            self.description,
+            self.direction,
+            if self.available { "True" } else { "False" },
+            if self.active { "True" } else { "False" }
+        )
+    }
+}
+
+/// Ports of `card` a node can use, given its `card.profile.device` and
+/// whether it captures
+pub fn node_ports(card: &Card, profile_device: i32, input: bool) -> Vec<CardPort> {
+    card.ports
+        .iter()
+        .filter(|p| p.is_input() == input && p.devices.contains(&profile_device))
+        .cloned()
+        .collect()
+}
+
+/// Match a port by name, or by description ignoring case
+pub fn find_port<'a>(ports: &'a [CardPort], ident: &str) -> Result<&'a CardPort, String> {
+    ports
+        .iter()
+        .find(|p| p.name == ident)
+        .or_else(|| {
+            ports
+                .iter()
+                .find(|p| p.description.eq_ignore_ascii_case(ident))
+        })
+        .ok_or_else(|| {
+            let names: Vec<&str> = ports.iter().map(|p| p.name.as_str()).collect();
+            format!(
+                "No port '{}'. Available ports: {}",
+                ident,
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+        })
+}
+
+/// The device with its card and `card.profile.device`, or None for devices
+/// not backed by a card (virtual devices, network streams)
+pub(crate) fn device_card(ident: &str) -> PyResult<(Device, Option<(Card, i32)>)> {
+    #[cfg(feature = "real-audio")]
+    let devices = crate::device::enumerate::list_devices_pw()
+        .map_err(pyo3::exceptions::PyRuntimeError::new_err)?;
+    #[cfg(not(feature = "real-audio"))]
+    let devices = crate::list_devices()?;
+    let device = resolve_device(&devices, ident)
+        .map_err(DeviceNotFoundError::new_err)?
+        .clone();
+
+    let Some((card_global_id, profile_device)) =
+        host::card_of(&device).map_err(pyo3::exceptions::PyRuntimeError::new_err)?
+    else {
+        return Ok((device, None));
+    };
+    let card = list_cards()?
+        .into_iter()
+        .find(|c| c.global_id == Some(card_global_id));
+    Ok((device, card.map(|c| (c, profile_device))))
+}
+
+/// Ports the device can use, empty if it isn't backed by a card
+pub fn list_device_ports(ident: &str) -> PyResult<Vec<CardPort>> {
+    let (device, card) = device_card(ident)?;
+    Ok(card
+        .map(|(card, profile_device)| {
+            node_ports(
+                &card,
+                profile_device,
+                device.device_type == DeviceType::Microphone,
+            )
+        })
+        .unwrap_or_default())
+}
+
+/// Make the device use another port, given by name or description
+pub fn set_device_port(ident: &str, port: &str) -> PyResult<()> {
+    let (device, card) = device_card(ident)?;
+    let (card, profile_device) = card.ok_or_else(|| {
+        ConfigError::new_err(format!(
+            "Device '{}' has no ports to choose from",
+            device.id
+        ))
+    })?;
+    let ports = node_ports(
+        &card,
+        profile_device,
+        device.device_type == DeviceType::Microphone,
+    );
+    let port = find_port(&ports, port).map_err(ConfigError::new_err)?;
+    host::set_port(&card, profile_device, port).map_err(pyo3::exceptions::PyRuntimeError::new_err)
+}
+
+#[cfg(feature = "real-audio")]
+mod host {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use pipewire as pw;
+    use pw::spa::param::ParamType;
+    use pw::spa::pod::{Object, Pod, Property, Value};
+    use pw::types::ObjectType;
+
+    use super::CardPort;
+    use crate::device::cards::Card;
+    use crate::device::params::Connection;
+    use crate::Device;
+
+    /// Global id of the node's card and its `card.profile.device`
+    pub fn card_of(device: &Device) -> Result<Option<(u32, i32)>, String> {
+        let Some(node_id) = device.node_id else {
+            return Ok(None);
+        };
+        let conn = Connection::new()?;
+        let found = Rc::new(RefCell::new(None));
+        let _listener = conn
+            .registry
+            .add_listener_local()
+            .global({
+                let found = found.clone();
+                move |global| {
+                    if global.type_ != ObjectType::Node || global.id != node_id {
+                        return;
+                    }
+                    let props = global.props;
+                    let card = props
+                        .and_then(|p| p.get("device.id"))
+                        .and_then(|id| id.parse().ok());
+                    let profile_device = props
+                        .and_then(|p| p.get("card.profile.device"))
+                        .and_then(|d| d.parse().ok());
+                    *found.borrow_mut() = card.zip(profile_device);
+                }
+            })
+            .register();
+        conn.roundtrip()?;
+        let found = found.take();
+        Ok(found)
+    }
+
+    pub fn set_port(card: &Card, profile_device: i32, port: &CardPort) -> Result<(), String> {
+        use pw::spa::sys::{SPA_PARAM_ROUTE_device, SPA_PARAM_ROUTE_index, SPA_PARAM_ROUTE_save};
+
+        let id = card
+            .global_id
+            .ok_or_else(|| format!("Card '{}' has no PipeWire object", card.id))?;
+        let conn = Connection::new()?;
+        let proxy: pw::device::Device = conn.bind(id, ObjectType::Device, card.version)?;
+        let obj = Object {
+            type_: pw::spa::utils::SpaTypes::ObjectParamRoute.as_raw(),
+            id: ParamType::Route.as_raw(),
+            properties: vec![
+                Property::new(SPA_PARAM_ROUTE_index, Value::Int(port.index)),
+                Property::new(SPA_PARAM_ROUTE_device, Value::Int(profile_device)),
+                Property::new(SPA_PARAM_ROUTE_save, Value::Bool(true)),
+            ],
+        };
+        let bytes = pw::spa::pod::serialize::PodSerializer::serialize(
+            std::io::Cursor::new(Vec::new()),
+            &Value::Object(obj),
+        )
+        .map_err(|e| format!("Failed to serialize route param: {:?}", e))?
+        .0
+        .into_inner();
+        let pod = Pod::from_bytes(&bytes).expect("serialized pod bytes should be valid");
+        proxy.set_param(ParamType::Route, 0, pod);
+        conn.roundtrip()
+    }
+}
+
+#[cfg(not(feature = "real-audio"))]
+mod host {
+    use super::CardPort;
+    use crate::device::cards::Card;
+    use crate::Device;
+
+    /// Every mock device but the Bluetooth headset is on the mock card,
+    /// microphones on its card device 0 and outputs on 1
+    pub fn card_of(device: &Device) -> Result<Option<(u32, i32)>, String> {
+        let is_virtual = crate::device::modules::virtual_devices()
+            .iter()
+            .any(|v| v.id == device.id);
+        if device.is_bluetooth || is_virtual {
+            return Ok(None);
+        }
+        let profile_device = match device.device_type {
+            crate::DeviceType::Microphone => 0,
+            _ => 1,
+        };
+        Ok(Some((crate::device::cards::MOCK_CARD_ID, profile_device)))
+    }
+
+    pub fn set_port(_card: &Card, profile_device: i32, port: &CardPort) -> Result<(), String> {
+        crate::device::cards::set_mock_port(profile_device, &port.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ports_of_a_node() {
+        let port = |index, name: &str, direction: &str, devices: Vec<i32>| CardPort {
+            index,
+            name: name.to_string(),
+            description: name.replace('-', " "),
+            direction: direction.to_string(),
+            available: true,
+            devices,
+            ..CardPort::default()
+        };
+        let card = Card {
+            ports: vec![
+                port(0, "analog-input-front-mic", "input", vec![0]),
+                port(1, "analog-input-rear-mic", "input", vec![0]),
+                port(2, "hdmi-input", "input", vec![2]),
+                port(3, "analog-output-headphones", "output", vec![1]),
+            ],
+            ..Card::default()
+        };
+
+        let mics = node_ports(&card, 0, true);
+        let names: Vec<&str> = mics.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["analog-input-front-mic", "analog-input-rear-mic"]
+        );
+        assert_eq!(find_port(&mics, "Analog Input Rear Mic").unwrap().index, 1);
+        assert!(find_port(&mics, "analog-output-headphones")
+            .unwrap_err()
+            .contains("analog-input-front-mic"));
+        assert!(node_ports(&card, 5, false).is_empty());
+    }
+}
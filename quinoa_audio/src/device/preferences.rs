@@ -0,0 +1,229 @@
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::errors::ConfigError;
+use crate::{Device, DeviceType};
+
+/// Current layout of the preferences file
+const FORMAT_VERSION: u32 = 1;
+
+/// A device named in the preferences file. The key is tried first; the id
+/// and name let a device without a stable key still be found.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PreferredDevice {
+    pub key: String,
+    pub id: String,
+    pub name: String,
+}
+
+impl PreferredDevice {
+    fn of(device: &Device) -> Self {
+        Self {
+            key: device.persistent_key(),
+            id: device.id.clone(),
+            name: device.name.clone(),
+        }
+    }
+
+    fn matches(&self, device: &Device) -> bool {
+        device.persistent_key() == self.key || device.id == self.id || device.name == self.name
+    }
+}
+
+/// What `save_preferred_devices()` writes: devices in order of preference
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PreferenceFile {
+    pub version: u32,
+    #[serde(default)]
+    pub microphones: Vec<PreferredDevice>,
+    #[serde(default)]
+    pub speakers: Vec<PreferredDevice>,
+}
+
+/// A device passed to `save_preferred_devices()`: a `Device` or anything
+/// `resolve_device` accepts
+#[derive(FromPyObject)]
+#[allow(clippy::large_enum_variant)]
+pub enum DeviceRef {
+    Device(Device),
+    Id(String),
+}
+
+/// The devices to use, from `load_preferred_devices()`
+#[derive(Clone, Debug)]
+#[pyclass]
+pub struct PreferredDevices {
+    #[pyo3(get)]
+    pub microphone: Option<Device>,
+    #[pyo3(get)]
+    pub speaker: Option<Device>,
+    /// No preferred microphone is connected; `microphone` is the default one
+    #[pyo3(get)]
+    pub microphone_is_fallback: bool,
+    #[pyo3(get)]
+    pub speaker_is_fallback: bool,
+}
+
+#[pymethods]
+impl PreferredDevices {
+    fn __repr__(&self) -> String {
+        let describe = |device: &Option<Device>, fallback: bool| match device {
+            Some(d) if fallback => format!("'{}' (fallback)", d.id),
+            Some(d) => format!("'{}'", d.id),
+            None => "None".to_string(),
+        };
+        format!(
+            "PreferredDevices(microphone={}, speaker={})",
+            describe(&self.microphone, self.microphone_is_fallback),
+            describe(&self.speaker, self.speaker_is_fallback)
+        )
+    }
+}
+
+/// The first preferred device that is connected; otherwise the default
+/// device of the type, or any device of it. The flag is set when a fallback
+/// was used.
+pub fn pick(
+    preferred: &[PreferredDevice],
+    devices: &[Device],
+    device_type: DeviceType,
+) -> (Option<Device>, bool) {
+    let of_type: Vec<&Device> = devices
+        .iter()
+        .filter(|d| d.device_type == device_type)
+        .collect();
+    if let Some(device) = preferred
+        .iter()
+        .find_map(|p| of_type.iter().find(|d| p.matches(d)))
+    {
+        return (Some((*device).clone()), false);
+    }
+    let fallback = of_type
+        .iter()
+        .find(|d| d.is_default)
+        .or_else(|| of_type.first())
+        .map(|d| (*d).clone());
+    (fallback, true)
+}
+
+fn resolve_all(refs: Vec<DeviceRef>, devices: &[Device]) -> PyResult<Vec<PreferredDevice>> {
+    refs.into_iter()
+        .map(|r| match r {
+            DeviceRef::Device(device) => Ok(PreferredDevice::of(&device)),
+            DeviceRef::Id(ident) => crate::device::resolve::resolve_device(devices, &ident)
+                .map(PreferredDevice::of)
+                .map_err(crate::errors::DeviceNotFoundError::new_err),
+        })
+        .collect()
+}
+
+pub fn save(path: &Path, microphones: Vec<DeviceRef>, speakers: Vec<DeviceRef>) -> PyResult<()> {
+    let devices = crate::list_devices()?;
+    let file = PreferenceFile {
+        version: FORMAT_VERSION,
+        microphones: resolve_all(microphones, &devices)?,
+        speakers: resolve_all(speakers, &devices)?,
+    };
+    let json = serde_json::to_string_pretty(&file)
+        .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))?;
+    // Via a temp file so a crash never leaves half a preferences file
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            pyo3::exceptions::PyIOError::new_err(format!("Failed to write {:?}: {}", path, e))
+        })
+}
+
+pub fn load(path: &Path) -> PyResult<PreferredDevices> {
+    // A missing file means no preferences yet
+    let file = match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str::<PreferenceFile>(&json).map_err(|e| {
+            ConfigError::new_err(format!("Invalid preferences file {:?}: {}", path, e))
+        })?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PreferenceFile::default(),
+        Err(e) => {
+            return Err(pyo3::exceptions::PyIOError::new_err(format!(
+                "Failed to read {:?}: {}",
+                path, e
+            )))
+        }
+    };
+    if file.version > FORMAT_VERSION {
+        return Err(ConfigError::new_err(format!(
+            "Preferences file {:?} has version {}, newer than this library supports ({})",
+            path, file.version, FORMAT_VERSION
+        )));
+    }
+    let devices = crate::list_devices()?;
+    let (microphone, microphone_is_fallback) =
+        pick(&file.microphones, &devices, DeviceType::Microphone);
+    let (speaker, speaker_is_fallback) = pick(&file.speakers, &devices, DeviceType::Speaker);
+    Ok(PreferredDevices {
+        microphone,
+        speaker,
+        microphone_is_fallback,
+        speaker_is_fallback,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str, device_type: DeviceType, is_default: bool) -> Device {
+        Device {
+            id: id.to_string(),
+            name: id.replace('_', " "),
+            device_type,
+            is_bluetooth: false,
+            sample_rate: 48000,
+            channels: 1,
+            is_default,
+            bluetooth_profile: None,
+            node_id: None,
+            object_serial: None,
+            persistent_key: Some(format!("usb:{}", id)),
+            icon_name: None,
+            vendor_name: None,
+            product_name: None,
+            bus: None,
+            bus_path: None,
+            state: None,
+        }
+    }
+
+    #[test]
+    fn test_pick_preferred_then_default() {
+        let devices = vec![
+            device("builtin_mic", DeviceType::Microphone, true),
+            device("usb_mic", DeviceType::Microphone, false),
+            device("speakers", DeviceType::Speaker, false),
+        ];
+        let headset = PreferredDevice {
+            key: "bluez5:00:1B:66:AA:BB:CC:in".to_string(),
+            id: "bluez_input.headset".to_string(),
+            name: "Headset".to_string(),
+        };
+        // The node was renamed, but the key still finds it
+        let usb = PreferredDevice {
+            key: "usb:usb_mic".to_string(),
+            id: "alsa_input.old-name".to_string(),
+            name: "Old name".to_string(),
+        };
+
+        let (mic, fallback) = pick(&[headset.clone(), usb], &devices, DeviceType::Microphone);
+        assert_eq!(mic.unwrap().id, "usb_mic");
+        assert!(!fallback);
+
+        let (mic, fallback) = pick(&[headset], &devices, DeviceType::Microphone);
+        assert_eq!(mic.unwrap().id, "builtin_mic");
+        assert!(fallback);
+
+        // No default speaker: any speaker will do
+        let (speaker, fallback) = pick(&[], &devices, DeviceType::Speaker);
+        assert_eq!(speaker.unwrap().id, "speakers");
+        assert!(fallback);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::Device;
+
+/// Kinds of device in the order pickers should offer them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Rank {
+    Default,
+    Headset,
+    Usb,
+    Webcam,
+    /// Built-in cards and anything else backed by hardware
+    Other,
+    /// Monitors of outputs and devices made of software
+    Virtual,
+}
+
+fn rank(device: &Device) -> Rank {
+    let icon = device.icon_name.as_deref().unwrap_or("");
+    let name = device.name.to_lowercase();
+    let id = device.id.to_lowercase();
+    if device.is_default {
+        Rank::Default
+    } else if id.contains("monitor") || name.starts_with("monitor of") {
+        Rank::Virtual
+    } else if device.is_bluetooth || icon.contains("headset") || name.contains("headset") {
+        Rank::Headset
+    } else if icon.contains("camera") || name.contains("webcam") || name.contains("camera") {
+        Rank::Webcam
+    } else if device.bus.as_deref() == Some("usb") {
+        Rank::Usb
+    } else if device.persistent_key.is_none() && device.bus.is_none() {
+        // No card or hardware identity behind it
+        Rank::Virtual
+    } else {
+        Rank::Other
+    }
+}
+
+/// Order devices the way a picker should list them: the default first, then
+/// headsets, USB microphones, webcams, other hardware, and monitors and
+/// virtual devices last. Devices of the same kind keep their order.
+pub fn rank_devices(mut devices: Vec<Device>) -> Vec<Device> {
+    devices.sort_by_key(rank);
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    fn device(id: &str, name: &str, bus: Option<&str>, icon: Option<&str>) -> Device {
+        Device {
+            id: id.to_string(),
+            name: name.to_string(),
+            device_type: DeviceType::Microphone,
+            is_bluetooth: false,
+            sample_rate: 48000,
+            channels: 1,
+            is_default: false,
+            bluetooth_profile: None,
+            node_id: None,
+            object_serial: None,
+            persistent_key: bus.map(|bus| format!("{}:{}", bus, id)),
+            icon_name: icon.map(String::from),
+            vendor_name: None,
+            product_name: None,
+            bus: bus.map(String::from),
+            bus_path: None,
+            state: None,
+        }
+    }
+
+    #[test]
+    fn test_rank_devices() {
+        let builtin = device("alsa_input.pci", "Built-in Audio", Some("pci"), None);
+        let mut default = device("alsa_input.pci-2", "Built-in Audio 2", Some("pci"), None);
+        default.is_default = true;
+        let webcam = device(
+            "alsa_input.usb-046d_C920",
+            "HD Pro Webcam C920",
+            Some("usb"),
+            Some("camera-web"),
+        );
+        let usb = device(
+            "alsa_input.usb-blue",
+            "Yeti Stereo Microphone",
+            Some("usb"),
+            None,
+        );
+        let mut headset = device("bluez_input.AA", "WH-1000XM4", None, None);
+        headset.is_bluetooth = true;
+        let echo_cancel = device(
+            "echo-cancel-source",
+            "Echo-cancelled microphone",
+            None,
+            None,
+        );
+        let monitor = device(
+            "alsa_output.pci.monitor",
+            "Monitor of Built-in Audio",
+            Some("pci"),
+            None,
+        );
+
+        let ranked = rank_devices(vec![
+            monitor.clone(),
+            builtin.clone(),
+            echo_cancel.clone(),
+            webcam.clone(),
+            usb.clone(),
+            headset.clone(),
+            default.clone(),
+        ]);
+        let ids: Vec<&str> = ranked.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                &default.id,
+                &headset.id,
+                &usb.id,
+                &webcam.id,
+                &builtin.id,
+                &monitor.id,
+                &echo_cancel.id,
+            ]
+        );
+    }
+}
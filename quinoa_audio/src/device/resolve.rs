@@ -0,0 +1,105 @@
+use crate::Device;
+
+/// Match a user-supplied identifier against enumerated devices.
+///
+/// Accepts the node name (our stable `Device.id`), the persistent key, the
+/// numeric global id, or the human-readable description, in that order of
+/// precedence. Descriptions
+/// are compared case-insensitively and must match exactly one device.
+pub fn resolve_device<'a>(devices: &'a [Device], ident: &str) -> Result<&'a Device, String> {
+    if let Some(device) = devices.iter().find(|d| d.id == ident) {
+        return Ok(device);
+    }
+
+    if let Some(device) = devices.iter().find(|d| d.persistent_key() == ident) {
+        return Ok(device);
+    }
+
+    if let Ok(global_id) = ident.parse::<u32>() {
+        if let Some(device) = devices.iter().find(|d| d.node_id == Some(global_id)) {
+            return Ok(device);
+        }
+    }
+
+    let by_name: Vec<&Device> = devices
+        .iter()
+        .filter(|d| d.name.eq_ignore_ascii_case(ident))
+        .collect();
+    match by_name.as_slice() {
+        [device] => Ok(device),
+        [] => Err(format!(
+            "Device '{}' not found. Available devices: {}",
+            ident,
+            describe(devices.iter())
+        )),
+        matches => Err(format!(
+            "Device '{}' is ambiguous, it matches: {}",
+            ident,
+            describe(matches.iter().copied())
+        )),
+    }
+}
+
+fn describe<'a>(devices: impl Iterator<Item = &'a Device>) -> String {
+    let list: Vec<String> = devices.map(|d| format!("{} ({})", d.name, d.id)).collect();
+    if list.is_empty() {
+        "none".to_string()
+    } else {
+        list.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+
+    fn mic(id: &str, name: &str, node_id: u32) -> Device {
+        Device {
+            id: id.to_string(),
+            name: name.to_string(),
+            device_type: DeviceType::Microphone,
+            is_bluetooth: false,
+            sample_rate: 48000,
+            channels: 1,
+            is_default: false,
+            bluetooth_profile: None,
+            node_id: Some(node_id),
+            object_serial: None,
+            persistent_key: None,
+            icon_name: None,
+            vendor_name: None,
+            product_name: None,
+            bus: None,
+            bus_path: None,
+            state: None,
+        }
+    }
+
+    #[test]
+    fn test_resolve_by_id_number_and_description() {
+        let devices = vec![
+            mic("alsa_input.usb-mic", "USB Mic", 41),
+            mic("alsa_input.pci-analog", "Built-in Audio", 42),
+            mic("alsa_input.pci-analog-2", "Built-in Audio", 43),
+        ];
+
+        assert_eq!(
+            resolve_device(&devices, "alsa_input.usb-mic").unwrap().id,
+            "alsa_input.usb-mic"
+        );
+        assert_eq!(
+            resolve_device(&devices, "42").unwrap().id,
+            "alsa_input.pci-analog"
+        );
+        assert_eq!(
+            resolve_device(&devices, "usb mic").unwrap().id,
+            "alsa_input.usb-mic"
+        );
+
+        let ambiguous = resolve_device(&devices, "Built-in Audio").unwrap_err();
+        assert!(ambiguous.contains("ambiguous"));
+        let missing = resolve_device(&devices, "Webcam").unwrap_err();
+        assert!(missing.contains("USB Mic (alsa_input.usb-mic)"));
+    }
+}
@@ -0,0 +1,59 @@
+//! Node states, telling a device that exists but is suspended apart from one
+//! that is streaming
+
+use std::collections::HashMap;
+
+/// Values of `Device.state` and `DeviceEvent.state`
+pub const NODE_STATES: &[&str] = &["error", "creating", "suspended", "idle", "running"];
+
+/// Name of a node state, one of `NODE_STATES`
+#[cfg(feature = "real-audio")]
+pub fn state_name(state: &pipewire::node::NodeState) -> &'static str {
+    use pipewire::node::NodeState;
+    match state {
+        NodeState::Error(_) => "error",
+        NodeState::Creating => "creating",
+        NodeState::Suspended => "suspended",
+        NodeState::Idle => "idle",
+        NodeState::Running => "running",
+    }
+}
+
+/// Last state seen of each node, so only transitions are reported
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+#[derive(Default)]
+pub struct StateTracker {
+    states: HashMap<u32, &'static str>,
+}
+
+#[cfg_attr(not(feature = "real-audio"), allow(dead_code))]
+impl StateTracker {
+    /// Record the state of a node; true if it is new or differs from the last
+    pub fn update(&mut self, node_id: u32, state: &'static str) -> bool {
+        self.states.insert(node_id, state) != Some(state)
+    }
+
+    pub fn remove(&mut self, node_id: u32) {
+        self.states.remove(&node_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_transitions_only() {
+        let mut tracker = StateTracker::default();
+        assert!(tracker.update(40, "suspended"));
+        assert!(!tracker.update(40, "suspended"));
+        assert!(tracker.update(41, "suspended"));
+        assert!(tracker.update(40, "running"));
+        assert!(!tracker.update(40, "running"));
+        assert!(tracker.update(40, "idle"));
+
+        // A node id reused after removal starts over
+        tracker.remove(41);
+        assert!(tracker.update(41, "suspended"));
+    }
+}
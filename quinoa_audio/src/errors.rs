@@ -0,0 +1,39 @@
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+
+create_exception!(
+    quinoa_audio,
+    ConfigError,
+    PyValueError,
+    "Invalid recording configuration."
+);
+create_exception!(
+    quinoa_audio,
+    OutputDirError,
+    ConfigError,
+    "The output directory cannot be created or written to."
+);
+create_exception!(
+    quinoa_audio,
+    DeviceNotFoundError,
+    ConfigError,
+    "The requested audio device does not exist."
+);
+create_exception!(
+    quinoa_audio,
+    UnsupportedFormatError,
+    ConfigError,
+    "The requested audio format is not supported."
+);
+create_exception!(
+    quinoa_audio,
+    InsufficientDiskSpaceError,
+    ConfigError,
+    "Not enough free disk space to start recording."
+);
+create_exception!(
+    quinoa_audio,
+    OutputExistsError,
+    OutputDirError,
+    "Output files of an earlier recording are in the way."
+);
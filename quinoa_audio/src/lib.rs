@@ -1,17 +1,73 @@
 use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Log a line to stderr, prefixed with the current thread's name. Session
+/// threads carry their session id in the name, so lines from concurrent
+/// sessions can be told apart.
+macro_rules! log {
+    // For threads that serve more than one session, e.g. Python's
+    (session = $id:expr; $($arg:tt)*) => {
+        eprintln!(
+            "quinoa[{} session-{}]: {}",
+            std::thread::current().name().unwrap_or("unnamed"),
+            $id,
+            format_args!($($arg)*)
+        )
+    };
+    ($($arg:tt)*) => {
+        eprintln!(
+            "quinoa[{}]: {}",
+            std::thread::current().name().unwrap_or("unnamed"),
+            format_args!($($arg)*)
+        )
+    };
+}
 
 mod capture;
+#[cfg(feature = "cli")]
+pub mod cli;
 mod device;
+mod dicts;
+mod errors;
+mod pickling;
+#[cfg(test)]
+mod testing;
 
-use capture::session::{start_recording_impl, AudioEvent, RecordingConfig, RecordingSession};
+use capture::builder::RecordingConfigBuilder;
+use capture::checksum::SessionVerification;
+use capture::clock::ClockInfo;
+use capture::detached::DetachedSession;
+use capture::health::{HealthReport, StreamHealth};
+use capture::levels::LevelSample;
+use capture::options::EncoderOptions;
+use capture::packets::OpusPacket;
+use capture::plugin::EncoderPlugins;
+use capture::pool::EncodeWorkerStats;
+use capture::registry::{SessionEntry, SessionHandle};
+use capture::session::{
+    join_with_timeout, resume_recording_impl, start_recording_impl, AudioEvent, RecordingConfig,
+    RecordingSession,
+};
+use capture::stats::{SessionStats, StreamTimings, TimingStats};
+use capture::validate::resolve_mic_id;
+use device::capabilities::DeviceCapabilities;
+use device::cards::{Card, CardProfile};
+use device::diff::{DeviceChange, DeviceDiff};
 #[cfg(feature = "real-audio")]
 use device::monitor::start_monitoring;
+use device::ports::CardPort;
+use device::preferences::{DeviceRef, PreferredDevices};
+use device::state::NODE_STATES;
 
+use pyo3::types::PyDict;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Mutex;
 use std::thread;
+use std::time::Duration;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[pyclass(eq, eq_int)]
 pub enum DeviceType {
     Microphone,
@@ -19,15 +75,69 @@ pub enum DeviceType {
     Monitor,
 }
 
-#[derive(Clone, Debug)]
-#[pyclass]
+/// Values of `DeviceEvent.type_`
+pub const DEVICE_EVENT_TYPES: &[&str] = &[
+    "added",
+    "removed",
+    "default_changed",
+    "state_changed",
+    "snapshot_complete",
+    "changed",
+    "disconnected",
+    "reconnecting",
+    "resynced",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[pyclass(module = "quinoa_audio", eq)]
 pub struct DeviceEvent {
     #[pyo3(get)]
-    pub type_: String, // "added", "removed", "default_changed"
+    #[serde(rename = "type")]
+    pub type_: String, // One of DEVICE_EVENT_TYPES
     #[pyo3(get)]
     pub device_id: Option<String>,
     #[pyo3(get)]
     pub device_name: Option<String>,
+    /// New state of the node for "state_changed", one of `NODE_STATES`
+    #[pyo3(get)]
+    pub state: Option<String>,
+    /// Part of the report of devices already present when the monitor
+    /// started, which ends with a "snapshot_complete" event (or, after
+    /// reconnecting to a restarted PipeWire, "resynced"); False for devices
+    /// plugged in or changed since
+    #[pyo3(get)]
+    #[serde(default)]
+    pub initial: bool,
+}
+
+#[pymethods]
+impl DeviceEvent {
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn as_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        dicts::as_dict(py, self)
+    }
+
+    #[staticmethod]
+    fn _from_state(state: &str) -> PyResult<Self> {
+        pickling::restore(state)
+    }
+
+    fn __reduce__<'py>(slf: &Bound<'py, Self>) -> PyResult<(Bound<'py, PyAny>, (String,))> {
+        pickling::reduce(slf)
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
 }
 
 #[pyclass]
@@ -35,10 +145,76 @@ pub struct DeviceMonitor {
     event_rx: Option<Mutex<Receiver<DeviceEvent>>>,
     thread_handle: Option<thread::JoinHandle<()>>,
     stop_tx: Option<Sender<()>>,
+    /// Set for mock monitors, which report what `inject()` is given
+    inject_tx: Option<Sender<DeviceEvent>>,
+    /// stop() was called
+    stopped: bool,
+}
+
+impl DeviceMonitor {
+    fn new_mock() -> Self {
+        let (inject_tx, event_rx) = std::sync::mpsc::channel();
+        Self {
+            event_rx: Some(Mutex::new(event_rx)),
+            thread_handle: None,
+            stop_tx: None,
+            inject_tx: Some(inject_tx),
+            stopped: false,
+        }
+    }
 }
 
 #[pymethods]
 impl DeviceMonitor {
+    /// A monitor that doesn't connect to PipeWire and reports only the events
+    /// passed to `inject()`, for testing hotplug handling without hardware.
+    /// Available in every build.
+    #[staticmethod]
+    fn mock() -> Self {
+        Self::new_mock()
+    }
+
+    /// Queue an event for the next `poll()` as if PipeWire had reported it:
+    /// `monitor.inject("added", "alsa_input.usb-mic", "USB Mic")`. Only mock
+    /// monitors accept events, i.e. `DeviceMonitor.mock()` or any monitor
+    /// from a build without real-audio.
+    #[pyo3(signature = (type_, device_id=None, device_name=None, state=None, initial=false))]
+    fn inject(
+        &self,
+        type_: String,
+        device_id: Option<String>,
+        device_name: Option<String>,
+        state: Option<String>,
+        initial: bool,
+    ) -> PyResult<()> {
+        if !DEVICE_EVENT_TYPES.contains(&type_.as_str()) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Unknown device event type {:?} (expected one of {:?})",
+                type_, DEVICE_EVENT_TYPES
+            )));
+        }
+        if let Some(state) = state.as_deref().filter(|s| !NODE_STATES.contains(s)) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Unknown node state {:?} (expected one of {:?})",
+                state, NODE_STATES
+            )));
+        }
+        let Some(tx) = &self.inject_tx else {
+            return Err(pyo3::exceptions::PyRuntimeError::new_err(
+                "Only mock monitors accept injected events; use DeviceMonitor.mock()",
+            ));
+        };
+        // The receiver lives as long as the monitor
+        let _ = tx.send(DeviceEvent {
+            type_,
+            device_id,
+            device_name,
+            state,
+            initial,
+        });
+        Ok(())
+    }
+
     fn poll(&self) -> PyResult<Vec<DeviceEvent>> {
         let mut events = Vec::new();
         if let Some(rx_mutex) = &self.event_rx {
@@ -51,23 +227,51 @@ impl DeviceMonitor {
         Ok(events)
     }
 
-    fn stop(&mut self) -> PyResult<()> {
+    /// Whether the monitor is still reporting events: False once stopped,
+    /// or if its thread died
+    fn is_running(&self) -> bool {
+        match &self.thread_handle {
+            Some(handle) => !handle.is_finished(),
+            // Mock monitors run until stopped
+            None => self.inject_tx.is_some() && !self.stopped,
+        }
+    }
+
+    /// Stop the monitor and wait for its thread to exit. Safe to call again,
+    /// or after the thread died.
+    ///
+    /// With a `timeout` (seconds), returns False if the thread is still running
+    /// when it expires; stop() can then be called again.
+    #[pyo3(signature = (timeout=None))]
+    fn stop(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<bool> {
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| {
+                pyo3::exceptions::PyValueError::new_err(format!("Invalid timeout: {}", e))
+            })?;
+
+        self.stopped = true;
         if let Some(tx) = self.stop_tx.take() {
             let _ = tx.send(());
         }
-        if let Some(handle) = self.thread_handle.take() {
-            Python::with_gil(|py| {
-                py.allow_threads(|| {
-                    let _ = handle.join();
-                });
-            });
+        let Some(handle) = self.thread_handle.take() else {
+            return Ok(true);
+        };
+        match py.allow_threads(|| join_with_timeout(handle, timeout)) {
+            Ok(()) => Ok(true),
+            Err(handle) => {
+                self.thread_handle = Some(handle);
+                Ok(false)
+            }
         }
-        Ok(())
     }
 }
 
-#[derive(Clone, Debug)]
-#[pyclass]
+/// Devices compare equal when every field matches, so an enumerated device
+/// and one built with a different default flag or sample rate are distinct
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[pyclass(module = "quinoa_audio", eq)]
 pub struct Device {
     #[pyo3(get)]
     pub id: String,
@@ -85,12 +289,42 @@ pub struct Device {
     pub is_default: bool,
     #[pyo3(get)]
     pub bluetooth_profile: Option<String>,
+    /// PipeWire global id of the node, when known. Ids are reused once a
+    /// node goes away; `id` is the one to store.
+    #[pyo3(get)]
+    pub node_id: Option<u32>,
+    /// The node's `object.serial`, which unlike `node_id` is never reused
+    /// while the server runs. Matches what `pw-dump` and `pw-cli` show.
+    #[pyo3(get)]
+    pub object_serial: Option<u64>,
+    /// Identity of the hardware, see `persistent_key()`
+    pub(crate) persistent_key: Option<String>,
+    /// Freedesktop icon name for the hardware, e.g. "audio-card-usb" or
+    /// "audio-headset-bluetooth"
+    #[pyo3(get)]
+    pub icon_name: Option<String>,
+    #[pyo3(get)]
+    pub vendor_name: Option<String>,
+    #[pyo3(get)]
+    pub product_name: Option<String>,
+    /// "usb", "pci", "bluetooth", ...
+    #[pyo3(get)]
+    pub bus: Option<String>,
+    /// Where the hardware is attached, e.g. "pci-0000:00:14.0-usb-0:2:1.0";
+    /// tells apart identical USB devices plugged into different ports
+    #[pyo3(get)]
+    pub bus_path: Option<String>,
+    /// The node's state when listed: "running" while streaming, "idle" when
+    /// open but not streaming, "suspended" when closed to save power, or
+    /// "creating" / "error"
+    #[pyo3(get)]
+    pub state: Option<String>,
 }
 
 #[pymethods]
 impl Device {
     #[new]
-    #[pyo3(signature = (id, name, device_type, is_bluetooth, sample_rate, channels, is_default, bluetooth_profile=None))]
+    #[pyo3(signature = (id, name, device_type, is_bluetooth, sample_rate, channels, is_default, bluetooth_profile=None, node_id=None, object_serial=None))]
     #[allow(clippy::too_many_arguments)]
     fn new(
         id: String,
@@ -101,6 +335,8 @@ impl Device {
         channels: u8,
         is_default: bool,
         bluetooth_profile: Option<String>,
+        node_id: Option<u32>,
+        object_serial: Option<u64>,
     ) -> Self {
         Device {
             id,
@@ -111,15 +347,71 @@ impl Device {
             channels,
             is_default,
             bluetooth_profile,
+            node_id,
+            object_serial,
+            persistent_key: None,
+            icon_name: None,
+            vendor_name: None,
+            product_name: None,
+            bus: None,
+            bus_path: None,
+            state: None,
         }
     }
 
+    /// A key for remembering this device across reboots, built from the
+    /// hardware behind it (USB serial, Bluetooth address, PCI slot) rather
+    /// than the node name, which may change. Pass it to
+    /// `resolve_persistent_key()` or use it as a device id.
+    pub fn persistent_key(&self) -> String {
+        self.persistent_key
+            .clone()
+            .unwrap_or_else(|| device::identity::fallback_key(&self.id))
+    }
+
     fn __repr__(&self) -> String {
         format!(
-            "Device(id='{}', name='{}', type={:?}, bt={})",
-            self.id, self.name, self.device_type, self.is_bluetooth
+            "Device(id='{}', name='{}', type={:?}, bt={}, node_id={})",
+            self.id,
+            self.name,
+            self.device_type,
+            self.is_bluetooth,
+            self.node_id
+                .map_or_else(|| "None".to_string(), |id| id.to_string())
         )
     }
+
+    /// Hashes the identifying fields; equal devices always share them
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (&self.id, self.node_id, self.object_serial).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The device's fields, with `persistent_key` and `device_type` as
+    /// strings
+    fn as_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = dicts::as_dict(py, self)?;
+        dict.set_item("persistent_key", self.persistent_key())?;
+        Ok(dict)
+    }
+
+    #[staticmethod]
+    fn _from_state(state: &str) -> PyResult<Self> {
+        pickling::restore(state)
+    }
+
+    fn __reduce__<'py>(slf: &Bound<'py, Self>) -> PyResult<(Bound<'py, PyAny>, (String,))> {
+        pickling::reduce(slf)
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
 }
 
 #[pyfunction]
@@ -133,7 +425,7 @@ fn list_devices() -> PyResult<Vec<Device>> {
     #[cfg(not(feature = "real-audio"))]
     {
         // Mock implementation
-        Ok(vec![
+        let mut devices = vec![
             Device {
                 id: "mock_mic_1".to_string(),
                 name: "Mock Microphone".to_string(),
@@ -143,6 +435,15 @@ fn list_devices() -> PyResult<Vec<Device>> {
                 channels: 1,
                 is_default: true,
                 bluetooth_profile: None,
+                node_id: Some(41),
+                object_serial: Some(141),
+                persistent_key: Some("alsa:pci-0000:00:1f.3:in:analog-stereo".to_string()),
+                icon_name: Some("audio-card-analog-pci".to_string()),
+                vendor_name: Some("Intel Corporation".to_string()),
+                product_name: Some("Cannon Lake PCH cAVS".to_string()),
+                bus: Some("pci".to_string()),
+                bus_path: Some("pci-0000:00:1f.3".to_string()),
+                state: Some("idle".to_string()),
             },
             Device {
                 id: "mock_speaker_1".to_string(),
@@ -153,6 +454,15 @@ fn list_devices() -> PyResult<Vec<Device>> {
                 channels: 2,
                 is_default: true,
                 bluetooth_profile: None,
+                node_id: Some(42),
+                object_serial: Some(142),
+                persistent_key: Some("alsa:pci-0000:00:1f.3:out:analog-stereo".to_string()),
+                icon_name: Some("audio-card-analog-pci".to_string()),
+                vendor_name: Some("Intel Corporation".to_string()),
+                product_name: Some("Cannon Lake PCH cAVS".to_string()),
+                bus: Some("pci".to_string()),
+                bus_path: Some("pci-0000:00:1f.3".to_string()),
+                state: Some("running".to_string()),
             },
             Device {
                 id: "mock_bt_headset".to_string(),
@@ -163,11 +473,158 @@ fn list_devices() -> PyResult<Vec<Device>> {
                 channels: 1,
                 is_default: false,
                 bluetooth_profile: Some("headset-head-unit".to_string()),
+                node_id: Some(43),
+                object_serial: Some(143),
+                persistent_key: Some("bluez5:00:1B:66:AA:BB:CC:in".to_string()),
+                icon_name: Some("audio-headset-bluetooth".to_string()),
+                vendor_name: None,
+                product_name: None,
+                bus: Some("bluetooth".to_string()),
+                bus_path: None,
+                state: Some("suspended".to_string()),
             },
-        ])
+        ];
+        devices.extend(device::modules::virtual_devices());
+        Ok(devices)
     }
 }
 
+/// The device a key from `Device.persistent_key()` names, or None if that
+/// hardware isn't connected
+#[pyfunction]
+fn resolve_persistent_key(key: &str) -> PyResult<Option<Device>> {
+    Ok(list_devices()?
+        .into_iter()
+        .find(|d| d.persistent_key() == key))
+}
+
+/// The devices in the order a picker should offer them: the default first,
+/// then headsets, USB microphones, webcams, other hardware, and monitors and
+/// virtual devices last. Devices of the same kind keep their order.
+#[pyfunction]
+fn rank_devices(devices: Vec<Device>) -> Vec<Device> {
+    device::rank::rank_devices(devices)
+}
+
+/// What changed between two `list_devices()` results: devices added,
+/// removed, and changed (e.g. in default status or state), matched by `id`.
+/// For reconciling a periodic listing with monitor events.
+#[pyfunction]
+fn diff_devices(old: Vec<Device>, new: Vec<Device>) -> DeviceDiff {
+    device::diff::diff_devices(&old, &new)
+}
+
+/// Save the user's device choices, most preferred first, as JSON at `path`.
+/// Devices may be given as `Device`s or ids; each is stored with its
+/// `persistent_key()` so it is found again after reboots and renames.
+#[pyfunction]
+#[pyo3(signature = (path, microphones=Vec::new(), speakers=Vec::new()))]
+fn save_preferred_devices(
+    path: PathBuf,
+    microphones: Vec<DeviceRef>,
+    speakers: Vec<DeviceRef>,
+) -> PyResult<()> {
+    device::preferences::save(&path, microphones, speakers)
+}
+
+/// The devices to use according to a file from `save_preferred_devices()`:
+/// the first connected preferred device of each kind, else the default one.
+/// A missing file counts as no preferences.
+#[pyfunction]
+fn load_preferred_devices(path: PathBuf) -> PyResult<PreferredDevices> {
+    device::preferences::load(&path)
+}
+
+/// Sample rates, formats, channel layouts and card profiles a device can be
+/// opened with, to check a configuration before starting a session.
+/// `device_id` may be a node name, a PipeWire global id or a description.
+#[pyfunction]
+fn get_device_capabilities(device_id: &str) -> PyResult<DeviceCapabilities> {
+    device::capabilities::get_device_capabilities(device_id)
+}
+
+/// Sound cards with their profiles and the active one
+#[pyfunction]
+fn list_cards() -> PyResult<Vec<Card>> {
+    device::cards::list_cards()
+}
+
+/// Switch a card to another profile, e.g. from "Analog Stereo Output" to
+/// "Analog Stereo Duplex" to bring its microphone back. `card_id` may be the
+/// card's id, a PipeWire global id or its name; `profile` a profile name or
+/// description. The choice is saved like one made in the sound settings.
+#[pyfunction]
+fn set_card_profile(card_id: &str, profile: &str) -> PyResult<()> {
+    device::cards::set_card_profile(card_id, profile)
+}
+
+/// Ports (connectors) of the card a device is on that the device can use,
+/// e.g. the internal and the headset microphone. Empty for devices not
+/// backed by a card.
+#[pyfunction]
+fn list_device_ports(device_id: &str) -> PyResult<Vec<CardPort>> {
+    device::ports::list_device_ports(device_id)
+}
+
+/// Make a device use another port, given by name or description, e.g.
+/// "Rear Microphone". The choice is saved like one made in the sound
+/// settings.
+#[pyfunction]
+fn set_device_port(device_id: &str, port: &str) -> PyResult<()> {
+    device::ports::set_device_port(device_id, port)
+}
+
+/// Whether a device is muted for every app, by the desktop's mute button or
+/// a hardware mute switch the driver reports
+#[pyfunction]
+fn get_device_mute(device_id: &str) -> PyResult<bool> {
+    device::mute::get_device_mute(device_id)
+}
+
+/// Load PipeWire's echo-cancel module and return the echo-cancelled source
+/// it creates, which can be recorded like any microphone. `source` is the
+/// microphone to clean (the default one if None) and `sink` the output whose
+/// sound is removed from it (the default one if None). The source exists
+/// until `destroy_echo_cancel()` or until this process exits.
+#[pyfunction]
+#[pyo3(signature = (name="quinoa-echo-cancel".to_string(), source=None, sink=None))]
+fn create_echo_cancel(
+    name: String,
+    source: Option<String>,
+    sink: Option<String>,
+) -> PyResult<Device> {
+    let source = source.map(|s| resolve_mic_id(&s)).transpose()?;
+    device::modules::create_echo_cancel(&name, source.as_deref(), sink.as_deref())
+}
+
+/// Unload an echo-cancel module created by `create_echo_cancel()`
+#[pyfunction]
+fn destroy_echo_cancel(device_id: &str) -> PyResult<()> {
+    device::modules::destroy_echo_cancel(device_id)
+}
+
+/// Create a null sink applications can be routed to, and return it so it can
+/// be recorded on its own (`RecordingConfig(system_audio=True,
+/// system_device_id=sink.id)`). With `playback`, what it receives is also
+/// played on `output` (the default output if None) so it stays audible. The
+/// sink exists until `destroy_virtual_sink()` or until this process exits.
+#[pyfunction]
+#[pyo3(signature = (name, description=None, playback=true, output=None))]
+fn create_virtual_sink(
+    name: String,
+    description: Option<String>,
+    playback: bool,
+    output: Option<String>,
+) -> PyResult<Device> {
+    device::modules::create_virtual_sink(&name, description.as_deref(), playback, output.as_deref())
+}
+
+/// Remove a sink created by `create_virtual_sink()`
+#[pyfunction]
+fn destroy_virtual_sink(device_id: &str) -> PyResult<()> {
+    device::modules::destroy_virtual_sink(device_id)
+}
+
 #[pyfunction]
 fn subscribe_device_changes() -> PyResult<DeviceMonitor> {
     #[cfg(feature = "real-audio")]
@@ -177,42 +634,253 @@ fn subscribe_device_changes() -> PyResult<DeviceMonitor> {
     #[cfg(not(feature = "real-audio"))]
     {
         // Mock implementation
-        use std::sync::mpsc::channel;
-        use std::sync::{Arc, Mutex};
-        let (event_tx, event_rx) = channel();
-        // Send a fake event
-        let _ = event_tx.send(DeviceEvent {
-            type_: "added".to_string(),
-            device_id: Some("mock_hotplug_mic".to_string()),
-            device_name: Some("Mock Hotplug Microphone".to_string()),
-        });
+        let monitor = DeviceMonitor::new_mock();
+        // An empty snapshot, then a fake hotplug
+        if let Some(tx) = &monitor.inject_tx {
+            let _ = tx.send(DeviceEvent {
+                type_: "snapshot_complete".to_string(),
+                device_id: None,
+                device_name: None,
+                state: None,
+                initial: true,
+            });
+            let _ = tx.send(DeviceEvent {
+                type_: "added".to_string(),
+                device_id: Some("mock_hotplug_mic".to_string()),
+                device_name: Some("Mock Hotplug Microphone".to_string()),
+                state: None,
+                initial: false,
+            });
+        }
+        Ok(monitor)
+    }
+}
 
-        Ok(DeviceMonitor {
-            event_rx: Some(Mutex::new(event_rx)),
-            thread_handle: None,
-            stop_tx: None,
-        })
+/// Decrypt a recording made with `encryption_recipient`, using the age
+/// identity file holding the matching private key. Writes to `output`, or
+/// next to `path` without its `.age` extension, and returns that path.
+#[pyfunction]
+#[pyo3(signature = (path, identity_file, output=None))]
+fn decrypt_recording(
+    py: Python<'_>,
+    path: PathBuf,
+    identity_file: PathBuf,
+    output: Option<PathBuf>,
+) -> PyResult<PathBuf> {
+    py.allow_threads(|| {
+        capture::encrypt::decrypt(&path, &identity_file, output.as_deref())
+            .map_err(pyo3::exceptions::PyRuntimeError::new_err)
+    })
+}
+
+/// Check the files of a recording against the checksums in its
+/// session.json, e.g. after copying or uploading them. Files are looked up
+/// next to the manifest.
+#[pyfunction]
+fn verify_session(py: Python<'_>, manifest_path: PathBuf) -> PyResult<SessionVerification> {
+    py.allow_threads(|| capture::checksum::verify_session(&manifest_path))
+}
+
+/// Capture metrics of this process (active sessions, dropouts, encoder
+/// backlog, reconnects) in the Prometheus text exposition format
+#[cfg(feature = "metrics")]
+#[pyfunction]
+fn collect_metrics() -> String {
+    capture::metrics::collect_metrics()
+}
+
+/// Serve `collect_metrics()` at http://host:port/metrics from a background
+/// thread until the process exits. Returns the port, which the system picks
+/// when `port` is 0.
+#[cfg(feature = "metrics")]
+#[pyfunction]
+#[pyo3(signature = (port=9464, host="127.0.0.1".to_string()))]
+fn serve_metrics(port: u16, host: String) -> PyResult<u16> {
+    capture::metrics::serve_metrics(&host, port).map_err(pyo3::exceptions::PyOSError::new_err)
+}
+
+/// Export tracing spans of the sessions started from now on (connect,
+/// negotiate, reconnect, finalize, under a root span per session) to the
+/// OpenTelemetry collector at `endpoint` over OTLP/HTTP, e.g.
+/// `http://localhost:4318`. Needs the curl command.
+#[cfg(feature = "otel")]
+#[pyfunction]
+#[pyo3(signature = (endpoint, service_name="quinoa".to_string(), headers=None))]
+fn enable_tracing(
+    endpoint: String,
+    service_name: String,
+    headers: Option<std::collections::HashMap<String, String>>,
+) -> PyResult<()> {
+    capture::trace::enable_tracing(&endpoint, &service_name, headers.unwrap_or_default())
+        .map_err(errors::ConfigError::new_err)
+}
+
+/// Start recording. `mic_encoder` / `system_encoder` replace the WAV output of
+/// a stream with a Python object implementing `open(spec)`, `write(data)` and
+/// `finalize()`.
+#[pyfunction]
+#[pyo3(signature = (config, mic_encoder=None, system_encoder=None))]
+fn start_recording(
+    config: RecordingConfig,
+    mic_encoder: Option<&Bound<'_, PyAny>>,
+    system_encoder: Option<&Bound<'_, PyAny>>,
+) -> PyResult<RecordingSession> {
+    let plugins = EncoderPlugins::from_py(mic_encoder, system_encoder)?;
+    start_recording_impl(config, plugins)
+}
+
+/// Continue a recording from the session.json of a session whose process
+/// exited without stopping it (e.g. after a crash). session.json doesn't keep
+/// secrets, so the resumed session sends no upload_headers or
+/// webhook_headers and doesn't stream to icecast_url.
+#[pyfunction]
+#[pyo3(signature = (session_manifest_path, mic_encoder=None, system_encoder=None))]
+fn resume_recording(
+    session_manifest_path: PathBuf,
+    mic_encoder: Option<&Bound<'_, PyAny>>,
+    system_encoder: Option<&Bound<'_, PyAny>>,
+) -> PyResult<RecordingSession> {
+    let plugins = EncoderPlugins::from_py(mic_encoder, system_encoder)?;
+    resume_recording_impl(&session_manifest_path, plugins)
+}
+
+/// Sessions started by this process whose audio thread is still running,
+/// including ones whose `RecordingSession` was dropped
+#[pyfunction]
+fn active_sessions() -> Vec<SessionHandle> {
+    capture::registry::active_sessions()
+}
+
+/// Start recording in a helper process that keeps recording if this
+/// interpreter exits or crashes. Encoder plugins aren't available there.
+#[pyfunction]
+fn start_detached_recording(py: Python<'_>, config: RecordingConfig) -> PyResult<DetachedSession> {
+    capture::detached::start_detached(py, config)
+}
+
+/// Control a background session again by its `session_id`, e.g. after the
+/// interpreter that started it was restarted
+#[pyfunction]
+fn attach_detached(py: Python<'_>, session_id: u64) -> PyResult<DetachedSession> {
+    capture::detached::attach(py, session_id)
+}
+
+/// Background sessions of this user that are still running, oldest first
+#[pyfunction]
+fn detached_sessions(py: Python<'_>) -> Vec<DetachedSession> {
+    capture::detached::list(py)
+}
+
+/// A `RecordingSession` handle on a running session by its `session_id`:
+/// one of this process's, e.g. started by an interpreter that has since
+/// exited, or a background one from `start_detached_recording()`. Handles on
+/// the same session share its events; each is returned to one of them.
+#[pyfunction]
+fn attach_session(py: Python<'_>, session_id: u64) -> PyResult<RecordingSession> {
+    match SessionEntry::attach(session_id) {
+        Some(state) => Ok(RecordingSession::attached(state)),
+        None => capture::detached::attach_session(py, session_id),
     }
 }
 
+/// Entry point of the helper process started by `start_detached_recording()`
 #[pyfunction]
-fn start_recording(config: RecordingConfig) -> PyResult<RecordingSession> {
-    start_recording_impl(config)
+#[pyo3(name = "_serve_detached")]
+fn serve_detached(py: Python<'_>, config: &str) -> PyResult<()> {
+    capture::detached::serve(py, config)
 }
 
 /// A Python module implemented in Rust.
-#[pymodule]
+///
+/// Doesn't rely on the GIL: shared state is behind Rust locks and atomics,
+/// and calls that block on them or on the audio thread detach from the
+/// interpreter first, so it runs on free-threaded Python builds.
+#[pymodule(gil_used = false)]
 fn quinoa_audio(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Device>()?;
     m.add_class::<DeviceType>()?;
+    m.add_class::<DeviceCapabilities>()?;
+    m.add_class::<Card>()?;
+    m.add_class::<CardProfile>()?;
+    m.add_class::<CardPort>()?;
+    m.add_class::<PreferredDevices>()?;
+    m.add_class::<DeviceDiff>()?;
+    m.add_class::<DeviceChange>()?;
     m.add_class::<RecordingConfig>()?;
+    m.add_class::<RecordingConfigBuilder>()?;
     m.add_class::<RecordingSession>()?;
+    m.add_class::<SessionHandle>()?;
+    m.add_class::<DetachedSession>()?;
     m.add_class::<AudioEvent>()?;
+    m.add_class::<ClockInfo>()?;
+    m.add_class::<LevelSample>()?;
+    m.add_class::<EncoderOptions>()?;
+    m.add_class::<OpusPacket>()?;
+    m.add_class::<SessionStats>()?;
+    m.add_class::<HealthReport>()?;
+    m.add_class::<StreamHealth>()?;
+    m.add_class::<StreamTimings>()?;
+    m.add_class::<TimingStats>()?;
+    m.add_class::<EncodeWorkerStats>()?;
     m.add_class::<DeviceMonitor>()?;
     m.add_class::<DeviceEvent>()?;
+    m.add_class::<SessionVerification>()?;
+    m.add("ConfigError", m.py().get_type::<errors::ConfigError>())?;
+    m.add(
+        "OutputDirError",
+        m.py().get_type::<errors::OutputDirError>(),
+    )?;
+    m.add(
+        "OutputExistsError",
+        m.py().get_type::<errors::OutputExistsError>(),
+    )?;
+    m.add(
+        "DeviceNotFoundError",
+        m.py().get_type::<errors::DeviceNotFoundError>(),
+    )?;
+    m.add(
+        "UnsupportedFormatError",
+        m.py().get_type::<errors::UnsupportedFormatError>(),
+    )?;
+    m.add(
+        "InsufficientDiskSpaceError",
+        m.py().get_type::<errors::InsufficientDiskSpaceError>(),
+    )?;
+    // "pipewire", or "mock" when built without real-audio
+    m.add("BACKEND", capture::diagnostics::backend())?;
     m.add_function(wrap_pyfunction!(list_devices, m)?)?;
+    m.add_function(wrap_pyfunction!(resolve_persistent_key, m)?)?;
+    m.add_function(wrap_pyfunction!(rank_devices, m)?)?;
+    m.add_function(wrap_pyfunction!(diff_devices, m)?)?;
+    m.add_function(wrap_pyfunction!(save_preferred_devices, m)?)?;
+    m.add_function(wrap_pyfunction!(load_preferred_devices, m)?)?;
+    m.add_function(wrap_pyfunction!(get_device_capabilities, m)?)?;
+    m.add_function(wrap_pyfunction!(list_cards, m)?)?;
+    m.add_function(wrap_pyfunction!(set_card_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(list_device_ports, m)?)?;
+    m.add_function(wrap_pyfunction!(set_device_port, m)?)?;
+    m.add_function(wrap_pyfunction!(get_device_mute, m)?)?;
+    m.add_function(wrap_pyfunction!(decrypt_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_session, m)?)?;
+    #[cfg(feature = "metrics")]
+    m.add_function(wrap_pyfunction!(collect_metrics, m)?)?;
+    #[cfg(feature = "metrics")]
+    m.add_function(wrap_pyfunction!(serve_metrics, m)?)?;
+    #[cfg(feature = "otel")]
+    m.add_function(wrap_pyfunction!(enable_tracing, m)?)?;
     m.add_function(wrap_pyfunction!(start_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(resume_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(active_sessions, m)?)?;
+    m.add_function(wrap_pyfunction!(start_detached_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(attach_detached, m)?)?;
+    m.add_function(wrap_pyfunction!(attach_session, m)?)?;
+    m.add_function(wrap_pyfunction!(detached_sessions, m)?)?;
+    m.add_function(wrap_pyfunction!(serve_detached, m)?)?;
     m.add_function(wrap_pyfunction!(subscribe_device_changes, m)?)?;
+    m.add_function(wrap_pyfunction!(create_echo_cancel, m)?)?;
+    m.add_function(wrap_pyfunction!(destroy_echo_cancel, m)?)?;
+    m.add_function(wrap_pyfunction!(create_virtual_sink, m)?)?;
+    m.add_function(wrap_pyfunction!(destroy_virtual_sink, m)?)?;
     Ok(())
 }
 
@@ -231,6 +899,8 @@ mod tests {
             2,
             false,
             None,
+            None,
+            None,
         );
 
         assert_eq!(device.id, "test_id");
@@ -0,0 +1,28 @@
+//! Pickle support. A picklable class reduces to its `_from_state` static
+//! method and a JSON string of its fields, so instances can be sent to
+//! worker processes. The class must name its module (`#[pyclass(module =
+//! "quinoa_audio")]`) for pickle to find it again.
+
+use pyo3::prelude::*;
+use pyo3::PyClass;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub fn state<T: Serialize>(value: &T) -> PyResult<String> {
+    serde_json::to_string(value)
+        .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(format!("Failed to pickle: {}", e)))
+}
+
+pub fn restore<T: DeserializeOwned>(state: &str) -> PyResult<T> {
+    serde_json::from_str(state).map_err(|e| {
+        pyo3::exceptions::PyValueError::new_err(format!("Invalid pickled state: {}", e))
+    })
+}
+
+/// The `__reduce__` result for `slf`
+pub fn reduce<'py, T: PyClass + Serialize>(
+    slf: &Bound<'py, T>,
+) -> PyResult<(Bound<'py, PyAny>, (String,))> {
+    let from_state = slf.as_any().get_type().getattr("_from_state")?;
+    Ok((from_state, (state(&*slf.borrow())?,)))
+}
@@ -0,0 +1,154 @@
+"""
+Runs the tests in this directory against a private PipeWire daemon with
+null devices, so the real-audio build can be tested without touching the
+desktop's audio or needing sound hardware.
+
+Needs `pipewire` and `wireplumber` on PATH and quinoa_audio built with
+`maturin develop --features real-audio`; the tests are skipped otherwise.
+"""
+
+import os
+import shutil
+import subprocess
+import time
+
+import pytest
+
+import quinoa_audio
+
+TEST_SOURCE = "quinoa-test-source"
+TEST_SINK = "quinoa-test-sink"
+
+PIPEWIRE_CONF = f"""
+context.properties = {{
+    core.daemon = true
+    core.name = pipewire-0
+    default.clock.rate = 48000
+    support.dbus = false
+}}
+
+context.spa-libs = {{
+    audio.convert.* = audioconvert/libspa-audioconvert
+    support.* = support/libspa-support
+}}
+
+context.modules = [
+    {{ name = libpipewire-module-protocol-native }}
+    {{ name = libpipewire-module-metadata }}
+    {{ name = libpipewire-module-spa-node-factory }}
+    {{ name = libpipewire-module-spa-device-factory }}
+    {{ name = libpipewire-module-client-node }}
+    {{ name = libpipewire-module-client-device }}
+    {{ name = libpipewire-module-adapter }}
+    {{ name = libpipewire-module-link-factory }}
+]
+
+context.objects = [
+    # Drives the graph in place of a sound card's clock
+    {{ factory = spa-node-factory
+        args = {{
+            factory.name = support.node.driver
+            node.name = quinoa-test-driver
+            node.group = pipewire.dummy
+            priority.driver = 20000
+        }}
+    }}
+    {{ factory = adapter
+        args = {{
+            factory.name = support.null-audio-sink
+            node.name = {TEST_SOURCE}
+            node.description = "Quinoa Test Microphone"
+            media.class = Audio/Source
+            audio.position = [ MONO ]
+            audio.rate = 48000
+            object.linger = true
+        }}
+    }}
+    {{ factory = adapter
+        args = {{
+            factory.name = support.null-audio-sink
+            node.name = {TEST_SINK}
+            node.description = "Quinoa Test Speakers"
+            media.class = Audio/Sink
+            audio.position = [ FL FR ]
+            audio.rate = 48000
+            monitor.channel-volumes = true
+            object.linger = true
+        }}
+    }}
+]
+"""
+
+STARTUP_TIMEOUT = 10.0
+
+
+def wait_for(condition, timeout, what):
+    deadline = time.monotonic() + timeout
+    while time.monotonic() < deadline:
+        if condition():
+            return
+        time.sleep(0.1)
+    pytest.fail(f"Timed out waiting for {what}")
+
+
+def devices_present():
+    try:
+        names = {device.id for device in quinoa_audio.list_devices()}
+    except RuntimeError:
+        return False
+    return {TEST_SOURCE, TEST_SINK} <= names
+
+
+@pytest.fixture(scope="session", autouse=True)
+def pipewire(tmp_path_factory):
+    """A PipeWire daemon and WirePlumber in their own runtime dir"""
+    if quinoa_audio.BACKEND != "pipewire":
+        pytest.skip("quinoa_audio was built without real-audio")
+    for program in ("pipewire", "wireplumber"):
+        if shutil.which(program) is None:
+            pytest.skip(f"{program} is not installed")
+
+    root = tmp_path_factory.mktemp("pipewire")
+    runtime_dir = root / "runtime"
+    runtime_dir.mkdir(mode=0o700)
+    conf = root / "pipewire.conf"
+    conf.write_text(PIPEWIRE_CONF)
+
+    env = {
+        "XDG_RUNTIME_DIR": str(runtime_dir),
+        "PIPEWIRE_RUNTIME_DIR": str(runtime_dir),
+        # Keep the desktop's configuration and saved defaults out of it
+        "XDG_CONFIG_HOME": str(root / "config"),
+        "XDG_STATE_HOME": str(root / "state"),
+        "DBUS_SESSION_BUS_ADDRESS": "disabled:",
+    }
+    saved = {key: os.environ.get(key) for key in [*env, "PIPEWIRE_REMOTE"]}
+    os.environ.pop("PIPEWIRE_REMOTE", None)
+    os.environ.update(env)
+
+    logs = (root / "pipewire.log").open("w")
+    daemons = [
+        subprocess.Popen(["pipewire", "-c", str(conf)], stdout=logs, stderr=logs)
+    ]
+    try:
+        wait_for(
+            lambda: (runtime_dir / "pipewire-0").exists(),
+            STARTUP_TIMEOUT,
+            "the PipeWire socket",
+        )
+        daemons.append(subprocess.Popen(["wireplumber"], stdout=logs, stderr=logs))
+        wait_for(devices_present, STARTUP_TIMEOUT, "the null devices")
+        yield {"source": TEST_SOURCE, "sink": TEST_SINK}
+    finally:
+        for daemon in reversed(daemons):
+            daemon.terminate()
+            try:
+                daemon.wait(timeout=5)
+            except subprocess.TimeoutExpired:
+                daemon.kill()
+        logs.close()
+        for key, value in saved.items():
+            if value is None:
+                os.environ.pop(key, None)
+            else:
+                os.environ[key] = value
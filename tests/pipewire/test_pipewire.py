@@ -0,0 +1,86 @@
+"""
+End-to-end tests of the real-audio backend against the daemon started in
+conftest.py.
+"""
+
+import time
+import wave
+
+import quinoa_audio
+
+
+def poll_until(source, condition, timeout=10.0):
+    """Poll `source` (a session or monitor) until an event satisfies `condition`"""
+    poll = getattr(source, "poll_events", None) or source.poll
+    seen = []
+    deadline = time.monotonic() + timeout
+    while time.monotonic() < deadline:
+        events = poll()
+        seen.extend(events)
+        if any(condition(event) for event in events):
+            return seen
+        time.sleep(0.05)
+    raise AssertionError(f"No matching event in {[e.type_ for e in seen]}")
+
+
+def test_enumerates_null_devices(pipewire):
+    devices = {device.id: device for device in quinoa_audio.list_devices()}
+
+    source = devices[pipewire["source"]]
+    assert source.device_type == quinoa_audio.DeviceType.Microphone
+    assert source.name == "Quinoa Test Microphone"
+    assert source.node_id is not None
+    assert not source.is_bluetooth
+
+    sink = devices[pipewire["sink"]]
+    assert sink.device_type == quinoa_audio.DeviceType.Speaker
+    assert sink.channels == 2
+
+
+def test_monitor_reports_new_sink(pipewire):
+    monitor = quinoa_audio.subscribe_device_changes()
+    try:
+        # The devices already present are reported first, flagged as initial
+        seen = poll_until(monitor, lambda e: e.type_ == "snapshot_complete")
+        assert any(e.device_id == pipewire["sink"] and e.initial for e in seen)
+
+        sink = quinoa_audio.create_virtual_sink("quinoa-test-hotplug", playback=False)
+        try:
+            def plugged(e):
+                return e.type_ == "added" and e.device_id == sink.id
+
+            seen = poll_until(monitor, plugged)
+            assert not next(e for e in seen if plugged(e)).initial
+        finally:
+            quinoa_audio.destroy_virtual_sink(sink.id)
+        poll_until(monitor, lambda e: e.type_ == "removed")
+    finally:
+        monitor.stop()
+
+
+def test_short_recording(pipewire, tmp_path):
+    config = quinoa_audio.RecordingConfig(
+        output_dir=str(tmp_path),
+        mic_device_id=pipewire["source"],
+        system_audio=True,
+        system_device_id=pipewire["sink"],
+        sample_rate=48000,
+    )
+    session = quinoa_audio.start_recording(config)
+    try:
+        poll_until(session, lambda e: e.type_ == "started")
+        poll_until(session, lambda e: e.type_ == "levels")
+        time.sleep(1.0)
+    finally:
+        assert session.stop(timeout=10.0)
+
+    events = session.poll_events()
+    assert not [e.message for e in events if e.type_ == "error"]
+    for name, channels in [("microphone.wav", 1), ("system.wav", 2)]:
+        with wave.open(str(tmp_path / name)) as wav:
+            assert wav.getframerate() == 48000
+            assert wav.getnchannels() == channels
+            # The null devices run on the dummy driver's clock, so a second
+            # of recording is about a second of (silent) audio
+            assert wav.getnframes() > 48000 // 2
+    assert (tmp_path / "session.json").exists()
@@ -0,0 +1,221 @@
+"""
+Stress tests for starting and stopping sessions, run against the mock backend.
+
+They check that stop() and poll_events() never deadlock, whichever thread,
+signal handler or atexit hook calls them, and that Python code is never run
+on a session's audio thread.
+"""
+
+import signal
+import subprocess
+import sys
+import threading
+import time
+
+import pytest
+
+import quinoa_audio
+
+
+def mock_config(output_dir, **kwargs):
+    return quinoa_audio.RecordingConfig(
+        output_dir=str(output_dir),
+        mic_device_id="mock_mic",
+        system_audio=True,
+        on_existing="overwrite",
+        **kwargs,
+    )
+
+
+def thread_name():
+    """The OS name of the calling thread, e.g. 'audio-session-3'"""
+    with open("/proc/thread-self/comm") as f:
+        return f.read().strip()
+
+
+def test_start_stop_hundreds_of_sessions(tmp_path):
+    for i in range(300):
+        session = quinoa_audio.start_recording(mock_config(tmp_path / str(i % 10)))
+        if i % 3 == 0:
+            session.poll_events()
+        assert session.stop(timeout=10.0)
+
+    assert quinoa_audio.active_sessions() == []
+
+
+def test_concurrent_stops(tmp_path):
+    sessions = [
+        quinoa_audio.start_recording(mock_config(tmp_path / str(i)))
+        for i in range(50)
+    ]
+    results = []
+
+    def stop_all():
+        for session in sessions:
+            results.append(session.stop(timeout=10.0))
+            session.poll_events()
+
+    threads = [threading.Thread(target=stop_all) for _ in range(4)]
+    for thread in threads:
+        thread.start()
+    for session in sessions:
+        session.poll_events()
+        session.health()
+    for thread in threads:
+        thread.join(timeout=30.0)
+        assert not thread.is_alive(), "stop() deadlocked"
+
+    assert all(results)
+    assert len(results) == 4 * len(sessions)
+
+
+def test_stop_from_signal_handler(tmp_path):
+    session = quinoa_audio.start_recording(mock_config(tmp_path))
+    stopped = []
+
+    def handler(signum, frame):
+        session.poll_events()
+        stopped.append(session.stop(timeout=10.0))
+
+    previous = signal.signal(signal.SIGALRM, handler)
+    try:
+        # Interrupt the main thread while it is polling
+        signal.setitimer(signal.ITIMER_REAL, 0.3)
+        deadline = time.monotonic() + 10.0
+        while not stopped and time.monotonic() < deadline:
+            session.poll_events()
+            session.health()
+            time.sleep(0.01)
+    finally:
+        signal.setitimer(signal.ITIMER_REAL, 0)
+        signal.signal(signal.SIGALRM, previous)
+
+    assert stopped == [True]
+    assert session.stop(timeout=1.0)
+    assert any(e.type_ == "stopped" for e in session.poll_events())
+
+
+def test_signal_handler_stops_while_another_thread_stops(tmp_path):
+    session = quinoa_audio.start_recording(mock_config(tmp_path))
+    results = []
+
+    def handler(signum, frame):
+        results.append(session.stop(timeout=10.0))
+        session.poll_events()
+
+    previous = signal.signal(signal.SIGUSR1, handler)
+    try:
+        worker = threading.Thread(
+            target=lambda: results.append(session.stop(timeout=10.0))
+        )
+        worker.start()
+        signal.raise_signal(signal.SIGUSR1)
+        worker.join(timeout=30.0)
+        assert not worker.is_alive(), "stop() deadlocked"
+    finally:
+        signal.signal(signal.SIGUSR1, previous)
+
+    assert results == [True, True]
+
+
+@pytest.mark.parametrize("stop_at_exit", [True, False])
+def test_exit_with_running_sessions(tmp_path, stop_at_exit):
+    script = f"""
+import atexit
+import quinoa_audio
+
+sessions = [
+    quinoa_audio.start_recording(
+        quinoa_audio.RecordingConfig(
+            output_dir={str(tmp_path)!r} + "/" + str(i),
+            mic_device_id="mock_mic",
+            system_audio=True,
+        )
+    )
+    for i in range(20)
+]
+for session in sessions:
+    session.poll_events()
+if {stop_at_exit!r}:
+    @atexit.register
+    def stop_all():
+        for session in sessions:
+            assert session.stop(timeout=10.0)
+            session.poll_events()
+"""
+    result = subprocess.run(
+        [sys.executable, "-c", script], capture_output=True, timeout=60
+    )
+    assert result.returncode == 0, result.stderr.decode()
+
+
+class RecordingEncoder:
+    """Encoder plugin noting which threads call it"""
+
+    def __init__(self):
+        self.threads = set()
+        self.calls = []
+
+    def open(self, spec):
+        self.threads.add(thread_name())
+        self.calls.append("open")
+
+    def write(self, data):
+        self.threads.add(thread_name())
+        self.calls.append("write")
+
+    def finalize(self):
+        self.threads.add(thread_name())
+        self.calls.append("finalize")
+
+
+def test_plugins_never_called_on_audio_thread(tmp_path):
+    for _ in range(20):
+        encoder = RecordingEncoder()
+        session = quinoa_audio.start_recording(
+            mock_config(tmp_path, encoder_threads=1), mic_encoder=encoder
+        )
+        time.sleep(0.05)
+        assert session.stop(timeout=10.0)
+
+        assert encoder.calls[0] == "open"
+        assert encoder.calls[-1] == "finalize"
+        assert not any(name.startswith("audio-session") for name in encoder.threads)
+
+
+class FailingEncoder:
+    """Encoder plugin whose writes fail, as on a full disk"""
+
+    def open(self, spec):
+        pass
+
+    def write(self, data):
+        raise OSError("No space left on device")
+
+    def finalize(self):
+        pass
+
+
+def test_queued_write_failure_is_reported(tmp_path):
+    # The write fails on the worker; the next write from the audio thread
+    # picks the error up and reports it
+    session = quinoa_audio.start_recording(
+        mock_config(tmp_path, encoder_threads=1), mic_encoder=FailingEncoder()
+    )
+    errors = []
+    deadline = time.monotonic() + 10
+    while not errors and time.monotonic() < deadline:
+        errors = [e for e in session.poll_events() if e.type_ == "error"]
+        time.sleep(0.05)
+    assert session.stop(timeout=10.0)
+
+    assert errors, "no error event"
+    assert "Stopped writing mic audio" in errors[0].message
+    assert "No space left on device" in errors[0].message
+
+
+def test_plugins_need_an_encoder_thread(tmp_path):
+    with pytest.raises(quinoa_audio.ConfigError):
+        quinoa_audio.start_recording(
+            mock_config(tmp_path, encoder_threads=0), mic_encoder=RecordingEncoder()
+        )